@@ -0,0 +1,305 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Simplicity Assembly
+//!
+//! A line-oriented text format for Simplicity programs, suitable for
+//! storing programs in a form which can be read and reviewed. Each line
+//! defines one node, naming it and the nodes it refers to:
+//!
+//! ```text
+//! # Check that the SHA256 of a witness is another witness
+//! preimage = witness : 2^512
+//! image = witness
+//! sha256 = jet sha256
+//! digest = comp preimage sha256
+//! both = pair digest image
+//! eq = jet eqv256
+//! main = comp both eq
+//! ```
+//!
+//! A node is one of `iden`, `unit`, `injl a`, `injr a`, `take a`,
+//! `drop a`, `comp a b`, `case a b`, `pair a b`, `disconnect a b`,
+//! `witness`, `hidden <cmr>`, `jet <name>` or `ext <name>`, where jets
+//! and extension nodes are written with their `Display` names and hashes
+//! in hex. `fail` nodes are rejected, since programs containing them can
+//! be neither type-checked nor encoded. A node may only refer to nodes
+//! defined on earlier lines, and the last line is the root of the
+//! program. Witness nodes may be annotated with their type, which is
+//! then required when the program is type-checked. Everything after a
+//! `#` is a comment.
+//!
+//! Witness values are not part of the text.
+//!
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use bitcoin_hashes::hex::FromHex;
+
+use crate::cmr::Cmr;
+use crate::core::term::DagTerm;
+use crate::core::types::{TypeConstraint, TypeInterner, TypePattern};
+use crate::extension::jets::JetsNode;
+use crate::extension::Jet;
+use crate::{Error, Program, Term, UnTypedProg};
+
+/// A program parsed from its assembly
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Assembly<Ext> {
+    /// The program, with one node for each line, in order
+    pub program: UnTypedProg<(), Ext>,
+    /// Constraints on the types of annotated witness nodes, to be passed
+    /// to `Program::from_untyped_nodes_with_constraints`
+    pub constraints: Vec<TypeConstraint>,
+}
+
+/// Parse a program from its assembly
+pub fn parse<Ext: Jet + Clone>(s: &str) -> Result<Assembly<Ext>, Error> {
+    let mut names = HashMap::<&str, usize>::new();
+    let mut nodes = Vec::<Term<(), Ext>>::new();
+    let mut constraints = vec![];
+    let mut interner = TypeInterner::new();
+
+    for (line_no, line) in s.lines().enumerate() {
+        let error = |msg: String| Error::TextParse(format!("line {}: {}", line_no + 1, msg));
+
+        let line = line.split('#').next().unwrap();
+        let (def, annotation) = match line.find(':') {
+            Some(pos) => (&line[..pos], Some(line[pos + 1..].trim())),
+            None => (line, None),
+        };
+        let mut tokens = def.split_whitespace();
+        let name = match tokens.next() {
+            Some(name) => name,
+            None if annotation.is_none() => continue,
+            None => return Err(error("expected a definition".to_owned())),
+        };
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(error(format!("invalid name `{}`", name)));
+        }
+        if tokens.next() != Some("=") {
+            return Err(error(format!("expected `=` after `{}`", name)));
+        }
+        let combinator = tokens
+            .next()
+            .ok_or_else(|| error(format!("missing definition of `{}`", name)))?;
+        let args = tokens.collect::<Vec<_>>();
+
+        let index = nodes.len();
+        let arity = match combinator {
+            "iden" | "unit" | "witness" => 0,
+            "injl" | "injr" | "take" | "drop" | "hidden" | "jet" | "ext" => 1,
+            "comp" | "case" | "pair" | "disconnect" => 2,
+            "fail" => return Err(error("`fail` nodes are not supported".to_owned())),
+            _ => return Err(error(format!("unknown combinator `{}`", combinator))),
+        };
+        if args.len() != arity {
+            return Err(error(format!(
+                "`{}` takes {} argument(s), but {} were given",
+                combinator,
+                arity,
+                args.len(),
+            )));
+        }
+        // Relative index of a previously defined node
+        let child = |n: usize| match names.get(args[n]) {
+            Some(&i) => Ok(index - i),
+            None => Err(error(format!("undefined node `{}`", args[n]))),
+        };
+        let hash = |n: usize| match Vec::<u8>::from_hex(args[n]) {
+            Ok(ref bytes) if bytes.len() == 32 => {
+                let mut ret = [0; 32];
+                ret.copy_from_slice(bytes);
+                Ok(ret)
+            }
+            _ => Err(error(format!(
+                "expected a 32-byte hex string, got `{}`",
+                args[n]
+            ))),
+        };
+
+        let node = match combinator {
+            "iden" => Term::Iden,
+            "unit" => Term::Unit,
+            "injl" => Term::InjL(child(0)?),
+            "injr" => Term::InjR(child(0)?),
+            "take" => Term::Take(child(0)?),
+            "drop" => Term::Drop(child(0)?),
+            "comp" => Term::Comp(child(0)?, child(1)?),
+            "case" => Term::Case(child(0)?, child(1)?),
+            "pair" => Term::Pair(child(0)?, child(1)?),
+            "disconnect" => Term::Disconnect(child(0)?, child(1)?),
+            "witness" => Term::Witness(()),
+            "hidden" => Term::Hidden(Cmr::from(hash(0)?)),
+            "jet" => match JetsNode::from_name(args[0]) {
+                Some(jet) => Term::Jet(*jet),
+                None => return Err(error(format!("unknown jet `{}`", args[0]))),
+            },
+            "ext" => match Ext::from_name(args[0]) {
+                Some(ext) => Term::Ext(ext.clone()),
+                None => return Err(error(format!("unknown extension `{}`", args[0]))),
+            },
+            _ => unreachable!(),
+        };
+
+        if let Some(annotation) = annotation {
+            if combinator != "witness" {
+                return Err(error("only witness nodes may have a type".to_owned()));
+            }
+            let ty = interner
+                .parse(annotation)
+                .map_err(|e| error(e.to_string()))?;
+            constraints.push(TypeConstraint::node(
+                index,
+                TypePattern::Var(0),
+                TypePattern::Final(ty),
+            ));
+        }
+        if names.insert(name, index).is_some() {
+            return Err(error(format!("`{}` is already defined", name)));
+        }
+        nodes.push(node);
+    }
+
+    if nodes.is_empty() {
+        return Err(Error::TextParse("empty program".to_owned()));
+    }
+    Ok(Assembly {
+        program: UnTypedProg(nodes),
+        constraints: constraints,
+    })
+}
+
+/// Print a program as assembly, naming the node at index `i` as `n<i>`
+/// and annotating every witness node with its type. The output parses to
+/// a program with the same nodes, and therefore the same CMR.
+pub fn print<Ext: Jet>(program: &Program<Ext>) -> String {
+    let mut ret = String::new();
+    for node in &program.nodes {
+        write!(ret, "n{} = {}", node.index, node.node.name()).unwrap();
+        for child in node.children() {
+            write!(ret, " n{}", child).unwrap();
+        }
+        match node.node {
+            Term::Witness(..) => write!(ret, " : {}", node.target_ty).unwrap(),
+            Term::Hidden(cmr) => write!(ret, " {}", cmr).unwrap(),
+            Term::Fail(a, b) => write!(ret, " {} {}", Cmr::from(a), Cmr::from(b)).unwrap(),
+            Term::Ext(ref e) => write!(ret, " {}", e).unwrap(),
+            Term::Jet(ref j) => write!(ret, " {}", j).unwrap(),
+            _ => {}
+        }
+        ret.push('\n');
+    }
+    ret
+}
+
+/// Parse a program from its assembly and convert it into a DAG
+pub fn parse_dag<Ext: Jet + Clone>(s: &str) -> Result<Arc<DagTerm<(), Ext>>, Error> {
+    Ok(DagTerm::from_untyped_prog(parse(s)?.program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bititer::BitIter;
+    use crate::cmr;
+    use crate::extension::dummy::DummyNode;
+
+    const HASH_CHECK: &str = "
+        # Check that the SHA256 of a witness is another witness
+        preimage = witness : 2^512
+        image = witness     # type is inferred
+        sha256 = jet sha256
+        digest = comp preimage sha256
+        both = pair digest image
+        eq = jet eqv256
+        main = comp both eq
+    ";
+
+    #[test]
+    fn round_trip() {
+        let asm = parse::<DummyNode>(HASH_CHECK).unwrap();
+        assert_eq!(asm.program.0.len(), 7);
+        assert_eq!(asm.program.0[4], Term::Pair(1, 3));
+        assert_eq!(asm.constraints.len(), 1);
+
+        let cmr = asm.program.cmrs()[6];
+        let prog = Program::<DummyNode>::from_untyped_nodes_with_constraints(
+            asm.program,
+            &mut BitIter::from(vec![0; 97].into_iter()),
+            &asm.constraints,
+        )
+        .unwrap();
+        assert_eq!(prog.root_node().cmr, cmr);
+
+        let text = print(&prog);
+        assert!(text.starts_with("n0 = witness : 2^512\n"));
+        let reparsed = parse::<DummyNode>(&text).unwrap();
+        assert_eq!(reparsed.program.cmrs()[6], cmr);
+        assert_eq!(parse_dag::<DummyNode>(&text).unwrap().cmr(), cmr);
+    }
+
+    #[test]
+    #[cfg(feature = "bitcoin")]
+    fn bitcoin_nodes() {
+        use crate::extension::bitcoin::BtcNode;
+
+        let cmr = Cmr::from([0x33; 32]);
+        let hidden = format!("u = unit\nh = hidden {}\nmain = case u h\n", cmr);
+        let asm = parse::<BtcNode>(&hidden).unwrap();
+        assert_eq!(asm.program.0[1], Term::Hidden(cmr));
+        let asm = parse::<BtcNode>("lt = ext locktime\nle = jet le32").unwrap();
+        assert_eq!(asm.program.0[0], Term::Ext(BtcNode::LockTime));
+        assert_eq!(asm.program.0[1], Term::Jet(JetsNode::LessThanV32));
+    }
+
+    #[test]
+    fn fail_cmr() {
+        let (a, b) = (Cmr::from([0x11; 32]), Cmr::from([0x22; 32]));
+        let text = format!("main = fail {} {}", a, b);
+        assert!(parse::<DummyNode>(&text).is_err());
+
+        // The CMR commits to the entropy
+        let fail =
+            |a: Cmr, b: Cmr| UnTypedProg::<(), DummyNode>(vec![Term::Fail(a.into(), b.into())]);
+        let cmr = fail(a, b).cmrs()[0];
+        assert_eq!(cmr, cmr::tag::fail().update(a, b));
+        assert_eq!(DagTerm::from_untyped_prog(fail(a, b)).cmr(), cmr);
+        assert_ne!(fail(b, a).cmrs()[0], cmr);
+    }
+
+    #[test]
+    fn parse_errors() {
+        for s in &[
+            "",
+            "# only a comment",
+            "a = iden\na = unit",
+            "a = comp b c",
+            "a = take",
+            "a = frobnicate",
+            "a = jet nosuchjet",
+            "a = hidden 1234",
+            "a = unit : 2",
+            "a = witness : 2^",
+            "1a = unit",
+            "a unit",
+        ] {
+            assert!(parse::<DummyNode>(s).is_err(), "parsed {:?}", s);
+        }
+    }
+}
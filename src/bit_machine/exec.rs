@@ -23,9 +23,9 @@ use std::cmp;
 use crate::bititer::BitIter;
 use crate::core::types::FinalTypeInner;
 use crate::extension;
+use crate::PackedValue;
 use crate::Program;
 use crate::Term;
-use crate::Value;
 
 use crate::extension::Jet as JetNode;
 
@@ -64,7 +64,7 @@ impl BitMachine {
         // assert!(self.write.len() + self.read.len() < self.read.capacity());
 
         self.write.push(Frame {
-            data: self.data.as_mut_ptr(),
+            data: self.data[..].as_mut_ptr(),
             abs_pos: self.next_pos,
             start: self.next_pos,
             len: len as isize,
@@ -204,30 +204,40 @@ impl BitMachine {
     }
 
     /// Write a value to the current write frame
-    fn write_value(&mut self, val: &Value) {
-        // FIXME don't recurse
-        match *val {
-            Value::Unit => {}
-            Value::SumL(ref a) => {
-                self.write_bit(false);
-                self.write_value(a);
+    fn write_value(&mut self, val: &PackedValue) {
+        let mut bits = val.iter();
+        // Without padding, the compact encoding is the Bit Machine encoding
+        if val.len() == val.ty().bit_width {
+            for bit in bits {
+                self.write_bit(bit);
             }
-            Value::SumR(ref a) => {
-                self.write_bit(true);
-                self.write_value(a);
-            }
-            Value::Prod(ref a, ref b) => {
-                self.write_value(a);
-                self.write_value(b);
+            return;
+        }
+
+        let mut stack = vec![&**val.ty()];
+        while let Some(ty) = stack.pop() {
+            match ty.ty {
+                FinalTypeInner::Unit => {}
+                FinalTypeInner::Sum(ref a, ref b) => {
+                    let bit = bits.next().expect("packed value matches its type");
+                    let child = if bit { b } else { a };
+                    self.write_bit(bit);
+                    self.skip(ty.bit_width - child.bit_width - 1);
+                    stack.push(child);
+                }
+                FinalTypeInner::Product(ref a, ref b) => {
+                    stack.push(b);
+                    stack.push(a);
+                }
             }
         }
     }
 
     /// Add a read frame with some given value in it, as input to the
     /// program
-    pub fn input(&mut self, input: &Value) {
+    pub fn input(&mut self, input: &PackedValue) {
         // FIXME typecheck this
-        self.new_frame(input.len());
+        self.new_frame(input.ty().bit_width);
         self.write_value(input);
         self.move_frame();
     }
@@ -237,14 +247,14 @@ impl BitMachine {
         &mut self,
         program: &Program<Ext>,
         txenv: &Ext::TxEnv,
-    ) -> Value {
+    ) -> PackedValue {
         enum CallStack {
             Goto(usize),
             MoveFrame,
             DropFrame,
             CopyFwd(usize),
             Back(usize),
-        };
+        }
 
         let mut ip = program.root_node();
         let mut call_stack = vec![];
@@ -389,13 +399,14 @@ impl BitMachine {
             };
         }
 
+        let target_ty = &program.root_node().target_ty;
         if output_width > 0 {
             let out_frame = self.write.last_mut().unwrap();
             out_frame.abs_pos -= out_frame.len;
-            Value::from_bits_and_type(out_frame, &program.root_node().target_ty)
-                .expect("unwrapping output value")
+            PackedValue::from_padded_bits(out_frame, target_ty).expect("unwrapping output value")
         } else {
-            Value::Unit
+            PackedValue::from_bits_and_type(&mut std::iter::empty(), target_ty)
+                .expect("zero-width values have no bits")
         }
     }
 }
@@ -185,23 +185,27 @@ impl Frame {
     }
 
     pub(in crate::bit_machine) fn copy_from(&mut self, other: &Frame, n: usize) {
+        let mut copied = 0;
         if self.abs_pos % 8 == 0 && other.abs_pos % 8 == 0 {
+            // Only whole bytes are copied directly: the last partial byte
+            // may be shared with the next frame
+            let bytes = n / 8;
             unsafe {
                 ptr::copy_nonoverlapping(
                     other.data.offset(other.abs_pos / 8),
                     self.data.offset(self.abs_pos / 8),
-                    (n + 7) / 8,
+                    bytes,
                 );
-                self.abs_pos += n as isize;
-            }
-        } else {
-            for i in 0..n as isize {
-                let bit = unsafe {
-                    let p = other.data.offset((other.abs_pos + i) / 8);
-                    *p & (1 << (7 - (other.abs_pos + i) % 8)) != 0
-                };
-                self.write_bit(bit);
             }
+            self.abs_pos += 8 * bytes as isize;
+            copied = 8 * bytes as isize;
+        }
+        for i in copied..n as isize {
+            let bit = unsafe {
+                let p = other.data.offset((other.abs_pos + i) / 8);
+                *p & (1 << (7 - (other.abs_pos + i) % 8)) != 0
+            };
+            self.write_bit(bit);
         }
     }
 }
@@ -212,6 +216,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)] // reads one bit per line
     fn read_u8() {
         let mut v = (0..100).collect::<Vec<u8>>();
         let p = v.as_mut_ptr();
@@ -263,4 +268,26 @@ mod tests {
         assert_eq!(f.read_u16(), 9027);
         assert_eq!(f.read_u32(), 1669571523);
     }
+
+    #[test]
+    fn copy_partial_byte() {
+        let mut src = vec![0x80];
+        let src = Frame {
+            data: src.as_mut_ptr(),
+            abs_pos: 0,
+            len: 8,
+            start: 0,
+        };
+        // A 4-bit frame, sharing its byte with the next frame
+        let mut v = vec![0x0f];
+        let mut dst = Frame {
+            data: v.as_mut_ptr(),
+            abs_pos: 0,
+            len: 4,
+            start: 0,
+        };
+        dst.copy_from(&src, 2);
+        assert_eq!(dst.abs_pos, 2);
+        assert_eq!(v, vec![0x8f]);
+    }
 }
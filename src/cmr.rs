@@ -30,6 +30,7 @@ impl From<[u8; 32]> for Cmr {
     }
 }
 
+#[allow(clippy::from_over_into)]
 impl Into<[u8; 32]> for Cmr {
     fn into(self) -> [u8; 32] {
         self.0.into_inner()
@@ -161,4 +162,10 @@ pub mod tag {
     pub fn disconnect() -> Cmr {
         Cmr::new(b"Simplicity\x1fCommitment\x1fdisconnect")
     }
+
+    /// Tagged hash used by `fail`, which is compressed with the 512 bits
+    /// of entropy of the node
+    pub fn fail() -> Cmr {
+        Cmr::new(b"Simplicity\x1fCommitment\x1ffail")
+    }
 }
@@ -0,0 +1,348 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # DAG Builder
+//!
+//! Construction of `DagTerm`s with maximal sharing. Nodes built through a
+//! `DagBuilder` are hash-consed, so identical subexpressions are
+//! represented by a single allocation and appear only once in the encoded
+//! program.
+//!
+//! A shared node has a single type, so subexpressions may only be merged
+//! if they are used at the same type. The combinator methods therefore
+//! share only nodes whose type is fixed by their children, while `import`
+//! type checks a complete program and merges nodes with equal types.
+//!
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use super::term::{DagTerm, Term};
+use super::types::{self, FinalType, TypeInterner};
+use crate::cmr::Cmr;
+use crate::extension::jets::JetsNode;
+use crate::extension::Jet;
+use crate::Error;
+
+/// A node with its children replaced by their addresses, and optionally
+/// the addresses of its source and target types
+type NodeKey<Extension> = (Term<(), Extension>, Option<(usize, usize)>);
+
+/// Builder context which hash-conses `DagTerm` nodes
+///
+/// Nodes are keyed by their combinator and the addresses of their
+/// children, so sharing is maximal as long as children were themselves
+/// built by the same builder. Witness nodes are never shared: each call
+/// to `witness` creates a distinct witness, as does each witness node
+/// passed to `import`.
+#[derive(Debug)]
+pub struct DagBuilder<Witness, Extension> {
+    /// Map from a node, with its children replaced by their addresses,
+    /// to the node itself. Nodes merged by `import` are additionally
+    /// keyed by the addresses of their interned source and target types.
+    /// Holding the nodes keeps the addresses valid.
+    nodes: HashMap<NodeKey<Extension>, Arc<DagTerm<Witness, Extension>>>,
+    /// Interner for the types computed by `import`
+    interner: TypeInterner,
+}
+
+impl<Witness, Extension: Hash + Eq + Clone> Default for DagBuilder<Witness, Extension> {
+    fn default() -> Self {
+        DagBuilder::new()
+    }
+}
+
+impl<Witness, Extension: Hash + Eq + Clone> DagBuilder<Witness, Extension> {
+    /// Create a new, empty builder
+    pub fn new() -> Self {
+        DagBuilder {
+            nodes: HashMap::new(),
+            interner: TypeInterner::new(),
+        }
+    }
+
+    /// Number of distinct nodes held by the builder
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether the builder holds no nodes
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Return the canonical copy of the given node, whose children should
+    /// already have been built by this builder
+    ///
+    /// Only nodes whose type is determined by the types of their children
+    /// are shared: compositions, pairs, disconnects, cases without hidden
+    /// branches, jets and extensions. Any other node, such as `iden` or
+    /// `injl`, may be used at several types and is returned as a new
+    /// allocation.
+    pub fn intern(
+        &mut self,
+        node: DagTerm<Witness, Extension>,
+    ) -> Arc<DagTerm<Witness, Extension>> {
+        let shared = match node {
+            DagTerm::Comp(..)
+            | DagTerm::Pair(..)
+            | DagTerm::Disconnect(..)
+            | DagTerm::Ext(..)
+            | DagTerm::Jet(..) => true,
+            DagTerm::Case(ref l, ref r) => match (&**l, &**r) {
+                (&DagTerm::Hidden(..), _) | (_, &DagTerm::Hidden(..)) => false,
+                _ => true,
+            },
+            _ => false,
+        };
+        if !shared {
+            return Arc::new(node);
+        }
+        let key = (node.shape(), None);
+        Arc::clone(self.nodes.entry(key).or_insert_with(|| Arc::new(node)))
+    }
+
+    /// Rebuild a complete program through this builder, merging every pair
+    /// of identical subexpressions which have the same type
+    ///
+    /// The program is type checked on its own, so any type left free by it
+    /// is taken to be the unit type. Importing an expression which is later
+    /// used at some other type may therefore produce an ill-typed program.
+    pub fn import(
+        &mut self,
+        dag: &DagTerm<Witness, Extension>,
+    ) -> Result<Arc<DagTerm<Witness, Extension>>, Error>
+    where
+        Witness: Clone,
+        Extension: Jet,
+    {
+        let typed = types::type_check_with_interner(dag.to_untyped_prog(), &mut self.interner)?;
+        // `to_untyped_prog` lists the nodes in the order in which
+        // `post_order_fold` visits them
+        let mut typed = typed.into_iter();
+        let nodes = &mut self.nodes;
+        Ok(
+            dag.post_order_fold(|node, children: Vec<Arc<DagTerm<_, _>>>| {
+                let ty = typed.next().expect("one typed node per DAG node");
+                let child = |n: usize| Arc::clone(&children[n]);
+                let node = match *node {
+                    DagTerm::Iden => DagTerm::Iden,
+                    DagTerm::Unit => DagTerm::Unit,
+                    DagTerm::InjL(..) => DagTerm::InjL(child(0)),
+                    DagTerm::InjR(..) => DagTerm::InjR(child(0)),
+                    DagTerm::Take(..) => DagTerm::Take(child(0)),
+                    DagTerm::Drop(..) => DagTerm::Drop(child(0)),
+                    DagTerm::Comp(..) => DagTerm::Comp(child(0), child(1)),
+                    DagTerm::Case(..) => DagTerm::Case(child(0), child(1)),
+                    DagTerm::Pair(..) => DagTerm::Pair(child(0), child(1)),
+                    DagTerm::Disconnect(..) => DagTerm::Disconnect(child(0), child(1)),
+                    DagTerm::Witness(ref w) => return Arc::new(DagTerm::Witness(w.clone())),
+                    DagTerm::Fail(a, b) => DagTerm::Fail(a, b),
+                    DagTerm::Hidden(cmr) => DagTerm::Hidden(cmr),
+                    DagTerm::Ext(ref e) => DagTerm::Ext(e.clone()),
+                    DagTerm::Jet(j) => DagTerm::Jet(j),
+                };
+                let types = (
+                    &*ty.source_ty as *const FinalType as usize,
+                    &*ty.target_ty as *const FinalType as usize,
+                );
+                let key = (node.shape(), Some(types));
+                Arc::clone(nodes.entry(key).or_insert_with(|| Arc::new(node)))
+            }),
+        )
+    }
+
+    /// The identity combinator
+    pub fn iden(&mut self) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::Iden)
+    }
+
+    /// The unit combinator
+    pub fn unit(&mut self) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::Unit)
+    }
+
+    /// Left injection of the given expression
+    pub fn injl(
+        &mut self,
+        l: &Arc<DagTerm<Witness, Extension>>,
+    ) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::InjL(Arc::clone(l)))
+    }
+
+    /// Right injection of the given expression
+    pub fn injr(
+        &mut self,
+        r: &Arc<DagTerm<Witness, Extension>>,
+    ) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::InjR(Arc::clone(r)))
+    }
+
+    /// Apply the given expression to the left half of a product
+    pub fn take(
+        &mut self,
+        l: &Arc<DagTerm<Witness, Extension>>,
+    ) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::Take(Arc::clone(l)))
+    }
+
+    /// Apply the given expression to the right half of a product
+    pub fn drop(
+        &mut self,
+        r: &Arc<DagTerm<Witness, Extension>>,
+    ) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::Drop(Arc::clone(r)))
+    }
+
+    /// Composition of two expressions, applying `l` first
+    pub fn comp(
+        &mut self,
+        l: &Arc<DagTerm<Witness, Extension>>,
+        r: &Arc<DagTerm<Witness, Extension>>,
+    ) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::Comp(Arc::clone(l), Arc::clone(r)))
+    }
+
+    /// Case analysis on the sum in the left half of the input
+    pub fn case(
+        &mut self,
+        l: &Arc<DagTerm<Witness, Extension>>,
+        r: &Arc<DagTerm<Witness, Extension>>,
+    ) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::Case(Arc::clone(l), Arc::clone(r)))
+    }
+
+    /// Pair of the outputs of two expressions
+    pub fn pair(
+        &mut self,
+        l: &Arc<DagTerm<Witness, Extension>>,
+        r: &Arc<DagTerm<Witness, Extension>>,
+    ) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::Pair(Arc::clone(l), Arc::clone(r)))
+    }
+
+    /// Disconnect combinator
+    pub fn disconnect(
+        &mut self,
+        l: &Arc<DagTerm<Witness, Extension>>,
+        r: &Arc<DagTerm<Witness, Extension>>,
+    ) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::Disconnect(Arc::clone(l), Arc::clone(r)))
+    }
+
+    /// A new witness, distinct from every other witness
+    pub fn witness(&mut self, w: Witness) -> Arc<DagTerm<Witness, Extension>> {
+        Arc::new(DagTerm::Witness(w))
+    }
+
+    /// Fail combinator
+    pub fn fail(&mut self, a: [u8; 32], b: [u8; 32]) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::Fail(a, b))
+    }
+
+    /// Hidden subexpression with the given CMR
+    pub fn hidden(&mut self, cmr: Cmr) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::Hidden(cmr))
+    }
+
+    /// Extension node
+    pub fn ext(&mut self, e: Extension) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::Ext(e))
+    }
+
+    /// Jet node
+    pub fn jet(&mut self, j: JetsNode) -> Arc<DagTerm<Witness, Extension>> {
+        self.intern(DagTerm::Jet(j))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::type_check;
+    use crate::extension::dummy::DummyNode;
+
+    #[test]
+    fn maximal_sharing() {
+        let mut builder = DagBuilder::<(), DummyNode>::new();
+        let unit = builder.unit();
+        let a = builder.injl(&unit);
+        let b = builder.injl(&unit);
+        // `injl` may be used at different types
+        assert!(!Arc::ptr_eq(&a, &b));
+        let j1 = builder.jet(JetsNode::Adder32);
+        let j2 = builder.jet(JetsNode::Adder32);
+        assert!(Arc::ptr_eq(&j1, &j2));
+        let p1 = builder.pair(&j1, &j2);
+        let p2 = builder.pair(&j2, &j1);
+        assert!(Arc::ptr_eq(&p1, &p2));
+
+        let w1 = builder.witness(());
+        let w2 = builder.witness(());
+        assert!(!Arc::ptr_eq(&w1, &w2));
+        let c1 = builder.comp(&a, &w1);
+        let c2 = builder.comp(&a, &w2);
+        assert!(!Arc::ptr_eq(&c1, &c2));
+
+        let unshared = DagTerm::Pair(
+            Arc::new(DagTerm::Comp(
+                Arc::new(DagTerm::Pair(
+                    Arc::new(DagTerm::InjL(Arc::new(DagTerm::Unit))),
+                    Arc::new(DagTerm::InjL(Arc::new(DagTerm::Unit))),
+                )),
+                Arc::new(DagTerm::Witness(())),
+            )),
+            Arc::new(DagTerm::Comp(
+                Arc::new(DagTerm::Pair(
+                    Arc::new(DagTerm::InjL(Arc::new(DagTerm::Unit))),
+                    Arc::new(DagTerm::InjL(Arc::new(DagTerm::Unit))),
+                )),
+                Arc::new(DagTerm::Witness(())),
+            )),
+        );
+        assert_eq!(unshared.to_untyped_prog().0.len(), 15);
+        let mut builder = DagBuilder::<(), DummyNode>::new();
+        let imported = builder.import(&unshared).unwrap();
+        // unit, injl, pair, two witnesses, two comps, root
+        assert_eq!(imported.to_untyped_prog().0.len(), 8);
+        assert_eq!(builder.len(), 6);
+    }
+
+    #[test]
+    fn polymorphic_nodes_keep_their_types() {
+        // The two outer `iden`s have type A -> A and the inner one has
+        // type A × A -> A × A, so they must not be merged
+        let unshared = DagTerm::<(), DummyNode>::Comp(
+            Arc::new(DagTerm::Pair(
+                Arc::new(DagTerm::Iden),
+                Arc::new(DagTerm::Iden),
+            )),
+            Arc::new(DagTerm::Iden),
+        );
+        assert!(type_check(unshared.to_untyped_prog()).is_ok());
+
+        let imported = DagBuilder::new().import(&unshared).unwrap();
+        let prog = imported.to_untyped_prog();
+        assert_eq!(prog.0.len(), 4);
+        assert!(type_check(prog).is_ok());
+
+        let mut builder = DagBuilder::new();
+        let (i1, i2, i3) = (builder.iden(), builder.iden(), builder.iden());
+        let pair = builder.pair(&i1, &i2);
+        let built = builder.comp(&pair, &i3);
+        assert_eq!(built.to_untyped_prog(), unshared.to_untyped_prog());
+        assert!(type_check(built.to_untyped_prog()).is_ok());
+    }
+}
@@ -0,0 +1,681 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Derived Combinators
+//!
+//! The standard library of expressions derived from the core combinators,
+//! as described in section 3 of the Simplicity Tech Report.
+//!
+//! Bits are values of type `2 = 1 + 1`, with `false` the left injection.
+//! Words of `2^n` bits are nested products of halves, most significant
+//! half first; `log_bits` arguments give the base-2 logarithm of the
+//! word width.
+//!
+//! Expressions are built directly rather than through a `DagBuilder`,
+//! so subexpressions are only shared where they are reused at the same
+//! type. A complete program built from these combinators can be passed
+//! to `DagBuilder::import`, which merges identical subexpressions of the
+//! same type.
+//!
+
+use std::sync::Arc;
+
+use super::term::{DagTerm, Value};
+use crate::cmr::Cmr;
+
+/// Shorthand for the expressions built by this module
+type Expr<Witness, Extension> = Arc<DagTerm<Witness, Extension>>;
+
+fn comp<W, E>(s: &Expr<W, E>, t: &Expr<W, E>) -> Expr<W, E> {
+    Arc::new(DagTerm::Comp(Arc::clone(s), Arc::clone(t)))
+}
+
+fn case<W, E>(s: &Expr<W, E>, t: &Expr<W, E>) -> Expr<W, E> {
+    Arc::new(DagTerm::Case(Arc::clone(s), Arc::clone(t)))
+}
+
+fn pair<W, E>(s: &Expr<W, E>, t: &Expr<W, E>) -> Expr<W, E> {
+    Arc::new(DagTerm::Pair(Arc::clone(s), Arc::clone(t)))
+}
+
+fn take<W, E>(t: &Expr<W, E>) -> Expr<W, E> {
+    Arc::new(DagTerm::Take(Arc::clone(t)))
+}
+
+fn drop<W, E>(t: &Expr<W, E>) -> Expr<W, E> {
+    Arc::new(DagTerm::Drop(Arc::clone(t)))
+}
+
+/// Projection given by a path of `O` (take) and `I` (drop) steps ending
+/// in `iden`, written as in the Tech Report: `path("OI")` is `take drop iden`
+fn path<W, E>(steps: &str) -> Expr<W, E> {
+    steps
+        .chars()
+        .rev()
+        .fold(Arc::new(DagTerm::Iden), |t, step| match step {
+            'O' => take(&t),
+            'I' => drop(&t),
+            _ => unreachable!("path steps are O or I"),
+        })
+}
+
+/// First projection, `A × B → A`
+pub fn fst<W, E>() -> Expr<W, E> {
+    path("O")
+}
+
+/// Second projection, `A × B → B`
+pub fn snd<W, E>() -> Expr<W, E> {
+    path("I")
+}
+
+/// Constant function `A → B` returning the given value of type `B`
+pub fn scribe<W, E>(value: &Value) -> Expr<W, E> {
+    value.post_order_fold(|value, children: Vec<Expr<W, E>>| {
+        Arc::new(match *value {
+            Value::Unit => DagTerm::Unit,
+            Value::SumL(..) => DagTerm::InjL(Arc::clone(&children[0])),
+            Value::SumR(..) => DagTerm::InjR(Arc::clone(&children[0])),
+            Value::Prod(..) => DagTerm::Pair(Arc::clone(&children[0]), Arc::clone(&children[1])),
+        })
+    })
+}
+
+/// Constant function `A → 2` returning `false`
+pub fn zero<W, E>() -> Expr<W, E> {
+    Arc::new(DagTerm::InjL(Arc::new(DagTerm::Unit)))
+}
+
+/// Constant function `A → 2` returning `true`
+pub fn one<W, E>() -> Expr<W, E> {
+    Arc::new(DagTerm::InjR(Arc::new(DagTerm::Unit)))
+}
+
+/// Case analysis `(A + B) × C → D` whose right branch is pruned, leaving
+/// only its CMR `h`. Reaching the right branch fails.
+pub fn assertl<W, E>(s: &Expr<W, E>, h: Cmr) -> Expr<W, E> {
+    case(s, &Arc::new(DagTerm::Hidden(h)))
+}
+
+/// Case analysis `(A + B) × C → D` whose left branch is pruned, leaving
+/// only its CMR `h`. Reaching the left branch fails.
+pub fn assertr<W, E>(h: Cmr, t: &Expr<W, E>) -> Expr<W, E> {
+    case(&Arc::new(DagTerm::Hidden(h)), t)
+}
+
+/// Branch `2 × A → B` on a bit: `⟨1, a⟩ ↦ s(a)` and `⟨0, a⟩ ↦ t(a)`
+pub fn cond<W, E>(s: &Expr<W, E>, t: &Expr<W, E>) -> Expr<W, E> {
+    case(&drop(t), &drop(s))
+}
+
+/// Pair the bit computed by `s` with the input, and branch on it
+fn branch<W, E>(s: &Expr<W, E>, then: &Expr<W, E>, otherwise: &Expr<W, E>) -> Expr<W, E> {
+    let iden = Arc::new(DagTerm::Iden);
+    comp(&pair(s, &iden), &cond(then, otherwise))
+}
+
+/// Negation `A → 2` of the predicate `t : A → 2`
+pub fn not<W, E>(t: &Expr<W, E>) -> Expr<W, E> {
+    let unit = Arc::new(DagTerm::Unit);
+    comp(&pair(t, &unit), &case(&one(), &zero()))
+}
+
+/// Conjunction `A → 2` of the predicates `s, t : A → 2`. `t` is only
+/// evaluated when `s` is true.
+pub fn and<W, E>(s: &Expr<W, E>, t: &Expr<W, E>) -> Expr<W, E> {
+    branch(s, t, &zero())
+}
+
+/// Disjunction `A → 2` of the predicates `s, t : A → 2`. `t` is only
+/// evaluated when `s` is false.
+pub fn or<W, E>(s: &Expr<W, E>, t: &Expr<W, E>) -> Expr<W, E> {
+    branch(s, &one(), t)
+}
+
+/// Exclusive or `A → 2` of the predicates `s, t : A → 2`
+pub fn xor<W, E>(s: &Expr<W, E>, t: &Expr<W, E>) -> Expr<W, E> {
+    branch(s, &not(t), t)
+}
+
+/// Majority `A → 2` of the predicates `s, t, u : A → 2`
+pub fn maj<W, E>(s: &Expr<W, E>, t: &Expr<W, E>, u: &Expr<W, E>) -> Expr<W, E> {
+    branch(s, &or(t, u), &and(t, u))
+}
+
+/// Path steps to the `i`th of the `2^log_parts` parts of a word, counting
+/// from the most significant part
+fn part_steps(log_parts: usize, i: usize) -> String {
+    (0..log_parts)
+        .rev()
+        .map(|level| if i & (1 << level) == 0 { 'O' } else { 'I' })
+        .collect()
+}
+
+/// Word whose parts, most significant first, are computed by the given
+/// expressions. The number of parts must be a power of two.
+fn gather<W, E>(parts: &[Expr<W, E>]) -> Expr<W, E> {
+    if parts.len() == 1 {
+        Arc::clone(&parts[0])
+    } else {
+        let (hi, lo) = parts.split_at(parts.len() / 2);
+        pair(&gather(hi), &gather(lo))
+    }
+}
+
+/// Projection `2^(2^log_bits) → 2^(2^log_part)` splitting the `i`th part
+/// out of a word, counting from the most significant part. Will panic if
+/// the part is wider than the word or `i` is out of range.
+pub fn word_part<W, E>(log_bits: usize, log_part: usize, i: usize) -> Expr<W, E> {
+    assert!(log_part <= log_bits, "part wider than the word");
+    assert!(i < 1 << (log_bits - log_part), "part {} out of range", i);
+    path(&part_steps(log_bits - log_part, i))
+}
+
+/// Projection `2^(2^log_bits) → 2` of the `i`th bit of a word, counting
+/// from the most significant bit. Will panic if `i` is out of range.
+pub fn word_bit<W, E>(log_bits: usize, i: usize) -> Expr<W, E> {
+    word_part(log_bits, 0, i)
+}
+
+/// Zero-extend the word `s : A → 2^(2^log_from)` to `A → 2^(2^log_to)`
+/// by pairing it with words of zeroes
+pub fn zero_extend<W, E>(s: &Expr<W, E>, log_from: usize, log_to: usize) -> Expr<W, E> {
+    let mut zeros = zero();
+    for _ in 0..log_from {
+        zeros = pair(&zeros, &zeros);
+    }
+    let mut ret = Arc::clone(s);
+    for _ in log_from..log_to {
+        ret = pair(&zeros, &ret);
+        zeros = pair(&zeros, &zeros);
+    }
+    ret
+}
+
+/// Full adder `(2^n × 2^n) × 2 → 2 × 2^n`, for `n = 2^log_bits`, taking
+/// two words and a carry bit to the carry bit and the sum
+pub fn full_adder<W, E>(log_bits: usize) -> Expr<W, E> {
+    // ((a, b), c) ↦ (maj(a, b, c), a ⊕ b ⊕ c)
+    let (a, b, c) = (path("OO"), path("OI"), path("I"));
+    let mut ret = pair(&maj(&a, &b, &c), &xor(&xor(&a, &b), &c));
+
+    for _ in 0..log_bits {
+        // ((x, y), c) ↦ (c1, sum_lo), where x = (x_hi, x_lo) and y = (y_hi, y_lo)
+        let lo_words = pair(&path("OOI"), &path("OII"));
+        let lo_add = comp(&pair(&lo_words, &path("I")), &ret);
+        // ((c1, sum_lo), ((x, y), c)) ↦ (c2, sum_hi)
+        let hi_words = pair(&path("IOOO"), &path("IOIO"));
+        let hi_add = comp(&pair(&hi_words, &path("OO")), &ret);
+        // ((c2, sum_hi), sum_lo) ↦ (c2, (sum_hi, sum_lo))
+        let reshape = pair(&path("OO"), &pair(&path("OI"), &path("I")));
+        let add = comp(&pair(&hi_add, &path("OI")), &reshape);
+
+        ret = comp(&pair(&lo_add, &Arc::new(DagTerm::Iden)), &add);
+    }
+    ret
+}
+
+/// Equality `2^n × 2^n → 2` of words, for `n = 2^log_bits`
+pub fn eq<W, E>(log_bits: usize) -> Expr<W, E> {
+    let mut ret = not(&xor(&fst(), &snd()));
+    for _ in 0..log_bits {
+        let hi_eq = comp(&pair(&path("OO"), &path("IO")), &ret);
+        let lo_eq = comp(&pair(&path("OI"), &path("II")), &ret);
+        ret = and(&hi_eq, &lo_eq);
+    }
+    ret
+}
+
+/// Bitwise complement `2^n → 2^n` of a word, for `n = 2^log_bits`
+pub fn complement<W, E>(log_bits: usize) -> Expr<W, E> {
+    let mut ret = not(&Arc::new(DagTerm::Iden));
+    for _ in 0..log_bits {
+        ret = pair(&take(&ret), &drop(&ret));
+    }
+    ret
+}
+
+/// Apply `op : 2 × 2 → 2` to each pair of corresponding bits of two
+/// words, `2^n × 2^n → 2^n` for `n = 2^log_bits`
+pub fn bitwise<W, E>(log_bits: usize, op: &Expr<W, E>) -> Expr<W, E> {
+    let mut ret = Arc::clone(op);
+    for _ in 0..log_bits {
+        let hi = comp(&pair(&path("OO"), &path("IO")), &ret);
+        let lo = comp(&pair(&path("OI"), &path("II")), &ret);
+        ret = pair(&hi, &lo);
+    }
+    ret
+}
+
+/// Apply `op : 2 × (2 × 2) → 2` to each triple of corresponding bits of
+/// three words, `2^n × (2^n × 2^n) → 2^n` for `n = 2^log_bits`
+pub fn bitwise3<W, E>(log_bits: usize, op: &Expr<W, E>) -> Expr<W, E> {
+    let mut ret = Arc::clone(op);
+    for _ in 0..log_bits {
+        let hi = comp(&pair(&path("OO"), &pair(&path("IOO"), &path("IIO"))), &ret);
+        let lo = comp(&pair(&path("OI"), &pair(&path("IOI"), &path("III"))), &ret);
+        ret = pair(&hi, &lo);
+    }
+    ret
+}
+
+/// Rotation `2^n → 2^n` of a word by `k` bits towards its least
+/// significant end, for `n = 2^log_bits`
+pub fn rotate_right<W, E>(log_bits: usize, k: usize) -> Expr<W, E> {
+    let n = 1 << log_bits;
+    let bits = (0..n)
+        .map(|i| word_bit(log_bits, (i + n - k % n) % n))
+        .collect::<Vec<_>>();
+    gather(&bits)
+}
+
+/// Logical shift `2^n → 2^n` of a word by `k` bits towards its least
+/// significant end, for `n = 2^log_bits`, filling with zeroes
+pub fn shift_right<W, E>(log_bits: usize, k: usize) -> Expr<W, E> {
+    let bits = (0..1 << log_bits)
+        .map(|i| {
+            if i < k {
+                zero()
+            } else {
+                word_bit(log_bits, i - k)
+            }
+        })
+        .collect::<Vec<_>>();
+    gather(&bits)
+}
+
+/// Full subtractor `(2^n × 2^n) × 2 → 2 × 2^n`, for `n = 2^log_bits`,
+/// taking two words and a borrow bit to the borrow bit and the difference
+pub fn full_subtractor<W, E>(log_bits: usize) -> Expr<W, E> {
+    // a - b - c = a + ¬b + ¬c - 2^n, which borrows exactly when the sum
+    // does not carry
+    let not_bit = complement(0);
+    let negated = pair(&path("OO"), &comp(&path("OI"), &complement(log_bits)));
+    let args = pair(&negated, &comp(&path("I"), &not_bit));
+    let borrow = pair(&comp(&path("O"), &not_bit), &path("I"));
+    comp(&args, &comp(&full_adder(log_bits), &borrow))
+}
+
+/// Full multiplier `(2^n × 2^n) × (2^n × 2^n) → 2^2n`, for
+/// `n = 2^log_bits`, taking `((a, b), (c, d))` to `a · b + c + d`
+pub fn full_multiplier<W, E>(log_bits: usize) -> Expr<W, E> {
+    // ((a, b), (c, d)) ↦ ((a ∧ b, c), d), which cannot overflow two bits
+    let bit_product = pair(
+        &pair(&and(&path("OO"), &path("OI")), &path("IO")),
+        &path("II"),
+    );
+    let mut ret = comp(&bit_product, &full_adder(0));
+
+    for _ in 0..log_bits {
+        // Pair the input with a half-width product of the given words
+        let mul = |a: &str, b: &str, c: &str, d: &str| {
+            let args = pair(&pair(&path(a), &path(b)), &pair(&path(c), &path(d)));
+            pair(&Arc::new(DagTerm::Iden), &comp(&args, &ret))
+        };
+        // With each word split into halves (x1, x0), the product is
+        // h · 2^2n + g0 · 2^n + e0, where
+        // e = a0 · b0 + c0 + d0, on x = ((a, b), (c, d))
+        let e = mul("OOI", "OII", "IOI", "III");
+        // f = a1 · b0 + c1 + e1, on (x, e)
+        let f = mul("OOOO", "OOII", "OIOO", "IO");
+        // g = a0 · b1 + f0 + d1, on ((x, e), f)
+        let g = mul("OOOOI", "OOOIO", "II", "OOIIO");
+        // h = a1 · b1 + f1 + g1, on (((x, e), f), g)
+        let h = mul("OOOOOO", "OOOOIO", "OIO", "IO");
+        let result = pair(&path("I"), &pair(&path("OII"), &path("OOOII")));
+        ret = comp(&e, &comp(&f, &comp(&g, &comp(&h, &result))));
+    }
+    ret
+}
+
+/// Round constants of SHA-256
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 block compression `2^256 × 2^512 → 2^256`, taking a midstate
+/// and a block to the next midstate. Both are sequences of big-endian
+/// 32-bit words, as in FIPS 180-4; no padding is added.
+pub fn sha256_block<W, E>() -> Expr<W, E> {
+    let word = |prefix: &str, log_words: usize, i: usize| {
+        path(&format!("{}{}", prefix, part_steps(log_words, i)))
+    };
+    // Addition modulo 2^32
+    let adder = comp(
+        &pair(&Arc::new(DagTerm::Iden), &zero()),
+        &comp(&full_adder(5), &snd()),
+    );
+    let add = |s: &Expr<W, E>, t: &Expr<W, E>| comp(&pair(s, t), &adder);
+
+    let xor3 = bitwise3(5, &xor(&path("O"), &xor(&path("IO"), &path("II"))));
+    let mix = |s: Expr<W, E>, t: Expr<W, E>, u: Expr<W, E>| comp(&pair(&s, &pair(&t, &u)), &xor3);
+    let big_sigma0 = mix(rotate_right(5, 2), rotate_right(5, 13), rotate_right(5, 22));
+    let big_sigma1 = mix(rotate_right(5, 6), rotate_right(5, 11), rotate_right(5, 25));
+    let sigma0 = mix(rotate_right(5, 7), rotate_right(5, 18), shift_right(5, 3));
+    let sigma1 = mix(rotate_right(5, 17), rotate_right(5, 19), shift_right(5, 10));
+    let choose = bitwise3(5, &cond(&fst(), &snd()));
+    let majority = bitwise3(5, &maj(&path("O"), &path("IO"), &path("II")));
+
+    // One round, ((state, window), k) ↦ (state, window), where the window
+    // holds the next 16 words of the message schedule
+    let round = {
+        let s = |i: usize| word("OO", 3, i);
+        let w = |i: usize| word("OI", 4, i);
+        let ch = comp(&pair(&s(4), &pair(&s(5), &s(6))), &choose);
+        let t1 = add(
+            &s(7),
+            &add(
+                &comp(&s(4), &big_sigma1),
+                &add(&ch, &add(&path("I"), &w(0))),
+            ),
+        );
+
+        // On (((state, window), k), t1)
+        let s = |i: usize| word("OOO", 3, i);
+        let w = |i: usize| word("OOI", 4, i);
+        let maj = comp(&pair(&s(0), &pair(&s(1), &s(2))), &majority);
+        let t2 = add(&comp(&s(0), &big_sigma0), &maj);
+        let state = gather(&[
+            add(&path("I"), &t2),
+            s(0),
+            s(1),
+            s(2),
+            add(&s(3), &path("I")),
+            s(4),
+            s(5),
+            s(6),
+        ]);
+        let next = add(
+            &comp(&w(14), &sigma1),
+            &add(&w(9), &add(&comp(&w(1), &sigma0), &w(0))),
+        );
+        let window = (1..16).map(w).chain(Some(next)).collect::<Vec<_>>();
+
+        comp(
+            &pair(&Arc::new(DagTerm::Iden), &t1),
+            &pair(&state, &gather(&window)),
+        )
+    };
+
+    let with_k = |k: u32| {
+        let k = pair(&Arc::new(DagTerm::Iden), &scribe(&Value::u32(k)));
+        comp(&k, &round)
+    };
+    let mut rounds = with_k(SHA256_K[0]);
+    for k in &SHA256_K[1..] {
+        rounds = comp(&rounds, &with_k(*k));
+    }
+    // ((midstate, block), (state, window)) ↦ midstate + state
+    let sums = (0..8)
+        .map(|i| add(&word("OO", 3, i), &word("IO", 3, i)))
+        .collect::<Vec<_>>();
+    comp(&pair(&Arc::new(DagTerm::Iden), &rounds), &gather(&sums))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::FinalType;
+    use crate::extension::dummy::DummyNode;
+    use crate::test_utils;
+    use std::str::FromStr;
+
+    /// Type-check `expr` with the given source and target types, failing
+    /// if it does not have them, and evaluate it on each of the inputs
+    fn run(expr: &Expr<(), DummyNode>, source: &str, target: &str, inputs: &[Value]) -> Vec<Value> {
+        let source = Arc::new(FinalType::from_str(source).unwrap());
+        let target = Arc::new(FinalType::from_str(target).unwrap());
+        test_utils::run(expr.to_untyped_prog(), &source, &target, inputs)
+            .into_iter()
+            .map(|output| output.expect("evaluation succeeds"))
+            .collect()
+    }
+
+    fn bit(b: bool) -> Value {
+        Value::u1(b as u8)
+    }
+
+    #[test]
+    fn constants_and_projections() {
+        let value = Value::prod(Value::u8(0xa5), bit(true));
+        let scribed = scribe(&value);
+        assert_eq!(run(&scribed, "2", "2^8 × 2", &[bit(false)]), vec![value]);
+        let zero = zero();
+        assert_eq!(run(&zero, "1", "2", &[Value::Unit]), vec![bit(false)]);
+        let one = one();
+        assert_eq!(run(&one, "1", "2", &[Value::Unit]), vec![bit(true)]);
+
+        let pair = [Value::prod(Value::u4(3), Value::u2(1))];
+        let fst = fst();
+        assert_eq!(run(&fst, "2^4 × 2^2", "2^4", &pair), vec![Value::u4(3)]);
+        let snd = snd();
+        assert_eq!(run(&snd, "2^4 × 2^2", "2^2", &pair), vec![Value::u2(1)]);
+
+        for i in 0..8 {
+            let bit_i = word_bit(3, i);
+            let expected = (0..=255u8)
+                .map(|n| bit(n & (0x80 >> i) != 0))
+                .collect::<Vec<_>>();
+            let inputs = (0..=255).map(Value::u8).collect::<Vec<_>>();
+            assert_eq!(run(&bit_i, "2^8", "2", &inputs), expected);
+        }
+
+        let word = [Value::u32(0xdead_beef)];
+        for (i, &byte) in [0xde, 0xad, 0xbe, 0xef].iter().enumerate() {
+            let part = word_part(5, 3, i);
+            assert_eq!(run(&part, "2^32", "2^8", &word), vec![Value::u8(byte)]);
+        }
+        let low = word_part(5, 4, 1);
+        assert_eq!(run(&low, "2^32", "2^16", &word), vec![Value::u16(0xbeef)]);
+        let whole = word_part(5, 5, 0);
+        assert_eq!(run(&whole, "2^32", "2^32", &word), word.to_vec());
+
+        let iden = Arc::new(DagTerm::Iden);
+        let word = zero_extend(&iden, 3, 5);
+        assert_eq!(
+            run(&word, "2^8", "2^32", &[Value::u8(0xc3)]),
+            vec![Value::u32(0xc3)]
+        );
+        let word = zero_extend(&iden, 0, 2);
+        assert_eq!(run(&word, "2", "2^4", &[bit(true)]), vec![Value::u4(1)]);
+    }
+
+    #[test]
+    fn branches_and_assertions() {
+        let fst = fst();
+        let snd = snd();
+        let cond = cond(&fst, &snd);
+        let inputs = [
+            Value::prod(bit(false), Value::prod(Value::u2(1), Value::u2(2))),
+            Value::prod(bit(true), Value::prod(Value::u2(1), Value::u2(2))),
+        ];
+        assert_eq!(
+            run(&cond, "2 × (2^2 × 2^2)", "2^2", &inputs),
+            vec![Value::u2(2), Value::u2(1)]
+        );
+
+        // Pruning a branch leaves the CMR unchanged
+        let h = fst.cmr();
+        let assertl = assertl(&snd, h);
+        assert_eq!(
+            run(
+                &assertl,
+                "(1 + 1) × 2^2",
+                "2^2",
+                &[Value::prod(bit(false), Value::u2(3))]
+            ),
+            vec![Value::u2(3)]
+        );
+        let assertr = assertr(h, &snd);
+        assert_eq!(
+            run(
+                &assertr,
+                "(1 + 1) × 2^2",
+                "2^2",
+                &[Value::prod(bit(true), Value::u2(3))]
+            ),
+            vec![Value::u2(3)]
+        );
+        assert_eq!(assertl.cmr(), case(&snd, &fst).cmr());
+        assert_eq!(assertr.cmr(), case(&fst, &snd).cmr());
+    }
+
+    #[test]
+    fn boolean_combinators() {
+        let a = word_bit(2, 1);
+        let b = word_bit(2, 2);
+        let c = word_bit(2, 3);
+        let inputs = (0..8).map(Value::u4).collect::<Vec<_>>();
+        let table = |f: fn(bool, bool, bool) -> bool| {
+            (0..8)
+                .map(|n| bit(f(n & 4 != 0, n & 2 != 0, n & 1 != 0)))
+                .collect::<Vec<_>>()
+        };
+
+        let not = not(&a);
+        assert_eq!(run(&not, "2^4", "2", &inputs), table(|a, _, _| !a));
+        let and = and(&a, &b);
+        assert_eq!(run(&and, "2^4", "2", &inputs), table(|a, b, _| a && b));
+        let or = or(&a, &b);
+        assert_eq!(run(&or, "2^4", "2", &inputs), table(|a, b, _| a || b));
+        let xor = xor(&a, &b);
+        assert_eq!(run(&xor, "2^4", "2", &inputs), table(|a, b, _| a ^ b));
+        let maj = maj(&a, &b, &c);
+        assert_eq!(
+            run(&maj, "2^4", "2", &inputs),
+            table(|a, b, c| (a as u8 + b as u8 + c as u8) >= 2)
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        let adder = full_adder(0);
+        let mut inputs = vec![];
+        let mut expected = vec![];
+        for n in 0..8 {
+            let (a, b, c) = (n & 4 != 0, n & 2 != 0, n & 1 != 0);
+            inputs.push(Value::prod(Value::prod(bit(a), bit(b)), bit(c)));
+            let total = a as u8 + b as u8 + c as u8;
+            expected.push(Value::prod(bit(total >= 2), bit(total & 1 != 0)));
+        }
+        assert_eq!(run(&adder, "(2 × 2) × 2", "2 × 2", &inputs), expected);
+
+        let adder = full_adder(3);
+        let eq = eq(3);
+        let mut sums = (vec![], vec![]);
+        let mut eqs = (vec![], vec![]);
+        for x in (0..=255u8).step_by(37) {
+            for y in (0..=255u8).step_by(23) {
+                for c in 0..2 {
+                    let xy = Value::prod(Value::u8(x), Value::u8(y));
+                    sums.0.push(Value::prod(xy.clone(), Value::u1(c)));
+                    let total = x as u16 + y as u16 + c as u16;
+                    sums.1
+                        .push(Value::prod(bit(total > 0xff), Value::u8(total as u8)));
+                }
+                let xx = Value::prod(Value::u8(x), Value::u8(x));
+                let xy = Value::prod(Value::u8(x), Value::u8(y));
+                eqs.0.extend(vec![xx, xy]);
+                eqs.1.extend(vec![bit(true), bit(x == y)]);
+            }
+        }
+        assert_eq!(run(&adder, "(2^8 × 2^8) × 2", "2 × 2^8", &sums.0), sums.1);
+        assert_eq!(run(&eq, "2^8 × 2^8", "2", &eqs.0), eqs.1);
+    }
+
+    #[test]
+    fn word_operations() {
+        let bytes = (0..=255u8).step_by(7).collect::<Vec<_>>();
+        let inputs = bytes.iter().map(|&x| Value::u8(x)).collect::<Vec<_>>();
+        let outputs = |f: fn(u8) -> u8| bytes.iter().map(|&x| Value::u8(f(x))).collect::<Vec<_>>();
+
+        let complement = complement(3);
+        assert_eq!(run(&complement, "2^8", "2^8", &inputs), outputs(|x| !x));
+        let rotate = rotate_right(3, 3);
+        assert_eq!(
+            run(&rotate, "2^8", "2^8", &inputs),
+            outputs(|x| x.rotate_right(3))
+        );
+        let shift = shift_right(3, 3);
+        assert_eq!(run(&shift, "2^8", "2^8", &inputs), outputs(|x| x >> 3));
+
+        let pairs = bytes
+            .iter()
+            .map(|&x| Value::prod(Value::u8(x), Value::u8(x.wrapping_mul(13))))
+            .collect::<Vec<_>>();
+        let and = bitwise(3, &and(&fst(), &snd()));
+        assert_eq!(
+            run(&and, "2^8 × 2^8", "2^8", &pairs),
+            outputs(|x| x & x.wrapping_mul(13))
+        );
+        let triples = bytes
+            .iter()
+            .map(|&x| {
+                let yz = Value::prod(Value::u8(x.wrapping_mul(5)), Value::u8(x ^ 0x5a));
+                Value::prod(Value::u8(x), yz)
+            })
+            .collect::<Vec<_>>();
+        let ch = bitwise3(3, &cond(&fst(), &snd()));
+        assert_eq!(
+            run(&ch, "2^8 × (2^8 × 2^8)", "2^8", &triples),
+            outputs(|x| (x & x.wrapping_mul(5)) | (!x & (x ^ 0x5a)))
+        );
+    }
+
+    #[test]
+    fn subtraction_and_multiplication() {
+        let subtractor = full_subtractor(3);
+        let mut differences = (vec![], vec![]);
+        for x in (0..=255u8).step_by(37) {
+            for y in (0..=255u8).step_by(23) {
+                for c in 0..2 {
+                    let xy = Value::prod(Value::u8(x), Value::u8(y));
+                    differences.0.push(Value::prod(xy, Value::u1(c)));
+                    let diff = x as i16 - y as i16 - c as i16;
+                    differences
+                        .1
+                        .push(Value::prod(bit(diff < 0), Value::u8(diff as u8)));
+                }
+            }
+        }
+        assert_eq!(
+            run(&subtractor, "(2^8 × 2^8) × 2", "2 × 2^8", &differences.0),
+            differences.1
+        );
+
+        let multiplier = full_multiplier(2);
+        let mut products = (vec![], vec![]);
+        for a in 0..16 {
+            for b in 0..16 {
+                for &(c, d) in &[(0, 0), (15, 15), (a, 15 - b)] {
+                    let ab = Value::prod(Value::u4(a), Value::u4(b));
+                    let cd = Value::prod(Value::u4(c), Value::u4(d));
+                    products.0.push(Value::prod(ab, cd));
+                    products.1.push(Value::u8(a * b + c + d));
+                }
+            }
+        }
+        assert_eq!(
+            run(&multiplier, "(2^4 × 2^4) × (2^4 × 2^4)", "2^8", &products.0),
+            products.1
+        );
+    }
+}
@@ -14,25 +14,11 @@
 //
 
 /// Core Module for simplicity
+pub mod builder;
+pub mod combinators;
+pub mod optimize;
+pub mod recognize;
 pub mod term;
+mod text;
 pub mod types;
-
-// handy function for converting bit vector to vec[u8]
-// # PANIC:
-// panics when bitvec length is not a multiple of 8.
-#[allow(dead_code)]
-pub(crate) fn bitvec_to_bytevec(bitvec: Vec<bool>) -> Vec<u8> {
-    let mut ret = vec![];
-    assert!(bitvec.len() % 8 == 0, "Bitvec len must be multiple of 8");
-    let mut start = 0;
-    while start < bitvec.len() {
-        //read a byte
-        let mut byte: u8 = 0;
-        for i in 0..8 {
-            byte += (bitvec[start + i] as u8) * (1u8 << (7 - i));
-        }
-        ret.push(byte);
-        start += 8;
-    }
-    ret
-}
+pub mod value;
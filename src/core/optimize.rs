@@ -0,0 +1,531 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Peephole Optimization
+//!
+//! Rewrites which shrink a program without changing its semantics. The
+//! rewrites change the program's CMR, so they are opt-in, and must be
+//! done before the program is committed to.
+//!
+//! The catalogue of rewrites, where `c` is a constant (an expression
+//! built from `unit`, `injl`, `injr` and `pair` alone, which ignores its
+//! input) and an expression is removable if it contains no jets,
+//! extension nodes, hidden nodes, `fail` or `disconnect`, and so cannot
+//! fail, and no witness nodes, so that removing it does not change which
+//! witness values the program reads:
+//!
+//! * `comp iden s` and `comp s iden` become `s`;
+//! * `comp s c` becomes `c` if `s` is removable;
+//! * `take c` and `drop c` become `c`;
+//! * `comp (pair s t) (take u)` becomes `comp s u` if `t` is removable,
+//!   and `comp (pair s t) (drop u)` becomes `comp t u` if `s` is
+//!   removable, which also removes nested projections such as
+//!   `take (take u)` out of nested pairs;
+//! * `pair (take iden) (drop iden)` becomes `iden`;
+//! * `case (drop s) (drop s)` becomes `drop s`.
+//!
+//! Afterwards, structurally identical subexpressions which have the same
+//! type, such as repeated scribes of the same constant word, are merged.
+//! Witness nodes are never merged.
+//!
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::{fmt, io};
+
+use super::term::{DagTerm, Term, UnTypedProg};
+use super::types::{self, FinalType, TypeConstraint, TypeInterner};
+use crate::encode::{self, BitWriter};
+use crate::extension::Jet;
+use crate::Error;
+
+/// Sizes of a program before and after optimization
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Report {
+    /// Number of rewrites applied
+    pub rewrites: usize,
+    /// Number of nodes in the original program
+    pub nodes_before: usize,
+    /// Number of nodes in the optimized program
+    pub nodes_after: usize,
+    /// Length, in bits, of the encoding of the original program,
+    /// excluding witness data
+    pub bits_before: usize,
+    /// Length, in bits, of the encoding of the optimized program,
+    /// excluding witness data
+    pub bits_after: usize,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} rewrites; {} → {} nodes; {} → {} bits",
+            self.rewrites, self.nodes_before, self.nodes_after, self.bits_before, self.bits_after,
+        )
+    }
+}
+
+/// Optimize a program, whose root must satisfy the given type
+/// constraint, such as `TypeConstraint::spending_program()`. The types
+/// of merged subexpressions depend on the type of the root, so the
+/// optimized program is only equivalent to the original at that type.
+pub fn optimize<W, E>(
+    dag: &DagTerm<W, E>,
+    root: &TypeConstraint,
+) -> Result<(Arc<DagTerm<W, E>>, Report), Error>
+where
+    W: Clone,
+    E: Jet + Clone + Ord,
+{
+    let original = dag.to_untyped_prog();
+
+    let mut rewriter = Rewriter {
+        flags: HashMap::new(),
+        nodes: vec![],
+        rewrites: 0,
+    };
+    let rewritten = dag.post_order_fold(|node, children: Vec<Arc<DagTerm<W, E>>>| {
+        let child = |n: usize| Arc::clone(&children[n]);
+        let node = match *node {
+            DagTerm::Iden => DagTerm::Iden,
+            DagTerm::Unit => DagTerm::Unit,
+            DagTerm::InjL(..) => DagTerm::InjL(child(0)),
+            DagTerm::InjR(..) => DagTerm::InjR(child(0)),
+            DagTerm::Take(..) => DagTerm::Take(child(0)),
+            DagTerm::Drop(..) => DagTerm::Drop(child(0)),
+            DagTerm::Comp(..) => DagTerm::Comp(child(0), child(1)),
+            DagTerm::Case(..) => DagTerm::Case(child(0), child(1)),
+            DagTerm::Pair(..) => DagTerm::Pair(child(0), child(1)),
+            DagTerm::Disconnect(..) => DagTerm::Disconnect(child(0), child(1)),
+            DagTerm::Witness(ref w) => DagTerm::Witness(w.clone()),
+            DagTerm::Fail(a, b) => DagTerm::Fail(a, b),
+            DagTerm::Hidden(cmr) => DagTerm::Hidden(cmr),
+            DagTerm::Ext(ref e) => DagTerm::Ext(e.clone()),
+            DagTerm::Jet(j) => DagTerm::Jet(j),
+        };
+        rewriter.rewrite(node)
+    });
+
+    let shared = share(rewritten.to_untyped_prog(), root)?;
+    let report = Report {
+        rewrites: rewriter.rewrites,
+        nodes_before: original.0.len(),
+        nodes_after: shared.0.len(),
+        bits_before: encoded_len(&original),
+        bits_after: encoded_len(&shared),
+    };
+    Ok((DagTerm::from_untyped_prog(shared), report))
+}
+
+/// Length, in bits, of the encoding of a program, excluding witness data
+pub fn encoded_len<W, E: Jet>(program: &UnTypedProg<W, E>) -> usize {
+    let mut w = BitWriter::new(io::sink());
+    let mut len = encode::encode_natural(program.0.len(), &mut w).expect("writing to sink");
+    for node in &program.0 {
+        len += encode::encode_node_no_witness(node, &mut w).expect("writing to sink");
+    }
+    len
+}
+
+/// Properties of a node built by the `Rewriter`
+#[derive(Clone, Copy)]
+struct Flags {
+    /// The node cannot fail and contains no witness nodes, so it may be
+    /// removed without changing the program's semantics or its witness
+    /// data
+    removable: bool,
+    /// The node ignores its input, and may be used at any source type
+    constant: bool,
+}
+
+/// Result of applying a rewrite rule to a node
+enum Rewritten<W, E> {
+    /// The node was replaced by an existing node
+    Node(Arc<DagTerm<W, E>>),
+    /// The node was replaced by a new node, which may be rewritten further
+    Term(DagTerm<W, E>),
+}
+
+/// Applies the rewrite rules to nodes whose children have already been
+/// rewritten
+struct Rewriter<W, E> {
+    /// Flags of every node built, keyed by address
+    flags: HashMap<usize, Flags>,
+    /// Every node built, which keeps the addresses in `flags` valid
+    nodes: Vec<Arc<DagTerm<W, E>>>,
+    /// Number of rewrites applied
+    rewrites: usize,
+}
+
+impl<W: Clone, E: Clone> Rewriter<W, E> {
+    fn flags(&self, node: &Arc<DagTerm<W, E>>) -> Flags {
+        self.flags[&(&**node as *const DagTerm<W, E> as usize)]
+    }
+
+    fn removable(&self, node: &Arc<DagTerm<W, E>>) -> bool {
+        self.flags(node).removable
+    }
+
+    fn constant(&self, node: &Arc<DagTerm<W, E>>) -> bool {
+        self.flags(node).constant
+    }
+
+    /// Build a node without rewriting it
+    fn node(&mut self, node: DagTerm<W, E>) -> Arc<DagTerm<W, E>> {
+        let children = node.children().map(|c| self.flags(c)).collect::<Vec<_>>();
+        let all_removable = children.iter().all(|c| c.removable);
+        let all_constant = children.iter().all(|c| c.constant);
+        let flags = match node {
+            DagTerm::Unit => Flags {
+                removable: true,
+                constant: true,
+            },
+            DagTerm::InjL(..) | DagTerm::InjR(..) | DagTerm::Pair(..) => Flags {
+                removable: all_removable,
+                constant: all_constant,
+            },
+            DagTerm::Iden
+            | DagTerm::Take(..)
+            | DagTerm::Drop(..)
+            | DagTerm::Comp(..)
+            | DagTerm::Case(..) => Flags {
+                removable: all_removable,
+                constant: false,
+            },
+            DagTerm::Witness(..)
+            | DagTerm::Disconnect(..)
+            | DagTerm::Fail(..)
+            | DagTerm::Hidden(..)
+            | DagTerm::Ext(..)
+            | DagTerm::Jet(..) => Flags {
+                removable: false,
+                constant: false,
+            },
+        };
+        let node = Arc::new(node);
+        self.flags
+            .insert(&*node as *const DagTerm<W, E> as usize, flags);
+        self.nodes.push(Arc::clone(&node));
+        node
+    }
+
+    /// Copy a constant, so that the copy can be used at a different
+    /// source type
+    fn copy_constant(&mut self, c: &Arc<DagTerm<W, E>>) -> Arc<DagTerm<W, E>> {
+        c.post_order_fold(|node, children: Vec<Arc<DagTerm<W, E>>>| {
+            let child = |n: usize| Arc::clone(&children[n]);
+            let node = match *node {
+                DagTerm::Unit => DagTerm::Unit,
+                DagTerm::InjL(..) => DagTerm::InjL(child(0)),
+                DagTerm::InjR(..) => DagTerm::InjR(child(0)),
+                DagTerm::Pair(..) => DagTerm::Pair(child(0), child(1)),
+                _ => unreachable!("constants contain only unit, injl, injr and pair"),
+            };
+            self.node(node)
+        })
+    }
+
+    /// Build a node, rewriting it until no rule applies
+    fn rewrite(&mut self, mut node: DagTerm<W, E>) -> Arc<DagTerm<W, E>> {
+        loop {
+            match self.step(&node) {
+                Some(Rewritten::Node(ret)) => {
+                    self.rewrites += 1;
+                    return ret;
+                }
+                Some(Rewritten::Term(term)) => {
+                    self.rewrites += 1;
+                    node = term;
+                }
+                None => return self.node(node),
+            }
+        }
+    }
+
+    /// Apply the first rule which matches the node, if any
+    fn step(&mut self, node: &DagTerm<W, E>) -> Option<Rewritten<W, E>> {
+        match *node {
+            DagTerm::Comp(ref s, ref t) => {
+                if let DagTerm::Iden = **s {
+                    return Some(Rewritten::Node(Arc::clone(t)));
+                }
+                if let DagTerm::Iden = **t {
+                    return Some(Rewritten::Node(Arc::clone(s)));
+                }
+                if self.constant(t) && self.removable(s) {
+                    return Some(Rewritten::Node(self.copy_constant(t)));
+                }
+                if let DagTerm::Pair(ref l, ref r) = **s {
+                    match **t {
+                        DagTerm::Take(ref u) if self.removable(r) => {
+                            return Some(Rewritten::Term(DagTerm::Comp(
+                                Arc::clone(l),
+                                Arc::clone(u),
+                            )));
+                        }
+                        DagTerm::Drop(ref u) if self.removable(l) => {
+                            return Some(Rewritten::Term(DagTerm::Comp(
+                                Arc::clone(r),
+                                Arc::clone(u),
+                            )));
+                        }
+                        _ => {}
+                    }
+                }
+                None
+            }
+            DagTerm::Take(ref c) | DagTerm::Drop(ref c) if self.constant(c) => {
+                Some(Rewritten::Node(self.copy_constant(c)))
+            }
+            DagTerm::Pair(ref s, ref t) => match (&**s, &**t) {
+                (DagTerm::Take(ref l), DagTerm::Drop(ref r)) => match (&**l, &**r) {
+                    (DagTerm::Iden, DagTerm::Iden) => Some(Rewritten::Term(DagTerm::Iden)),
+                    _ => None,
+                },
+                _ => None,
+            },
+            DagTerm::Case(ref s, ref t) if Arc::ptr_eq(s, t) => match **s {
+                // The `drop` node is rebuilt, since its source type changes
+                DagTerm::Drop(ref u) => Some(Rewritten::Term(DagTerm::Drop(Arc::clone(u)))),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Merge structurally identical nodes which have the same type
+fn share<W, E>(
+    program: UnTypedProg<W, E>,
+    root: &TypeConstraint,
+) -> Result<UnTypedProg<W, E>, Error>
+where
+    W: Clone,
+    E: Jet + Clone + Ord,
+{
+    let typed = types::type_check_with_constraints(
+        program,
+        std::slice::from_ref(root),
+        &mut TypeInterner::new(),
+    )?;
+
+    // Index of each original node in the new program
+    let mut new_index = Vec::<usize>::with_capacity(typed.len());
+    // Map from a node, with its children given by their new absolute
+    // indices, and its types to its new index
+    let mut seen = BTreeMap::<(Term<(), E>, Arc<FinalType>, Arc<FinalType>), usize>::new();
+    let mut nodes = Vec::<Term<W, E>>::with_capacity(typed.len());
+    for (index, typed_node) in typed.into_iter().enumerate() {
+        let abs = |i: usize| new_index[index - i];
+        let rel = |i: usize| nodes.len() - abs(i);
+        let (key, node) = match typed_node.node {
+            Term::Iden => (Term::Iden, Term::Iden),
+            Term::Unit => (Term::Unit, Term::Unit),
+            Term::InjL(i) => (Term::InjL(abs(i)), Term::InjL(rel(i))),
+            Term::InjR(i) => (Term::InjR(abs(i)), Term::InjR(rel(i))),
+            Term::Take(i) => (Term::Take(abs(i)), Term::Take(rel(i))),
+            Term::Drop(i) => (Term::Drop(abs(i)), Term::Drop(rel(i))),
+            Term::Comp(i, j) => (Term::Comp(abs(i), abs(j)), Term::Comp(rel(i), rel(j))),
+            Term::Case(i, j) => (Term::Case(abs(i), abs(j)), Term::Case(rel(i), rel(j))),
+            Term::Pair(i, j) => (Term::Pair(abs(i), abs(j)), Term::Pair(rel(i), rel(j))),
+            Term::Disconnect(i, j) => (
+                Term::Disconnect(abs(i), abs(j)),
+                Term::Disconnect(rel(i), rel(j)),
+            ),
+            Term::Witness(w) => {
+                new_index.push(nodes.len());
+                nodes.push(Term::Witness(w));
+                continue;
+            }
+            Term::Fail(a, b) => (Term::Fail(a, b), Term::Fail(a, b)),
+            Term::Hidden(cmr) => (Term::Hidden(cmr), Term::Hidden(cmr)),
+            Term::Ext(e) => (Term::Ext(e.clone()), Term::Ext(e)),
+            Term::Jet(j) => (Term::Jet(j), Term::Jet(j)),
+        };
+        let key = (key, typed_node.source_ty, typed_node.target_ty);
+        let next = nodes.len();
+        let idx = *seen.entry(key).or_insert(next);
+        if idx == next {
+            nodes.push(node);
+        }
+        new_index.push(idx);
+    }
+    Ok(UnTypedProg(nodes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::combinators;
+    use crate::core::types::FinalTypeInner;
+    use crate::extension::dummy::DummyNode;
+    use crate::test_utils;
+    use crate::Value;
+    use std::str::FromStr;
+
+    /// Every value of a (small) type
+    fn values(ty: &FinalType) -> Vec<Value> {
+        match ty.ty {
+            FinalTypeInner::Unit => vec![Value::Unit],
+            FinalTypeInner::Sum(ref l, ref r) => values(l)
+                .into_iter()
+                .map(Value::sum_l)
+                .chain(values(r).into_iter().map(Value::sum_r))
+                .collect(),
+            FinalTypeInner::Product(ref l, ref r) => {
+                let rs = values(r);
+                values(l)
+                    .into_iter()
+                    .flat_map(|a| rs.iter().map(move |b| Value::prod(a.clone(), b.clone())))
+                    .collect()
+            }
+        }
+    }
+
+    /// Optimize the expression, then check that it shrank and that it
+    /// computes the same function on every input
+    fn check(expr: &DagTerm<(), DummyNode>, source: &str, target: &str) -> usize {
+        let source = Arc::new(FinalType::from_str(source).unwrap());
+        let target = Arc::new(FinalType::from_str(target).unwrap());
+        let root = TypeConstraint::root(Arc::clone(&source).into(), Arc::clone(&target).into());
+        let (optimized, report) = optimize(expr, &root).unwrap();
+        assert!(report.nodes_after < report.nodes_before);
+        assert!(report.bits_after < report.bits_before);
+
+        let inputs = values(&source);
+        let outputs = [expr.to_untyped_prog(), optimized.to_untyped_prog()]
+            .iter()
+            .map(|prog| test_utils::run(prog.clone(), &source, &target, &inputs))
+            .collect::<Vec<_>>();
+        for (input, (a, b)) in inputs.iter().zip(outputs[0].iter().zip(&outputs[1])) {
+            assert!(a.is_some(), "on input {}", input);
+            assert_eq!(a, b, "on input {}", input);
+        }
+        report.nodes_after
+    }
+
+    #[test]
+    fn rewrites() {
+        let dag = |d: Arc<DagTerm<(), DummyNode>>| d;
+
+        assert_eq!(check(&dag(dag!(comp(iden, take(iden)))), "2 × 2", "2"), 2);
+        assert_eq!(check(&dag(dag!(comp(drop(iden), iden))), "2 × 2", "2"), 2);
+        // Nested projections out of nested pairs
+        let proj = dag!(comp(
+            pair(pair(drop(iden), take(iden)), take(iden)),
+            take(drop(iden))
+        ));
+        assert_eq!(check(&dag(proj), "2 × 2", "2"), 2);
+        // Constants
+        let swap = dag!(pair(drop(iden), take(iden)));
+        let two = dag!(comp(swap, scribe(Value::u2(2))));
+        assert_eq!(check(&dag(two), "2 × 2", "2^2"), 4);
+        assert_eq!(check(&dag(dag!(take(injr(unit)))), "2 × 2", "2"), 2);
+        assert_eq!(
+            check(&dag(dag!(pair(take(iden), drop(iden)))), "2 × 2", "2 × 2"),
+            1
+        );
+        let not = dag!(case(injr(unit), injl(unit)));
+        let bits = dag! {
+            let s = drop(comp(pair(iden, unit), not));
+            case(s, s)
+        };
+        assert_eq!(check(&dag(bits), "2 × 2", "2"), 9);
+
+        // Derived combinators, which share few of their subexpressions
+        check(&combinators::full_adder(2), "(2^4 × 2^4) × 2", "2 × 2^4");
+    }
+
+    #[test]
+    fn partial_expressions() {
+        // The pair's right component may fail, so is not removed
+        let expr: Arc<DagTerm<(), DummyNode>> =
+            dag!(comp(pair(take(iden), jet(eqv32)), take(iden)));
+        let ty = FinalType::from_str("2^32 × 2^32").unwrap();
+        let root = TypeConstraint::root(Arc::new(ty).into(), types::TypePattern::Var(0));
+        let (optimized, report) = optimize(&*expr, &root).unwrap();
+        assert_eq!(report.rewrites, 0);
+        // Only the two `iden`s, which have the same type, are merged
+        assert_eq!(report.nodes_before, 7);
+        assert_eq!(optimized.to_untyped_prog().0.len(), 6);
+    }
+
+    #[test]
+    fn witnesses() {
+        let ty = FinalType::from_str("2 × 2").unwrap();
+        let root = TypeConstraint::root(Arc::new(ty).into(), types::TypePattern::Var(0));
+        let count = |dag: &DagTerm<(), DummyNode>| {
+            let prog = dag.to_untyped_prog();
+            prog.0
+                .iter()
+                .filter(|node| **node == Term::Witness(()))
+                .count()
+        };
+
+        // Witnesses in discarded branches are kept, so that the program
+        // reads the same witness data
+        let exprs: [Arc<DagTerm<(), DummyNode>>; 3] = [
+            dag!(comp(pair(take(iden), witness), take(iden))),
+            dag!(comp(pair(witness, drop(iden)), drop(iden))),
+            dag!(comp(witness, unit)),
+        ];
+        for expr in &exprs {
+            let (optimized, report) = optimize(&**expr, &root).unwrap();
+            assert_eq!(report.rewrites, 0);
+            assert_eq!(count(&optimized), 1);
+        }
+
+        // Other rules still apply around them
+        let expr: Arc<DagTerm<(), DummyNode>> = dag!(comp(iden, comp(witness, take(unit))));
+        let (optimized, report) = optimize(&*expr, &root).unwrap();
+        assert_eq!(report.rewrites, 2);
+        assert_eq!(count(&optimized), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "bitcoin")]
+    fn compiled_policy() {
+        use crate::extension::bitcoin::BtcNode;
+        use crate::policy::ast::Policy;
+        use miniscript::DummyKey;
+
+        let policy = Policy::<DummyKey>::from_str("or(and(pk(),older(7)),after(100))").unwrap();
+        let prog: UnTypedProg<(), BtcNode> = policy.compile().unwrap();
+        let dag = DagTerm::from_untyped_prog(prog.clone());
+
+        let spend = TypeConstraint::spending_program();
+        let (optimized, report) = optimize(&*dag, &spend).unwrap();
+        // The compiler already shares its subexpressions
+        assert!(report.bits_after <= report.bits_before, "{}", report);
+        let optimized = optimized.to_untyped_prog();
+        assert_eq!(report.nodes_after, optimized.0.len());
+        assert_eq!(report.bits_after, encoded_len(&optimized));
+
+        // Both programs have the same witnesses, of the same types
+        let witnesses = |prog: UnTypedProg<(), BtcNode>| {
+            types::type_check_with_constraints(
+                prog,
+                std::slice::from_ref(&spend),
+                &mut TypeInterner::new(),
+            )
+            .unwrap()
+            .into_iter()
+            .filter(|node| node.node == Term::Witness(()))
+            .map(|node| node.target_ty)
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(witnesses(prog), witnesses(optimized));
+    }
+}
@@ -0,0 +1,409 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Jet Recognition
+//!
+//! A jet has the same CMR as its specification, `JetsNode::spec`, so any
+//! subexpression with that CMR can be replaced by the jet without changing
+//! the CMR of the program, while making it much cheaper to run.
+//!
+//! A CMR does not tell a pruned branch from an unpruned one, so an
+//! expression which never fails may have the CMR of a specification
+//! which can. A subexpression is therefore only replaced if it is the
+//! specification itself, with its hidden nodes in the same places.
+//!
+//! Extension nodes have no specification in this library, so they are
+//! recognized by their own CMR, which only finds them in extensions
+//! whose nodes commit to the expression they stand for.
+//!
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use super::term::{DagTerm, Term};
+use super::types::{self, FinalType, TypeInterner};
+use crate::cmr::Cmr;
+use crate::extension::dummy::DummyNode;
+use crate::extension::jets::JetsNode;
+use crate::extension::Jet;
+use crate::{Error, Program};
+
+/// A jet or extension node which may replace a subexpression
+enum Known<Ext: 'static> {
+    /// A jet, with its specification
+    Jet(JetsNode, Arc<DagTerm<(), DummyNode>>),
+    Ext(&'static Ext),
+}
+
+impl<Ext: Jet> Known<Ext> {
+    /// Every jet with a specification, and every node of the extension,
+    /// keyed by CMR
+    fn all() -> HashMap<Cmr, Known<Ext>> {
+        let jets = JetsNode::ALL.iter().filter_map(|j| {
+            let spec = j.spec::<(), DummyNode>()?;
+            Some((spec.cmr(), Known::Jet(*j, spec)))
+        });
+        let exts = Ext::ALL.iter().map(|e| (e.cmr(), Known::Ext(e)));
+        jets.chain(exts).collect()
+    }
+
+    /// Whether the node has the given source and target type
+    fn has_type(
+        &self,
+        source: &Arc<FinalType>,
+        target: &Arc<FinalType>,
+        interner: &mut TypeInterner,
+    ) -> bool {
+        let (source_name, target_name) = match *self {
+            Known::Jet(ref j, _) => (j.source_type(), j.target_type()),
+            Known::Ext(e) => (e.source_type(), e.target_type()),
+        };
+        source_name.to_final(interner).ok().as_ref() == Some(source)
+            && target_name.to_final(interner).ok().as_ref() == Some(target)
+    }
+
+    fn to_dag<W>(&self) -> DagTerm<W, Ext>
+    where
+        Ext: Clone,
+    {
+        match *self {
+            Known::Jet(j, _) => DagTerm::Jet(j),
+            Known::Ext(e) => DagTerm::Ext(e.clone()),
+        }
+    }
+
+    /// Whether the subexpression rooted at the given node is the jet's
+    /// specification, with any hidden branches in the same places.
+    /// Extension nodes have no specification, so only their CMR and type
+    /// are checked.
+    fn is_spec_of<W>(&self, nodes: &[Term<W, Ext>], index: usize) -> bool {
+        let spec = match *self {
+            Known::Jet(_, ref spec) => spec,
+            Known::Ext(..) => return true,
+        };
+
+        let mut seen = HashSet::new();
+        let mut stack = vec![(index, &**spec)];
+        while let Some((index, spec)) = stack.pop() {
+            if !seen.insert((index, spec as *const DagTerm<(), DummyNode> as usize)) {
+                continue;
+            }
+            match (&nodes[index], spec) {
+                (Term::Iden, DagTerm::Iden) | (Term::Unit, DagTerm::Unit) => {}
+                (Term::InjL(i), DagTerm::InjL(s))
+                | (Term::InjR(i), DagTerm::InjR(s))
+                | (Term::Take(i), DagTerm::Take(s))
+                | (Term::Drop(i), DagTerm::Drop(s)) => stack.push((index - i, s)),
+                (Term::Comp(i, j), DagTerm::Comp(s, t))
+                | (Term::Case(i, j), DagTerm::Case(s, t))
+                | (Term::Pair(i, j), DagTerm::Pair(s, t))
+                | (Term::Disconnect(i, j), DagTerm::Disconnect(s, t)) => {
+                    stack.push((index - i, s));
+                    stack.push((index - j, t));
+                }
+                (Term::Hidden(a), DagTerm::Hidden(b)) if a == b => {}
+                (Term::Jet(a), DagTerm::Jet(b)) if a == b => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+/// Replace every subexpression of a DAG which is the specification of a
+/// jet, or has the CMR of a node of the extension, and whose type is that
+/// of the node, by the node. The largest such subexpressions are replaced, and the CMR
+/// of the DAG is unchanged.
+///
+/// The DAG is type-checked first, with any type it leaves free set to
+/// unit, so a polymorphic subexpression is only replaced if the rest of
+/// the DAG forces it to the type of the node.
+pub fn recognize_jets<W, Ext>(dag: &DagTerm<W, Ext>) -> Result<Arc<DagTerm<W, Ext>>, Error>
+where
+    W: Clone,
+    Ext: Jet + Clone,
+{
+    let program = dag.to_untyped_prog();
+    let cmrs = program.cmrs();
+    let mut interner = TypeInterner::new();
+    let typed = types::type_check_with_interner(program, &mut interner)?;
+    let nodes = typed
+        .iter()
+        .map(|node| node.node.clone())
+        .collect::<Vec<_>>();
+
+    Ok(replace(&nodes, &cmrs, |index, known| {
+        let node = &typed[index];
+        known.has_type(&node.source_ty, &node.target_ty, &mut interner)
+    }))
+}
+
+/// Replace every subexpression of a program which is the specification
+/// of a jet, or has the CMR of a node of the extension, and whose type is
+/// that of the node, by the node. The CMR of the program is unchanged, but its nodes are
+/// renumbered, so any type constraints on individual nodes no longer
+/// apply.
+pub fn recognize_program_jets<Ext>(program: &Program<Ext>) -> Result<Program<Ext>, Error>
+where
+    Ext: Jet + Clone,
+{
+    let nodes = program
+        .nodes
+        .iter()
+        .map(|node| node.node.clone())
+        .collect::<Vec<_>>();
+    let cmrs = program
+        .nodes
+        .iter()
+        .map(|node| node.cmr)
+        .collect::<Vec<_>>();
+
+    let mut interner = TypeInterner::new();
+    let dag = replace(&nodes, &cmrs, |index, known| {
+        let node = &program.nodes[index];
+        known.has_type(&node.source_ty, &node.target_ty, &mut interner)
+    });
+    Program::from_dag(&dag)
+}
+
+/// Rebuild a program as a DAG, replacing each node whose CMR is known,
+/// which is the specification of the known node, and for which `accept`
+/// holds, by the known node
+fn replace<W, Ext, F>(nodes: &[Term<W, Ext>], cmrs: &[Cmr], mut accept: F) -> Arc<DagTerm<W, Ext>>
+where
+    W: Clone,
+    Ext: Jet + Clone,
+    F: FnMut(usize, &Known<Ext>) -> bool,
+{
+    let known = Known::<Ext>::all();
+    let mut dag = Vec::<Arc<DagTerm<W, Ext>>>::with_capacity(nodes.len());
+    for (index, node) in nodes.iter().enumerate() {
+        let child = |i: usize| Arc::clone(&dag[index - i]);
+        // Only combinators can be replaced; leaves have their own CMRs
+        let replacement = match known.get(&cmrs[index]) {
+            Some(k)
+                if node.children().next().is_some()
+                    && k.is_spec_of(nodes, index)
+                    && accept(index, k) =>
+            {
+                Some(k.to_dag())
+            }
+            _ => None,
+        };
+        let new = replacement.unwrap_or_else(|| match *node {
+            Term::Iden => DagTerm::Iden,
+            Term::Unit => DagTerm::Unit,
+            Term::InjL(i) => DagTerm::InjL(child(i)),
+            Term::InjR(i) => DagTerm::InjR(child(i)),
+            Term::Take(i) => DagTerm::Take(child(i)),
+            Term::Drop(i) => DagTerm::Drop(child(i)),
+            Term::Comp(i, j) => DagTerm::Comp(child(i), child(j)),
+            Term::Case(i, j) => DagTerm::Case(child(i), child(j)),
+            Term::Pair(i, j) => DagTerm::Pair(child(i), child(j)),
+            Term::Disconnect(i, j) => DagTerm::Disconnect(child(i), child(j)),
+            Term::Witness(ref w) => DagTerm::Witness(w.clone()),
+            Term::Fail(a, b) => DagTerm::Fail(a, b),
+            Term::Hidden(cmr) => DagTerm::Hidden(cmr),
+            Term::Ext(ref e) => DagTerm::Ext(e.clone()),
+            Term::Jet(j) => DagTerm::Jet(j),
+        });
+        dag.push(Arc::new(new));
+    }
+    Arc::clone(dag.last().expect("non-empty program"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bititer::BitIter;
+    use crate::core::combinators;
+    use crate::core::types::{TypeConstraint, TypePattern};
+    use crate::encode::BitWrite;
+    use crate::exec::BitMachine;
+    use crate::extension::TypeName;
+    use crate::UnTypedProg;
+    use std::{fmt, io};
+
+    #[test]
+    fn jets() {
+        // A specification standing alone becomes its jet
+        let spec = JetsNode::EqV32.spec::<(), DummyNode>().unwrap();
+        let recognized = recognize_jets(&*spec).unwrap();
+        assert_eq!(*recognized, DagTerm::Jet(JetsNode::EqV32));
+        assert_eq!(recognized.cmr(), spec.cmr());
+
+        // So does one inside a larger expression. `adder32` contains the
+        // specification of `fulladder32`, but is replaced as a whole.
+        let adder = JetsNode::Adder32.spec::<(), DummyNode>().unwrap();
+        let expr: Arc<DagTerm<(), DummyNode>> = dag! {
+            let swap = pair(drop(iden), take(iden));
+            comp(pair(comp(swap, adder), swap), drop(spec))
+        };
+        assert_eq!(
+            recognize_jets(&*expr).unwrap().to_untyped_prog(),
+            UnTypedProg(vec![
+                Term::Iden,
+                Term::Drop(1),
+                Term::Iden,
+                Term::Take(1),
+                Term::Pair(3, 1),
+                Term::Jet(JetsNode::Adder32),
+                Term::Comp(2, 1),
+                Term::Pair(1, 3),
+                Term::Jet(JetsNode::EqV32),
+                Term::Drop(1),
+                Term::Comp(3, 1),
+            ]),
+        );
+    }
+
+    #[test]
+    fn pruned_branches() {
+        // This has the CMR and type of the specification of `eqv32`, whose
+        // `assertr` is `case` with a hidden left branch. It never fails, so
+        // it must not be replaced.
+        let eq = combinators::eq(5);
+        let expr: Arc<DagTerm<(), DummyNode>> = dag!(comp(pair(eq, unit), case(unit, unit)));
+        let spec = JetsNode::EqV32.spec::<(), DummyNode>().unwrap();
+        assert_eq!(expr.cmr(), spec.cmr());
+
+        let recognized = recognize_jets(&*expr).unwrap();
+        assert_eq!(recognized.to_untyped_prog(), expr.to_untyped_prog());
+    }
+
+    /// Extension whose nodes stand for pure expressions
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    enum TestNode {
+        /// `not : 2 → 2`
+        Not,
+        /// `take iden : 2^2 × 2 → 2^2`
+        Fst,
+    }
+
+    fn not() -> Arc<DagTerm<(), TestNode>> {
+        dag!(comp(pair(iden, unit), case(injr(unit), injl(unit))))
+    }
+
+    impl Jet for TestNode {
+        type TxEnv = ();
+
+        const ALL: &'static [Self] = &[TestNode::Not, TestNode::Fst];
+
+        fn decode<I: Iterator<Item = u8>>(_: &mut BitIter<I>) -> Result<Self, Error> {
+            Err(Error::ParseError("[test extension]"))
+        }
+
+        fn encode<W: BitWrite>(&self, _: &mut W) -> io::Result<usize> {
+            Ok(0)
+        }
+
+        fn exec(&self, _: &mut BitMachine, _: &()) {
+            unimplemented!()
+        }
+
+        fn cmr(&self) -> Cmr {
+            match *self {
+                TestNode::Not => not().cmr(),
+                TestNode::Fst => {
+                    let fst: Arc<DagTerm<(), TestNode>> = dag!(take(iden));
+                    fst.cmr()
+                }
+            }
+        }
+
+        fn source_type(&self) -> TypeName {
+            match *self {
+                TestNode::Not => TypeName(b"2"),
+                TestNode::Fst => TypeName(b"*t2"),
+            }
+        }
+
+        fn target_type(&self) -> TypeName {
+            match *self {
+                TestNode::Not => TypeName(b"2"),
+                TestNode::Fst => TypeName(b"t"),
+            }
+        }
+    }
+
+    impl fmt::Display for TestNode {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(match *self {
+                TestNode::Not => "not",
+                TestNode::Fst => "fst",
+            })
+        }
+    }
+
+    #[test]
+    fn recognition() {
+        let not = not();
+        let expr: Arc<DagTerm<(), TestNode>> = dag!(comp(take(iden), comp(not, not)));
+        let cmr = expr.cmr();
+
+        // `take iden` is used at type `2 × 1 → 2`, not that of `Fst`, so
+        // only the two `not`s are replaced
+        let recognized = recognize_jets(&*expr).unwrap();
+        assert_eq!(recognized.cmr(), cmr);
+        assert_eq!(
+            recognized.to_untyped_prog(),
+            UnTypedProg(vec![
+                Term::Iden,
+                Term::Take(1),
+                Term::Ext(TestNode::Not),
+                Term::Comp(1, 1),
+                Term::Comp(3, 1),
+            ]),
+        );
+
+        // At the type of `Fst`, it is replaced as well
+        let fst: Arc<DagTerm<(), TestNode>> = dag!(comp(pair(pair(not, not), not), take(iden)));
+        let recognized = recognize_jets(&*fst).unwrap();
+        assert_eq!(recognized.cmr(), fst.cmr());
+        assert!(recognized
+            .to_untyped_prog()
+            .0
+            .contains(&Term::Ext(TestNode::Fst)));
+
+        // Ill-typed DAGs are rejected rather than rewritten
+        let ill_typed: Arc<DagTerm<(), TestNode>> = dag!(comp(unit, not));
+        assert!(recognize_jets(&*ill_typed).is_err());
+
+        // The same holds for programs, which carry their types
+        let program = Program::<TestNode>::from_untyped_nodes_with_constraints(
+            expr.to_untyped_prog(),
+            &mut BitIter::from(vec![0x00].into_iter()),
+            &[TypeConstraint::spending_program()],
+        );
+        assert!(program.is_err());
+        let program = Program::<TestNode>::from_untyped_nodes_with_constraints(
+            expr.to_untyped_prog(),
+            &mut BitIter::from(vec![0x00].into_iter()),
+            &[TypeConstraint::root(
+                TypePattern::Product(
+                    Box::new(TypePattern::Final(TypeInterner::new().pow2s()[1].clone())),
+                    Box::new(TypePattern::Unit),
+                ),
+                TypePattern::Var(0),
+            )],
+        )
+        .unwrap();
+        let recognized = recognize_program_jets(&program).unwrap();
+        assert_eq!(recognized.root_node().cmr, cmr);
+        assert_eq!(recognized.nodes.len(), 5);
+        assert_eq!(recognized.nodes[0].node, Term::Iden);
+        assert_eq!(recognized.nodes[1].node, Term::Take(1));
+        assert_eq!(recognized.nodes[2].node, Term::Ext(TestNode::Not));
+    }
+}
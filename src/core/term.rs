@@ -1,10 +1,13 @@
-use super::types;
+use super::text::Cursor;
+use super::types::{self, FinalType};
+use crate::bititer::BitIter;
+use crate::extension::Jet as JetNode;
 use crate::Error;
 use crate::{cmr, extension};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::hash::{Hash, Hasher};
-use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
 
 /// Simplicity expression node, including Bitcoin/Elements extensions
 ///
@@ -47,25 +50,25 @@ pub enum Term<Witness, Extension> {
 pub enum DagTerm<Witness, Extension> {
     Iden,
     Unit,
-    InjL(Rc<DagTerm<Witness, Extension>>),
-    InjR(Rc<DagTerm<Witness, Extension>>),
-    Take(Rc<DagTerm<Witness, Extension>>),
-    Drop(Rc<DagTerm<Witness, Extension>>),
+    InjL(Arc<DagTerm<Witness, Extension>>),
+    InjR(Arc<DagTerm<Witness, Extension>>),
+    Take(Arc<DagTerm<Witness, Extension>>),
+    Drop(Arc<DagTerm<Witness, Extension>>),
     Comp(
-        Rc<DagTerm<Witness, Extension>>,
-        Rc<DagTerm<Witness, Extension>>,
+        Arc<DagTerm<Witness, Extension>>,
+        Arc<DagTerm<Witness, Extension>>,
     ),
     Case(
-        Rc<DagTerm<Witness, Extension>>,
-        Rc<DagTerm<Witness, Extension>>,
+        Arc<DagTerm<Witness, Extension>>,
+        Arc<DagTerm<Witness, Extension>>,
     ),
     Pair(
-        Rc<DagTerm<Witness, Extension>>,
-        Rc<DagTerm<Witness, Extension>>,
+        Arc<DagTerm<Witness, Extension>>,
+        Arc<DagTerm<Witness, Extension>>,
     ),
     Disconnect(
-        Rc<DagTerm<Witness, Extension>>,
-        Rc<DagTerm<Witness, Extension>>,
+        Arc<DagTerm<Witness, Extension>>,
+        Arc<DagTerm<Witness, Extension>>,
     ),
     Witness(Witness),
     Fail([u8; 32], [u8; 32]),
@@ -79,156 +82,391 @@ pub struct UnTypedProg<Witness, Extension>(pub Vec<Term<Witness, Extension>>);
 
 impl<Witness, Extension> DagTerm<Witness, Extension> {
     /// Create a DAG representation from an untyped representation
-    pub fn from_untyped_prog(untyped_prog: UnTypedProg<Witness, Extension>) -> Rc<Self> {
+    pub fn from_untyped_prog(untyped_prog: UnTypedProg<Witness, Extension>) -> Arc<Self> {
         assert!(
             !untyped_prog.0.is_empty(),
             "Untyped Program len must be greater than 0"
         );
-        let mut dag: Vec<Rc<DagTerm<_, _>>> = vec![];
+        let mut dag: Vec<Arc<DagTerm<_, _>>> = vec![];
         for (index, term) in untyped_prog.0.into_iter().enumerate() {
             let dag_term = match term {
-                Term::Iden => Rc::new(DagTerm::Iden),
-                Term::Unit => Rc::new(DagTerm::Unit),
-                Term::InjL(l) => Rc::new(DagTerm::InjL(Rc::clone(&dag[index - l]))),
-                Term::InjR(r) => Rc::new(DagTerm::InjR(Rc::clone(&dag[index - r]))),
-                Term::Take(l) => Rc::new(DagTerm::Take(Rc::clone(&dag[index - l]))),
-                Term::Drop(r) => Rc::new(DagTerm::Drop(Rc::clone(&dag[index - r]))),
-                Term::Comp(l, r) => Rc::new(DagTerm::Comp(
-                    Rc::clone(&dag[index - l]),
-                    Rc::clone(&dag[index - r]),
+                Term::Iden => Arc::new(DagTerm::Iden),
+                Term::Unit => Arc::new(DagTerm::Unit),
+                Term::InjL(l) => Arc::new(DagTerm::InjL(Arc::clone(&dag[index - l]))),
+                Term::InjR(r) => Arc::new(DagTerm::InjR(Arc::clone(&dag[index - r]))),
+                Term::Take(l) => Arc::new(DagTerm::Take(Arc::clone(&dag[index - l]))),
+                Term::Drop(r) => Arc::new(DagTerm::Drop(Arc::clone(&dag[index - r]))),
+                Term::Comp(l, r) => Arc::new(DagTerm::Comp(
+                    Arc::clone(&dag[index - l]),
+                    Arc::clone(&dag[index - r]),
                 )),
-                Term::Case(l, r) => Rc::new(DagTerm::Case(
-                    Rc::clone(&dag[index - l]),
-                    Rc::clone(&dag[index - r]),
+                Term::Case(l, r) => Arc::new(DagTerm::Case(
+                    Arc::clone(&dag[index - l]),
+                    Arc::clone(&dag[index - r]),
                 )),
-                Term::Pair(l, r) => Rc::new(DagTerm::Pair(
-                    Rc::clone(&dag[index - l]),
-                    Rc::clone(&dag[index - r]),
+                Term::Pair(l, r) => Arc::new(DagTerm::Pair(
+                    Arc::clone(&dag[index - l]),
+                    Arc::clone(&dag[index - r]),
                 )),
-                Term::Disconnect(l, r) => Rc::new(DagTerm::Disconnect(
-                    Rc::clone(&dag[index - l]),
-                    Rc::clone(&dag[index - r]),
+                Term::Disconnect(l, r) => Arc::new(DagTerm::Disconnect(
+                    Arc::clone(&dag[index - l]),
+                    Arc::clone(&dag[index - r]),
                 )),
-                Term::Witness(w) => Rc::new(DagTerm::Witness(w)),
+                Term::Witness(w) => Arc::new(DagTerm::Witness(w)),
                 //TODO: understand how Fail works and rename `a` and `b`
-                Term::Fail(a, b) => Rc::new(DagTerm::Fail(a, b)),
-                Term::Hidden(c) => Rc::new(DagTerm::Hidden(c)),
-                Term::Ext(e) => Rc::new(DagTerm::Ext(e)),
-                Term::Jet(j) => Rc::new(DagTerm::Jet(j)),
+                Term::Fail(a, b) => Arc::new(DagTerm::Fail(a, b)),
+                Term::Hidden(c) => Arc::new(DagTerm::Hidden(c)),
+                Term::Ext(e) => Arc::new(DagTerm::Ext(e)),
+                Term::Jet(j) => Arc::new(DagTerm::Jet(j)),
             };
             dag.push(dag_term);
         }
-        Rc::clone(dag.last().unwrap())
+        Arc::clone(dag.last().unwrap())
     }
 }
 
-// A direct comparison for Rc<> results in comparison
-// of underllying inner values whereas we desire to
-// compare the referececs.
-#[derive(Debug)]
-struct RcWrapper<Witness, Extension> {
-    rc: Rc<DagTerm<Witness, Extension>>,
+impl<Witness, Extension> Term<Witness, Extension> {
+    /// The name of the combinator, as used when displaying programs
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Term::Iden => "iden",
+            Term::Unit => "unit",
+            Term::InjL(..) => "injl",
+            Term::InjR(..) => "injr",
+            Term::Take(..) => "take",
+            Term::Drop(..) => "drop",
+            Term::Comp(..) => "comp",
+            Term::Case(..) => "case",
+            Term::Pair(..) => "pair",
+            Term::Disconnect(..) => "disconnect",
+            Term::Witness(..) => "witness",
+            Term::Fail(..) => "fail",
+            Term::Hidden(..) => "hidden",
+            Term::Ext(..) => "ext",
+            Term::Jet(..) => "jet",
+        }
+    }
+
+    /// The name of the combinator, followed by the name of the node for
+    /// extension nodes and jets
+    pub fn label(&self) -> String
+    where
+        Extension: fmt::Display,
+    {
+        match *self {
+            Term::Ext(ref e) => format!("{} {}", self.name(), e),
+            Term::Jet(ref j) => format!("{} {}", self.name(), j),
+            _ => self.name().to_owned(),
+        }
+    }
+
+    /// The relative indices of the children of this node, left first
+    pub fn children(&self) -> impl Iterator<Item = usize> {
+        let children = match *self {
+            Term::InjL(i) | Term::InjR(i) | Term::Take(i) | Term::Drop(i) => (Some(i), None),
+            Term::Comp(i, j) | Term::Case(i, j) | Term::Pair(i, j) | Term::Disconnect(i, j) => {
+                (Some(i), Some(j))
+            }
+            _ => (None, None),
+        };
+        children.0.into_iter().chain(children.1)
+    }
+}
+
+impl<Witness, Extension: extension::Jet> Term<Witness, Extension> {
+    /// The commitment Merkle root of this node, given those of its
+    /// children, left first
+    pub fn cmr(&self, children: &[cmr::Cmr]) -> cmr::Cmr {
+        match *self {
+            Term::Iden => cmr::tag::iden(),
+            Term::Unit => cmr::tag::unit(),
+            Term::InjL(..) => cmr::tag::injl().update_1(children[0]),
+            Term::InjR(..) => cmr::tag::injr().update_1(children[0]),
+            Term::Take(..) => cmr::tag::take().update_1(children[0]),
+            Term::Drop(..) => cmr::tag::drop().update_1(children[0]),
+            Term::Comp(..) => cmr::tag::comp().update(children[0], children[1]),
+            Term::Case(..) => cmr::tag::case().update(children[0], children[1]),
+            Term::Pair(..) => cmr::tag::pair().update(children[0], children[1]),
+            Term::Disconnect(..) => cmr::tag::disconnect().update_1(children[0]),
+            Term::Witness(..) => cmr::tag::witness(),
+            Term::Fail(a, b) => cmr::tag::fail().update(cmr::Cmr::from(a), cmr::Cmr::from(b)),
+            Term::Hidden(cmr) => cmr,
+            Term::Ext(ref b) => b.cmr(),
+            Term::Jet(ref j) => j.cmr(),
+        }
+    }
 }
 
-impl<Witness, Extension> PartialEq for RcWrapper<Witness, Extension> {
-    fn eq(&self, other: &Self) -> bool {
-        Rc::ptr_eq(&self.rc, &other.rc)
+impl<Witness, Extension: extension::Jet> UnTypedProg<Witness, Extension> {
+    /// The commitment Merkle roots of every node of the program, which
+    /// need neither types nor witnesses. The last is that of the program.
+    pub fn cmrs(&self) -> Vec<cmr::Cmr> {
+        let mut ret = Vec::<cmr::Cmr>::with_capacity(self.0.len());
+        let mut children = Vec::with_capacity(2);
+        for (index, node) in self.0.iter().enumerate() {
+            children.clear();
+            children.extend(node.children().map(|i| ret[index - i]));
+            ret.push(node.cmr(&children));
+        }
+        ret
     }
 }
 
-impl<Witness, Extension> Eq for RcWrapper<Witness, Extension> {}
+impl<Witness, Extension: Clone> DagTerm<Witness, Extension> {
+    /// This node with its witness dropped and its children replaced by
+    /// their addresses, which identify them while they are alive
+    pub(crate) fn shape(&self) -> Term<(), Extension> {
+        fn addr<W, E>(child: &Arc<DagTerm<W, E>>) -> usize {
+            &**child as *const DagTerm<W, E> as usize
+        }
 
-impl<Witness, Extension> From<Rc<DagTerm<Witness, Extension>>> for RcWrapper<Witness, Extension> {
-    fn from(dag: Rc<DagTerm<Witness, Extension>>) -> Self {
-        Self { rc: dag }
+        match *self {
+            DagTerm::Iden => Term::Iden,
+            DagTerm::Unit => Term::Unit,
+            DagTerm::InjL(ref l) => Term::InjL(addr(l)),
+            DagTerm::InjR(ref l) => Term::InjR(addr(l)),
+            DagTerm::Take(ref l) => Term::Take(addr(l)),
+            DagTerm::Drop(ref l) => Term::Drop(addr(l)),
+            DagTerm::Comp(ref l, ref r) => Term::Comp(addr(l), addr(r)),
+            DagTerm::Case(ref l, ref r) => Term::Case(addr(l), addr(r)),
+            DagTerm::Pair(ref l, ref r) => Term::Pair(addr(l), addr(r)),
+            DagTerm::Disconnect(ref l, ref r) => Term::Disconnect(addr(l), addr(r)),
+            DagTerm::Witness(..) => Term::Witness(()),
+            DagTerm::Fail(a, b) => Term::Fail(a, b),
+            DagTerm::Hidden(cmr) => Term::Hidden(cmr),
+            DagTerm::Ext(ref e) => Term::Ext(e.clone()),
+            DagTerm::Jet(j) => Term::Jet(j),
+        }
     }
 }
 
-impl<Witness, Extension> Hash for RcWrapper<Witness, Extension>
-where
-    Witness: Hash,
-    Extension: Hash,
-{
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.rc.hash(state);
+impl<Witness, Extension: extension::Jet + Clone> DagTerm<Witness, Extension> {
+    /// The commitment Merkle root of the expression, which needs neither
+    /// types nor witnesses. Shared subexpressions are only hashed once.
+    pub fn cmr(&self) -> cmr::Cmr {
+        self.post_order_fold(|node, children: Vec<cmr::Cmr>| node.shape().cmr(&children))
     }
 }
 
-impl<Witness, Extension> DagTerm<Witness, Extension>
-where
-    Witness: Hash + Clone,
-    Extension: Hash + Clone,
-{
-    /// Convert a DagTerm into into a untyped program vec.
-    pub fn into_untyped_prog(self) -> UnTypedProg<Witness, Extension> {
-        // helper function to recrusively compute the index positions
-        // of the children.
-        fn into_helper<Witness, Extension>(
-            dag: Rc<DagTerm<Witness, Extension>>,
-            index_map: &mut HashMap<RcWrapper<Witness, Extension>, usize>,
-            prog: &mut Vec<Term<Witness, Extension>>,
-        ) -> usize
-        where
-            RcWrapper<Witness, Extension>: Hash,
-            Witness: Clone,
-            Extension: Clone,
-        {
-            // insert one child into prog
-            macro_rules! insert_one_child {
-                ($term: expr, $l : expr) => {
-                    match index_map.get(&RcWrapper::from(Rc::clone($l))) {
-                        Some(ind) => prog.push($term(prog.len() - ind)),
-                        None => {
-                            let ind = into_helper(Rc::clone($l), index_map, prog);
-                            prog.push($term(prog.len() - ind));
-                        }
-                    }
-                };
+impl<Witness, Extension> DagTerm<Witness, Extension> {
+    /// The children of this node, left first
+    pub fn children(&self) -> impl Iterator<Item = &Arc<Self>> {
+        let children = match *self {
+            DagTerm::InjL(ref l)
+            | DagTerm::InjR(ref l)
+            | DagTerm::Take(ref l)
+            | DagTerm::Drop(ref l) => (Some(l), None),
+            DagTerm::Comp(ref l, ref r)
+            | DagTerm::Case(ref l, ref r)
+            | DagTerm::Pair(ref l, ref r)
+            | DagTerm::Disconnect(ref l, ref r) => (Some(l), Some(r)),
+            _ => (None, None),
+        };
+        children.0.into_iter().chain(children.1)
+    }
+
+    /// Mutable references to the children of this node
+    fn children_mut(&mut self) -> [Option<&mut Arc<Self>>; 2] {
+        match *self {
+            DagTerm::InjL(ref mut l)
+            | DagTerm::InjR(ref mut l)
+            | DagTerm::Take(ref mut l)
+            | DagTerm::Drop(ref mut l) => [Some(l), None],
+            DagTerm::Comp(ref mut l, ref mut r)
+            | DagTerm::Case(ref mut l, ref mut r)
+            | DagTerm::Pair(ref mut l, ref mut r)
+            | DagTerm::Disconnect(ref mut l, ref mut r) => [Some(l), Some(r)],
+            _ => [None, None],
+        }
+    }
+
+    /// Iterate over the nodes of the DAG, children left to right before
+    /// their parents, yielding each node once however many times it is
+    /// shared. Does not recurse, so is safe on arbitrarily deep programs.
+    pub fn post_order_iter(&self) -> PostOrderIter<'_, Witness, Extension> {
+        PostOrderIter {
+            stack: vec![(self, false)],
+            visited: HashSet::new(),
+        }
+    }
+
+    /// Compute a value for every node of the DAG, in the order of
+    /// `post_order_iter`. `f` is given the results for the node's children.
+    ///
+    /// A result is dropped as soon as the last of its node's parents has
+    /// been given it, and is only cloned for parents before the last, so
+    /// results may own everything computed for their descendants without
+    /// the fold taking quadratic time or memory on deep DAGs.
+    pub fn post_order_fold<T, F>(&self, mut f: F) -> T
+    where
+        T: Clone,
+        F: FnMut(&Self, Vec<T>) -> T,
+    {
+        let mut n_parents = HashMap::<*const Self, usize>::new();
+        for node in self.post_order_iter() {
+            for child in node.children() {
+                *n_parents.entry(&**child).or_insert(0) += 1;
             }
+        }
 
-            // insert two children into prog
-            macro_rules! insert_two_child {
-                ($term: expr, $l : expr, $r: expr) => {{
-                    let l_ind = match index_map.get(&RcWrapper::from(Rc::clone($l))) {
-                        Some(ind) => *ind,
-                        None => into_helper(Rc::clone($l), index_map, prog),
-                    };
-                    let r_ind = match index_map.get(&RcWrapper::from(Rc::clone($r))) {
-                        Some(ind) => *ind,
-                        None => into_helper(Rc::clone($r), index_map, prog),
-                    };
-                    prog.push($term(prog.len() - l_ind, prog.len() - r_ind));
-                }};
+        let mut results = HashMap::<*const Self, T>::new();
+        for node in self.post_order_iter() {
+            let mut child_results = Vec::with_capacity(2);
+            for child in node.children() {
+                let key: *const Self = &**child;
+                let remaining = n_parents.get_mut(&key).unwrap();
+                *remaining -= 1;
+                child_results.push(if *remaining == 0 {
+                    results.remove(&key).unwrap()
+                } else {
+                    results[&key].clone()
+                });
             }
+            let result = f(node, child_results);
+            results.insert(node, result);
+        }
+        results.remove(&(self as *const Self)).unwrap()
+    }
+
+    /// Walk the DAG in the order of `post_order_iter`, calling back into
+    /// the visitor for every edge and every node
+    pub fn visit<V: DagVisitor<Witness, Extension>>(&self, visitor: &mut V) {
+        let mut iter = self.post_order_iter();
+        while let Some(node) =
+            iter.next_with(|parent, child, n| visitor.visit_child(parent, child, n))
+        {
+            visitor.visit_node(node);
+        }
+    }
+}
 
-            if index_map.contains_key(&RcWrapper::from(Rc::clone(&dag))) {
-                return *index_map.get(&RcWrapper::from(Rc::clone(&dag))).unwrap();
+/// Callbacks for `DagTerm::visit`
+pub trait DagVisitor<Witness, Extension> {
+    /// Called for the edge from a node to its `n`th child, when the node
+    /// is first reached and before any of its children are visited. This
+    /// happens for every edge, including edges to nodes which have already
+    /// been visited. Returning `false` skips the child, and its descendants
+    /// that are not reachable some other way.
+    fn visit_child(
+        &mut self,
+        _parent: &DagTerm<Witness, Extension>,
+        _child: &DagTerm<Witness, Extension>,
+        _n: usize,
+    ) -> bool {
+        true
+    }
+
+    /// Called once for every node, after its children
+    fn visit_node(&mut self, node: &DagTerm<Witness, Extension>);
+}
+
+/// Post-order iterator over a DAG, obtained from `DagTerm::post_order_iter`
+pub struct PostOrderIter<'a, Witness, Extension> {
+    /// Nodes still to be yielded, with whether their children have
+    /// already been pushed
+    stack: Vec<(&'a DagTerm<Witness, Extension>, bool)>,
+    /// Nodes whose children have been pushed, keyed by address
+    visited: HashSet<*const DagTerm<Witness, Extension>>,
+}
+
+impl<'a, Witness, Extension> PostOrderIter<'a, Witness, Extension> {
+    /// Yield the next node, only descending from a node into its `n`th
+    /// child `child` if `descend(node, child, n)` returns true
+    pub fn next_with<F>(&mut self, mut descend: F) -> Option<&'a DagTerm<Witness, Extension>>
+    where
+        F: FnMut(&'a DagTerm<Witness, Extension>, &'a DagTerm<Witness, Extension>, usize) -> bool,
+    {
+        while let Some((node, children_pushed)) = self.stack.pop() {
+            if children_pushed {
+                return Some(node);
             }
-            match dag.as_ref() {
-                DagTerm::Unit => prog.push(Term::Unit),
-                DagTerm::Iden => prog.push(Term::Iden),
-                DagTerm::InjL(l) => insert_one_child!(Term::InjL, l),
-                DagTerm::InjR(r) => insert_one_child!(Term::InjR, r),
-                DagTerm::Take(l) => insert_one_child!(Term::Take, l),
-                DagTerm::Drop(r) => insert_one_child!(Term::Drop, r),
-                DagTerm::Comp(l, r) => insert_two_child!(Term::Comp, l, r),
-                DagTerm::Case(l, r) => insert_two_child!(Term::Case, l, r),
-                DagTerm::Pair(l, r) => insert_two_child!(Term::Pair, l, r),
-                DagTerm::Disconnect(l, r) => insert_two_child!(Term::Disconnect, l, r),
-                DagTerm::Witness(ref w) => prog.push(Term::Witness(w.clone())),
-                DagTerm::Fail(a, b) => prog.push(Term::Fail(*a, *b)),
-                DagTerm::Hidden(cmr) => prog.push(Term::Hidden(*cmr)),
-                DagTerm::Ext(ref e) => prog.push(Term::Ext(e.clone())),
-                DagTerm::Jet(ref j) => prog.push(Term::Jet(*j)),
+            if !self.visited.insert(node) {
+                continue;
+            }
+            self.stack.push((node, true));
+            let mut descend_to = [None, None];
+            for (n, child) in node.children().enumerate() {
+                if descend(node, child, n) {
+                    descend_to[n] = Some(&**child);
+                }
+            }
+            for child in descend_to.iter().rev().flatten() {
+                if !self.visited.contains(&(*child as *const _)) {
+                    self.stack.push((child, false));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<'a, Witness, Extension> Iterator for PostOrderIter<'a, Witness, Extension> {
+    type Item = &'a DagTerm<Witness, Extension>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with(|_, _, _| true)
+    }
+}
+
+impl<Witness, Extension> Drop for DagTerm<Witness, Extension> {
+    /// Drop the DAG without recursing, by detaching every child which
+    /// would be freed along with its parent and dropping it separately
+    fn drop(&mut self) {
+        fn detach<Witness, Extension>(
+            node: &mut DagTerm<Witness, Extension>,
+            stack: &mut Vec<DagTerm<Witness, Extension>>,
+        ) {
+            for child in node.children_mut().iter_mut().flatten() {
+                if let Some(child) = Arc::get_mut(child) {
+                    if child.children().next().is_some() {
+                        stack.push(std::mem::replace(child, DagTerm::Unit));
+                    }
+                }
             }
-            // insert the current node remembering it's index for reusing
-            index_map.insert(RcWrapper::from(dag), prog.len() - 1);
-            prog.len() - 1
         }
 
+        let mut stack = vec![];
+        detach(self, &mut stack);
+        while let Some(mut node) = stack.pop() {
+            detach(&mut node, &mut stack);
+        }
+    }
+}
+
+impl<Witness, Extension> DagTerm<Witness, Extension>
+where
+    Witness: Clone,
+    Extension: Clone,
+{
+    /// Convert a DagTerm into into a untyped program vec.
+    pub fn into_untyped_prog(self) -> UnTypedProg<Witness, Extension> {
+        self.to_untyped_prog()
+    }
+
+    /// Convert a DagTerm into an untyped program vec, without consuming it
+    pub fn to_untyped_prog(&self) -> UnTypedProg<Witness, Extension> {
         let mut prog = vec![];
-        let mut index_map = HashMap::new();
-        let _len = into_helper(Rc::new(self), &mut index_map, &mut prog);
+        self.post_order_fold(|node, children: Vec<usize>| {
+            let idx = prog.len();
+            prog.push(match *node {
+                DagTerm::Unit => Term::Unit,
+                DagTerm::Iden => Term::Iden,
+                DagTerm::InjL(..) => Term::InjL(idx - children[0]),
+                DagTerm::InjR(..) => Term::InjR(idx - children[0]),
+                DagTerm::Take(..) => Term::Take(idx - children[0]),
+                DagTerm::Drop(..) => Term::Drop(idx - children[0]),
+                DagTerm::Comp(..) => Term::Comp(idx - children[0], idx - children[1]),
+                DagTerm::Case(..) => Term::Case(idx - children[0], idx - children[1]),
+                DagTerm::Pair(..) => Term::Pair(idx - children[0], idx - children[1]),
+                DagTerm::Disconnect(..) => Term::Disconnect(idx - children[0], idx - children[1]),
+                DagTerm::Witness(ref w) => Term::Witness(w.clone()),
+                DagTerm::Fail(a, b) => Term::Fail(a, b),
+                DagTerm::Hidden(cmr) => Term::Hidden(cmr),
+                DagTerm::Ext(ref e) => Term::Ext(e.clone()),
+                DagTerm::Jet(j) => Term::Jet(j),
+            });
+            idx
+        });
 
         UnTypedProg(prog)
     }
@@ -246,14 +484,48 @@ impl Value {
     #![allow(clippy::len_without_is_empty)]
     /// The length, in bits, of the value when encoded in the Bit Machine
     pub fn len(&self) -> usize {
-        match *self {
+        self.post_order_fold(|value, children: Vec<usize>| match *value {
             Value::Unit => 0,
-            Value::SumL(ref s) => 1 + s.len(),
-            Value::SumR(ref s) => 1 + s.len(),
-            Value::Prod(ref s, ref t) => s.len() + t.len(),
+            Value::SumL(..) | Value::SumR(..) => 1 + children[0],
+            Value::Prod(..) => children[0] + children[1],
+        })
+    }
+
+    /// The children of this value, left first
+    fn children(&self) -> [Option<&Value>; 2] {
+        match *self {
+            Value::Unit => [None, None],
+            Value::SumL(ref v) | Value::SumR(ref v) => [Some(v), None],
+            Value::Prod(ref l, ref r) => [Some(l), Some(r)],
         }
     }
 
+    /// Compute a result for every node of the value, visiting children
+    /// left to right before their parents. `f` is given the results for
+    /// the node's children. Does not recurse, so is safe on arbitrarily
+    /// deep values.
+    pub fn post_order_fold<T, F>(&self, mut f: F) -> T
+    where
+        F: FnMut(&Value, Vec<T>) -> T,
+    {
+        let mut stack = vec![(self, false)];
+        let mut results = vec![];
+        while let Some((value, children_done)) = stack.pop() {
+            if children_done {
+                let n_children = value.children().iter().flatten().count();
+                let children = results.split_off(results.len() - n_children);
+                let result = f(value, children);
+                results.push(result);
+            } else {
+                stack.push((value, true));
+                for child in value.children().iter().rev().flatten() {
+                    stack.push((child, false));
+                }
+            }
+        }
+        results.pop().unwrap()
+    }
+
     /// Encode a single bit as a value. Will panic if the input is out of range
     pub fn u1(n: u8) -> Value {
         match n {
@@ -277,8 +549,8 @@ impl Value {
     pub fn u4(n: u8) -> Value {
         let w0 = (n & 12) / 4;
         let w1 = n & 3;
-        if n > 7 {
-            panic!("{} out of range for Value::u2", n);
+        if n > 15 {
+            panic!("{} out of range for Value::u4", n);
         }
         Value::Prod(Box::new(Value::u2(w0)), Box::new(Value::u2(w1)))
     }
@@ -311,28 +583,88 @@ impl Value {
         Value::Prod(Box::new(Value::u32(w0)), Box::new(Value::u32(w1)))
     }
 
+    /// Encode a 256-bit number, given as big-endian bytes, as a value
+    pub fn u256(bytes: &[u8; 32]) -> Value {
+        fn word(bytes: &[u8]) -> Value {
+            if bytes.len() == 1 {
+                Value::u8(bytes[0])
+            } else {
+                let (l, r) = bytes.split_at(bytes.len() / 2);
+                Value::prod(word(l), word(r))
+            }
+        }
+        word(bytes)
+    }
+
+    /// Decode a value of the given type from bytes, in the same bitwise
+    /// encoding as witness data. The bytes must contain exactly the
+    /// value, padded with at most seven zero bits to a whole byte.
+    pub fn from_bytes(bytes: &[u8], ty: &types::FinalType) -> Result<Value, Error> {
+        let mut bits = BitIter::from(bytes.iter().cloned());
+        let ret = Value::from_bits_and_type(&mut bits, ty)?;
+        if (bits.n_total_read() + 7) / 8 != bytes.len() {
+            Err(Error::ValueShape("value followed by trailing bytes"))
+        } else if bits.any(|bit| bit) {
+            Err(Error::ValueShape("value padded with non-zero bits"))
+        } else {
+            Ok(ret)
+        }
+    }
+
+    /// Encode the value as bytes, in the same bitwise encoding as witness
+    /// data, padding the final byte with zeroes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bits = vec![];
+        self.append_bits(&mut bits);
+        bits.chunks(8)
+            .map(|byte| {
+                byte.iter()
+                    .enumerate()
+                    .fold(0, |acc, (i, &bit)| acc | ((bit as u8) << (7 - i)))
+            })
+            .collect()
+    }
+
     /// Convert the value to a byte array.
     pub fn into_bits(self) -> Vec<bool> {
         let mut ret = vec![];
-        fn into_bit_helper(v: Value, ret: &mut Vec<bool>) {
-            match v {
+        self.append_bits(&mut ret);
+        ret
+    }
+
+    fn append_bits(&self, ret: &mut Vec<bool>) {
+        let mut stack = vec![self];
+        while let Some(value) = stack.pop() {
+            match *value {
                 Value::Unit => {}
-                Value::SumL(l) => {
+                Value::SumL(ref l) => {
                     ret.push(false);
-                    into_bit_helper(*l, ret);
+                    stack.push(l);
                 }
-                Value::SumR(r) => {
+                Value::SumR(ref r) => {
                     ret.push(true);
-                    into_bit_helper(*r, ret);
+                    stack.push(r);
                 }
-                Value::Prod(l, r) => {
-                    into_bit_helper(*l, ret);
-                    into_bit_helper(*r, ret);
+                Value::Prod(ref l, ref r) => {
+                    stack.push(r);
+                    stack.push(l);
                 }
             }
         }
-        into_bit_helper(self, &mut ret);
-        ret
+    }
+
+    /// If the value has the shape of a word of `2^(2^log_bits)`, append
+    /// its bits to the given vector, most significant first
+    fn append_word_bits(&self, log_bits: usize, ret: &mut Vec<bool>) -> bool {
+        match (self, log_bits) {
+            (Value::SumL(l), 0) if **l == Value::Unit => ret.push(false),
+            (Value::SumR(r), 0) if **r == Value::Unit => ret.push(true),
+            (Value::Prod(l, r), n) if n > 0 => {
+                return l.append_word_bits(n - 1, ret) && r.append_word_bits(n - 1, ret);
+            }
+            _ => return false,
+        }
+        true
     }
 
     /// Convenience constructor for a left sum of a value
@@ -351,6 +683,55 @@ impl Value {
     }
 }
 
+macro_rules! impl_value_to_word {
+    ($(#[$doc:meta])* $fn:ident, $ty:ty, $log_bits:expr, $name:expr) => {
+        impl Value {
+            $(#[$doc])*
+            pub fn $fn(&self) -> Result<$ty, Error> {
+                let mut bits = Vec::with_capacity(1 << $log_bits);
+                if self.append_word_bits($log_bits, &mut bits) {
+                    Ok(bits.into_iter().fold(0, |acc, bit| (acc << 1) | bit as $ty))
+                } else {
+                    Err(Error::ValueShape($name))
+                }
+            }
+        }
+    };
+}
+
+impl_value_to_word!(
+    /// Decode an 8-bit word
+    to_u8, u8, 3, "8-bit word"
+);
+impl_value_to_word!(
+    /// Decode a 16-bit word
+    to_u16, u16, 4, "16-bit word"
+);
+impl_value_to_word!(
+    /// Decode a 32-bit word
+    to_u32, u32, 5, "32-bit word"
+);
+impl_value_to_word!(
+    /// Decode a 64-bit word
+    to_u64, u64, 6, "64-bit word"
+);
+
+impl Value {
+    /// Decode a 256-bit word as big-endian bytes
+    pub fn to_u256(&self) -> Result<[u8; 32], Error> {
+        let mut bits = Vec::with_capacity(256);
+        if self.append_word_bits(8, &mut bits) {
+            let mut ret = [0; 32];
+            for (n, bit) in bits.into_iter().enumerate() {
+                ret[n / 8] |= (bit as u8) << (7 - n % 8);
+            }
+            Ok(ret)
+        } else {
+            Err(Error::ValueShape("256-bit word"))
+        }
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -384,17 +765,420 @@ impl Value {
         bits: &mut Bits,
         ty: &types::FinalType,
     ) -> Result<Value, Error> {
-        match ty.ty {
-            types::FinalTypeInner::Unit => Ok(Value::Unit),
-            types::FinalTypeInner::Sum(ref l, ref r) => match bits.next() {
-                Some(false) => Ok(Value::SumL(Box::new(Value::from_bits_and_type(bits, &*l)?))),
-                Some(true) => Ok(Value::SumR(Box::new(Value::from_bits_and_type(bits, &*r)?))),
-                None => Err(Error::EndOfStream),
-            },
-            types::FinalTypeInner::Product(ref l, ref r) => Ok(Value::Prod(
-                Box::new(Value::from_bits_and_type(&mut *bits, &*l)?),
-                Box::new(Value::from_bits_and_type(bits, &*r)?),
-            )),
+        enum Task<'a> {
+            Read(&'a types::FinalType),
+            SumL,
+            SumR,
+            Prod,
+        }
+
+        let mut tasks = vec![Task::Read(ty)];
+        let mut results = vec![];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Read(ty) => match ty.ty {
+                    types::FinalTypeInner::Unit => results.push(Value::Unit),
+                    types::FinalTypeInner::Sum(ref l, ref r) => match bits.next() {
+                        Some(false) => {
+                            tasks.push(Task::SumL);
+                            tasks.push(Task::Read(l));
+                        }
+                        Some(true) => {
+                            tasks.push(Task::SumR);
+                            tasks.push(Task::Read(r));
+                        }
+                        None => return Err(Error::EndOfStream),
+                    },
+                    types::FinalTypeInner::Product(ref l, ref r) => {
+                        tasks.push(Task::Prod);
+                        tasks.push(Task::Read(r));
+                        tasks.push(Task::Read(l));
+                    }
+                },
+                Task::SumL => {
+                    let l = results.pop().unwrap();
+                    results.push(Value::sum_l(l));
+                }
+                Task::SumR => {
+                    let r = results.pop().unwrap();
+                    results.push(Value::sum_r(r));
+                }
+                Task::Prod => {
+                    let r = results.pop().unwrap();
+                    let l = results.pop().unwrap();
+                    results.push(Value::prod(l, r));
+                }
+            }
+        }
+        Ok(results.pop().unwrap())
+    }
+}
+
+impl Drop for Value {
+    /// Drop the value without recursing, by detaching every child and
+    /// dropping it separately
+    fn drop(&mut self) {
+        fn detach(value: &mut Value, stack: &mut Vec<Value>) {
+            match *value {
+                Value::Unit => {}
+                Value::SumL(ref v) | Value::SumR(ref v) if **v == Value::Unit => {}
+                Value::SumL(ref mut v) | Value::SumR(ref mut v) => {
+                    stack.push(std::mem::replace(&mut **v, Value::Unit));
+                }
+                Value::Prod(ref mut l, ref mut r) => {
+                    stack.push(std::mem::replace(&mut **l, Value::Unit));
+                    stack.push(std::mem::replace(&mut **r, Value::Unit));
+                }
+            }
+        }
+
+        let mut stack = vec![];
+        detach(self, &mut stack);
+        while let Some(mut value) = stack.pop() {
+            detach(&mut value, &mut stack);
+        }
+    }
+}
+
+impl Value {
+    /// Parse a value of the given type, written as by `Display`. Values
+    /// of word types may instead be written as hex (`0xdeadbeef`) or
+    /// binary (`0b0110`) literals with exactly as many digits as the word
+    /// has bits, and the unit value may be left implicit.
+    pub fn parse_with_type(s: &str, ty: &FinalType) -> Result<Value, Error> {
+        let mut cursor = Cursor::new(s);
+        let ret = parse_typed(&mut cursor, ty)?;
+        if cursor.is_empty() {
+            Ok(ret)
+        } else {
+            Err(cursor.error("unexpected trailing characters"))
+        }
+    }
+}
+
+/// Constructor of a value whose children are still being parsed
+enum Pending<T> {
+    /// Left injection, of the value about to be parsed
+    SumL,
+    /// Right injection, of the value about to be parsed
+    SumR,
+    /// Product, whose left value is about to be parsed, with what is
+    /// needed to parse its right value
+    Left(T),
+    /// Product, whose right value is about to be parsed
+    Right(Value),
+}
+
+/// Apply pending constructors to a parsed value, until one of them needs
+/// a right value to be parsed. Returns the value so far, along with what
+/// is needed to parse the right value, if there is one.
+fn complete<T>(
+    cursor: &mut Cursor,
+    stack: &mut Vec<Pending<T>>,
+    mut value: Value,
+) -> Result<(Value, Option<T>), Error> {
+    loop {
+        match stack.pop() {
+            None => return Ok((value, None)),
+            Some(Pending::SumL) => value = Value::sum_l(value),
+            Some(Pending::SumR) => value = Value::sum_r(value),
+            Some(Pending::Left(right)) => {
+                cursor.expect(",")?;
+                return Ok((value, Some(right)));
+            }
+            Some(Pending::Right(left)) => {
+                cursor.expect(")")?;
+                value = Value::prod(left, value);
+            }
         }
     }
 }
+
+/// Parse a word literal, if the type is a word and a literal comes next
+fn parse_word(cursor: &mut Cursor, ty: &FinalType) -> Result<Option<Value>, Error> {
+    if ty.word_index().is_none() {
+        return Ok(None);
+    }
+    let (digits, bits_per_digit) = if cursor.eat("0x") {
+        (cursor.take_while(|c| c.is_ascii_hexdigit()), 4)
+    } else if cursor.eat("0b") {
+        (cursor.take_while(|c| c == '0' || c == '1'), 1)
+    } else {
+        return Ok(None);
+    };
+    if digits.len() * bits_per_digit != ty.bit_width {
+        return Err(cursor.error(&format!("expected a {}-bit literal", ty.bit_width)));
+    }
+    let mut bits = digits.chars().flat_map(|c| {
+        let digit = c.to_digit(16).unwrap();
+        (0..bits_per_digit)
+            .rev()
+            .map(move |i| digit & (1 << i) != 0)
+    });
+    Value::from_bits_and_type(&mut bits, ty).map(Some)
+}
+
+/// Parse a value of a known type. Does not recurse, so is safe on
+/// arbitrarily deep values.
+fn parse_typed(cursor: &mut Cursor, mut ty: &FinalType) -> Result<Value, Error> {
+    let mut stack = vec![];
+    loop {
+        let value = loop {
+            if let Some(value) = parse_word(cursor, ty)? {
+                break value;
+            }
+            match ty.ty {
+                types::FinalTypeInner::Unit => {
+                    cursor.eat("ε");
+                    break Value::Unit;
+                }
+                types::FinalTypeInner::Sum(ref l, ref r) => {
+                    if cursor.eat("0") {
+                        stack.push(Pending::SumL);
+                        ty = l;
+                    } else if cursor.eat("1") {
+                        stack.push(Pending::SumR);
+                        ty = r;
+                    } else {
+                        return Err(cursor.error("expected `0` or `1`"));
+                    }
+                }
+                types::FinalTypeInner::Product(ref l, ref r) => {
+                    cursor.expect("(")?;
+                    stack.push(Pending::Left(&**r));
+                    ty = l;
+                }
+            }
+        };
+        match complete(cursor, &mut stack, value)? {
+            (value, None) => return Ok(value),
+            (left, Some(right)) => {
+                stack.push(Pending::Right(left));
+                ty = right;
+            }
+        }
+    }
+}
+
+/// Parse a value of unknown type, exactly as written by `Display`. Does
+/// not recurse, so is safe on arbitrarily deep values.
+fn parse_untyped(cursor: &mut Cursor) -> Result<Value, Error> {
+    let mut stack = vec![];
+    loop {
+        let value = loop {
+            if cursor.eat("ε") {
+                break Value::Unit;
+            } else if cursor.eat("(") {
+                stack.push(Pending::Left(()));
+                continue;
+            } else if cursor.eat("0") {
+                stack.push(Pending::SumL);
+            } else if cursor.eat("1") {
+                stack.push(Pending::SumR);
+            } else {
+                return Err(cursor.error("expected a value"));
+            }
+            // The child of a sum value is omitted if it is the unit
+            match cursor.rest().chars().next() {
+                Some('ε') | Some('(') | Some('0') | Some('1') => {}
+                _ => break Value::Unit,
+            }
+        };
+        match complete(cursor, &mut stack, value)? {
+            (value, None) => return Ok(value),
+            (left, Some(())) => stack.push(Pending::Right(left)),
+        }
+    }
+}
+
+impl FromStr for Value {
+    type Err = Error;
+
+    /// Parse a value written as by `Display`, optionally followed by
+    /// `: <type>`, in which case it is parsed as by `parse_with_type`
+    fn from_str(s: &str) -> Result<Value, Error> {
+        match s.rfind(':') {
+            Some(idx) => {
+                let ty = FinalType::from_str(&s[idx + 1..])?;
+                Value::parse_with_type(&s[..idx], &ty)
+            }
+            None => {
+                let mut cursor = Cursor::new(s);
+                let ret = parse_untyped(&mut cursor)?;
+                if cursor.is_empty() {
+                    Ok(ret)
+                } else {
+                    Err(cursor.error("unexpected trailing characters"))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::TypeInterner;
+
+    #[test]
+    fn parse_values() {
+        for v in &[
+            Value::Unit,
+            Value::u1(1),
+            Value::u8(0xa5),
+            Value::sum_l(Value::sum_r(Value::u2(2))),
+            Value::prod(Value::sum_r(Value::Unit), Value::Unit),
+        ] {
+            assert_eq!(Value::from_str(&v.to_string()).unwrap(), *v);
+        }
+
+        assert_eq!(
+            Value::from_str("0xdeadbeef : 2^32").unwrap(),
+            Value::u32(0xdeadbeef)
+        );
+        assert_eq!(
+            Value::from_str("1(0b1, 0xff) : 1 + 2 × 2^8").unwrap(),
+            Value::sum_r(Value::prod(Value::u1(1), Value::u8(0xff)))
+        );
+        assert_eq!(
+            Value::from_str("(0, ε) : 2 × 1").unwrap(),
+            Value::prod(Value::u1(0), Value::Unit)
+        );
+
+        for s in &[
+            "",
+            "2",
+            "(0,1",
+            "0x12 : 2^16",
+            "0 : 1",
+            "(0,1) : 2^4",
+            "0xff : 1 + 2^8",
+        ] {
+            assert!(Value::from_str(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parse_deep_values() {
+        let n = 100_000;
+        let size = |v: &Value| v.post_order_fold(|_, c: Vec<usize>| 1 + c.iter().sum::<usize>());
+
+        let value = Value::from_str(&"0".repeat(n)).unwrap();
+        assert_eq!(value.len(), n);
+        let value = Value::from_str(&format!("{}ε{}", "(".repeat(n), ",1)".repeat(n))).unwrap();
+        assert_eq!(size(&value), 3 * n + 1);
+
+        // 1 + (1 + (... + 1))
+        let ty_str = format!("{}1{}", "(1 + ".repeat(n), ")".repeat(n));
+        let ty = FinalType::from_str(&ty_str).unwrap();
+        assert_eq!(ty.bit_width(), n);
+        let value = Value::parse_with_type(&format!("{}ε", "1".repeat(n)), &ty).unwrap();
+        assert_eq!(value.len(), n);
+    }
+
+    #[test]
+    fn integer_conversions() {
+        assert_eq!(Value::u8(0xa5).to_u8().unwrap(), 0xa5);
+        assert_eq!(Value::u16(0xbeef).to_u16().unwrap(), 0xbeef);
+        assert_eq!(Value::u32(0xdeadbeef).to_u32().unwrap(), 0xdeadbeef);
+        assert_eq!(
+            Value::u64(0x0123_4567_89ab_cdef).to_u64().unwrap(),
+            0x0123_4567_89ab_cdef
+        );
+        assert!(Value::u64(7).to_u32().is_err());
+        assert!(Value::prod(Value::u4(1), Value::Unit).to_u8().is_err());
+
+        let mut bytes = [0; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = i as u8 * 7;
+        }
+        let value = Value::u256(&bytes);
+        assert_eq!(value.to_u256().unwrap(), bytes);
+        assert_eq!(value.to_bytes(), bytes.to_vec());
+
+        let word256 = TypeInterner::new().pow2s()[9].clone();
+        assert_eq!(Value::from_bytes(&bytes, &word256).unwrap(), value);
+        assert!(Value::from_bytes(&bytes[1..], &word256).is_err());
+        assert!(Value::from_bytes(&[0; 33], &word256).is_err());
+
+        let ty = FinalType::from_str("2 × (1 + 2^4)").unwrap();
+        let value = Value::from_str("(1, 10b1010) : 2 × (1 + 2^4)").unwrap();
+        assert_eq!(value.to_bytes(), vec![0xe8]);
+        assert_eq!(Value::from_bytes(&[0xe8], &ty).unwrap(), value);
+        assert!(Value::from_bytes(&[0xe9], &ty).is_err());
+    }
+
+    #[test]
+    fn post_order_traversal() {
+        let iden = Arc::new(DagTerm::<(), ()>::Iden);
+        let comp = Arc::new(DagTerm::Comp(Arc::clone(&iden), Arc::clone(&iden)));
+        let take = Arc::new(DagTerm::Take(Arc::clone(&comp)));
+        let case = DagTerm::Case(Arc::clone(&comp), take);
+
+        let nodes = case.post_order_iter().collect::<Vec<_>>();
+        assert_eq!(nodes.len(), 4);
+        let names = nodes
+            .iter()
+            .map(|node| match **node {
+                DagTerm::Iden => "iden",
+                DagTerm::Comp(..) => "comp",
+                DagTerm::Take(..) => "take",
+                DagTerm::Case(..) => "case",
+                _ => "other",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["iden", "comp", "take", "case"]);
+
+        // Count the nodes reachable without taking the right branch of a case
+        struct LeftOnly(usize);
+        impl DagVisitor<(), ()> for LeftOnly {
+            fn visit_child(
+                &mut self,
+                parent: &DagTerm<(), ()>,
+                _: &DagTerm<(), ()>,
+                n: usize,
+            ) -> bool {
+                match *parent {
+                    DagTerm::Case(..) => n != 1,
+                    _ => true,
+                }
+            }
+
+            fn visit_node(&mut self, _: &DagTerm<(), ()>) {
+                self.0 += 1;
+            }
+        }
+        let mut visitor = LeftOnly(0);
+        case.visit(&mut visitor);
+        assert_eq!(visitor.0, 3);
+    }
+
+    #[test]
+    fn dag_across_threads() {
+        let prog = UnTypedProg(vec![Term::<(), ()>::Iden, Term::Unit, Term::Comp(2, 1)]);
+        let dag = DagTerm::from_untyped_prog(prog.clone());
+        let handle = std::thread::spawn(move || (*dag).clone().into_untyped_prog());
+        assert_eq!(handle.join().unwrap(), prog);
+    }
+
+    #[test]
+    fn deep_comp_chain() {
+        let iden = Arc::new(DagTerm::<(), ()>::Iden);
+        let mut dag = Arc::clone(&iden);
+        for _ in 0..1_000_000 {
+            dag = Arc::new(DagTerm::Comp(dag, Arc::clone(&iden)));
+        }
+
+        let prog = (*dag).clone().into_untyped_prog();
+        assert_eq!(prog.0.len(), 1_000_001);
+        assert_eq!(prog.0[1], Term::Comp(1, 1));
+        assert_eq!(prog.0[1_000_000], Term::Comp(1, 1_000_000));
+        drop(dag);
+
+        let dag = DagTerm::from_untyped_prog(prog);
+        assert_eq!(
+            dag.post_order_fold(|_, depths: Vec<usize>| 1 + depths.iter().max().unwrap_or(&0)),
+            1_000_001
+        );
+    }
+}
@@ -0,0 +1,80 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Text Parsing
+//!
+//! Helpers shared by the parsers for the `Display` forms of types
+//! and values
+//!
+
+use crate::Error;
+
+/// Position within a string being parsed, skipping whitespace between
+/// tokens
+pub(crate) struct Cursor<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// Start parsing at the beginning of a string
+    pub(crate) fn new(s: &'a str) -> Cursor<'a> {
+        Cursor { s: s, pos: 0 }
+    }
+
+    /// The unparsed remainder of the string, after any whitespace
+    pub(crate) fn rest(&mut self) -> &'a str {
+        let rest = &self.s[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+        &self.s[self.pos..]
+    }
+
+    /// Whether the whole string has been parsed
+    pub(crate) fn is_empty(&mut self) -> bool {
+        self.rest().is_empty()
+    }
+
+    /// Consume the given token, if it comes next
+    pub(crate) fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consume the given token, which must come next
+    pub(crate) fn expect(&mut self, token: &str) -> Result<(), Error> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", token)))
+        }
+    }
+
+    /// Consume the longest (possibly empty) prefix of characters
+    /// satisfying the predicate
+    pub(crate) fn take_while<F: Fn(char) -> bool>(&mut self, f: F) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|c| !f(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    /// Parse error at the current position
+    pub(crate) fn error(&self, msg: &str) -> Error {
+        Error::TextParse(format!("{} at position {} of `{}`", msg, self.pos, self.s))
+    }
+}
@@ -1,27 +1,20 @@
 //FIXME: Remove this later
 #![allow(dead_code)]
 
-use std::{cell::RefCell, cmp, fmt, mem, rc::Rc, sync::Arc};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::{cmp, fmt, mem, sync::Arc};
 
 use crate::extension;
 use crate::extension::Jet as ExtNode;
 use crate::Error;
 use crate::Term;
 
-use super::term::UnTypedProg;
-
-#[derive(Clone, Debug)]
-enum Type {
-    Unit,
-    Sum(RcVar, RcVar),
-    Product(RcVar, RcVar),
-}
-
-impl Type {
-    fn into_rcvar(self) -> RcVar {
-        Rc::new(RefCell::new(UnificationVar::concrete(self)))
-    }
-}
+use super::term::{DagTerm, UnTypedProg};
+use super::text::Cursor;
+use crate::cmr::Cmr;
+use crate::extension::jets::JetsNode;
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum FinalTypeInner {
@@ -57,24 +50,263 @@ impl FinalType {
             bit_width: a.bit_width + b.bit_width,
         }
     }
+
+    /// If this is the word type `2^(2^i)`, for `i` at most 9, return `i`.
+    /// A word's width is a power of two and each of its products has two
+    /// equal halves, which are compared by pointer when they are shared.
+    pub(crate) fn word_index(&self) -> Option<usize> {
+        if !self.bit_width.is_power_of_two() || self.bit_width > 512 {
+            return None;
+        }
+        let mut ty = self;
+        loop {
+            match ty.ty {
+                FinalTypeInner::Sum(ref a, ref b)
+                    if a.ty == FinalTypeInner::Unit && b.ty == FinalTypeInner::Unit =>
+                {
+                    return Some(self.bit_width.trailing_zeros() as usize);
+                }
+                FinalTypeInner::Product(ref a, ref b)
+                    if a.bit_width == b.bit_width && (Arc::ptr_eq(a, b) || a == b) =>
+                {
+                    ty = a;
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+thread_local! {
+    /// The unit type and the word types `2` through `2^512`, shared by
+    /// every interner on this thread
+    static POW2S: [Arc<FinalType>; 11] = {
+        let unit = Arc::new(FinalType::unit());
+        let mut pow2s = [
+            unit.clone(),
+            unit.clone(),
+            unit.clone(),
+            unit.clone(),
+            unit.clone(),
+            unit.clone(),
+            unit.clone(),
+            unit.clone(),
+            unit.clone(),
+            unit.clone(),
+            unit.clone(),
+        ];
+        pow2s[1] = Arc::new(FinalType::sum(unit.clone(), unit));
+        for i in 2..11 {
+            let prev = pow2s[i - 1].clone();
+            pow2s[i] = Arc::new(FinalType::prod(prev.clone(), prev));
+        }
+        pow2s
+    };
+}
+
+/// Type interner, which ensures that structurally equal types built
+/// through it share a single allocation, and can therefore be compared
+/// by pointer
+#[derive(Clone, Debug)]
+pub struct TypeInterner {
+    /// `pow2s[0]` is the unit type and `pow2s[i]` is `2^(2^(i - 1))`
+    pow2s: [Arc<FinalType>; 11],
+    /// Map from (is-sum, left child, right child) to the interned type.
+    /// Children are keyed by address; since each entry holds references
+    /// to its children, these addresses cannot be reused while it exists.
+    cache: HashMap<(bool, usize, usize), Arc<FinalType>>,
+}
+
+impl Default for TypeInterner {
+    fn default() -> Self {
+        TypeInterner::new()
+    }
+}
+
+impl TypeInterner {
+    /// Create a new interner, which already contains the unit type and
+    /// the word types `2` through `2^512`. These are shared with every
+    /// other interner on the same thread, so creating an interner does
+    /// not allocate.
+    pub fn new() -> TypeInterner {
+        TypeInterner {
+            pow2s: POW2S.with(|pow2s| pow2s.clone()),
+            cache: HashMap::new(),
+        }
+    }
+
+    /// The unit type
+    pub fn unit(&self) -> Arc<FinalType> {
+        self.pow2s[0].clone()
+    }
+
+    /// The word types: `pow2s()[0]` is the unit type, `pow2s()[1]` is
+    /// `2`, and `pow2s()[i]` is `2^(2^(i - 1))`, up to `2^512`
+    pub fn pow2s(&self) -> &[Arc<FinalType>; 11] {
+        &self.pow2s
+    }
+
+    /// The sum type `a + b`. If `a` and `b` were themselves obtained
+    /// from this interner, the result is shared with every other equal
+    /// type obtained from it.
+    pub fn sum(&mut self, a: Arc<FinalType>, b: Arc<FinalType>) -> Arc<FinalType> {
+        self.intern(true, a, b)
+    }
+
+    /// The product type `a × b`. If `a` and `b` were themselves obtained
+    /// from this interner, the result is shared with every other equal
+    /// type obtained from it.
+    pub fn product(&mut self, a: Arc<FinalType>, b: Arc<FinalType>) -> Arc<FinalType> {
+        self.intern(false, a, b)
+    }
+
+    /// Parse a type in the form written by `FinalType`'s `Display`.
+    /// Either `×` or `*` may be used for products, and parentheses may be
+    /// omitted, in which case products bind tighter than sums and both
+    /// associate to the right.
+    pub fn parse(&mut self, s: &str) -> Result<Arc<FinalType>, Error> {
+        let mut cursor = Cursor::new(s);
+        // Without recursion: for every open parenthesis, and for the whole
+        // type, the terms of the sum so far and the factors of the current
+        // product
+        let mut groups = vec![(vec![], vec![])];
+        loop {
+            while cursor.eat("(") {
+                groups.push((vec![], vec![]));
+            }
+            let atom = self.parse_atom(&mut cursor)?;
+            groups.last_mut().unwrap().1.push(atom);
+
+            loop {
+                if cursor.eat("×") || cursor.eat("*") {
+                    break;
+                } else if cursor.eat("+") {
+                    let (terms, factors) = groups.last_mut().unwrap();
+                    let product = self.fold_right(false, factors.drain(..));
+                    terms.push(product);
+                    break;
+                }
+
+                let (mut terms, factors) = groups.pop().unwrap();
+                let product = self.fold_right(false, factors.into_iter());
+                terms.push(product);
+                let group = self.fold_right(true, terms.into_iter());
+                match groups.last_mut() {
+                    Some(parent) => {
+                        cursor.expect(")")?;
+                        parent.1.push(group);
+                    }
+                    None if cursor.is_empty() => return Ok(group),
+                    None => return Err(cursor.error("unexpected trailing characters")),
+                }
+            }
+        }
+    }
+
+    /// Combine a nonempty sequence of sums or products, associating to
+    /// the right
+    fn fold_right<I>(&mut self, is_sum: bool, types: I) -> Arc<FinalType>
+    where
+        I: DoubleEndedIterator<Item = Arc<FinalType>>,
+    {
+        let mut types = types.rev();
+        let last = types.next().unwrap();
+        types.fold(last, |acc, ty| self.intern(is_sum, ty, acc))
+    }
+
+    /// Parse a word or the unit type
+    fn parse_atom(&mut self, cursor: &mut Cursor) -> Result<Arc<FinalType>, Error> {
+        if cursor.eat("2^") {
+            let exp = cursor.take_while(|c| c.is_ascii_digit());
+            match exp.parse::<usize>() {
+                Ok(n) if n.is_power_of_two() && n <= 512 => {
+                    Ok(self.pow2s[n.trailing_zeros() as usize + 1].clone())
+                }
+                _ => Err(cursor.error("expected a power of two, at most 512")),
+            }
+        } else if cursor.eat("2") {
+            Ok(self.pow2s[1].clone())
+        } else if cursor.eat("1") {
+            Ok(self.unit())
+        } else {
+            Err(cursor.error("expected a type"))
+        }
+    }
+
+    /// The word type `a + b` or `a × b`, if `a` and `b` are this
+    /// interner's unit type or one of its words, and the result is a word
+    fn word(&self, is_sum: bool, a: &Arc<FinalType>, b: &Arc<FinalType>) -> Option<Arc<FinalType>> {
+        if !Arc::ptr_eq(a, b) {
+            return None;
+        }
+        let i = match a.bit_width {
+            0 => 0,
+            n if n.is_power_of_two() => n.trailing_zeros() as usize + 1,
+            _ => return None,
+        };
+        if i < 10 && is_sum == (i == 0) && Arc::ptr_eq(a, &self.pow2s[i]) {
+            Some(self.pow2s[i + 1].clone())
+        } else {
+            None
+        }
+    }
+
+    fn intern(&mut self, is_sum: bool, a: Arc<FinalType>, b: Arc<FinalType>) -> Arc<FinalType> {
+        // The words are not in the cache, since they are shared
+        if let Some(word) = self.word(is_sum, &a, &b) {
+            return word;
+        }
+        let key = (
+            is_sum,
+            &*a as *const FinalType as usize,
+            &*b as *const FinalType as usize,
+        );
+        self.cache
+            .entry(key)
+            .or_insert_with(|| {
+                if is_sum {
+                    Arc::new(FinalType::sum(a, b))
+                } else {
+                    Arc::new(FinalType::prod(a, b))
+                }
+            })
+            .clone()
+    }
 }
 
-pub(crate) fn pow2_types() -> [Arc<FinalType>; 11] {
-    let word0 = Arc::new(FinalType::unit());
-    let word1 = Arc::new(FinalType::sum(Arc::clone(&word0), Arc::clone(&word0)));
-    let word2 = Arc::new(FinalType::prod(Arc::clone(&word1), Arc::clone(&word1)));
-    let word4 = Arc::new(FinalType::prod(Arc::clone(&word2), Arc::clone(&word2)));
-    let word8 = Arc::new(FinalType::prod(Arc::clone(&word4), Arc::clone(&word4)));
-    let word16 = Arc::new(FinalType::prod(Arc::clone(&word8), Arc::clone(&word8)));
-    let word32 = Arc::new(FinalType::prod(Arc::clone(&word16), Arc::clone(&word16)));
-    let word64 = Arc::new(FinalType::prod(Arc::clone(&word32), Arc::clone(&word32)));
-    let word128 = Arc::new(FinalType::prod(Arc::clone(&word64), Arc::clone(&word64)));
-    let word256 = Arc::new(FinalType::prod(Arc::clone(&word128), Arc::clone(&word128)));
-    let word512 = Arc::new(FinalType::prod(Arc::clone(&word256), Arc::clone(&word256)));
+impl Drop for FinalType {
+    /// Drop the type without recursing, by detaching every child which
+    /// would be freed along with its parent and dropping it separately
+    fn drop(&mut self) {
+        fn detach(ty: &mut FinalType, stack: &mut Vec<FinalType>) {
+            if let FinalTypeInner::Sum(ref mut a, ref mut b)
+            | FinalTypeInner::Product(ref mut a, ref mut b) = ty.ty
+            {
+                for child in [a, b].iter_mut() {
+                    if let Some(child) = Arc::get_mut(child) {
+                        if child.ty != FinalTypeInner::Unit {
+                            stack.push(mem::replace(child, FinalType::unit()));
+                        }
+                    }
+                }
+            }
+        }
 
-    [
-        word0, word1, word2, word4, word8, word16, word32, word64, word128, word256, word512,
-    ]
+        let mut stack = vec![];
+        detach(self, &mut stack);
+        while let Some(mut ty) = stack.pop() {
+            detach(&mut ty, &mut stack);
+        }
+    }
+}
+
+impl FromStr for FinalType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<FinalType, Error> {
+        let ty = TypeInterner::new().parse(s)?;
+        Ok((*ty).clone())
+    }
 }
 
 impl fmt::Display for FinalType {
@@ -101,6 +333,7 @@ impl fmt::Display for FinalType {
                         "2^32" => write!(f, "2^64"),
                         "2^64" => write!(f, "2^128"),
                         "2^128" => write!(f, "2^256"),
+                        "2^256" => write!(f, "2^512"),
                         _ => write!(f, "({} × {})", a, b),
                     }
                 } else {
@@ -116,255 +349,654 @@ impl FinalType {
         self.bit_width
     }
 
-    fn from_var(var: RcVar) -> Result<Arc<FinalType>, Error> {
-        let var = find_root(var);
-        let mut var_borr = var.borrow_mut();
-
-        let existing_type = match var_borr.var {
-            Variable::Free => Type::Unit,
-            Variable::Bound(ref ty, ref mut occurs_check) => {
-                if *occurs_check {
-                    return Err(Error::OccursCheck);
+    /// The name of this type in the prefix notation of
+    /// `extension::TypeName`, using the shortest name for each word
+    pub fn to_type_name(&self) -> String {
+        let mut ret = String::new();
+        // Prefix order, without recursion: a node is written before its
+        // children, so the right child is pushed first
+        let mut stack = vec![self];
+        while let Some(ty) = stack.pop() {
+            match (ty.word_index(), &ty.ty) {
+                (Some(i), _) => ret.push(extension::WORD_LETTERS[i] as char),
+                (None, FinalTypeInner::Unit) => ret.push('1'),
+                (None, FinalTypeInner::Sum(a, b)) => {
+                    ret.push('+');
+                    stack.push(b);
+                    stack.push(a);
+                }
+                (None, FinalTypeInner::Product(a, b)) => {
+                    ret.push('*');
+                    stack.push(b);
+                    stack.push(a);
                 }
-                *occurs_check = true;
-                ty.clone()
             }
-            Variable::EqualTo(..) => unreachable!(),
-            Variable::Finalized(ref done) => return Ok(done.clone()),
-        };
+        }
+        ret
+    }
+}
 
-        let (sub1, sub2) = match existing_type {
-            Type::Unit => {
-                let ret = Arc::new(FinalType {
-                    ty: FinalTypeInner::Unit,
-                    bit_width: 0,
-                });
-                var_borr.var = Variable::Finalized(ret.clone());
-                return Ok(ret);
-            }
-            Type::Sum(ref sub1, ref sub2) => (sub1.clone(), sub2.clone()),
-            Type::Product(ref sub1, ref sub2) => (sub1.clone(), sub2.clone()),
-        };
-        drop(var_borr);
-
-        let sub1 = find_root(sub1);
-        let sub2 = find_root(sub2);
-
-        let sub1_borr = sub1.borrow_mut();
-        let final1 = match sub1_borr.var {
-            Variable::Free => {
-                drop(sub1_borr);
-                Arc::new(FinalType {
-                    ty: FinalTypeInner::Unit,
-                    bit_width: 0,
-                })
-            }
-            Variable::Bound(..) => {
-                drop(sub1_borr);
-                FinalType::from_var(sub1.clone())?
-            }
-            Variable::EqualTo(..) => unreachable!(),
-            Variable::Finalized(ref f1) => {
-                let ret = f1.clone();
-                drop(sub1_borr);
-                ret
-            }
-        };
-        // drop(sub1_borr);
-        let sub2_borr = sub2.borrow_mut();
-        let final2 = match sub2_borr.var {
-            Variable::Free => {
-                drop(sub2_borr);
-                Arc::new(FinalType {
-                    ty: FinalTypeInner::Unit,
-                    bit_width: 0,
-                })
-            }
-            Variable::Bound(..) => {
-                drop(sub2_borr);
-                FinalType::from_var(sub2.clone())?
-            }
-            Variable::EqualTo(..) => unreachable!(),
-            Variable::Finalized(ref f2) => {
-                let ret = f2.clone();
-                drop(sub2_borr);
-                ret
+/// A single step in the path from a node's source or target type to
+/// some type variable within it
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PathStep {
+    /// The source type of the node
+    Source,
+    /// The target type of the node
+    Target,
+    /// Left child of a sum type
+    SumL,
+    /// Right child of a sum type
+    SumR,
+    /// Left child of a product type
+    ProdL,
+    /// Right child of a product type
+    ProdR,
+}
+
+impl fmt::Display for PathStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            PathStep::Source => "source",
+            PathStep::Target => "target",
+            PathStep::SumL => "sum.left",
+            PathStep::SumR => "sum.right",
+            PathStep::ProdL => "product.left",
+            PathStep::ProdR => "product.right",
+        })
+    }
+}
+
+/// Two types which could not be unified while type-checking a node
+#[derive(Clone, Debug)]
+pub struct TypeMismatch {
+    /// Index of the node whose constraints could not be satisfied
+    pub index: usize,
+    /// Label of the node at that index, as given by `Term::label`
+    pub node: String,
+    /// The two conflicting types. Parts of these types which were not
+    /// (yet) constrained are shown as `1`
+    pub types: (Arc<FinalType>, Arc<FinalType>),
+}
+
+impl fmt::Display for TypeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "node {} ({}): cannot unify {} with {}",
+            self.index, self.node, self.types.0, self.types.1,
+        )
+    }
+}
+
+/// A type which was inferred to contain itself
+#[derive(Clone, Debug)]
+pub struct OccursCheckFailure {
+    /// Index of the node whose type is recursive
+    pub index: usize,
+    /// Label of the node at that index, as given by `Term::label`
+    pub node: String,
+    /// Path from the node's source or target type to the first variable
+    /// which (indirectly) contains itself
+    pub path: Vec<PathStep>,
+}
+
+impl fmt::Display for OccursCheckFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "node {} ({}): infinite type at ", self.index, self.node)?;
+        for (n, step) in self.path.iter().enumerate() {
+            if n > 0 {
+                f.write_str(" → ")?;
             }
-        };
-        // drop(sub2_borr);
-
-        let ret = match existing_type {
-            Type::Unit => unreachable!(),
-            Type::Sum(..) => Arc::new(FinalType {
-                bit_width: 1 + cmp::max(final1.bit_width, final2.bit_width),
-                ty: FinalTypeInner::Sum(final1, final2),
-            }),
-            Type::Product(..) => Arc::new(FinalType {
-                bit_width: final1.bit_width + final2.bit_width,
-                ty: FinalTypeInner::Product(final1, final2),
-            }),
-        };
-        var.borrow_mut().var = Variable::Finalized(ret.clone());
-        Ok(ret)
+            fmt::Display::fmt(step, f)?;
+        }
+        Ok(())
     }
 }
 
-#[derive(Clone)]
-enum Variable {
-    /// Free variable
-    Free,
-    /// Bound to some type (which may itself contain other free variables,
-    /// or not). Contains a boolean which is only used by the finalization
-    /// function, for the occurs-check
-    Bound(Type, bool),
-    /// Equal to another variable (the included `RcVar` is the "parent"
-    /// pointer in union-find terms)
-    EqualTo(RcVar),
-    /// Complete type has been set in place
-    Finalized(Arc<FinalType>),
+/// Shape of an expected type, some parts of which may be left open
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TypePattern {
+    /// Any type. Within a single `TypeConstraint`, every occurrence of
+    /// the same index must be the same type.
+    Var(usize),
+    /// The unit type
+    Unit,
+    /// A sum of two types
+    Sum(Box<TypePattern>, Box<TypePattern>),
+    /// A product of two types
+    Product(Box<TypePattern>, Box<TypePattern>),
+    /// A complete type
+    Final(Arc<FinalType>),
+}
+
+impl From<Arc<FinalType>> for TypePattern {
+    fn from(ty: Arc<FinalType>) -> TypePattern {
+        TypePattern::Final(ty)
+    }
 }
 
-impl fmt::Debug for Variable {
+impl fmt::Display for TypePattern {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Variable::Free => f.write_str("?"),
-            Variable::Bound(ref ty, b) => write!(f, "[{:?}/{}]", ty, b),
-            Variable::EqualTo(ref other) => write!(f, "={:?}", other),
-            Variable::Finalized(..) => unimplemented!(),
+            TypePattern::Var(n) if n < 26 => write!(f, "{}", (b'A' + n as u8) as char),
+            TypePattern::Var(n) => write!(f, "T{}", n),
+            TypePattern::Unit => f.write_str("1"),
+            TypePattern::Sum(ref a, ref b) => write!(f, "({} + {})", a, b),
+            TypePattern::Product(ref a, ref b) => write!(f, "({} × {})", a, b),
+            TypePattern::Final(ref ty) => fmt::Display::fmt(ty, f),
         }
     }
 }
 
-struct UnificationVar {
-    var: Variable,
-    rank: usize,
+/// Expected source and target types of a node, which are unified with
+/// the inferred types before unconstrained variables are set to `Unit`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TypeConstraint {
+    /// Index of the constrained node, or `None` for the root
+    pub index: Option<usize>,
+    /// Expected source type
+    pub source: TypePattern,
+    /// Expected target type
+    pub target: TypePattern,
 }
 
-impl fmt::Debug for UnificationVar {
+impl TypeConstraint {
+    /// Constrain the type of the program root
+    pub fn root(source: TypePattern, target: TypePattern) -> TypeConstraint {
+        TypeConstraint {
+            index: None,
+            source: source,
+            target: target,
+        }
+    }
+
+    /// Constrain the type of the node at the given index
+    pub fn node(index: usize, source: TypePattern, target: TypePattern) -> TypeConstraint {
+        TypeConstraint {
+            index: Some(index),
+            source: source,
+            target: target,
+        }
+    }
+
+    /// The type `1 → 1` required of a top-level spending program
+    pub fn spending_program() -> TypeConstraint {
+        TypeConstraint::root(TypePattern::Unit, TypePattern::Unit)
+    }
+
+    /// The type `2^256 × A → B × C` required of the first child of a
+    /// `disconnect` node
+    pub fn disconnect_fragment() -> TypeConstraint {
+        let word256 = TypeInterner::new().pow2s()[9].clone();
+        TypeConstraint::root(
+            TypePattern::Product(
+                Box::new(TypePattern::Final(word256)),
+                Box::new(TypePattern::Var(0)),
+            ),
+            TypePattern::Product(Box::new(TypePattern::Var(1)), Box::new(TypePattern::Var(2))),
+        )
+    }
+}
+
+/// A node whose inferred type does not match the constraint given for it
+#[derive(Clone, Debug)]
+pub struct ConstraintViolation {
+    /// Index of the constrained node
+    pub index: usize,
+    /// Label of the node at that index, as given by `Term::label`
+    pub node: String,
+    /// Whether the source or the target type was in violation
+    pub step: PathStep,
+    /// The expected type
+    pub expected: TypePattern,
+    /// The type inferred from the program alone. Parts of this type which
+    /// were not constrained are shown as `1`
+    pub found: Arc<FinalType>,
+}
+
+impl fmt::Display for ConstraintViolation {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "[{}]{:?}", self.rank, self.var)
+        write!(
+            f,
+            "node {} ({}): {} type {} does not match {}",
+            self.index, self.node, self.step, self.found, self.expected,
+        )
     }
 }
 
-type RcVar = Rc<RefCell<UnificationVar>>;
+/// Index of a unification variable within an `Arena`
+type Var = usize;
 
-impl UnificationVar {
-    fn free() -> UnificationVar {
-        UnificationVar {
+/// Type constructor, whose children are unification variables
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Type {
+    Unit,
+    Sum(Var, Var),
+    Product(Var, Var),
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Variable {
+    /// Free variable
+    Free,
+    /// Bound to some type (which may itself contain other free variables,
+    /// or not)
+    Bound(Type),
+    /// Equal to another variable (the included `Var` is the "parent"
+    /// pointer in union-find terms)
+    EqualTo(Var),
+}
+
+#[derive(Copy, Clone, Debug)]
+struct UnificationVar {
+    var: Variable,
+    rank: usize,
+}
+
+/// Pair of types which could not be unified
+struct Mismatch(Type, Type);
+
+/// Union-find structure holding every unification variable used while
+/// type-checking a program. Variables are referred to by their index,
+/// so that no reference counting or interior mutability is needed.
+#[derive(Default, Debug)]
+struct Arena {
+    vars: Vec<UnificationVar>,
+    /// Scratch space for `unify`, kept here to avoid reallocating it
+    /// on every call
+    pending: Vec<(Var, Var)>,
+    /// If enabled, the previous value of every overwritten variable, so
+    /// that changes since a `mark` can be undone
+    trail: Option<Vec<(Var, UnificationVar)>>,
+}
+
+impl Arena {
+    /// Allocate a new free variable
+    fn free(&mut self) -> Var {
+        self.vars.push(UnificationVar {
             var: Variable::Free,
             rank: 0,
-        }
+        });
+        self.vars.len() - 1
     }
 
-    fn concrete(ty: Type) -> UnificationVar {
-        UnificationVar {
-            var: Variable::Bound(ty, false),
+    /// Allocate a new variable bound to the given type
+    fn concrete(&mut self, ty: Type) -> Var {
+        self.vars.push(UnificationVar {
+            var: Variable::Bound(ty),
             rank: 0,
+        });
+        self.vars.len() - 1
+    }
+
+    /// Access a variable in order to modify it, recording its previous
+    /// value if the trail is enabled
+    fn update(&mut self, var: Var) -> &mut UnificationVar {
+        if let Some(ref mut trail) = self.trail {
+            trail.push((var, self.vars[var]));
         }
+        &mut self.vars[var]
     }
-}
 
-fn bind(rcvar: &RcVar, ty: Type) -> Result<(), Error> {
-    // Cloning a `Variable` is cheap, as the nontrivial variants merely
-    // hold `Rc`s
-    let self_var = rcvar.borrow().var.clone();
-    match self_var {
-        Variable::Free => {
-            rcvar.borrow_mut().var = Variable::Bound(ty, false);
-            Ok(())
-        }
-        Variable::EqualTo(..) => unreachable!(
-            "Tried to bind unification variable which was not \
-             the representative of its equivalence class"
-        ),
-        Variable::Finalized(..) => unreachable!(),
-        Variable::Bound(self_ty, _) => match (self_ty, ty) {
-            (Type::Unit, Type::Unit) => Ok(()),
-            (Type::Sum(al1, al2), Type::Sum(be1, be2))
-            | (Type::Product(al1, al2), Type::Product(be1, be2)) => {
-                unify(al1, be1)?;
-                unify(al2, be2)
-            }
-            // FIXME output a sane error
-            _ => {
-                //            (a, b) => {
-                /*
-                let self_s = match a {
-                    Type::Unit => "unit",
-                    Type::Sum(..) => "sum",
-                    Type::Product(..) => "prod",
-                };
-                let b_s = match b {
-                    Type::Unit => "unit",
-                    Type::Sum(..) => "sum",
-                    Type::Product(..) => "prod",
-                };
-                */
-                Err(Error::TypeCheck)
-            }
-        },
-    }
-}
-
-fn find_root(mut node: RcVar) -> RcVar {
-    loop {
-        // Double-assignment needed for pre-NLL borrowck reasons
-        let parent = match node.borrow().var {
-            Variable::EqualTo(ref parent) => Some(parent.clone()),
-            _ => None,
-        };
-        let parent = match parent {
-            Some(x) => x,
-            _ => break node,
-        };
+    /// Current state of the arena, to which `undo` can return
+    fn mark(&self) -> (usize, usize) {
+        let trail_len = self.trail.as_ref().map_or(0, Vec::len);
+        (self.vars.len(), trail_len)
+    }
+
+    /// Undo all changes made since the given `mark`. The trail must have
+    /// been enabled, and not cleared, since then.
+    fn undo(&mut self, (n_vars, trail_len): (usize, usize)) {
+        let trail = self.trail.as_mut().expect("trail is enabled");
+        while trail.len() > trail_len {
+            let (var, old) = trail.pop().unwrap();
+            self.vars[var] = old;
+        }
+        self.vars.truncate(n_vars);
+    }
 
-        // Extra scope for pre-NLL borrowck reasons
-        {
-            let parent_borr = parent.borrow();
-            if let Variable::EqualTo(ref grandparent) = parent_borr.var {
-                node.borrow_mut().var = Variable::EqualTo(grandparent.clone());
+    /// Find the representative of a variable's equivalence class,
+    /// compressing the path to it along the way
+    fn find(&mut self, var: Var) -> Var {
+        let mut root = var;
+        while let Variable::EqualTo(parent) = self.vars[root].var {
+            root = parent;
+        }
+        let mut node = var;
+        while let Variable::EqualTo(parent) = self.vars[node].var {
+            if parent != root {
+                self.update(node).var = Variable::EqualTo(root);
             }
+            node = parent;
         }
-        node = parent;
+        root
     }
-}
 
-fn unify(mut alpha: RcVar, mut beta: RcVar) -> Result<(), Error> {
-    alpha = find_root(alpha);
-    beta = find_root(beta);
+    /// Find the representative of a variable's equivalence class,
+    /// without modifying the arena
+    fn find_imm(&self, mut var: Var) -> Var {
+        while let Variable::EqualTo(parent) = self.vars[var].var {
+            var = parent;
+        }
+        var
+    }
 
-    // Already unified, done
-    if Rc::ptr_eq(&alpha, &beta) {
-        return Ok(());
+    /// Whether the type of a variable contains itself
+    fn is_cyclic(&self, var: Var) -> bool {
+        // Variables on the current path map to `true`, and those whose
+        // types have been fully explored to `false`
+        let root = self.find_imm(var);
+        let mut on_path = HashMap::new();
+        on_path.insert(root, true);
+        let mut stack = vec![(root, 0)];
+        while let Some(&mut (var, ref mut n_done)) = stack.last_mut() {
+            let child = match self.vars[var].var {
+                Variable::Bound(Type::Sum(a, b)) | Variable::Bound(Type::Product(a, b))
+                    if *n_done < 2 =>
+                {
+                    *n_done += 1;
+                    if *n_done == 1 {
+                        a
+                    } else {
+                        b
+                    }
+                }
+                _ => {
+                    on_path.insert(var, false);
+                    stack.pop();
+                    continue;
+                }
+            };
+            let child = self.find_imm(child);
+            match on_path.get(&child) {
+                Some(true) => return true,
+                Some(false) => {}
+                None => {
+                    on_path.insert(child, true);
+                    stack.push((child, 0));
+                }
+            }
+        }
+        false
     }
 
-    // Adjust ranks for union-find path halving
-    let rank_ord = { alpha.borrow().rank.cmp(&beta.borrow().rank) };
-    match rank_ord {
-        cmp::Ordering::Less => mem::swap(&mut alpha, &mut beta),
-        cmp::Ordering::Equal => alpha.borrow_mut().rank += 1,
-        _ => {}
+    /// Constrain a variable to be equal to the given type
+    fn bind(&mut self, var: Var, ty: Type) -> Result<(), Mismatch> {
+        let root = self.find(var);
+        if let Variable::Free = self.vars[root].var {
+            self.update(root).var = Variable::Bound(ty);
+            return Ok(());
+        }
+        let ty_var = self.concrete(ty);
+        self.unify(root, ty_var)
     }
 
-    // Do the unification
-    let be_var = {
-        let mut be_borr = beta.borrow_mut();
-        mem::replace(&mut be_borr.var, Variable::EqualTo(alpha.clone()))
-    };
-    match be_var {
-        Variable::Free => {} // nothing to do
-        Variable::Bound(be_type, _) => bind(&alpha, be_type)?,
-        Variable::EqualTo(..) => unreachable!(),
-        Variable::Finalized(..) => unreachable!(),
+    /// Unify two variables, using union-by-rank
+    fn unify(&mut self, alpha: Var, beta: Var) -> Result<(), Mismatch> {
+        self.pending.clear();
+        self.pending.push((alpha, beta));
+        while let Some((alpha, beta)) = self.pending.pop() {
+            let alpha = self.find(alpha);
+            let beta = self.find(beta);
+            // Already unified, done
+            if alpha == beta {
+                continue;
+            }
+
+            let (al_var, be_var) = (self.vars[alpha].var, self.vars[beta].var);
+            // Make the higher-ranked variable the representative
+            let (root, child) = match self.vars[alpha].rank.cmp(&self.vars[beta].rank) {
+                cmp::Ordering::Less => (beta, alpha),
+                cmp::Ordering::Equal => {
+                    self.update(alpha).rank += 1;
+                    (alpha, beta)
+                }
+                cmp::Ordering::Greater => (alpha, beta),
+            };
+            self.update(child).var = Variable::EqualTo(root);
+
+            match (al_var, be_var) {
+                (Variable::Free, Variable::Free) => {}
+                (Variable::Bound(ty), Variable::Free) | (Variable::Free, Variable::Bound(ty)) => {
+                    self.update(root).var = Variable::Bound(ty);
+                }
+                (Variable::Bound(al_ty), Variable::Bound(be_ty)) => {
+                    self.update(root).var = Variable::Bound(al_ty);
+                    match (al_ty, be_ty) {
+                        (Type::Unit, Type::Unit) => {}
+                        (Type::Sum(al1, al2), Type::Sum(be1, be2))
+                        | (Type::Product(al1, al2), Type::Product(be1, be2)) => {
+                            self.pending.push((al2, be2));
+                            self.pending.push((al1, be1));
+                        }
+                        (al_ty, be_ty) => return Err(Mismatch(al_ty, be_ty)),
+                    }
+                }
+                (Variable::EqualTo(..), _) | (_, Variable::EqualTo(..)) => unreachable!(),
+            }
+        }
+        Ok(())
     }
 
-    Ok(())
+    /// Render the current state of a type, without finalizing it. Free
+    /// variables, and variables which recursively contain themselves, are
+    /// shown as `Unit`. Used only for error reporting.
+    fn snapshot(&self, ty: Type) -> Arc<FinalType> {
+        fn snapshot_var(arena: &Arena, var: Var, seen: &mut Vec<Var>) -> Arc<FinalType> {
+            let var = arena.find_imm(var);
+            if seen.contains(&var) {
+                return Arc::new(FinalType::unit());
+            }
+            match arena.vars[var].var {
+                Variable::Bound(ty) => {
+                    seen.push(var);
+                    let ret = snapshot_ty(arena, ty, seen);
+                    seen.pop();
+                    ret
+                }
+                Variable::Free | Variable::EqualTo(..) => Arc::new(FinalType::unit()),
+            }
+        }
+
+        fn snapshot_ty(arena: &Arena, ty: Type, seen: &mut Vec<Var>) -> Arc<FinalType> {
+            match ty {
+                Type::Unit => Arc::new(FinalType::unit()),
+                Type::Sum(a, b) => Arc::new(FinalType::sum(
+                    snapshot_var(arena, a, seen),
+                    snapshot_var(arena, b, seen),
+                )),
+                Type::Product(a, b) => Arc::new(FinalType::prod(
+                    snapshot_var(arena, a, seen),
+                    snapshot_var(arena, b, seen),
+                )),
+            }
+        }
+
+        snapshot_ty(self, ty, &mut vec![])
+    }
+
+    /// Render the current state of a variable, as with `snapshot`
+    fn snapshot_var(&self, var: Var) -> Arc<FinalType> {
+        match self.vars[self.find_imm(var)].var {
+            Variable::Bound(ty) => self.snapshot(ty),
+            Variable::Free | Variable::EqualTo(..) => Arc::new(FinalType::unit()),
+        }
+    }
+
+    /// Allocate a variable of the given shape, with `names` mapping the
+    /// pattern's variables to those already allocated for it
+    fn pattern(&mut self, pat: &TypePattern, names: &mut HashMap<usize, Var>) -> Var {
+        match *pat {
+            TypePattern::Var(n) => {
+                if let Some(var) = names.get(&n) {
+                    return *var;
+                }
+                let var = self.free();
+                names.insert(n, var);
+                var
+            }
+            TypePattern::Unit => self.concrete(Type::Unit),
+            TypePattern::Sum(ref a, ref b) => {
+                let (a, b) = (self.pattern(a, names), self.pattern(b, names));
+                self.concrete(Type::Sum(a, b))
+            }
+            TypePattern::Product(ref a, ref b) => {
+                let (a, b) = (self.pattern(a, names), self.pattern(b, names));
+                self.concrete(Type::Product(a, b))
+            }
+            TypePattern::Final(ref ty) => self.final_type(ty, &mut HashMap::new()),
+        }
+    }
+
+    /// Allocate a variable equal to a complete type, reusing variables
+    /// for subtypes which share an allocation
+    fn final_type(&mut self, ty: &Arc<FinalType>, memo: &mut HashMap<usize, Var>) -> Var {
+        let key = &**ty as *const FinalType as usize;
+        if let Some(var) = memo.get(&key) {
+            return *var;
+        }
+        let ty = match ty.ty {
+            FinalTypeInner::Unit => Type::Unit,
+            FinalTypeInner::Sum(ref a, ref b) => {
+                Type::Sum(self.final_type(a, memo), self.final_type(b, memo))
+            }
+            FinalTypeInner::Product(ref a, ref b) => {
+                Type::Product(self.final_type(a, memo), self.final_type(b, memo))
+            }
+        };
+        let var = self.concrete(ty);
+        memo.insert(key, var);
+        var
+    }
 }
 
+/// Finalization state of a single equivalence class
 #[derive(Clone)]
+enum FinalState {
+    /// Not yet visited
+    Pending,
+    /// Currently being finalized; encountering it again means that the
+    /// type contains itself
+    InProgress,
+    /// Complete type has been computed
+    Done(Arc<FinalType>),
+}
+
+/// Converts unification variables into hash-consed final types, so that
+/// structurally equal types share a single allocation
+struct Finalizer<'a> {
+    states: Vec<FinalState>,
+    /// Whether each finalized equivalence class contains a free variable,
+    /// which was set to `Unit`
+    defaulted: Vec<bool>,
+    interner: &'a mut TypeInterner,
+    /// Scratch space for `finalize`: explicit stack of (variable, number
+    /// of children finalized so far), so that deeply nested types cannot
+    /// overflow the call stack
+    stack: Vec<(Var, usize)>,
+    /// Scratch space for `finalize`: finalized children awaiting their
+    /// parent
+    results: Vec<Arc<FinalType>>,
+}
+
+impl<'a> Finalizer<'a> {
+    fn new(arena: &Arena, interner: &'a mut TypeInterner) -> Finalizer<'a> {
+        Finalizer {
+            states: vec![FinalState::Pending; arena.vars.len()],
+            defaulted: vec![false; arena.vars.len()],
+            interner: interner,
+            stack: vec![],
+            results: vec![],
+        }
+    }
+
+    /// Convert a unification variable into a final type, setting all
+    /// free variables to `Unit`. On failure of the occurs check, returns
+    /// the path from `var` to the recursive variable.
+    fn finalize(&mut self, arena: &mut Arena, var: Var) -> Result<Arc<FinalType>, Vec<PathStep>> {
+        let root = arena.find(var);
+        if let FinalState::Done(ref done) = self.states[root] {
+            return Ok(done.clone());
+        }
+
+        self.stack.clear();
+        self.results.clear();
+        self.stack.push((root, 0));
+        let mut path = vec![];
+
+        while let Some(&(var, n_done)) = self.stack.last() {
+            if n_done == 0 {
+                match self.states[var] {
+                    FinalState::Done(ref done) => {
+                        self.results.push(done.clone());
+                        self.stack.pop();
+                        path.pop();
+                        continue;
+                    }
+                    FinalState::InProgress => return Err(path),
+                    FinalState::Pending => self.states[var] = FinalState::InProgress,
+                }
+            }
+
+            let ret = match arena.vars[var].var {
+                Variable::Free => {
+                    self.defaulted[var] = true;
+                    self.interner.unit()
+                }
+                Variable::Bound(Type::Unit) => self.interner.unit(),
+                Variable::Bound(Type::Sum(a, b)) | Variable::Bound(Type::Product(a, b))
+                    if n_done < 2 =>
+                {
+                    let is_sum = match arena.vars[var].var {
+                        Variable::Bound(Type::Sum(..)) => true,
+                        _ => false,
+                    };
+                    let (child, step) = match (n_done, is_sum) {
+                        (0, true) => (a, PathStep::SumL),
+                        (0, false) => (a, PathStep::ProdL),
+                        (_, true) => (b, PathStep::SumR),
+                        (_, false) => (b, PathStep::ProdR),
+                    };
+                    self.stack.last_mut().unwrap().1 += 1;
+                    path.push(step);
+                    self.stack.push((arena.find(child), 0));
+                    continue;
+                }
+                Variable::Bound(Type::Sum(a, b)) => {
+                    self.defaulted[var] =
+                        self.defaulted[arena.find(a)] || self.defaulted[arena.find(b)];
+                    let final2 = self.results.pop().unwrap();
+                    let final1 = self.results.pop().unwrap();
+                    self.interner.sum(final1, final2)
+                }
+                Variable::Bound(Type::Product(a, b)) => {
+                    self.defaulted[var] =
+                        self.defaulted[arena.find(a)] || self.defaulted[arena.find(b)];
+                    let final2 = self.results.pop().unwrap();
+                    let final1 = self.results.pop().unwrap();
+                    self.interner.product(final1, final2)
+                }
+                Variable::EqualTo(..) => unreachable!(),
+            };
+            self.states[var] = FinalState::Done(ret.clone());
+            self.results.push(ret);
+            self.stack.pop();
+            path.pop();
+        }
+        Ok(self.results.pop().unwrap())
+    }
+
+    /// Whether a finalized variable contained any free variables
+    fn is_defaulted(&self, arena: &Arena, var: Var) -> bool {
+        self.defaulted[arena.find_imm(var)]
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
 struct UnificationArrow {
-    source: Rc<RefCell<UnificationVar>>,
-    target: Rc<RefCell<UnificationVar>>,
+    source: Var,
+    target: Var,
 }
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
@@ -372,213 +1004,1047 @@ pub struct TypedNode<Witness, Ext> {
     pub node: Term<Witness, Ext>,
     pub source_ty: Arc<FinalType>,
     pub target_ty: Arc<FinalType>,
+    /// Whether some part of the target type was left unconstrained by
+    /// the program, and therefore set to `Unit`
+    pub target_defaulted: bool,
 }
 
-/// Convenience method for converting a type for an extension
-/// field from a name to an actual `Type`
-fn type_from_name<I: Iterator<Item = u8>>(n: &mut I, pow2s: &[RcVar]) -> Type {
-    match n.next() {
-        Some(b'1') => Type::Unit,
-        Some(b'2') => {
-            let unit = Type::Unit.into_rcvar();
-            Type::Sum(unit.clone(), unit)
+/// Indices of the witness nodes whose types were not fully determined
+/// by the program. Any part of such a type was set to `Unit`, so it will
+/// consume fewer witness bits than the user may have intended.
+pub fn defaulted_witnesses<Witness, Ext>(nodes: &[TypedNode<Witness, Ext>]) -> Vec<usize> {
+    nodes
+        .iter()
+        .enumerate()
+        .filter(|&(_, node)| match node.node {
+            Term::Witness(..) => node.target_defaulted,
+            _ => false,
+        })
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Add the type constraints of a single node to the unification state
+fn unify_node<Witness, Ext: extension::Jet>(
+    arena: &mut Arena,
+    idx: usize,
+    node: UnificationArrow,
+    arrows: &[UnificationArrow],
+    vec_nodes: &[Term<Witness, Ext>],
+    word256: Var,
+    jet_arrow: Option<UnificationArrow>,
+) -> Result<(), Mismatch> {
+    match vec_nodes[idx] {
+        Term::Iden => arena.unify(node.source, node.target)?,
+        Term::Unit => arena.bind(node.target, Type::Unit)?,
+        Term::InjL(i) => {
+            let i = idx - i;
+            arena.unify(node.source, arrows[i].source)?;
+            let target_type = Type::Sum(arrows[i].target, arena.free());
+            arena.bind(node.target, target_type)?;
         }
-        Some(b'i') => Type::Product(pow2s[4].clone(), pow2s[4].clone()),
-        Some(b'l') => Type::Product(pow2s[5].clone(), pow2s[5].clone()),
-        Some(b'h') => Type::Product(pow2s[7].clone(), pow2s[7].clone()),
-        Some(b'+') => Type::Sum(
-            type_from_name(&mut *n, pow2s).into_rcvar(),
-            type_from_name(&mut *n, pow2s).into_rcvar(),
-        ),
-        Some(b'*') => Type::Product(
-            type_from_name(&mut *n, pow2s).into_rcvar(),
-            type_from_name(&mut *n, pow2s).into_rcvar(),
-        ),
-        Some(x) => panic!("Do not understand byte {} in type name", x as char),
-        None => panic!("unexpected end of string parsing type"),
-    }
+        Term::InjR(i) => {
+            let i = idx - i;
+            arena.unify(node.source, arrows[i].source)?;
+            let target_type = Type::Sum(arena.free(), arrows[i].target);
+            arena.bind(node.target, target_type)?;
+        }
+        Term::Take(i) => {
+            let i = idx - i;
+            arena.unify(node.target, arrows[i].target)?;
+            let source_type = Type::Product(arrows[i].source, arena.free());
+            arena.bind(node.source, source_type)?;
+        }
+        Term::Drop(i) => {
+            let i = idx - i;
+            arena.unify(node.target, arrows[i].target)?;
+            let source_type = Type::Product(arena.free(), arrows[i].source);
+            arena.bind(node.source, source_type)?;
+        }
+        Term::Comp(i, j) => {
+            let (i, j) = (idx - i, idx - j);
+            arena.unify(node.source, arrows[i].source)?;
+            arena.unify(arrows[i].target, arrows[j].source)?;
+            arena.unify(node.target, arrows[j].target)?;
+        }
+        Term::Case(i, j) => {
+            let (i, j) = (idx - i, idx - j);
+            let var1 = arena.free();
+            let var2 = arena.free();
+            let var3 = arena.free();
+
+            let sum12_var = arena.concrete(Type::Sum(var1, var2));
+            arena.bind(node.source, Type::Product(sum12_var, var3))?;
+            if let Term::Hidden(..) = vec_nodes[i] {
+            } else {
+                arena.bind(arrows[i].source, Type::Product(var1, var3))?;
+                arena.unify(node.target, arrows[i].target)?;
+            }
+            if let Term::Hidden(..) = vec_nodes[j] {
+            } else {
+                arena.bind(arrows[j].source, Type::Product(var2, var3))?;
+                arena.unify(node.target, arrows[j].target)?;
+            }
+        }
+        Term::Pair(i, j) => {
+            let (i, j) = (idx - i, idx - j);
+            arena.unify(node.source, arrows[i].source)?;
+            arena.unify(node.source, arrows[j].source)?;
+            arena.bind(
+                node.target,
+                Type::Product(arrows[i].target, arrows[j].target),
+            )?;
+        }
+        Term::Disconnect(i, j) => {
+            let (i, j) = (idx - i, idx - j);
+            // See chapter 6 (Delegation) of TR
+            // Be careful, this order changed! https://github.com/ElementsProject/simplicity/pull/46
+            let var_a = arena.free();
+            let var_b = arena.free();
+            let var_c = arena.free();
+            let var_d = arena.free();
+
+            arena.bind(arrows[i].source, Type::Product(word256, var_a))?;
+            arena.bind(arrows[i].target, Type::Product(var_b, var_c))?;
+
+            arena.unify(node.source, var_a)?;
+            arena.bind(node.target, Type::Product(var_b, var_d))?;
+
+            arena.unify(arrows[j].source, var_c)?;
+            arena.unify(arrows[j].target, var_d)?;
+        }
+        Term::Witness(..) => {
+            // No type constraints
+        }
+        Term::Hidden(..) => {
+            // No type constraints
+        }
+        Term::Ext(..) | Term::Jet(..) => {
+            let jet_arrow = jet_arrow.expect("types of extension nodes are given");
+            arena.unify(node.source, jet_arrow.source)?;
+            arena.unify(node.target, jet_arrow.target)?;
+        }
+        Term::Fail(..) => unimplemented!("Cannot typecheck a program with `Fail` in it"),
+    };
+    Ok(())
 }
 
 /// Attach types to all nodes in a program
 pub fn type_check<Witness, Ext: extension::Jet>(
     program: UnTypedProg<Witness, Ext>,
+) -> Result<Vec<TypedNode<Witness, Ext>>, Error> {
+    type_check_with_interner(program, &mut TypeInterner::new())
+}
+
+/// Attach types to all nodes in a program, obtaining the final types
+/// from the given interner so that they are shared with any other types
+/// it has produced
+pub fn type_check_with_interner<Witness, Ext: extension::Jet>(
+    program: UnTypedProg<Witness, Ext>,
+    interner: &mut TypeInterner,
+) -> Result<Vec<TypedNode<Witness, Ext>>, Error> {
+    type_check_with_constraints(program, &[], interner)
+}
+
+/// Attach types to all nodes in a program, requiring that the given
+/// nodes have the given types
+pub fn type_check_with_constraints<Witness, Ext: extension::Jet>(
+    program: UnTypedProg<Witness, Ext>,
+    constraints: &[TypeConstraint],
+    interner: &mut TypeInterner,
 ) -> Result<Vec<TypedNode<Witness, Ext>>, Error> {
     let vec_nodes = program.0;
     if vec_nodes.is_empty() {
-        return Ok(vec![]);
-    }
-
-    let two_0 = Type::Unit.into_rcvar();
-    let two_1 = Type::Sum(two_0.clone(), two_0).into_rcvar();
-    let two_2 = Type::Product(two_1.clone(), two_1.clone()).into_rcvar();
-    let two_4 = Type::Product(two_2.clone(), two_2.clone()).into_rcvar();
-    let two_8 = Type::Product(two_4.clone(), two_4.clone()).into_rcvar();
-    let two_16 = Type::Product(two_8.clone(), two_8.clone()).into_rcvar();
-    let two_32 = Type::Product(two_16.clone(), two_16.clone()).into_rcvar();
-    let two_64 = Type::Product(two_32.clone(), two_32.clone()).into_rcvar();
-    let two_128 = Type::Product(two_64.clone(), two_64.clone()).into_rcvar();
-    let two_256 = Type::Product(two_128.clone(), two_128.clone()).into_rcvar();
-    // pow2s[i] = 2^(2^i)
-    let pow2s = [
-        two_1, two_2, two_4, two_8, two_16, two_32, two_64, two_128, two_256,
-    ];
-
-    let mut rcs = Vec::<Rc<UnificationArrow>>::with_capacity(vec_nodes.len());
+        return if constraints.is_empty() {
+            Ok(vec![])
+        } else {
+            Err(Error::BadIndex)
+        };
+    }
+
+    let mut arena = Arena::default();
+    // Variables for complete types, keyed by the address of their
+    // (interned) `FinalType`
+    let mut final_vars = HashMap::new();
+    let word256 = arena.final_type(&interner.pow2s()[9], &mut final_vars);
+
+    let mut arrows = Vec::<UnificationArrow>::with_capacity(vec_nodes.len());
     let mut finals = Vec::<TypedNode<Witness, Ext>>::with_capacity(vec_nodes.len());
 
     // Compute most general unifier for all types in the DAG
     for (idx, program_node) in vec_nodes.iter().enumerate() {
         let node = UnificationArrow {
-            source: Rc::new(RefCell::new(UnificationVar::free())),
-            target: Rc::new(RefCell::new(UnificationVar::free())),
+            source: arena.free(),
+            target: arena.free(),
         };
 
-        match program_node {
-            Term::Iden => unify(node.source.clone(), node.target.clone())?,
-            Term::Unit => bind(&node.target, Type::Unit)?,
-            Term::InjL(i) => {
-                let i = idx - i;
-                unify(node.source.clone(), rcs[i].source.clone())?;
-                let target_type = Type::Sum(
-                    rcs[i].target.clone(),
-                    Rc::new(RefCell::new(UnificationVar::free())),
-                );
-                bind(&node.target, target_type)?;
-            }
-            Term::InjR(i) => {
-                let i = idx - i;
-                unify(node.source.clone(), rcs[i].source.clone())?;
-                let target_type = Type::Sum(
-                    Rc::new(RefCell::new(UnificationVar::free())),
-                    rcs[i].target.clone(),
-                );
-                bind(&node.target, target_type)?;
-            }
-            Term::Take(i) => {
-                let i = idx - i;
-                unify(node.target.clone(), rcs[i].target.clone())?;
-                let target_type = Type::Product(
-                    rcs[i].source.clone(),
-                    Rc::new(RefCell::new(UnificationVar::free())),
-                );
-                bind(&node.source, target_type)?;
-            }
-            Term::Drop(i) => {
-                let i = idx - i;
-                unify(node.target.clone(), rcs[i].target.clone())?;
-                let target_type = Type::Product(
-                    Rc::new(RefCell::new(UnificationVar::free())),
-                    rcs[i].source.clone(),
-                );
-                bind(&node.source, target_type)?;
-            }
-            Term::Comp(i, j) => {
-                let (i, j) = (idx - i, idx - j);
-                unify(node.source.clone(), rcs[i].source.clone())?;
-                unify(rcs[i].target.clone(), rcs[j].source.clone())?;
-                unify(node.target.clone(), rcs[j].target.clone())?;
-            }
-            Term::Case(i, j) => {
-                let (i, j) = (idx - i, idx - j);
-                let var1 = Rc::new(RefCell::new(UnificationVar::free()));
-                let var2 = Rc::new(RefCell::new(UnificationVar::free()));
-                let var3 = Rc::new(RefCell::new(UnificationVar::free()));
-
-                let sum12_ty = Type::Sum(var1.clone(), var2.clone());
-                let sum12_var = Rc::new(RefCell::new(UnificationVar::free()));
-                bind(&sum12_var, sum12_ty)?;
-
-                let source_ty = Type::Product(sum12_var, var3.clone());
-                bind(&node.source, source_ty)?;
-                if let Term::Hidden(..) = vec_nodes[i] {
-                } else {
-                    bind(
-                        &find_root(rcs[i].source.clone()),
-                        Type::Product(var1.clone(), var3.clone()),
-                    )?;
-                    unify(node.target.clone(), rcs[i].target.clone())?;
+        let jet_names = match *program_node {
+            Term::Ext(ref e) => Some((e.source_type(), e.target_type())),
+            Term::Jet(ref j) => Some((j.source_type(), j.target_type())),
+            _ => None,
+        };
+        let jet_arrow = match jet_names {
+            Some((source, target)) => {
+                let source = source.to_final(interner)?;
+                let target = target.to_final(interner)?;
+                Some(UnificationArrow {
+                    source: arena.final_type(&source, &mut final_vars),
+                    target: arena.final_type(&target, &mut final_vars),
+                })
+            }
+            None => None,
+        };
+
+        unify_node(
+            &mut arena, idx, node, &arrows, &vec_nodes, word256, jet_arrow,
+        )
+        .map_err(|Mismatch(a, b)| {
+            Error::TypeCheck(TypeMismatch {
+                index: idx,
+                node: program_node.label(),
+                types: (arena.snapshot(a), arena.snapshot(b)),
+            })
+        })?;
+
+        arrows.push(node);
+    }
+
+    // Apply user-supplied constraints
+    for constraint in constraints {
+        let idx = match constraint.index {
+            None => vec_nodes.len() - 1,
+            Some(idx) if idx < vec_nodes.len() => idx,
+            Some(_) => return Err(Error::BadIndex),
+        };
+        let mut names = HashMap::new();
+        for &(step, var, pattern) in &[
+            (PathStep::Source, arrows[idx].source, &constraint.source),
+            (PathStep::Target, arrows[idx].target, &constraint.target),
+        ] {
+            let found = arena.snapshot_var(var);
+            let expected = arena.pattern(pattern, &mut names);
+            arena.unify(var, expected).map_err(|_| {
+                Error::TypeConstraint(ConstraintViolation {
+                    index: idx,
+                    node: vec_nodes[idx].label(),
+                    step: step,
+                    expected: pattern.clone(),
+                    found: found,
+                })
+            })?;
+        }
+    }
+
+    // Finalize, setting all unconstrained types to `Unit` and doing the
+    // occurs check. (All the magic happens inside `Finalizer::finalize`.)
+    let mut finalizer = Finalizer::new(&arena, interner);
+    for (idx, node) in vec_nodes.into_iter().enumerate() {
+        let occurs_failure = |step: PathStep, mut path: Vec<PathStep>| {
+            path.insert(0, step);
+            Error::OccursCheck(OccursCheckFailure {
+                index: idx,
+                node: node.label(),
+                path: path,
+            })
+        };
+        let source_ty = finalizer
+            .finalize(&mut arena, arrows[idx].source)
+            .map_err(|path| occurs_failure(PathStep::Source, path))?;
+        let target_ty = finalizer
+            .finalize(&mut arena, arrows[idx].target)
+            .map_err(|path| occurs_failure(PathStep::Target, path))?;
+        finals.push(TypedNode {
+            node: node,
+            source_ty: source_ty,
+            target_ty: target_ty,
+            target_defaulted: finalizer.is_defaulted(&arena, arrows[idx].target),
+        });
+    }
+
+    Ok(finals)
+}
+
+/// Two types which could not be unified while building an expression
+/// with a `TypedBuilder`
+#[derive(Clone, Debug)]
+pub struct BuildMismatch {
+    /// Name of the combinator being built
+    pub node: String,
+    /// The requirement of the combinator which could not be met
+    pub constraint: &'static str,
+    /// The two types which had to be equal, as inferred before the
+    /// combinator was built
+    pub types: (TypePattern, TypePattern),
+    /// Whether the types could only be unified by an infinite type
+    pub infinite: bool,
+}
+
+impl fmt::Display for BuildMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.infinite {
+            write!(
+                f,
+                "{}: {}, but unifying {} with {} gives an infinite type",
+                self.node, self.constraint, self.types.0, self.types.1,
+            )
+        } else {
+            write!(
+                f,
+                "{}: {}, but {} does not unify with {}",
+                self.node, self.constraint, self.types.0, self.types.1,
+            )
+        }
+    }
+}
+
+/// A requirement which could not be met while building a combinator
+struct Unmet {
+    constraint: &'static str,
+    types: (Var, Var),
+    infinite: bool,
+}
+
+/// Expression built by a `TypedBuilder`, whose source and target types
+/// are tracked by the builder. It must only be used with the builder
+/// which created it.
+#[derive(Debug)]
+pub struct TypedExpr<Witness, Ext> {
+    dag: Arc<DagTerm<Witness, Ext>>,
+    arrow: UnificationArrow,
+}
+
+impl<Witness, Ext> Clone for TypedExpr<Witness, Ext> {
+    fn clone(&self) -> Self {
+        TypedExpr {
+            dag: Arc::clone(&self.dag),
+            arrow: self.arrow,
+        }
+    }
+}
+
+impl<Witness, Ext> TypedExpr<Witness, Ext> {
+    /// The underlying expression
+    pub fn dag(&self) -> &Arc<DagTerm<Witness, Ext>> {
+        &self.dag
+    }
+}
+
+/// Whether an expression is a hidden node, whose type is unconstrained
+fn is_hidden<Witness, Ext>(dag: &DagTerm<Witness, Ext>) -> bool {
+    match *dag {
+        DagTerm::Hidden(..) => true,
+        _ => false,
+    }
+}
+
+/// Builder of `DagTerm`s which infers types as each combinator is built,
+/// so that a type error is reported by the constructor which caused it
+///
+/// Combinators with children return an error, leaving the builder
+/// unchanged, if the children's types are incompatible. A `TypedExpr`
+/// used as the child of several combinators is shared, and so has the
+/// same type in each of them; polymorphic expressions such as `iden`
+/// should be built again for each type they are used at.
+#[derive(Debug)]
+pub struct TypedBuilder<Witness, Ext> {
+    arena: Arena,
+    interner: TypeInterner,
+    /// Variables for complete types, keyed by the address of their
+    /// (interned) `FinalType`
+    final_vars: HashMap<usize, Var>,
+    word256: Var,
+    phantom: PhantomData<fn() -> DagTerm<Witness, Ext>>,
+}
+
+impl<Witness, Ext> Default for TypedBuilder<Witness, Ext> {
+    fn default() -> Self {
+        TypedBuilder::new()
+    }
+}
+
+impl<Witness, Ext> TypedBuilder<Witness, Ext> {
+    /// Create a new builder
+    pub fn new() -> Self {
+        let mut arena = Arena {
+            trail: Some(vec![]),
+            ..Arena::default()
+        };
+        let interner = TypeInterner::new();
+        let mut final_vars = HashMap::new();
+        let word256 = arena.final_type(&interner.pow2s()[9], &mut final_vars);
+        TypedBuilder {
+            arena: arena,
+            interner: interner,
+            final_vars: final_vars,
+            word256: word256,
+            phantom: PhantomData,
+        }
+    }
+
+    /// The source and target types of an expression, as inferred so far.
+    /// Parts of the types which are not yet determined are shown as
+    /// variables, named in order of appearance.
+    pub fn types(&mut self, expr: &TypedExpr<Witness, Ext>) -> (TypePattern, TypePattern) {
+        let mut names = HashMap::new();
+        let source = self.pattern(expr.arrow.source, &mut names);
+        let target = self.pattern(expr.arrow.target, &mut names);
+        (source, target)
+    }
+
+    /// Render the current state of a variable, with free variables (and
+    /// any variable which contains itself) named according to `names`
+    fn pattern(&mut self, var: Var, names: &mut HashMap<Var, usize>) -> TypePattern {
+        fn pattern(
+            arena: &Arena,
+            interner: &mut TypeInterner,
+            var: Var,
+            names: &mut HashMap<Var, usize>,
+            seen: &mut Vec<Var>,
+        ) -> TypePattern {
+            let var = arena.find_imm(var);
+            let ty = match arena.vars[var].var {
+                Variable::Bound(ty) if !seen.contains(&var) => ty,
+                _ => {
+                    let n = names.len();
+                    return TypePattern::Var(*names.entry(var).or_insert(n));
                 }
-                if let Term::Hidden(..) = vec_nodes[j] {
-                } else {
-                    bind(
-                        &find_root(rcs[j].source.clone()),
-                        Type::Product(var2.clone(), var3.clone()),
-                    )?;
-                    unify(node.target.clone(), rcs[j].target.clone())?;
+            };
+            let (a, b) = match ty {
+                Type::Unit => return TypePattern::Final(interner.unit()),
+                Type::Sum(a, b) | Type::Product(a, b) => (a, b),
+            };
+            seen.push(var);
+            let a = pattern(arena, interner, a, names, seen);
+            let b = pattern(arena, interner, b, names, seen);
+            seen.pop();
+            match (ty, a, b) {
+                (Type::Sum(..), TypePattern::Final(a), TypePattern::Final(b)) => {
+                    TypePattern::Final(interner.sum(a, b))
                 }
+                (Type::Product(..), TypePattern::Final(a), TypePattern::Final(b)) => {
+                    TypePattern::Final(interner.product(a, b))
+                }
+                (Type::Sum(..), a, b) => TypePattern::Sum(Box::new(a), Box::new(b)),
+                (_, a, b) => TypePattern::Product(Box::new(a), Box::new(b)),
             }
-            Term::Pair(i, j) => {
-                let (i, j) = (idx - i, idx - j);
-                unify(node.source.clone(), rcs[i].source.clone())?;
-                unify(node.source.clone(), rcs[j].source.clone())?;
-                bind(
-                    &node.target,
-                    Type::Product(rcs[i].target.clone(), rcs[j].target.clone()),
-                )?;
+        }
+
+        pattern(&self.arena, &mut self.interner, var, names, &mut vec![])
+    }
+
+    /// Require two types to be equal
+    fn require(&mut self, constraint: &'static str, a: Var, b: Var) -> Result<(), Unmet> {
+        let mark = self.arena.mark();
+        let infinite = match self.arena.unify(a, b) {
+            Err(_) => false,
+            Ok(()) if self.arena.is_cyclic(a) => true,
+            Ok(()) => return Ok(()),
+        };
+        self.arena.undo(mark);
+        Err(Unmet {
+            constraint: constraint,
+            types: (a, b),
+            infinite: infinite,
+        })
+    }
+
+    /// Require a variable to be equal to the given type
+    fn require_type(&mut self, constraint: &'static str, var: Var, ty: Type) -> Result<(), Unmet> {
+        let ty = self.arena.concrete(ty);
+        self.require(constraint, var, ty)
+    }
+
+    /// Run `f`, which adds the constraints of a combinator named `node`,
+    /// undoing all of its changes if some requirement cannot be met
+    fn transaction<T, F>(&mut self, node: &str, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Self) -> Result<T, Unmet>,
+    {
+        let mark = self.arena.mark();
+        let ret = match f(self) {
+            Ok(ret) => Ok(ret),
+            Err(unmet) => {
+                // Render the types as they were just before the failed
+                // requirement, since `require` undoes its own changes
+                let mut names = HashMap::new();
+                let a = self.pattern(unmet.types.0, &mut names);
+                let b = self.pattern(unmet.types.1, &mut names);
+                self.arena.undo(mark);
+                Err(Error::TypeBuild(BuildMismatch {
+                    node: node.to_owned(),
+                    constraint: unmet.constraint,
+                    types: (a, b),
+                    infinite: unmet.infinite,
+                }))
             }
-            Term::Disconnect(i, j) => {
-                let (i, j) = (idx - i, idx - j);
-                // See chapter 6 (Delegation) of TR
-                // Be careful, this order changed! https://github.com/ElementsProject/simplicity/pull/46
-                let var_a = Rc::new(RefCell::new(UnificationVar::free()));
-                let var_b = Rc::new(RefCell::new(UnificationVar::free()));
-                let var_c = Rc::new(RefCell::new(UnificationVar::free()));
-                let var_d = Rc::new(RefCell::new(UnificationVar::free()));
-
-                let s_source = Type::Product(pow2s[8].clone(), var_a.clone()).into_rcvar();
-                let s_target = Type::Product(var_b.clone(), var_c.clone()).into_rcvar();
-                unify(rcs[i].source.clone(), s_source)?;
-                unify(rcs[i].target.clone(), s_target)?;
-
-                let node_target = Type::Product(var_b, var_d.clone()).into_rcvar();
-                unify(node.source.clone(), var_a)?;
-                unify(node.target.clone(), node_target)?;
-
-                unify(rcs[j].source.clone(), var_c)?;
-                unify(rcs[j].target.clone(), var_d)?;
-            }
-            Term::Witness(..) => {
-                // No type constraints
-            }
-            Term::Hidden(..) => {
-                // No type constraints
-            }
-            Term::Ext(ref bn) => {
-                bind(
-                    &node.source,
-                    type_from_name(&mut bn.source_type(), &pow2s[..]),
+        };
+        self.arena.trail.as_mut().unwrap().clear();
+        ret
+    }
+
+    fn expr(node: DagTerm<Witness, Ext>, source: Var, target: Var) -> TypedExpr<Witness, Ext> {
+        TypedExpr {
+            dag: Arc::new(node),
+            arrow: UnificationArrow {
+                source: source,
+                target: target,
+            },
+        }
+    }
+
+    /// Require that an expression has the given source and target types.
+    /// Within the two patterns, every occurrence of the same `Var` index
+    /// must be the same type.
+    pub fn constrain(
+        &mut self,
+        expr: &TypedExpr<Witness, Ext>,
+        source: &TypePattern,
+        target: &TypePattern,
+    ) -> Result<(), Error> {
+        self.transaction("constraint", |b| {
+            let mut names = HashMap::new();
+            let source = b.arena.pattern(source, &mut names);
+            b.require(
+                "the source type must match the pattern",
+                expr.arrow.source,
+                source,
+            )?;
+            let target = b.arena.pattern(target, &mut names);
+            b.require(
+                "the target type must match the pattern",
+                expr.arrow.target,
+                target,
+            )
+        })
+    }
+
+    /// The identity combinator, `A → A`
+    pub fn iden(&mut self) -> TypedExpr<Witness, Ext> {
+        let ty = self.arena.free();
+        Self::expr(DagTerm::Iden, ty, ty)
+    }
+
+    /// The unit combinator, `A → 1`
+    pub fn unit(&mut self) -> TypedExpr<Witness, Ext> {
+        let source = self.arena.free();
+        let target = self.arena.concrete(Type::Unit);
+        Self::expr(DagTerm::Unit, source, target)
+    }
+
+    /// Left injection of the given expression
+    pub fn injl(&mut self, t: &TypedExpr<Witness, Ext>) -> Result<TypedExpr<Witness, Ext>, Error> {
+        let right = self.arena.free();
+        let target = self.arena.concrete(Type::Sum(t.arrow.target, right));
+        let node = DagTerm::InjL(Arc::clone(&t.dag));
+        Ok(Self::expr(node, t.arrow.source, target))
+    }
+
+    /// Right injection of the given expression
+    pub fn injr(&mut self, t: &TypedExpr<Witness, Ext>) -> Result<TypedExpr<Witness, Ext>, Error> {
+        let left = self.arena.free();
+        let target = self.arena.concrete(Type::Sum(left, t.arrow.target));
+        let node = DagTerm::InjR(Arc::clone(&t.dag));
+        Ok(Self::expr(node, t.arrow.source, target))
+    }
+
+    /// Apply the given expression to the left half of a product
+    pub fn take(&mut self, t: &TypedExpr<Witness, Ext>) -> Result<TypedExpr<Witness, Ext>, Error> {
+        let right = self.arena.free();
+        let source = self.arena.concrete(Type::Product(t.arrow.source, right));
+        let node = DagTerm::Take(Arc::clone(&t.dag));
+        Ok(Self::expr(node, source, t.arrow.target))
+    }
+
+    /// Apply the given expression to the right half of a product
+    pub fn drop(&mut self, t: &TypedExpr<Witness, Ext>) -> Result<TypedExpr<Witness, Ext>, Error> {
+        let left = self.arena.free();
+        let source = self.arena.concrete(Type::Product(left, t.arrow.source));
+        let node = DagTerm::Drop(Arc::clone(&t.dag));
+        Ok(Self::expr(node, source, t.arrow.target))
+    }
+
+    /// Composition of two expressions, applying `s` first
+    pub fn comp(
+        &mut self,
+        s: &TypedExpr<Witness, Ext>,
+        t: &TypedExpr<Witness, Ext>,
+    ) -> Result<TypedExpr<Witness, Ext>, Error> {
+        self.transaction("comp", |b| {
+            b.require(
+                "the target of the first child must be the source of the second",
+                s.arrow.target,
+                t.arrow.source,
+            )
+        })?;
+        let node = DagTerm::Comp(Arc::clone(&s.dag), Arc::clone(&t.dag));
+        Ok(Self::expr(node, s.arrow.source, t.arrow.target))
+    }
+
+    /// Case analysis on the sum in the left half of the input. Either
+    /// child may be `hidden`, in which case it is not type-checked.
+    pub fn case(
+        &mut self,
+        s: &TypedExpr<Witness, Ext>,
+        t: &TypedExpr<Witness, Ext>,
+    ) -> Result<TypedExpr<Witness, Ext>, Error> {
+        let (source, target) = self.transaction("case", |b| {
+            let (left, right, rest) = (b.arena.free(), b.arena.free(), b.arena.free());
+            let sum = b.arena.concrete(Type::Sum(left, right));
+            let source = b.arena.concrete(Type::Product(sum, rest));
+            let target = b.arena.free();
+            if !is_hidden(&s.dag) {
+                b.require_type(
+                    "the left child must have source A × C, for a case with source (A + B) × C",
+                    s.arrow.source,
+                    Type::Product(left, rest),
                 )?;
-                bind(
-                    &node.target,
-                    type_from_name(&mut bn.target_type(), &pow2s[..]),
+                b.require(
+                    "both children must have the same target",
+                    target,
+                    s.arrow.target,
                 )?;
             }
-            Term::Jet(ref jt) => {
-                bind(
-                    &node.source,
-                    type_from_name(&mut jt.source_type(), &pow2s[..]),
+            if !is_hidden(&t.dag) {
+                b.require_type(
+                    "the right child must have source B × C, for a case with source (A + B) × C",
+                    t.arrow.source,
+                    Type::Product(right, rest),
                 )?;
-
-                bind(
-                    &node.target,
-                    type_from_name(&mut jt.target_type(), &pow2s[..]),
+                b.require(
+                    "both children must have the same target",
+                    target,
+                    t.arrow.target,
                 )?;
             }
-            Term::Fail(..) => unimplemented!("Cannot typecheck a program with `Fail` in it"),
-        };
+            Ok((source, target))
+        })?;
+        let node = DagTerm::Case(Arc::clone(&s.dag), Arc::clone(&t.dag));
+        Ok(Self::expr(node, source, target))
+    }
 
-        rcs.push(Rc::new(node));
+    /// Pair of the outputs of two expressions
+    pub fn pair(
+        &mut self,
+        s: &TypedExpr<Witness, Ext>,
+        t: &TypedExpr<Witness, Ext>,
+    ) -> Result<TypedExpr<Witness, Ext>, Error> {
+        self.transaction("pair", |b| {
+            b.require(
+                "both children must have the same source",
+                s.arrow.source,
+                t.arrow.source,
+            )
+        })?;
+        let target = self
+            .arena
+            .concrete(Type::Product(s.arrow.target, t.arrow.target));
+        let node = DagTerm::Pair(Arc::clone(&s.dag), Arc::clone(&t.dag));
+        Ok(Self::expr(node, s.arrow.source, target))
     }
 
-    // Finalize, setting all unconstrained types to `Unit` and doing the
-    // occurs check. (All the magic happens inside `FinalType::from_var`.)
-    for (idx, node) in vec_nodes.into_iter().enumerate() {
-        finals.push(TypedNode {
-            node: node,
-            source_ty: FinalType::from_var(rcs[idx].source.clone())?,
-            target_ty: FinalType::from_var(rcs[idx].target.clone())?,
+    /// Disconnect combinator, `A → B × D` for children of types
+    /// `2^256 × A → B × C` and `C → D`
+    pub fn disconnect(
+        &mut self,
+        s: &TypedExpr<Witness, Ext>,
+        t: &TypedExpr<Witness, Ext>,
+    ) -> Result<TypedExpr<Witness, Ext>, Error> {
+        let (source, target) = self.transaction("disconnect", |b| {
+            let (source, output, middle) = (b.arena.free(), b.arena.free(), b.arena.free());
+            b.require_type(
+                "the first child must have source 2^256 × A",
+                s.arrow.source,
+                Type::Product(b.word256, source),
+            )?;
+            b.require_type(
+                "the first child must have target B × C",
+                s.arrow.target,
+                Type::Product(output, middle),
+            )?;
+            b.require(
+                "the second child must have source C, for a first child with target B × C",
+                t.arrow.source,
+                middle,
+            )?;
+            let target = b.arena.concrete(Type::Product(output, t.arrow.target));
+            Ok((source, target))
+        })?;
+        let node = DagTerm::Disconnect(Arc::clone(&s.dag), Arc::clone(&t.dag));
+        Ok(Self::expr(node, source, target))
+    }
+
+    /// A witness, whose type is determined by the expressions it is used in
+    pub fn witness(&mut self, w: Witness) -> TypedExpr<Witness, Ext> {
+        let (source, target) = (self.arena.free(), self.arena.free());
+        Self::expr(DagTerm::Witness(w), source, target)
+    }
+
+    /// Hidden subexpression with the given CMR, which may only be used
+    /// as a child of `case`
+    pub fn hidden(&mut self, cmr: Cmr) -> TypedExpr<Witness, Ext> {
+        let (source, target) = (self.arena.free(), self.arena.free());
+        Self::expr(DagTerm::Hidden(cmr), source, target)
+    }
+
+    /// Allocate variables for the fixed types of a jet or extension node
+    fn primitive_arrow<J: extension::Jet>(&mut self, j: &J) -> Result<(Var, Var), Error> {
+        let source = j.source_type().to_final(&mut self.interner)?;
+        let target = j.target_type().to_final(&mut self.interner)?;
+        Ok((
+            self.arena.final_type(&source, &mut self.final_vars),
+            self.arena.final_type(&target, &mut self.final_vars),
+        ))
+    }
+
+    /// Extension node
+    pub fn ext(&mut self, e: Ext) -> Result<TypedExpr<Witness, Ext>, Error>
+    where
+        Ext: extension::Jet,
+    {
+        let (source, target) = self.primitive_arrow(&e)?;
+        Ok(Self::expr(DagTerm::Ext(e), source, target))
+    }
+
+    /// Jet node
+    pub fn jet(&mut self, j: JetsNode) -> Result<TypedExpr<Witness, Ext>, Error> {
+        let (source, target) = self.primitive_arrow(&j)?;
+        Ok(Self::expr(DagTerm::Jet(j), source, target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::dummy::DummyNode;
+    use crate::extension::jets::JetsNode;
+
+    #[test]
+    fn type_mismatch_diagnostics() {
+        // comp unit adder32
+        let prog = UnTypedProg::<(), DummyNode>(vec![
+            Term::Unit,
+            Term::Jet(JetsNode::Adder32),
+            Term::Comp(2, 1),
+        ]);
+        match type_check(prog) {
+            Err(Error::TypeCheck(e)) => {
+                assert_eq!(e.index, 2);
+                assert_eq!(e.node, "comp");
+                assert_eq!(e.types.0.to_string(), "1");
+                assert_eq!(e.types.1.to_string(), "2^64");
+            }
+            x => panic!("unexpected type-checking result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn occurs_check_diagnostics() {
+        // p = pair iden unit; comp p p
+        let prog = UnTypedProg::<(), DummyNode>(vec![
+            Term::Iden,
+            Term::Unit,
+            Term::Pair(2, 1),
+            Term::Comp(1, 1),
+        ]);
+        match type_check(prog) {
+            Err(Error::OccursCheck(e)) => {
+                assert_eq!(e.index, 0);
+                assert_eq!(e.node, "iden");
+                assert_eq!(e.path, vec![PathStep::Source, PathStep::ProdL]);
+            }
+            x => panic!("unexpected type-checking result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn interner_shares_words() {
+        let mut interner = TypeInterner::new();
+        let prog = UnTypedProg::<(), DummyNode>(vec![Term::Jet(JetsNode::Adder32)]);
+        let typed = type_check_with_interner(prog, &mut interner).unwrap();
+
+        let two = interner.pow2s()[1].clone();
+        let word32 = interner.pow2s()[6].clone();
+        assert!(Arc::ptr_eq(&typed[0].source_ty, &interner.pow2s()[7]));
+        assert!(Arc::ptr_eq(
+            &typed[0].target_ty,
+            &interner.product(two, word32)
+        ));
+
+        // Building a word gives the shared word, which every interner on
+        // the thread has
+        let word16 = interner.pow2s()[5].clone();
+        let word32 = interner.product(word16.clone(), word16);
+        assert!(Arc::ptr_eq(&word32, &TypeInterner::new().pow2s()[6]));
+        let unit = interner.unit();
+        assert!(Arc::ptr_eq(
+            &interner.sum(unit.clone(), unit),
+            &interner.pow2s()[1]
+        ));
+    }
+
+    #[test]
+    fn word_index() {
+        for (i, word) in TypeInterner::new().pow2s().iter().enumerate() {
+            assert_eq!(word.word_index(), i.checked_sub(1));
+        }
+        // Words built without an interner do not share their halves
+        let unit = || Arc::new(FinalType::unit());
+        let two = || Arc::new(FinalType::sum(unit(), unit()));
+        let word2 = FinalType::prod(two(), two());
+        assert!(!Arc::ptr_eq(&two(), &two()));
+        assert_eq!(word2.word_index(), Some(1));
+        for s in &["1", "(1 + 2)", "(2 × 1)", "(2 × 2^2)", "(2^512 × 2^512)"] {
+            assert_eq!(FinalType::from_str(s).unwrap().word_index(), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn root_constraints() {
+        // take iden
+        let prog = UnTypedProg::<(), DummyNode>(vec![Term::Iden, Term::Take(1)]);
+        let typed = type_check_with_constraints(
+            prog.clone(),
+            &[TypeConstraint::disconnect_fragment()],
+            &mut TypeInterner::new(),
+        )
+        .unwrap();
+        assert_eq!(typed[1].source_ty.to_string(), "(2^256 × 1)");
+        assert_eq!(typed[1].target_ty.to_string(), "2^256");
+
+        match type_check_with_constraints(
+            prog,
+            &[TypeConstraint::spending_program()],
+            &mut TypeInterner::new(),
+        ) {
+            Err(Error::TypeConstraint(e)) => {
+                assert_eq!(e.index, 1);
+                assert_eq!(e.node, "take");
+                assert_eq!(e.step, PathStep::Source);
+                assert_eq!(e.found.to_string(), "(1 × 1)");
+                assert_eq!(e.expected, TypePattern::Unit);
+            }
+            x => panic!("unexpected type-checking result {:?}", x),
+        }
+
+        // Jets are named in errors as they are when displayed
+        let prog = UnTypedProg::<(), DummyNode>(vec![Term::Jet(JetsNode::Adder32)]);
+        match type_check_with_constraints(
+            prog,
+            &[TypeConstraint::spending_program()],
+            &mut TypeInterner::new(),
+        ) {
+            Err(Error::TypeConstraint(e)) => assert_eq!(e.node, "jet adder32"),
+            x => panic!("unexpected type-checking result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn witness_defaulting() {
+        // witness; comp witness adder32
+        let prog = UnTypedProg::<(), DummyNode>(vec![
+            Term::Witness(()),
+            Term::Jet(JetsNode::Adder32),
+            Term::Comp(2, 1),
+            Term::Witness(()),
+        ]);
+        let typed = type_check(prog).unwrap();
+        assert_eq!(typed[0].target_ty.to_string(), "2^64");
+        assert_eq!(typed[3].target_ty.to_string(), "1");
+        assert_eq!(defaulted_witnesses(&typed), vec![3]);
+    }
+
+    #[test]
+    fn deep_type_name() {
+        let mut interner = TypeInterner::new();
+        let mut ty = interner.unit();
+        for _ in 0..100_000 {
+            let unit = interner.unit();
+            ty = interner.sum(ty, unit);
+        }
+        let name = ty.to_type_name();
+        // The innermost `+11` is the word `2`
+        assert_eq!(
+            name,
+            format!("{}2{}", "+".repeat(99_999), "1".repeat(99_999))
+        );
+    }
+
+    #[test]
+    fn parse_types() {
+        let mut interner = TypeInterner::new();
+        for s in &[
+            "1",
+            "2",
+            "2^512",
+            "((2^8 + 2^16) × (2^4 × 2^512))",
+            "(1 + (2 × 1))",
+        ] {
+            assert_eq!(interner.parse(s).unwrap().to_string(), *s);
+        }
+        let ty = interner.parse("2 * 2^32 + 1").unwrap();
+        assert_eq!(ty.to_string(), "((2 × 2^32) + 1)");
+        assert!(Arc::ptr_eq(
+            &interner.parse("(2^16*2^16)").unwrap(),
+            &interner.pow2s()[6]
+        ));
+
+        for s in &["", "3", "2^3", "(1 + 1", "1 1", "2^1024"] {
+            assert!(FinalType::from_str(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn typed_builder() {
+        let mut b = TypedBuilder::<(), DummyNode>::new();
+        let show =
+            |(source, target): (TypePattern, TypePattern)| format!("{} → {}", source, target);
+
+        let iden = b.iden();
+        assert_eq!(show(b.types(&iden)), "A → A");
+        let unit = b.unit();
+        let p = b.pair(&iden, &unit).unwrap();
+        assert_eq!(show(b.types(&p)), "A → (A × 1)");
+        let take = b.take(&p).unwrap();
+        assert_eq!(show(b.types(&take)), "(A × B) → (A × 1)");
+
+        // comp unit adder32
+        let adder = b.jet(JetsNode::Adder32).unwrap();
+        match b.comp(&unit, &adder) {
+            Err(Error::TypeBuild(e)) => {
+                assert_eq!(e.node, "comp");
+                assert!(!e.infinite);
+                assert_eq!(e.types.0.to_string(), "1");
+                assert_eq!(e.types.1.to_string(), "2^64");
+            }
+            x => panic!("unexpected build result {:?}", x),
+        }
+
+        // comp p p needs A = A × 1
+        match b.comp(&p, &p) {
+            Err(Error::TypeBuild(e)) => {
+                assert_eq!(e.node, "comp");
+                assert!(e.infinite);
+                assert_eq!(e.types.0.to_string(), "(A × 1)");
+                assert_eq!(e.types.1.to_string(), "A");
+            }
+            x => panic!("unexpected build result {:?}", x),
+        }
+        // Failed constructors leave the types unchanged
+        assert_eq!(show(b.types(&p)), "A → (A × 1)");
+        assert_eq!(show(b.types(&unit)), "A → 1");
+
+        let w = b.witness(());
+        let case = b.case(&adder, &w).unwrap();
+        assert_eq!(show(b.types(&case)), "((2^32 + A) × 2^32) → (2 × 2^32)");
+        // Shared expressions have a single type, so a polymorphic
+        // expression must be built again for each type it is used at
+        let hidden = b.hidden(Cmr::from([0; 32]));
+        let iden = b.iden();
+        let case = b.case(&hidden, &iden).unwrap();
+        assert_eq!(show(b.types(&case)), "((A + B) × C) → (B × C)");
+
+        let ws = b.witness(());
+        let unit = b.unit();
+        let input = b.pair(&ws, &unit).unwrap();
+        let prog = b.comp(&input, &case).unwrap();
+        assert!(b
+            .constrain(&prog, &TypePattern::Var(0), &TypePattern::Unit)
+            .is_err());
+        b.constrain(&prog, &TypePattern::Unit, &TypePattern::Var(0))
+            .unwrap();
+        assert_eq!(show(b.types(&prog)), "1 → (A × 1)");
+
+        // The eagerly inferred types agree with `type_check`
+        let typed = type_check(prog.dag().to_untyped_prog()).unwrap();
+        let root = typed.last().unwrap();
+        assert_eq!(root.source_ty.to_string(), "1");
+        assert_eq!(root.target_ty.to_string(), "(1 × 1)");
+    }
+
+    #[test]
+    fn final_types_are_shared() {
+        let prog = UnTypedProg::<(), DummyNode>(vec![
+            Term::Jet(JetsNode::Adder32),
+            Term::Jet(JetsNode::Subtractor32),
+        ]);
+        let typed = type_check(prog).unwrap();
+        assert!(Arc::ptr_eq(&typed[0].source_ty, &typed[1].source_ty));
+        assert!(Arc::ptr_eq(&typed[0].target_ty, &typed[1].target_ty));
+    }
+}
+
+#[cfg(all(test, feature = "unstable"))]
+mod legacy;
+
+#[cfg(all(test, feature = "unstable"))]
+mod benches {
+    use super::*;
+    use crate::extension::jets::JetsNode;
+    use test::{black_box, Bencher};
+
+    #[cfg(feature = "elements")]
+    include!("../test_programs/sighash_all.rs");
+
+    fn long_chain() -> UnTypedProg<(), JetsNode> {
+        // f_0 = multiplier32; f_{n+1} = comp (pair f_n f_n) fullmultiplier32
+        let mut nodes = vec![Term::Jet(JetsNode::Multiplier32)];
+        for _ in 0..2000 {
+            nodes.push(Term::Pair(1, 1));
+            nodes.push(Term::Jet(JetsNode::FullMultiplier32));
+            nodes.push(Term::Comp(2, 1));
+        }
+        UnTypedProg(nodes)
+    }
+
+    #[cfg(feature = "elements")]
+    fn sighash_all() -> UnTypedProg<(), crate::extension::elements::ElementsNode> {
+        let mut iter = crate::bititer::BitIter::from(SIGHASH_ALL.iter().cloned());
+        let prog = crate::encode::decode_program_no_witness(&mut iter).unwrap();
+        // Both engines must agree before their speeds are compared
+        let typed = type_check(prog.clone()).unwrap();
+        let old = legacy::type_check(&prog).unwrap();
+        for (node, (source, target)) in typed.iter().zip(old.iter()) {
+            assert_eq!(node.source_ty, *source);
+            assert_eq!(node.target_ty, *target);
+        }
+        prog
+    }
+
+    #[bench]
+    fn type_check_long_chain(bh: &mut Bencher) {
+        let prog = long_chain();
+        bh.iter(|| {
+            let typed = type_check(prog.clone()).unwrap();
+            black_box(typed);
         });
     }
 
-    Ok(finals)
+    #[bench]
+    fn legacy_type_check_long_chain(bh: &mut Bencher) {
+        let prog = long_chain();
+        bh.iter(|| {
+            let typed = legacy::type_check(&prog).unwrap();
+            black_box(typed);
+        });
+    }
+
+    #[cfg(feature = "elements")]
+    #[bench]
+    fn type_check_sighash_all(bh: &mut Bencher) {
+        let prog = sighash_all();
+        bh.iter(|| {
+            let typed = type_check(prog.clone()).unwrap();
+            black_box(typed);
+        });
+    }
+
+    #[cfg(feature = "elements")]
+    #[bench]
+    fn legacy_type_check_sighash_all(bh: &mut Bencher) {
+        let prog = sighash_all();
+        bh.iter(|| {
+            let typed = legacy::type_check(&prog).unwrap();
+            black_box(typed);
+        });
+    }
 }
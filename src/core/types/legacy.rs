@@ -0,0 +1,315 @@
+//! The type inference engine which predates the arena-based one, kept
+//! so that the two can be benchmarked against each other
+
+use std::{cell::RefCell, cmp, mem, rc::Rc, sync::Arc};
+
+use super::FinalType;
+use crate::core::term::UnTypedProg;
+use crate::extension::{self, Jet};
+use crate::Term;
+
+/// Failure to type check; the old engine did not say why
+#[derive(Debug)]
+pub struct Failed;
+
+#[derive(Clone, Debug)]
+enum Type {
+    Unit,
+    Sum(RcVar, RcVar),
+    Product(RcVar, RcVar),
+}
+
+impl Type {
+    fn into_rcvar(self) -> RcVar {
+        Rc::new(RefCell::new(UnificationVar::concrete(self)))
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Variable {
+    /// Free variable
+    Free,
+    /// Bound to some type, along with a flag for the occurs check
+    Bound(Type, bool),
+    /// Equal to another variable
+    EqualTo(RcVar),
+    /// Complete type has been set in place
+    Finalized(Arc<FinalType>),
+}
+
+#[derive(Debug)]
+struct UnificationVar {
+    var: Variable,
+    rank: usize,
+}
+
+type RcVar = Rc<RefCell<UnificationVar>>;
+
+/// Source and target type of a node
+pub type Arrow = (Arc<FinalType>, Arc<FinalType>);
+
+impl UnificationVar {
+    fn free() -> RcVar {
+        Rc::new(RefCell::new(UnificationVar {
+            var: Variable::Free,
+            rank: 0,
+        }))
+    }
+
+    fn concrete(ty: Type) -> UnificationVar {
+        UnificationVar {
+            var: Variable::Bound(ty, false),
+            rank: 0,
+        }
+    }
+}
+
+fn finalize(var: RcVar) -> Result<Arc<FinalType>, Failed> {
+    let var = find_root(var);
+    let existing_type = {
+        let mut var_borr = var.borrow_mut();
+        match var_borr.var {
+            Variable::Free => Type::Unit,
+            Variable::Bound(ref ty, ref mut occurs_check) => {
+                if *occurs_check {
+                    return Err(Failed);
+                }
+                *occurs_check = true;
+                ty.clone()
+            }
+            Variable::EqualTo(..) => unreachable!(),
+            Variable::Finalized(ref done) => return Ok(done.clone()),
+        }
+    };
+
+    let ret = match existing_type {
+        Type::Unit => Arc::new(FinalType::unit()),
+        Type::Sum(sub1, sub2) => Arc::new(FinalType::sum(finalize(sub1)?, finalize(sub2)?)),
+        Type::Product(sub1, sub2) => Arc::new(FinalType::prod(finalize(sub1)?, finalize(sub2)?)),
+    };
+    var.borrow_mut().var = Variable::Finalized(ret.clone());
+    Ok(ret)
+}
+
+fn bind(rcvar: &RcVar, ty: Type) -> Result<(), Failed> {
+    let self_var = rcvar.borrow().var.clone();
+    match self_var {
+        Variable::Free => {
+            rcvar.borrow_mut().var = Variable::Bound(ty, false);
+            Ok(())
+        }
+        Variable::EqualTo(..) | Variable::Finalized(..) => unreachable!(),
+        Variable::Bound(self_ty, _) => match (self_ty, ty) {
+            (Type::Unit, Type::Unit) => Ok(()),
+            (Type::Sum(al1, al2), Type::Sum(be1, be2))
+            | (Type::Product(al1, al2), Type::Product(be1, be2)) => {
+                unify(al1, be1)?;
+                unify(al2, be2)
+            }
+            _ => Err(Failed),
+        },
+    }
+}
+
+fn find_root(mut node: RcVar) -> RcVar {
+    loop {
+        let parent = match node.borrow().var {
+            Variable::EqualTo(ref parent) => Some(parent.clone()),
+            _ => None,
+        };
+        let parent = match parent {
+            Some(x) => x,
+            _ => break node,
+        };
+
+        {
+            let parent_borr = parent.borrow();
+            if let Variable::EqualTo(ref grandparent) = parent_borr.var {
+                node.borrow_mut().var = Variable::EqualTo(grandparent.clone());
+            }
+        }
+        node = parent;
+    }
+}
+
+fn unify(mut alpha: RcVar, mut beta: RcVar) -> Result<(), Failed> {
+    alpha = find_root(alpha);
+    beta = find_root(beta);
+    if Rc::ptr_eq(&alpha, &beta) {
+        return Ok(());
+    }
+
+    let rank_ord = { alpha.borrow().rank.cmp(&beta.borrow().rank) };
+    match rank_ord {
+        cmp::Ordering::Less => mem::swap(&mut alpha, &mut beta),
+        cmp::Ordering::Equal => alpha.borrow_mut().rank += 1,
+        _ => {}
+    }
+
+    let be_var = {
+        let mut be_borr = beta.borrow_mut();
+        mem::replace(&mut be_borr.var, Variable::EqualTo(alpha.clone()))
+    };
+    match be_var {
+        Variable::Free => Ok(()),
+        Variable::Bound(be_type, _) => bind(&alpha, be_type),
+        Variable::EqualTo(..) | Variable::Finalized(..) => unreachable!(),
+    }
+}
+
+fn type_from_name<I: Iterator<Item = u8>>(n: &mut I, pow2s: &[RcVar]) -> Type {
+    let letter = n.next().expect("type names are validated");
+    if letter == b'1' {
+        return Type::Unit;
+    }
+    if let Some(i) = extension::WORD_LETTERS.iter().position(|&l| l == letter) {
+        return match i {
+            0 => Type::Sum(Type::Unit.into_rcvar(), Type::Unit.into_rcvar()),
+            i => Type::Product(pow2s[i - 1].clone(), pow2s[i - 1].clone()),
+        };
+    }
+    let left = type_from_name(&mut *n, pow2s).into_rcvar();
+    let right = type_from_name(&mut *n, pow2s).into_rcvar();
+    match letter {
+        b'+' => Type::Sum(left, right),
+        b'*' => Type::Product(left, right),
+        _ => unreachable!("type names are validated"),
+    }
+}
+
+/// Compute the source and target type of every node of a program
+pub fn type_check<Witness, Ext: extension::Jet>(
+    program: &UnTypedProg<Witness, Ext>,
+) -> Result<Vec<Arrow>, Failed> {
+    let nodes = &program.0;
+
+    let mut pow2s = vec![Type::Sum(Type::Unit.into_rcvar(), Type::Unit.into_rcvar()).into_rcvar()];
+    for i in 0..9 {
+        let prev = pow2s[i].clone();
+        pow2s.push(Type::Product(prev.clone(), prev).into_rcvar());
+    }
+
+    let mut arrows = Vec::<(RcVar, RcVar)>::with_capacity(nodes.len());
+    for (idx, term) in nodes.iter().enumerate() {
+        let (source, target) = (UnificationVar::free(), UnificationVar::free());
+        match *term {
+            Term::Iden => unify(source.clone(), target.clone())?,
+            Term::Unit => bind(&target, Type::Unit)?,
+            Term::InjL(i) => {
+                let i = idx - i;
+                unify(source.clone(), arrows[i].0.clone())?;
+                bind(
+                    &target,
+                    Type::Sum(arrows[i].1.clone(), UnificationVar::free()),
+                )?;
+            }
+            Term::InjR(i) => {
+                let i = idx - i;
+                unify(source.clone(), arrows[i].0.clone())?;
+                bind(
+                    &target,
+                    Type::Sum(UnificationVar::free(), arrows[i].1.clone()),
+                )?;
+            }
+            Term::Take(i) => {
+                let i = idx - i;
+                unify(target.clone(), arrows[i].1.clone())?;
+                bind(
+                    &source,
+                    Type::Product(arrows[i].0.clone(), UnificationVar::free()),
+                )?;
+            }
+            Term::Drop(i) => {
+                let i = idx - i;
+                unify(target.clone(), arrows[i].1.clone())?;
+                bind(
+                    &source,
+                    Type::Product(UnificationVar::free(), arrows[i].0.clone()),
+                )?;
+            }
+            Term::Comp(i, j) => {
+                let (i, j) = (idx - i, idx - j);
+                unify(source.clone(), arrows[i].0.clone())?;
+                unify(arrows[i].1.clone(), arrows[j].0.clone())?;
+                unify(target.clone(), arrows[j].1.clone())?;
+            }
+            Term::Case(i, j) => {
+                let (i, j) = (idx - i, idx - j);
+                let (var1, var2, var3) = (
+                    UnificationVar::free(),
+                    UnificationVar::free(),
+                    UnificationVar::free(),
+                );
+                let sum12 = Type::Sum(var1.clone(), var2.clone()).into_rcvar();
+                bind(&source, Type::Product(sum12, var3.clone()))?;
+                for &(k, var) in &[(i, &var1), (j, &var2)] {
+                    if let Term::Hidden(..) = nodes[k] {
+                        continue;
+                    }
+                    bind(
+                        &find_root(arrows[k].0.clone()),
+                        Type::Product(var.clone(), var3.clone()),
+                    )?;
+                    unify(target.clone(), arrows[k].1.clone())?;
+                }
+            }
+            Term::Pair(i, j) => {
+                let (i, j) = (idx - i, idx - j);
+                unify(source.clone(), arrows[i].0.clone())?;
+                unify(source.clone(), arrows[j].0.clone())?;
+                bind(
+                    &target,
+                    Type::Product(arrows[i].1.clone(), arrows[j].1.clone()),
+                )?;
+            }
+            Term::Disconnect(i, j) => {
+                let (i, j) = (idx - i, idx - j);
+                let (var_a, var_b, var_c, var_d) = (
+                    UnificationVar::free(),
+                    UnificationVar::free(),
+                    UnificationVar::free(),
+                    UnificationVar::free(),
+                );
+                let s_source = Type::Product(pow2s[8].clone(), var_a.clone()).into_rcvar();
+                let s_target = Type::Product(var_b.clone(), var_c.clone()).into_rcvar();
+                unify(arrows[i].0.clone(), s_source)?;
+                unify(arrows[i].1.clone(), s_target)?;
+                unify(source.clone(), var_a)?;
+                unify(
+                    target.clone(),
+                    Type::Product(var_b, var_d.clone()).into_rcvar(),
+                )?;
+                unify(arrows[j].0.clone(), var_c)?;
+                unify(arrows[j].1.clone(), var_d)?;
+            }
+            Term::Witness(..) | Term::Hidden(..) => {}
+            Term::Ext(ref ext) => {
+                bind(
+                    &source,
+                    type_from_name(&mut ext.source_type().0.iter().cloned(), &pow2s),
+                )?;
+                bind(
+                    &target,
+                    type_from_name(&mut ext.target_type().0.iter().cloned(), &pow2s),
+                )?;
+            }
+            Term::Jet(ref jet) => {
+                bind(
+                    &source,
+                    type_from_name(&mut jet.source_type().0.iter().cloned(), &pow2s),
+                )?;
+                bind(
+                    &target,
+                    type_from_name(&mut jet.target_type().0.iter().cloned(), &pow2s),
+                )?;
+            }
+            Term::Fail(..) => return Err(Failed),
+        }
+        arrows.push((source, target));
+    }
+
+    arrows
+        .into_iter()
+        .map(|(source, target)| Ok((finalize(source)?, finalize(target)?)))
+        .collect()
+}
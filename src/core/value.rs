@@ -0,0 +1,351 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Packed Values
+//!
+//! Compact representation of Simplicity values as a bit vector together
+//! with the value's type. Unlike `Value`, which allocates a node for every
+//! sum and product, a packed value costs one bit per sum, and all of its
+//! traversals are iterative.
+//!
+
+use std::fmt;
+use std::sync::Arc;
+
+use super::term::Value;
+use super::types::{FinalType, FinalTypeInner};
+use crate::bititer::BitIter;
+use crate::Error;
+
+/// A Simplicity value of a known type, stored in the same compact
+/// bitwise encoding used for witness data
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct PackedValue {
+    /// Encoded bits, most significant first, with the final byte padded
+    /// with zeroes
+    bytes: Vec<u8>,
+    /// Number of encoded bits
+    len: usize,
+    /// Type of the value
+    ty: Arc<FinalType>,
+}
+
+impl PackedValue {
+    /// Read a value of the given type from its compact encoding, as used
+    /// for witness data
+    pub fn from_bits_and_type<Bits: Iterator<Item = bool>>(
+        bits: &mut Bits,
+        ty: &Arc<FinalType>,
+    ) -> Result<PackedValue, Error> {
+        PackedValue::read(bits, ty, false)
+    }
+
+    /// Read a value of the given type from its padded encoding, as used
+    /// in the Bit Machine. Exactly `ty.bit_width` bits are consumed.
+    pub fn from_padded_bits<Bits: Iterator<Item = bool>>(
+        bits: &mut Bits,
+        ty: &Arc<FinalType>,
+    ) -> Result<PackedValue, Error> {
+        PackedValue::read(bits, ty, true)
+    }
+
+    fn read<Bits: Iterator<Item = bool>>(
+        bits: &mut Bits,
+        ty: &Arc<FinalType>,
+        padded: bool,
+    ) -> Result<PackedValue, Error> {
+        let mut ret = PackedValue::empty(ty);
+        let mut stack = vec![&**ty];
+        while let Some(ty) = stack.pop() {
+            match ty.ty {
+                FinalTypeInner::Unit => {}
+                FinalTypeInner::Sum(ref l, ref r) => {
+                    let bit = bits.next().ok_or(Error::EndOfStream)?;
+                    ret.push_bit(bit);
+                    let child = if bit { r } else { l };
+                    if padded {
+                        for _ in 0..ty.bit_width - 1 - child.bit_width {
+                            bits.next().ok_or(Error::EndOfStream)?;
+                        }
+                    }
+                    stack.push(child);
+                }
+                FinalTypeInner::Product(ref l, ref r) => {
+                    stack.push(r);
+                    stack.push(l);
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Pack a value, checking that it has the given type
+    pub fn from_value(value: &Value, ty: &Arc<FinalType>) -> Result<PackedValue, Error> {
+        let mut ret = PackedValue::empty(ty);
+        let mut stack = vec![(value, &**ty)];
+        while let Some((value, ty)) = stack.pop() {
+            match (value, &ty.ty) {
+                (Value::Unit, FinalTypeInner::Unit) => {}
+                (Value::SumL(v), FinalTypeInner::Sum(l, _)) => {
+                    ret.push_bit(false);
+                    stack.push((v, l));
+                }
+                (Value::SumR(v), FinalTypeInner::Sum(_, r)) => {
+                    ret.push_bit(true);
+                    stack.push((v, r));
+                }
+                (Value::Prod(a, b), FinalTypeInner::Product(l, r)) => {
+                    stack.push((b, r));
+                    stack.push((a, l));
+                }
+                _ => return Err(Error::ValueShape("value of the given type")),
+            }
+        }
+        Ok(ret)
+    }
+
+    fn empty(ty: &Arc<FinalType>) -> PackedValue {
+        PackedValue {
+            bytes: Vec::with_capacity((ty.bit_width + 7) / 8),
+            len: 0,
+            ty: Arc::clone(ty),
+        }
+    }
+
+    fn push_bit(&mut self, bit: bool) {
+        if self.len % 8 == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 1 << (7 - self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    /// Unpack the value into a tree
+    pub fn to_value(&self) -> Value {
+        Value::from_bits_and_type(&mut self.iter(), &self.ty)
+            .expect("packed value matches its type")
+    }
+
+    /// The type of the value
+    pub fn ty(&self) -> &Arc<FinalType> {
+        &self.ty
+    }
+
+    /// The length, in bits, of the compact encoding of the value. This is
+    /// at most the value's width in the Bit Machine, which includes padding.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the compact encoding is empty, i.e. the value contains
+    /// no sums
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The compact encoding, padded with zeroes to a whole byte
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Iterator over the bits of the compact encoding
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        BitIter::from(self.bytes.iter().cloned()).take(self.len)
+    }
+
+    /// Formatter which uses the value's type to print it readably: words
+    /// of up to 64 bits in decimal and larger words in hex, products as
+    /// tuples `(a, b)` and sums as `L(a)` or `R(b)`, with the parentheses
+    /// omitted when the injected value is the unit `()`
+    pub fn pretty(&self) -> PrettyValue<'_> {
+        PrettyValue(self)
+    }
+}
+
+/// Type-aware formatter for a packed value, obtained from
+/// `PackedValue::pretty`
+pub struct PrettyValue<'a>(&'a PackedValue);
+
+impl<'a> fmt::Display for PrettyValue<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        enum Item<'a> {
+            Ty(&'a FinalType),
+            Str(&'static str),
+        }
+
+        let mut bits = self.0.iter();
+        let mut stack = vec![Item::Ty(&self.0.ty)];
+        while let Some(item) = stack.pop() {
+            let ty = match item {
+                Item::Ty(ty) => ty,
+                Item::Str(s) => {
+                    f.write_str(s)?;
+                    continue;
+                }
+            };
+
+            if ty.word_index().is_some() {
+                let mut word = bits.by_ref().take(ty.bit_width);
+                if ty.bit_width <= 64 {
+                    let n = word.fold(0u64, |acc, bit| (acc << 1) | bit as u64);
+                    write!(f, "{}", n)?;
+                } else {
+                    f.write_str("0x")?;
+                    while let Some(bit) = word.next() {
+                        let nibble = word
+                            .by_ref()
+                            .take(3)
+                            .fold(bit as u8, |acc, bit| (acc << 1) | bit as u8);
+                        write!(f, "{:x}", nibble)?;
+                    }
+                }
+                continue;
+            }
+
+            match ty.ty {
+                FinalTypeInner::Unit => f.write_str("()")?,
+                FinalTypeInner::Sum(ref l, ref r) => {
+                    let bit = bits.next().expect("packed value matches its type");
+                    f.write_str(if bit { "R" } else { "L" })?;
+                    let child = if bit { r } else { l };
+                    if child.ty != FinalTypeInner::Unit {
+                        f.write_str("(")?;
+                        stack.push(Item::Str(")"));
+                        stack.push(Item::Ty(child));
+                    }
+                }
+                FinalTypeInner::Product(ref l, ref r) => {
+                    f.write_str("(")?;
+                    stack.push(Item::Str(")"));
+                    stack.push(Item::Ty(r));
+                    stack.push(Item::Str(", "));
+                    stack.push(Item::Ty(l));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for PackedValue {
+    /// Formats the value exactly as the corresponding `Value`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        enum Item<'a> {
+            Ty(&'a FinalType),
+            Str(&'static str),
+        }
+
+        let mut bits = self.iter();
+        let mut stack = vec![Item::Ty(&self.ty)];
+        while let Some(item) = stack.pop() {
+            let ty = match item {
+                Item::Ty(ty) => ty,
+                Item::Str(s) => {
+                    f.write_str(s)?;
+                    continue;
+                }
+            };
+            match ty.ty {
+                FinalTypeInner::Unit => f.write_str("ε")?,
+                FinalTypeInner::Sum(ref l, ref r) => {
+                    let bit = bits.next().expect("packed value matches its type");
+                    f.write_str(if bit { "1" } else { "0" })?;
+                    let child = if bit { r } else { l };
+                    if child.ty != FinalTypeInner::Unit {
+                        stack.push(Item::Ty(child));
+                    }
+                }
+                FinalTypeInner::Product(ref l, ref r) => {
+                    f.write_str("(")?;
+                    stack.push(Item::Str(")"));
+                    stack.push(Item::Ty(r));
+                    stack.push(Item::Str(","));
+                    stack.push(Item::Ty(l));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::TypeInterner;
+    use std::str::FromStr;
+
+    #[test]
+    fn packed_values() {
+        let ty = Arc::new(FinalType::from_str("(1 + 2^4) × 2 + 2^8").unwrap());
+        for value in &[
+            Value::from_str("0(0, 1)").unwrap(),
+            Value::from_str("0(1((0,1),(1,0)), 0)").unwrap(),
+            Value::sum_r(Value::u8(0xa5)),
+        ] {
+            let packed = PackedValue::from_value(value, &ty).unwrap();
+            assert_eq!(packed.to_value(), *value);
+            assert_eq!(packed.to_string(), value.to_string());
+            assert_eq!(packed.len(), value.len());
+            assert_eq!(packed.iter().collect::<Vec<_>>(), value.clone().into_bits());
+            assert_eq!(packed.as_bytes(), &value.to_bytes()[..]);
+        }
+        assert!(PackedValue::from_value(&Value::u8(1), &ty).is_err());
+
+        // `0(0, 1)` is padded by 2 bits after the first sum and by 4 after the second
+        let mut padded = "000000001".chars().map(|c| c == '1');
+        let packed = PackedValue::from_padded_bits(&mut padded, &ty).unwrap();
+        assert_eq!(padded.next(), None);
+        assert_eq!(packed.to_value(), Value::from_str("0(0, 1)").unwrap());
+    }
+
+    #[test]
+    fn large_packed_value() {
+        let word256 = TypeInterner::new().pow2s()[9].clone();
+        let bytes = [0x5a; 32];
+        let packed =
+            PackedValue::from_bits_and_type(&mut BitIter::from(bytes.iter().cloned()), &word256)
+                .unwrap();
+        assert_eq!(packed.len(), 256);
+        assert_eq!(packed.as_bytes(), &bytes[..]);
+        assert_eq!(packed.to_value(), Value::u256(&bytes));
+    }
+
+    #[test]
+    fn pretty_values() {
+        let outpoint = Arc::new(FinalType::from_str("2^256 × 2^32").unwrap());
+        let value = Value::from_str(
+            "(0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef, 0x00000002) \
+             : 2^256 × 2^32",
+        )
+        .unwrap();
+        let packed = PackedValue::from_value(&value, &outpoint).unwrap();
+        assert_eq!(
+            packed.pretty().to_string(),
+            "(0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef, 2)",
+        );
+
+        let ty = Arc::new(FinalType::from_str("(1 + 2^4) × 2 + (1 × 1 + 2^8)").unwrap());
+        for (s, pretty) in &[
+            ("0(0, 1)", "L((L, 1))"),
+            ("0(1((1,1),(1,0)), 0)", "L((R(14), 0))"),
+            ("1 0(ε, ε)", "R(L(((), ())))"),
+        ] {
+            let value = Value::from_str(s).unwrap();
+            let packed = PackedValue::from_value(&value, &ty).unwrap();
+            assert_eq!(packed.pretty().to_string(), *pretty);
+        }
+    }
+}
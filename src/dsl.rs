@@ -0,0 +1,244 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Expression DSL
+//!
+//! The `dag!` macro, for writing Simplicity expressions in Rust
+//!
+
+/// Write a Simplicity expression, producing an `Arc<DagTerm>`
+///
+/// An expression is one of
+///
+/// * a combinator: `iden`, `unit`, `injl(e)`, `injr(e)`, `take(e)`,
+///   `drop(e)`, `comp(e, f)`, `case(e, f)`, `pair(e, f)` or
+///   `disconnect(e, f)`;
+/// * `witness`, whose value is `Default::default()`, or `witness(w)`
+///   for a Rust expression `w`;
+/// * `jet(name)`, for the jet which is displayed as `name`, such as
+///   `jet(sha256)`. An unknown name is a compile error;
+/// * `ext(e)` or `hidden(cmr)`, for Rust expressions `e` and `cmr`;
+/// * `scribe(v)`, the constant function returning the `Value` `v`;
+/// * the name of an `Arc<DagTerm>` variable, which is shared rather than
+///   copied;
+/// * `{ e }`, for a Rust expression `e` of type `Arc<DagTerm>`.
+///
+/// The expression may be preceded by `let` bindings, such as
+/// `let name = expression;`, to share subexpressions.
+///
+/// Prefixing the whole input with `builder =>`, where `builder` is a
+/// `DagBuilder` variable, builds every node through it. In that mode
+/// `scribe` is not available.
+///
+/// ```
+/// use simplicity::dag;
+/// use simplicity::core::term::DagTerm;
+/// use simplicity::extension::dummy::DummyNode;
+///
+/// let expr: std::sync::Arc<DagTerm<(), DummyNode>> = dag! {
+///     let hash = comp(witness, jet(sha256));
+///     comp(pair(hash, hash), jet(eqv256))
+/// };
+/// // The shared hash is encoded once
+/// assert_eq!(expr.to_untyped_prog().0.len(), 6);
+/// ```
+///
+/// ```compile_fail
+/// use simplicity::dag;
+/// use simplicity::core::term::DagTerm;
+/// use simplicity::extension::dummy::DummyNode;
+///
+/// let expr: std::sync::Arc<DagTerm<(), DummyNode>> = dag!(jet(sha255));
+/// ```
+#[macro_export]
+macro_rules! dag {
+    // Builder mode
+    ($builder:ident => $($rest:tt)+) => {
+        $crate::dag!(@top [$builder] $($rest)+)
+    };
+    // Let bindings
+    (@top [$($b:ident)*] let $name:ident = $($rest:tt)+) => {
+        $crate::dag!(@let [$($b)*] $name [] $($rest)+)
+    };
+    (@top [$($b:ident)*] $($e:tt)+) => {
+        $crate::dag!(@expr [$($b)*] $($e)+)
+    };
+    (@let [$($b:ident)*] $name:ident [$($e:tt)+] ; $($rest:tt)+) => {{
+        let $name = $crate::dag!(@expr [$($b)*] $($e)+);
+        $crate::dag!(@top [$($b)*] $($rest)+)
+    }};
+    (@let [$($b:ident)*] $name:ident [$($e:tt)*] $t:tt $($rest:tt)*) => {
+        $crate::dag!(@let [$($b)*] $name [$($e)* $t] $($rest)*)
+    };
+
+    // Expressions
+    (@expr [$($b:ident)*] iden) => {
+        $crate::dag!(@node [$($b)*] iden Iden)
+    };
+    (@expr [$($b:ident)*] unit) => {
+        $crate::dag!(@node [$($b)*] unit Unit)
+    };
+    (@expr [$($b:ident)*] witness) => {
+        $crate::dag!(@expr [$($b)*] witness(::std::default::Default::default()))
+    };
+    (@expr [$($b:ident)*] witness($w:expr)) => {
+        $crate::dag!(@node [$($b)*] witness Witness $w)
+    };
+    (@expr [$($b:ident)*] hidden($cmr:expr)) => {
+        $crate::dag!(@node [$($b)*] hidden Hidden $cmr)
+    };
+    (@expr [$($b:ident)*] ext($e:expr)) => {
+        $crate::dag!(@node [$($b)*] ext Ext $e)
+    };
+    (@expr [$($b:ident)*] jet($name:ident)) => {
+        $crate::dag!(@node [$($b)*] jet Jet $crate::extension::jets::names::$name)
+    };
+    (@expr [] scribe($v:expr)) => {
+        $crate::core::combinators::scribe(&$v)
+    };
+    (@expr [$($b:ident)*] injl($($e:tt)+)) => {
+        $crate::dag!(@unary [$($b)*] injl InjL $($e)+)
+    };
+    (@expr [$($b:ident)*] injr($($e:tt)+)) => {
+        $crate::dag!(@unary [$($b)*] injr InjR $($e)+)
+    };
+    (@expr [$($b:ident)*] take($($e:tt)+)) => {
+        $crate::dag!(@unary [$($b)*] take Take $($e)+)
+    };
+    (@expr [$($b:ident)*] drop($($e:tt)+)) => {
+        $crate::dag!(@unary [$($b)*] drop Drop $($e)+)
+    };
+    (@expr [$($b:ident)*] comp($($e:tt)+)) => {
+        $crate::dag!(@binary [$($b)*] comp Comp [] $($e)+)
+    };
+    (@expr [$($b:ident)*] case($($e:tt)+)) => {
+        $crate::dag!(@binary [$($b)*] case Case [] $($e)+)
+    };
+    (@expr [$($b:ident)*] pair($($e:tt)+)) => {
+        $crate::dag!(@binary [$($b)*] pair Pair [] $($e)+)
+    };
+    (@expr [$($b:ident)*] disconnect($($e:tt)+)) => {
+        $crate::dag!(@binary [$($b)*] disconnect Disconnect [] $($e)+)
+    };
+    (@expr [$($b:ident)*] { $e:expr }) => {
+        $e
+    };
+    (@expr [$($b:ident)*] $name:ident) => {
+        ::std::sync::Arc::clone(&$name)
+    };
+    (@expr [$($b:ident)*] $($e:tt)*) => {
+        compile_error!(concat!("unknown Simplicity expression: ", stringify!($($e)*)))
+    };
+
+    // Children, which are built before their parent so that the builder
+    // is not borrowed twice
+    (@unary [$($b:ident)*] $method:ident $variant:ident $($e:tt)+) => {{
+        let child = $crate::dag!(@expr [$($b)*] $($e)+);
+        $crate::dag!(@node [$($b)*] $method $variant child)
+    }};
+    (@binary [$($b:ident)*] $method:ident $variant:ident [$($l:tt)+] , $($r:tt)+) => {{
+        let left = $crate::dag!(@expr [$($b)*] $($l)+);
+        let right = $crate::dag!(@expr [$($b)*] $($r)+);
+        $crate::dag!(@node [$($b)*] $method $variant left, right)
+    }};
+    (@binary [$($b:ident)*] $method:ident $variant:ident [$($l:tt)*] $t:tt $($rest:tt)*) => {
+        $crate::dag!(@binary [$($b)*] $method $variant [$($l)* $t] $($rest)*)
+    };
+
+    // Nodes, whose children have been built
+    (@node [] $method:ident $variant:ident) => {
+        ::std::sync::Arc::new($crate::core::term::DagTerm::$variant)
+    };
+    (@node [] $method:ident $variant:ident $l:ident, $r:ident) => {
+        ::std::sync::Arc::new($crate::core::term::DagTerm::$variant($l, $r))
+    };
+    (@node [] $method:ident $variant:ident $e:expr) => {
+        ::std::sync::Arc::new($crate::core::term::DagTerm::$variant($e))
+    };
+    (@node [$b:ident] $method:ident $variant:ident) => {
+        $b.$method()
+    };
+    (@node [$b:ident] $method:ident $variant:ident $l:ident, $r:ident) => {
+        $b.$method(&$l, &$r)
+    };
+    (@node [$b:ident] injl $variant:ident $e:ident) => {
+        $b.injl(&$e)
+    };
+    (@node [$b:ident] injr $variant:ident $e:ident) => {
+        $b.injr(&$e)
+    };
+    (@node [$b:ident] take $variant:ident $e:ident) => {
+        $b.take(&$e)
+    };
+    (@node [$b:ident] drop $variant:ident $e:ident) => {
+        $b.drop(&$e)
+    };
+    (@node [$b:ident] $method:ident $variant:ident $e:expr) => {
+        $b.$method($e)
+    };
+
+    // Plain expressions
+    ($($rest:tt)+) => {
+        $crate::dag!(@top [] $($rest)+)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::builder::DagBuilder;
+    use crate::core::term::DagTerm;
+    use crate::extension::dummy::DummyNode;
+    use crate::Value;
+    use std::sync::Arc;
+
+    #[test]
+    fn expressions() {
+        let pk = Value::u256(&[0x12; 32]);
+        let plain: Arc<DagTerm<(), DummyNode>> = dag! {
+            let sig = witness;
+            let check = comp(pair(scribe(pk), sig), jet(schnorrassert));
+            let sel = take(drop(iden));
+            comp(pair(sel, check), case(injl(unit), injr({ Arc::new(DagTerm::Unit) })))
+        };
+
+        let mut builder = DagBuilder::new();
+        let scribed = crate::core::combinators::scribe(&pk);
+        let built = dag! {
+            builder =>
+            let sig = witness;
+            let check = comp(pair(scribed, sig), jet(schnorrassert));
+            let sel = take(drop(iden));
+            comp(pair(sel, check), case(injl(unit), injr(unit)))
+        };
+        assert_eq!(plain.to_untyped_prog(), built.to_untyped_prog());
+        // Witnesses are never merged, so the two programs are only equal
+        // up to their witness nodes
+        let plain = builder.import(&plain).unwrap();
+        let built = builder.import(&built).unwrap();
+        assert_eq!(plain.to_untyped_prog(), built.to_untyped_prog());
+    }
+
+    #[test]
+    #[cfg(feature = "bitcoin")]
+    fn extensions() {
+        use crate::core::term::Term;
+        use crate::extension::bitcoin::BtcNode;
+        use crate::extension::jets::JetsNode;
+
+        let prog: Arc<DagTerm<(), _>> = dag!(comp(ext(BtcNode::LockTime), jet(le32)));
+        let prog = prog.to_untyped_prog();
+        assert_eq!(prog.0[0], Term::Ext(BtcNode::LockTime));
+        assert_eq!(prog.0[1], Term::Jet(JetsNode::LessThanV32));
+    }
+}
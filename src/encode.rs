@@ -145,7 +145,7 @@ impl BitWrite for Vec<bool> {
     }
 }
 
-impl<'a, B: BitWrite> BitWrite for &'a mut B {
+impl<B: BitWrite> BitWrite for &mut B {
     fn write_bit(&mut self, b: bool) -> io::Result<()> {
         (**self).write_bit(b)
     }
@@ -37,6 +37,8 @@ pub enum DummyNode {}
 impl extension::Jet for DummyNode {
     type TxEnv = TxEnv;
 
+    const ALL: &'static [Self] = &[];
+
     fn decode<I: Iterator<Item = u8>>(_: &mut BitIter<I>) -> Result<DummyNode, Error> {
         Err(Error::ParseError("[unavailable extension]"))
     }
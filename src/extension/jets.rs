@@ -18,315 +18,449 @@
 //! blockchain
 //!
 
-use std::{fmt, io};
+use std::sync::Arc;
 
-use super::TypeName;
 use crate::bitcoin_hashes::{sha256, Hash, HashEngine};
-use crate::bititer::BitIter;
-use crate::cmr::Cmr;
-use crate::encode;
+use crate::cmr::{self, Cmr};
+use crate::core::combinators;
+use crate::core::term::{DagTerm, Value};
 use crate::exec;
-use crate::extension;
-use crate::Error;
 
-/// Set of new Simplicity nodes enabled by the Bitcoin extension
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
-pub enum JetsNode {
-    Adder32,
-    FullAdder32,
-    Subtractor32,
-    FullSubtractor32,
-    Multiplier32,
-    FullMultiplier32,
-    Sha256HashBlock,
-    SchnorrAssert,
-    // Temparory jets for compiler
-    EqV256,
-    Sha256,
-    LessThanV32, // less than verify for u32
-    EqV32,
+/// CMR of a jet which has no specification yet, committing to the
+/// constant of its reference implementation
+fn jet_cmr(constant: [u8; 32]) -> Cmr {
+    Cmr::new(b"Simplicity\x1fJet").update_1(Cmr::from(constant))
 }
 
-impl fmt::Display for JetsNode {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(match *self {
-            JetsNode::Adder32 => "adder32",
-            JetsNode::FullAdder32 => "fulladder32",
-            JetsNode::Subtractor32 => "subtractor32",
-            JetsNode::FullSubtractor32 => "fullsubtractor32",
-            JetsNode::Multiplier32 => "multiplier32",
-            JetsNode::FullMultiplier32 => "fullmultiplier32",
-            JetsNode::Sha256HashBlock => "sha256hashblock",
-            JetsNode::SchnorrAssert => "schnorrassert",
-            JetsNode::EqV256 => "eqv256",
-            JetsNode::Sha256 => "sha256",
-            JetsNode::LessThanV32 => "le32",
-            JetsNode::EqV32 => "eqv32",
-        })
+jet_table! {
+    /// Set of new Simplicity nodes enabled by the Bitcoin extension
+    #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+    pub enum JetsNode {
+        type TxEnv = ();
+        prefix = (0b11, 2);
+        exec = execute;
+
+        Adder32 {
+            name: adder32,
+            source: b"l",
+            target: b"*2i",
+            code: (48 + 0, 6),
+            cmr: Cmr::from([
+                0x4d, 0x68, 0x46, 0x5b, 0x30, 0x64, 0x01, 0x72, 0xa0, 0x7d, 0x1a, 0x84, 0xb7, 0x9d,
+                0x22, 0x2a, 0x98, 0x07, 0xba, 0x13, 0x51, 0x7d, 0x8b, 0x04, 0xd4, 0xa3, 0x13, 0x21,
+                0x09, 0xbf, 0x27, 0x85,
+            ]),
+        },
+        FullAdder32 {
+            name: fulladder32,
+            source: b"*l2",
+            target: b"*2i",
+            code: (48 + 4, 6),
+            cmr: Cmr::from([
+                0x79, 0xd0, 0x3c, 0xc9, 0x5a, 0x8d, 0x59, 0x28, 0x04, 0xde, 0x73, 0x69, 0x58, 0x92,
+                0x50, 0x78, 0x51, 0xa5, 0x67, 0x73, 0x88, 0x4f, 0x63, 0x75, 0xbd, 0xd5, 0x79, 0xd6,
+                0x57, 0x47, 0x12, 0x5b,
+            ]),
+        },
+        Subtractor32 {
+            name: subtractor32,
+            source: b"l",
+            target: b"*2i",
+            code: (48 + 1, 6),
+            cmr: Cmr::from([
+                0x25, 0x25, 0xe1, 0x81, 0xae, 0x52, 0x5d, 0xb2, 0xb8, 0x84, 0x37, 0x5c, 0x02, 0x85,
+                0x42, 0xe1, 0xda, 0x8b, 0xd5, 0x76, 0xd5, 0x80, 0x09, 0x44, 0x7a, 0xab, 0xa7, 0x22,
+                0x1c, 0x1d, 0xba, 0xe5,
+            ]),
+        },
+        FullSubtractor32 {
+            name: fullsubtractor32,
+            source: b"*l2",
+            target: b"*2i",
+            code: (48 + 5, 6),
+            cmr: Cmr::from([
+                0x08, 0xf3, 0x59, 0x0d, 0x34, 0x47, 0xd2, 0xe8, 0x44, 0x58, 0x45, 0x6d, 0x8e, 0x6b,
+                0xd8, 0x85, 0x76, 0x7a, 0x70, 0xb9, 0xe9, 0x18, 0xd2, 0x1d, 0x52, 0xb3, 0x3f, 0xc1,
+                0x21, 0x86, 0xdf, 0xa6,
+            ]),
+        },
+        Multiplier32 {
+            name: multiplier32,
+            source: b"l",
+            target: b"l",
+            code: (24 + 1, 5),
+            cmr: Cmr::from([
+                0x69, 0xb5, 0x8a, 0x47, 0x13, 0x3f, 0xb3, 0x52, 0x89, 0xab, 0x88, 0xf5, 0x8a, 0xda,
+                0x78, 0x0c, 0xb4, 0x97, 0x25, 0x5c, 0xde, 0xac, 0x3f, 0x2b, 0xa0, 0x24, 0xf4, 0xec,
+                0x2c, 0x05, 0xd9, 0x9d,
+            ]),
+        },
+        FullMultiplier32 {
+            name: fullmultiplier32,
+            source: b"*ll",
+            target: b"l",
+            code: (24 + 3, 5),
+            cmr: Cmr::from([
+                0x64, 0x43, 0x13, 0x78, 0x67, 0x8e, 0x85, 0x1f, 0x87, 0xa4, 0xa4, 0x28, 0xa9, 0x0e,
+                0xea, 0x76, 0x14, 0x08, 0x82, 0x15, 0x46, 0xc8, 0x1c, 0x3b, 0x39, 0x15, 0x1a, 0x19,
+                0x5f, 0x24, 0x98, 0x60,
+            ]),
+        },
+        Sha256HashBlock {
+            name: sha256hashblock,
+            source: b"*h*hh",
+            target: b"h",
+            code: (14, 4),
+            cmr: Cmr::from([
+                0xf6, 0x8b, 0x2f, 0xf5, 0x2f, 0x65, 0x1a, 0x2e, 0x42, 0x59, 0xf9, 0x90, 0x0f, 0xb5,
+                0xf3, 0x3f, 0xb2, 0xa9, 0xfc, 0x7b, 0x51, 0x4d, 0x55, 0xc4, 0x69, 0x8d, 0x84, 0x8c,
+                0x20, 0x20, 0x80, 0x7b,
+            ]),
+        },
+        SchnorrAssert {
+            name: schnorrassert,
+            source: b"*h*hh",
+            target: b"1",
+            code: (15 * 16 + 0, 8),
+            cmr: jet_cmr([
+                0xee, 0xae, 0x47, 0xe2, 0xf7, 0x87, 0x6c, 0x3b, 0x9c, 0xbc, 0xd4, 0x04, 0xa3, 0x38,
+                0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
+                0x31, 0xba, 0xec, 0x9b,
+            ]),
+        },
+        // Temporary jets for the compiler
+        EqV256 {
+            name: eqv256,
+            source: b"*hh",
+            target: b"1",
+            code: (15 * 16 + 1, 8),
+            cmr: Cmr::from([
+                0xd9, 0x5e, 0x39, 0xb9, 0x65, 0x1e, 0x25, 0xb3, 0xd5, 0x17, 0x6b, 0xc5, 0xeb, 0x7f,
+                0x18, 0x35, 0x9a, 0x88, 0x19, 0x88, 0x7f, 0x18, 0xbd, 0xbb, 0xd7, 0xe6, 0xce, 0xf1,
+                0x72, 0x3a, 0x19, 0x2e,
+            ]),
+        },
+        Sha256 {
+            name: sha256,
+            source: b"*hh",
+            target: b"h",
+            code: (15 * 16 + 2, 8),
+            cmr: Cmr::from([
+                0xaf, 0xc8, 0x5e, 0xa3, 0xb4, 0x28, 0x60, 0xe5, 0x6e, 0x25, 0x4c, 0x5f, 0xce, 0x4f,
+                0x1b, 0xa1, 0xbf, 0xaf, 0xb1, 0xd9, 0x44, 0xa4, 0x3d, 0xe3, 0x19, 0xda, 0xf1, 0x84,
+                0xe3, 0x32, 0x74, 0x22,
+            ]),
+        },
+        LessThanV32 {
+            name: le32,
+            source: b"l",
+            target: b"1",
+            code: (15 * 16 + 3, 8),
+            cmr: Cmr::from([
+                0x55, 0xc5, 0x98, 0x76, 0x8f, 0xa7, 0x09, 0x48, 0xe1, 0x93, 0xa2, 0x26, 0x7b, 0x2d,
+                0xcd, 0xfb, 0x49, 0x66, 0xe3, 0x18, 0x48, 0x55, 0x61, 0x1b, 0x1b, 0x58, 0x4f, 0x73,
+                0x52, 0x86, 0x6b, 0x3e,
+            ]),
+        },
+        EqV32 {
+            name: eqv32,
+            source: b"l",
+            target: b"1",
+            code: (15 * 16 + 4, 8),
+            cmr: Cmr::from([
+                0x89, 0x62, 0x52, 0x68, 0xe4, 0xc2, 0x0b, 0x13, 0x2a, 0x29, 0x83, 0xe9, 0xbf, 0x97,
+                0xc7, 0x8a, 0x11, 0x00, 0x10, 0x8b, 0x22, 0xf0, 0x6a, 0x75, 0xec, 0x15, 0xbb, 0xaf,
+                0x07, 0xd4, 0xf1, 0x8d,
+            ]),
+        },
     }
 }
 
-impl extension::Jet for JetsNode {
-    type TxEnv = ();
-    /// Name of the source type for this node
-    fn source_type(&self) -> TypeName {
-        match *self {
-            JetsNode::Adder32 => TypeName(b"l"),
-            JetsNode::FullAdder32 => TypeName(b"*l2"),
-            JetsNode::Subtractor32 => TypeName(b"l"),
-            JetsNode::FullSubtractor32 => TypeName(b"*l2"),
-            JetsNode::Multiplier32 => TypeName(b"l"),
-            JetsNode::FullMultiplier32 => TypeName(b"*ll"),
-            JetsNode::Sha256HashBlock => TypeName(b"*h*hh"),
-            JetsNode::SchnorrAssert => TypeName(b"*h*hh"),
-            JetsNode::EqV256 => TypeName(b"*hh"),
-            JetsNode::Sha256 => TypeName(b"*hh"),
-            JetsNode::LessThanV32 => TypeName(b"l"),
-            JetsNode::EqV32 => TypeName(b"l"),
+/// Execute a node in the Bit Machine
+fn execute(node: &JetsNode, mac: &mut exec::BitMachine, _tx_env: &()) {
+    match *node {
+        JetsNode::Adder32 => {
+            let a = mac.read_u32();
+            let b = mac.read_u32();
+            let (res, overflow) = a.overflowing_add(b);
+            mac.write_bit(overflow);
+            mac.write_u32(res);
         }
-    }
+        JetsNode::FullAdder32 => {
+            let a = mac.read_u32();
+            let b = mac.read_u32();
+            let carry = mac.read_bit();
+            let (res, overflow_1) = a.overflowing_add(b);
+            let (res, overflow_2) = res.overflowing_add(carry as u32);
+            mac.write_bit(overflow_1 || overflow_2);
+            mac.write_u32(res);
+        }
+        JetsNode::Subtractor32 => {
+            let a = mac.read_u32();
+            let b = mac.read_u32();
+            let (res, overflow) = a.overflowing_sub(b);
+            mac.write_bit(overflow);
+            mac.write_u32(res);
+        }
+        JetsNode::FullSubtractor32 => {
+            let a = mac.read_u32();
+            let b = mac.read_u32();
+            let carry = mac.read_bit();
+            let (res, overflow_1) = a.overflowing_sub(b);
+            let (res, overflow_2) = res.overflowing_sub(carry as u32);
+            mac.write_bit(overflow_1 || overflow_2);
+            mac.write_u32(res);
+        }
+        JetsNode::Multiplier32 => {
+            let a = mac.read_u32() as u64;
+            let b = mac.read_u32() as u64;
+            mac.write_u64(a * b);
+        }
+        JetsNode::FullMultiplier32 => {
+            let a = mac.read_u32() as u64;
+            let b = mac.read_u32() as u64;
+            let c = mac.read_u32() as u64;
+            let d = mac.read_u32() as u64;
+            mac.write_u64(a * b + c + d);
+        }
+        JetsNode::Sha256HashBlock => {
+            let hash = mac.read_32bytes();
+            let block = mac.read_bytes(64);
+            let sha2_midstate = sha256::Midstate::from_inner(hash);
+            let mut engine = sha256::HashEngine::from_midstate(sha2_midstate, 0);
+            engine.input(&block);
+            let h = sha256::Hash::from_engine(engine).into_inner();
+            mac.write_bytes(&h);
+        }
+        JetsNode::SchnorrAssert => {
+            let _pubkey = mac.read_32bytes();
+            let _sig = mac.read_bytes(64);
+            //Check the signature here later
+        }
+        JetsNode::EqV256 => {
+            let a = mac.read_32bytes();
+            let b = mac.read_32bytes();
 
-    /// Name of the target type for this node
-    fn target_type(&self) -> TypeName {
-        match *self {
-            JetsNode::Adder32 => TypeName(b"*2i"),
-            JetsNode::FullAdder32 => TypeName(b"*2i"),
-            JetsNode::Subtractor32 => TypeName(b"*2i"),
-            JetsNode::FullSubtractor32 => TypeName(b"*2i"),
-            JetsNode::Multiplier32 => TypeName(b"l"),
-            JetsNode::FullMultiplier32 => TypeName(b"l"),
-            JetsNode::Sha256HashBlock => TypeName(b"h"),
-            JetsNode::SchnorrAssert => TypeName(b"1"),
-            JetsNode::EqV256 => TypeName(b"1"),
-            JetsNode::Sha256 => TypeName(b"h"),
-            JetsNode::LessThanV32 => TypeName(b"1"),
-            JetsNode::EqV32 => TypeName(b"1"),
+            // FIXME:
+            // Get Error here instead of assert
+            assert!(a == b);
         }
-    }
+        JetsNode::Sha256 => {
+            let data = mac.read_32bytes();
+            let h = sha256::Hash::hash(&data);
 
-    /// CMR for this node
-    fn cmr(&self) -> Cmr {
-        let cmr = Cmr::new(b"Simplicity\x1fJet");
-        match *self {
-            JetsNode::Adder32 => cmr.update_1(Cmr::from([
-                0x8e, 0x38, 0x9a, 0x7d, 0x75, 0x42, 0x9a, 0x8a, 0x6f, 0x5b, 0x44, 0x8e, 0xc8, 0xe8,
-                0x45, 0x85, 0x20, 0xe2, 0x76, 0xfc, 0x8e, 0x09, 0xef, 0x5a, 0x68, 0xf3, 0xf3, 0x2d,
-                0x9f, 0xb9, 0x79, 0x35,
-            ])),
-            JetsNode::FullAdder32 => cmr.update_1(Cmr::from([
-                0xb9, 0x14, 0xe4, 0xb5, 0x9f, 0x8e, 0xde, 0xd4, 0xcd, 0x03, 0x6e, 0x03, 0xff, 0xa5,
-                0xf1, 0x1a, 0xa8, 0x66, 0x8a, 0xe4, 0x98, 0x63, 0xbb, 0xb4, 0x3a, 0x0d, 0x7c, 0x3a,
-                0x14, 0xc9, 0x16, 0xf0,
-            ])),
-            JetsNode::Subtractor32 => cmr.update_1(Cmr::from([
-                0x75, 0xeb, 0xd5, 0x69, 0xbf, 0xce, 0x7a, 0xf8, 0x03, 0x0c, 0x49, 0xc7, 0x3e, 0x10,
-                0x4c, 0x03, 0x65, 0xde, 0x89, 0x8e, 0xa8, 0xd5, 0x26, 0x70, 0xbf, 0xfe, 0x9f, 0x6e,
-                0x31, 0x2f, 0xf6, 0xe6,
-            ])),
-            JetsNode::FullSubtractor32 => cmr.update_1(Cmr::from([
-                0x7a, 0x52, 0xe8, 0x3e, 0x25, 0x3a, 0xe7, 0x76, 0xb0, 0xb9, 0x48, 0xf1, 0x50, 0x83,
-                0x52, 0x8e, 0x1c, 0x5d, 0x58, 0xcd, 0x5e, 0x03, 0xd4, 0xf2, 0xf0, 0x4a, 0x96, 0x26,
-                0xe0, 0x47, 0x6a, 0xeb,
-            ])),
-            JetsNode::Multiplier32 => cmr.update_1(Cmr::from([
-                0x40, 0x59, 0x14, 0xc9, 0x52, 0x4c, 0x48, 0x73, 0xce, 0x5d, 0xdb, 0x06, 0xfd, 0x30,
-                0xd6, 0xd5, 0xfc, 0x4a, 0xc1, 0xfa, 0xc0, 0xee, 0xf8, 0xd8, 0x2d, 0xe6, 0xc6, 0x22,
-                0x7f, 0xb2, 0xd2, 0xcd,
-            ])),
-            JetsNode::FullMultiplier32 => cmr.update_1(Cmr::from([
-                0x89, 0xa0, 0xae, 0x09, 0x8a, 0xff, 0x5e, 0x9c, 0x40, 0x90, 0x74, 0x47, 0x91, 0xff,
-                0x5c, 0x8e, 0xe1, 0x7a, 0x8c, 0xeb, 0x9e, 0x49, 0x42, 0x24, 0xe9, 0x19, 0xde, 0xb1,
-                0x1c, 0x5b, 0x8a, 0xf4,
-            ])),
-            JetsNode::Sha256HashBlock => cmr.update_1(Cmr::from([
-                0xee, 0xae, 0x47, 0xe2, 0xf7, 0x87, 0x6c, 0x3b, 0x9c, 0xbc, 0xd4, 0x04, 0xa3, 0x38,
-                0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
-                0x31, 0xba, 0xec, 0x9a,
-            ])),
-            JetsNode::SchnorrAssert => cmr.update_1(Cmr::from([
-                0xee, 0xae, 0x47, 0xe2, 0xf7, 0x87, 0x6c, 0x3b, 0x9c, 0xbc, 0xd4, 0x04, 0xa3, 0x38,
-                0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
-                0x31, 0xba, 0xec, 0x9b, //only last `a` changed to `b` from sha2 block cmr
-            ])),
-            JetsNode::EqV256 => cmr.update_1(Cmr::from([
-                0xee, 0xae, 0x47, 0xe2, 0xf7, 0x87, 0x6c, 0x3b, 0x9c, 0xbc, 0xd4, 0x04, 0xa3, 0x38,
-                0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
-                0x31, 0xba, 0xec, 0x9c, //only last `a` changed to `c` from sha2 block cmr
-            ])),
-            JetsNode::Sha256 => cmr.update_1(Cmr::from([
-                0xee, 0xae, 0x47, 0xe2, 0xf7, 0x87, 0x6c, 0x3b, 0x9c, 0xbc, 0xd4, 0x04, 0xa3, 0x38,
-                0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
-                0x31, 0xba, 0xec, 0x9d, //only last `a` changed to `d` from sha2 block cmr
-            ])),
-            JetsNode::LessThanV32 => cmr.update_1(Cmr::from([
-                0xee, 0xae, 0x47, 0xe2, 0xf7, 0x87, 0x6c, 0x3b, 0x9c, 0xbc, 0xd4, 0x04, 0xa3, 0x38,
-                0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
-                0x31, 0xba, 0xec, 0x9e, //only last `a` changed to `e` from sha2 block cmr
-            ])),
-            JetsNode::EqV32 => cmr.update_1(Cmr::from([
-                0xee, 0xae, 0x47, 0xe2, 0xf7, 0x87, 0x6c, 0x3b, 0x9c, 0xbc, 0xd4, 0x04, 0xa3, 0x38,
-                0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
-                0x31, 0xba, 0xec, 0x9f, //only last `a` changed to `f` from sha2 block cmr
-            ])),
+            mac.write_bytes(&h);
         }
-    }
+        JetsNode::LessThanV32 => {
+            let a = mac.read_u32();
+            let b = mac.read_u32();
 
-    /// Encode the node into a bitstream
-    fn encode<W: encode::BitWrite>(&self, w: &mut W) -> io::Result<usize> {
-        match *self {
-            JetsNode::Adder32 => w.write_u8(48 + 0, 6),
-            JetsNode::Subtractor32 => w.write_u8(48 + 1, 6),
-            JetsNode::Multiplier32 => w.write_u8(24 + 1, 5),
-            JetsNode::FullAdder32 => w.write_u8(48 + 2, 6),
-            JetsNode::FullSubtractor32 => w.write_u8(48 + 3, 6),
-            JetsNode::FullMultiplier32 => w.write_u8(24 + 3, 5),
-            JetsNode::Sha256HashBlock => w.write_u8(14, 4),
-            JetsNode::SchnorrAssert => w.write_u8(15 * 16 + 0, 8),
-            JetsNode::EqV256 => w.write_u8(15 * 16 + 1, 8),
-            JetsNode::Sha256 => w.write_u8(15 * 16 + 2, 8),
-            JetsNode::LessThanV32 => w.write_u8(15 * 16 + 3, 8),
-            JetsNode::EqV32 => w.write_u8(15 * 16 + 3, 8),
+            // FIXME: error
+            assert!(a < b);
         }
-    }
+        JetsNode::EqV32 => {
+            let a = mac.read_u32();
+            let b = mac.read_u32();
 
-    /// Decode a natural number according to section 7.2.1
-    /// of the Simplicity whitepaper. Assumes that a 11 has
-    /// already been read from the stream
-    fn decode<I: Iterator<Item = u8>>(iter: &mut BitIter<I>) -> Result<Self, Error> {
-        match iter.next() {
-            Some(false) => {
-                let code = match iter.read_bits_be(2) {
-                    Some(code) => code,
-                    None => return Err(Error::EndOfStream),
-                };
-                match code {
-                    0 => match iter.next() {
-                        Some(false) => Ok(JetsNode::Adder32),
-                        Some(true) => Ok(JetsNode::Subtractor32),
-                        None => Err(Error::EndOfStream),
-                    },
-                    1 => Ok(JetsNode::Multiplier32),
-                    2 => match iter.next() {
-                        Some(false) => Ok(JetsNode::FullAdder32),
-                        Some(true) => Ok(JetsNode::FullSubtractor32),
-                        None => Err(Error::EndOfStream),
-                    },
-                    3 => Ok(JetsNode::FullMultiplier32),
-                    _ => unreachable!(),
-                }
-            }
-            Some(true) => match iter.next() {
-                Some(false) => Ok(JetsNode::Sha256HashBlock),
-                Some(true) => {
-                    // Some custom jets for fast developement
-                    // FIXME: Get a consensus for encoding with Rusell
-                    let code = match iter.read_bits_be(4) {
-                        Some(code) => code,
-                        None => return Err(Error::EndOfStream),
-                    };
-                    match code {
-                        0 => Ok(JetsNode::SchnorrAssert),
-                        1 => Ok(JetsNode::EqV256),
-                        2 => Ok(JetsNode::Sha256),
-                        3 => Ok(JetsNode::LessThanV32),
-                        4 => Ok(JetsNode::EqV32),
-                        _ => unreachable!(),
-                    }
-                }
-                None => Err(Error::EndOfStream),
-            },
-            None => Err(Error::EndOfStream),
+            // FIXME: error
+            assert!(a == b);
         }
     }
+}
 
-    fn exec(&self, mac: &mut exec::BitMachine, _tx_env: &Self::TxEnv) {
-        match *self {
-            JetsNode::Adder32 => {
-                let a = mac.read_u32();
-                let b = mac.read_u32();
-                let (res, overflow) = a.overflowing_add(b);
-                mac.write_bit(overflow);
-                mac.write_u32(res);
-            }
-            JetsNode::FullAdder32 => {
-                let a = mac.read_u32();
-                let b = mac.read_u32();
-                let carry = mac.read_bit();
-                let (res, overflow_1) = a.overflowing_add(b);
-                let (res, overflow_2) = res.overflowing_add(carry as u32);
-                mac.write_bit(overflow_1 || overflow_2);
-                mac.write_u32(res);
-            }
-            JetsNode::Subtractor32 => {
-                let a = mac.read_u32();
-                let b = mac.read_u32();
-                let (res, overflow) = a.overflowing_sub(b);
-                mac.write_bit(overflow);
-                mac.write_u32(res);
-            }
-            JetsNode::FullSubtractor32 => {
-                let a = mac.read_u32();
-                let b = mac.read_u32();
-                let carry = mac.read_bit();
-                let (res, overflow_1) = a.overflowing_sub(b);
-                let (res, overflow_2) = res.overflowing_sub(carry as u32);
-                mac.write_bit(overflow_1 || overflow_2);
-                mac.write_u32(res);
-            }
+/// Initial midstate of SHA-256, as big-endian words
+const SHA256_IV: [u8; 32] = [
+    0x6a, 0x09, 0xe6, 0x67, 0xbb, 0x67, 0xae, 0x85, 0x3c, 0x6e, 0xf3, 0x72, 0xa5, 0x4f, 0xf5, 0x3a,
+    0x51, 0x0e, 0x52, 0x7f, 0x9b, 0x05, 0x68, 0x8c, 0x1f, 0x83, 0xd9, 0xab, 0x5b, 0xe0, 0xcd, 0x19,
+];
+
+/// SHA-256 padding completing a message of `len` bytes to a whole
+/// number of blocks, for `len` a multiple of 32
+fn sha256_padding(len: u64) -> Value {
+    let mut lo = [0; 32];
+    for (n, byte) in lo[24..].iter_mut().enumerate() {
+        *byte = ((len * 8) >> (56 - 8 * n)) as u8;
+    }
+    if len % 64 == 32 {
+        lo[0] = 0x80;
+        Value::u256(&lo)
+    } else {
+        let mut hi = [0; 32];
+        hi[0] = 0x80;
+        Value::prod(Value::u256(&hi), Value::u256(&lo))
+    }
+}
+
+impl JetsNode {
+    /// Pure Simplicity expression computing the same function as `exec`,
+    /// or `None` for `SchnorrAssert`, which does not check its signature
+    /// yet and so has nothing to specify
+    ///
+    /// The CMR of every other jet is the CMR of its specification.
+    /// `Sha256HashBlock` and `Sha256` follow `exec` in padding their input
+    /// as a complete message: the former hashes a single 512-bit block
+    /// from the given midstate, the latter the first 256 bits of its
+    /// input.
+    pub fn spec<W, E>(&self) -> Option<Arc<DagTerm<W, E>>> {
+        // Fail unless the predicate is true, pruning a `unit` branch
+        let verify = |predicate: Arc<DagTerm<W, E>>| {
+            let fail = combinators::assertr(cmr::tag::unit(), &dag!(unit));
+            dag!(comp(pair({ predicate }, unit), { fail }))
+        };
+        let zero = combinators::zero;
+
+        let spec = match *self {
+            JetsNode::Adder32 => dag!(comp(pair(iden, { zero() }), { combinators::full_adder(5) })),
+            JetsNode::FullAdder32 => combinators::full_adder(5),
+            JetsNode::Subtractor32 => dag!(comp(pair(iden, { zero() }), {
+                combinators::full_subtractor(5)
+            })),
+            JetsNode::FullSubtractor32 => combinators::full_subtractor(5),
             JetsNode::Multiplier32 => {
-                let a = mac.read_u32() as u64;
-                let b = mac.read_u32() as u64;
-                mac.write_u64(a * b);
-            }
-            JetsNode::FullMultiplier32 => {
-                let a = mac.read_u32() as u64;
-                let b = mac.read_u32() as u64;
-                let c = mac.read_u32() as u64;
-                let d = mac.read_u32() as u64;
-                mac.write_u64(a * b + c + d);
+                let zeros = Value::prod(Value::u32(0), Value::u32(0));
+                dag!(comp(pair(iden, scribe(zeros)), {
+                    combinators::full_multiplier(5)
+                }))
             }
+            JetsNode::FullMultiplier32 => combinators::full_multiplier(5),
             JetsNode::Sha256HashBlock => {
-                let hash = mac.read_32bytes();
-                let block = mac.read_bytes(64);
-                let sha2_midstate = sha256::Midstate::from_inner(hash);
-                let mut engine = sha256::HashEngine::from_midstate(sha2_midstate, 0);
-                engine.input(&block);
-                let h = sha256::Hash::from_engine(engine).into_inner();
-                mac.write_bytes(&h);
+                let block = combinators::sha256_block();
+                dag!(comp(pair(block, scribe(sha256_padding(64))), block))
             }
-            JetsNode::SchnorrAssert => {
-                let _pubkey = mac.read_32bytes();
-                let _sig = mac.read_bytes(64);
-                //Check the signature here later
+            JetsNode::SchnorrAssert => return None,
+            JetsNode::EqV256 => verify(combinators::eq(8)),
+            JetsNode::Sha256 => {
+                let iv = Value::u256(&SHA256_IV);
+                let message = dag!(pair(take(iden), scribe(sha256_padding(32))));
+                dag!(comp(pair(scribe(iv), message), {
+                    combinators::sha256_block()
+                }))
             }
-            JetsNode::EqV256 => {
-                let a = mac.read_32bytes();
-                let b = mac.read_32bytes();
+            JetsNode::LessThanV32 => verify(dag!(comp(
+                comp(pair(iden, { zero() }), { combinators::full_subtractor(5) }),
+                take(iden)
+            ))),
+            JetsNode::EqV32 => verify(combinators::eq(5)),
+        };
+        Some(spec)
+    }
+}
 
-                // FIXME:
-                // Get Error here instead of assert
-                assert!(a == b);
-            }
-            JetsNode::Sha256 => {
-                let data = mac.read_32bytes();
-                let h = sha256::Hash::hash(&data);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::TypeInterner;
+    use crate::extension::dummy::DummyNode;
+    use crate::extension::Jet;
+    use crate::test_utils;
+    use crate::{Term, UnTypedProg};
 
-                mac.write_bytes(&h);
-            }
-            JetsNode::LessThanV32 => {
-                let a = mac.read_u32();
-                let b = mac.read_u32();
+    /// xorshift64, so that failures are reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        fn u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
 
-                // FIXME: error
-                assert!(a < b);
+        fn u256(&mut self) -> Value {
+            let mut bytes = [0; 32];
+            for chunk in bytes.chunks_mut(8) {
+                let word = self.u64();
+                for (n, byte) in chunk.iter_mut().enumerate() {
+                    *byte = (word >> (56 - 8 * n)) as u8;
+                }
             }
-            JetsNode::EqV32 => {
-                let a = mac.read_u32();
-                let b = mac.read_u32();
+            Value::u256(&bytes)
+        }
+    }
+
+    /// Run the program, at the type of the jet, on each input, returning
+    /// `None` where it fails
+    fn run(
+        program: UnTypedProg<(), DummyNode>,
+        jet: JetsNode,
+        inputs: &[Value],
+    ) -> Vec<Option<Value>> {
+        let mut interner = TypeInterner::new();
+        let source = jet.source_type().to_final(&mut interner).unwrap();
+        let target = jet.target_type().to_final(&mut interner).unwrap();
+        test_utils::run(program, &source, &target, inputs)
+    }
+
+    #[test]
+    fn specs_match_exec() {
+        let mut rng = Rng(0x0123_4567_89ab_cdef);
+        for jet in JetsNode::ALL {
+            let samples = match *jet {
+                JetsNode::Sha256HashBlock | JetsNode::Sha256 => 2,
+                _ => 16,
+            };
+            let inputs = (0..samples)
+                .map(|i| match *jet {
+                    JetsNode::Adder32
+                    | JetsNode::Subtractor32
+                    | JetsNode::Multiplier32
+                    | JetsNode::LessThanV32 => Value::u64(rng.u64()),
+                    JetsNode::FullAdder32 | JetsNode::FullSubtractor32 => {
+                        Value::prod(Value::u64(rng.u64()), Value::u1(i as u8 & 1))
+                    }
+                    JetsNode::FullMultiplier32 => {
+                        Value::prod(Value::u64(rng.u64()), Value::u64(rng.u64()))
+                    }
+                    JetsNode::Sha256HashBlock | JetsNode::SchnorrAssert => {
+                        Value::prod(rng.u256(), Value::prod(rng.u256(), rng.u256()))
+                    }
+                    JetsNode::Sha256 => Value::prod(rng.u256(), rng.u256()),
+                    // Compare equal words half of the time
+                    JetsNode::EqV256 => {
+                        let a = rng.u256();
+                        let b = if i % 2 == 0 { a.clone() } else { rng.u256() };
+                        Value::prod(a, b)
+                    }
+                    JetsNode::EqV32 => {
+                        let a = rng.u64() as u32;
+                        let b = if i % 2 == 0 { a } else { rng.u64() as u32 };
+                        Value::prod(Value::u32(a), Value::u32(b))
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            let spec = match jet.spec::<(), DummyNode>() {
+                Some(spec) => spec.to_untyped_prog(),
+                None => continue,
+            };
+            let expected = run(UnTypedProg(vec![Term::Jet(*jet)]), *jet, &inputs);
+            assert_eq!(run(spec, *jet, &inputs), expected, "{}", jet);
+        }
+    }
 
-                // FIXME: error
-                assert!(a == b);
+    #[test]
+    fn spec_cmrs() {
+        // Every jet but `SchnorrAssert` commits to its specification
+        for jet in JetsNode::ALL {
+            match jet.spec::<(), DummyNode>() {
+                Some(spec) => assert_eq!(spec.cmr(), jet.cmr(), "{}", jet),
+                None => assert_eq!(*jet, JetsNode::SchnorrAssert),
             }
         }
     }
+
+    #[test]
+    fn sha256_spec() {
+        // The hash of 32 zero bytes
+        let expected = sha256::Hash::hash(&[0; 32]).into_inner();
+        let input = Value::prod(Value::u256(&[0; 32]), Value::u256(&[0xff; 32]));
+        let spec = JetsNode::Sha256
+            .spec::<(), DummyNode>()
+            .unwrap()
+            .to_untyped_prog();
+        assert_eq!(
+            run(spec, JetsNode::Sha256, &[input]),
+            vec![Some(Value::u256(&expected))]
+        );
+    }
 }
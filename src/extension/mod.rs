@@ -17,6 +17,122 @@
 //! Extensions to the Simplicity language needed for blockchain support
 //!
 
+/// Declare a set of nodes from a single table, generating the enum, its
+/// `Display` implementation and its `Jet` implementation
+///
+/// Each node gives its name, which must be an identifier, the names of its
+/// source and target types,
+/// its code as a `(value, length)` pair of bits and its CMR. Codes start
+/// with the `prefix` bits which are read before `Jet::decode` is called,
+/// and must be prefix-free, since `decode` reads bits until they match
+/// a code. `exec` names a function executing a node.
+///
+/// The macro also generates a module `names` holding each node as a
+/// constant named by the node's name, so that names can be resolved at
+/// compile time.
+macro_rules! jet_table {
+    (
+        $(#[$attr:meta])*
+        pub enum $name:ident {
+            type TxEnv = $txenv:ty;
+            prefix = ($prefix:expr, $prefix_len:expr);
+            exec = $exec:path;
+
+            $(
+                $(#[$variant_attr:meta])*
+                $variant:ident {
+                    name: $display:ident,
+                    source: $source:expr,
+                    target: $target:expr,
+                    code: ($code:expr, $len:expr),
+                    cmr: $cmr:expr,
+                },
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        pub enum $name {
+            $($(#[$variant_attr])* $variant,)*
+        }
+
+        impl ::std::fmt::Display for $name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                f.write_str(match *self {
+                    $($name::$variant => stringify!($display),)*
+                })
+            }
+        }
+
+        /// Every node, named by its name
+        #[allow(non_upper_case_globals)]
+        pub mod names {
+            $(
+                pub const $display: super::$name = super::$name::$variant;
+            )*
+        }
+
+        impl $crate::extension::Jet for $name {
+            type TxEnv = $txenv;
+
+            const ALL: &'static [Self] = &[$($name::$variant,)*];
+
+            fn decode<I: Iterator<Item = u8>>(
+                iter: &mut $crate::bititer::BitIter<I>,
+            ) -> Result<Self, $crate::Error> {
+                let codes: &[(Self, u8, usize)] = &[$(($name::$variant, $code, $len),)*];
+                let (mut code, mut len): (u8, usize) = ($prefix, $prefix_len);
+                loop {
+                    match iter.next() {
+                        Some(bit) => {
+                            code = code << 1 | bit as u8;
+                            len += 1;
+                        }
+                        None => return Err($crate::Error::EndOfStream),
+                    }
+                    if let Some(&(node, ..)) = codes.iter().find(|c| (c.1, c.2) == (code, len)) {
+                        return Ok(node);
+                    }
+                    if !codes.iter().any(|c| c.2 > len && c.1 >> (c.2 - len) == code) {
+                        return Err($crate::Error::ParseError(concat!(
+                            "[unassigned ",
+                            stringify!($name),
+                            " code]"
+                        )));
+                    }
+                }
+            }
+
+            fn encode<W: $crate::encode::BitWrite>(&self, w: &mut W) -> ::std::io::Result<usize> {
+                match *self {
+                    $($name::$variant => w.write_u8($code, $len),)*
+                }
+            }
+
+            fn exec(&self, mac: &mut $crate::exec::BitMachine, txenv: &Self::TxEnv) {
+                $exec(self, mac, txenv)
+            }
+
+            fn cmr(&self) -> $crate::cmr::Cmr {
+                match *self {
+                    $($name::$variant => $cmr,)*
+                }
+            }
+
+            fn source_type(&self) -> $crate::extension::TypeName {
+                match *self {
+                    $($name::$variant => $crate::extension::TypeName($source),)*
+                }
+            }
+
+            fn target_type(&self) -> $crate::extension::TypeName {
+                match *self {
+                    $($name::$variant => $crate::extension::TypeName($target),)*
+                }
+            }
+        }
+    };
+}
+
 #[cfg(feature = "bitcoin")]
 pub mod bitcoin;
 pub mod dummy;
@@ -24,10 +140,11 @@ pub mod dummy;
 pub mod elements;
 pub mod jets;
 
-use std::{fmt, io};
+use std::{fmt, io, sync::Arc};
 
 use crate::bititer::BitIter;
 use crate::cmr::Cmr;
+use crate::core::types::{FinalType, TypeInterner};
 use crate::encode;
 use crate::exec;
 use crate::Error;
@@ -46,17 +163,71 @@ pub use self::dummy as bitcoin;
 /// a prefix (Polish) notation, where + and * represent sum and product types
 /// respectively, and base types are represented by:
 ///
-/// | char | type         |
-/// |------|--------------|
-/// | `1`  | unit         |
-/// | `2`  | single bit   |
-/// | `i`  | 32-bit word  |
-/// | `l`  | 64-bit word  |
-/// | `h`  | 256-bit word |
+/// | char | type          |
+/// |------|---------------|
+/// | `1`  | unit          |
+/// | `2`  | single bit    |
+/// | `t`  | 2-bit word    |
+/// | `n`  | 4-bit word    |
+/// | `c`  | 8-bit word    |
+/// | `s`  | 16-bit word   |
+/// | `i`  | 32-bit word   |
+/// | `l`  | 64-bit word   |
+/// | `w`  | 128-bit word  |
+/// | `h`  | 256-bit word  |
+/// | `m`  | 512-bit word  |
 ///
 #[derive(Clone)]
 pub struct TypeName(pub &'static [u8]);
 
+/// Letters for the word types `2^(2^i)`, indexed by `i`
+pub(crate) const WORD_LETTERS: &[u8; 10] = b"2tncsilwhm";
+
+impl TypeName {
+    /// Parse the name into a type, returning an error if it contains an
+    /// unknown character, ends early or has trailing characters
+    pub fn to_final(&self, interner: &mut TypeInterner) -> Result<Arc<FinalType>, Error> {
+        fn parse(name: &mut TypeName, interner: &mut TypeInterner) -> Option<Arc<FinalType>> {
+            match name.next()? {
+                b'1' => Some(interner.unit()),
+                b'+' => {
+                    let a = parse(name, interner)?;
+                    let b = parse(name, interner)?;
+                    Some(interner.sum(a, b))
+                }
+                b'*' => {
+                    let a = parse(name, interner)?;
+                    let b = parse(name, interner)?;
+                    Some(interner.product(a, b))
+                }
+                x => {
+                    let i = WORD_LETTERS.iter().position(|&c| c == x)?;
+                    Some(interner.pow2s()[i + 1].clone())
+                }
+            }
+        }
+
+        let mut name = self.clone();
+        match parse(&mut name, interner) {
+            Some(ty) if name.0.is_empty() => Ok(ty),
+            _ => Err(Error::BadTypeName(
+                String::from_utf8_lossy(self.0).into_owned(),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TypeName {
+    /// Writes the type in the same form as `FinalType`'s `Display`, or
+    /// the raw name if it is malformed
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_final(&mut TypeInterner::new()) {
+            Ok(ty) => fmt::Display::fmt(&ty, f),
+            Err(_) => f.write_str(&String::from_utf8_lossy(self.0)),
+        }
+    }
+}
+
 impl Iterator for TypeName {
     type Item = u8;
     fn next(&mut self) -> Option<u8> {
@@ -71,10 +242,13 @@ impl Iterator for TypeName {
 }
 
 /// Trait representing an extension (Bitcoin or Elements) to Simplicity
-pub trait Jet: Sized + fmt::Display {
+pub trait Jet: Sized + fmt::Display + 'static {
     /// Transaction environment
     type TxEnv;
 
+    /// Every node of this extension
+    const ALL: &'static [Self];
+
     /// Decode a node from a bit iterator
     fn decode<I: Iterator<Item = u8>>(iter: &mut BitIter<I>) -> Result<Self, Error>;
 
@@ -93,4 +267,98 @@ pub trait Jet: Sized + fmt::Display {
 
     /// The name of the target type of this node
     fn target_type(&self) -> TypeName;
+
+    /// Look up a node by the name it is displayed with
+    fn from_name(name: &str) -> Option<&'static Self> {
+        Self::ALL.iter().find(|node| node.to_string() == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::BitWrite;
+
+    fn check_type_names<J: Jet + fmt::Debug>() {
+        let mut interner = TypeInterner::new();
+        for jet in J::ALL {
+            for name in &[jet.source_type(), jet.target_type()] {
+                let ty = match name.to_final(&mut interner) {
+                    Ok(ty) => ty,
+                    Err(e) => panic!("{:?}: {}", jet, e),
+                };
+                assert_eq!(name.to_string(), ty.to_string());
+
+                let canonical = ty.to_type_name().into_bytes().into_boxed_slice();
+                let reparsed = TypeName(Box::leak(canonical))
+                    .to_final(&mut interner)
+                    .unwrap();
+                assert!(Arc::ptr_eq(&ty, &reparsed), "{:?}: {}", jet, name);
+            }
+        }
+    }
+
+    #[test]
+    fn all_type_names() {
+        check_type_names::<jets::JetsNode>();
+        #[cfg(feature = "bitcoin")]
+        check_type_names::<bitcoin::BtcNode>();
+        #[cfg(feature = "elements")]
+        check_type_names::<elements::ElementsNode>();
+    }
+
+    fn check_codes<J: Jet + fmt::Debug + PartialEq>(prefix: u64) {
+        for jet in J::ALL {
+            let mut w = encode::BitWriter::new(Vec::<u8>::new());
+            let len = jet.encode(&mut w).expect("encoding to a Vec");
+            w.flush_all().expect("flushing");
+
+            let mut iter = BitIter::new(w.into_inner().into_iter());
+            assert_eq!(iter.read_bits_be(2), Some(prefix));
+            assert_eq!(J::decode(&mut iter).ok().as_ref(), Some(jet));
+            assert_eq!(iter.n_total_read(), len, "{:?}", jet);
+        }
+    }
+
+    #[test]
+    fn all_codes() {
+        check_codes::<jets::JetsNode>(0b11);
+        #[cfg(feature = "bitcoin")]
+        check_codes::<bitcoin::BtcNode>(0b10);
+        #[cfg(feature = "elements")]
+        check_codes::<elements::ElementsNode>(0b10);
+    }
+
+    #[test]
+    fn unassigned_code() {
+        // No jet code starts with `11111`
+        let mut iter = BitIter::new(vec![0b1110_0000].into_iter());
+        match jets::JetsNode::decode(&mut iter) {
+            Err(Error::ParseError(..)) => {}
+            x => panic!("unexpected result: {:?}", x),
+        }
+        assert_eq!(iter.n_total_read(), 3);
+
+        let mut iter = BitIter::new(vec![].into_iter());
+        match jets::JetsNode::decode(&mut iter) {
+            Err(Error::EndOfStream) => {}
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn malformed_type_names() {
+        let mut interner = TypeInterner::new();
+        for name in &[&b""[..], b"*l", b"+1", b"x", b"*lx", b"11"] {
+            match TypeName(name).to_final(&mut interner) {
+                Err(Error::BadTypeName(..)) => {}
+                x => panic!("unexpected result for {:?}: {:?}", name, x),
+            }
+        }
+
+        let name = TypeName(b"*+cs*nm");
+        assert_eq!(name.to_string(), "((2^8 + 2^16) × (2^4 × 2^512))");
+        let ty = name.to_final(&mut interner).unwrap();
+        assert_eq!(ty.to_type_name(), "*+cs*nm");
+    }
 }
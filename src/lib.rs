@@ -12,15 +12,21 @@
 // If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
 //
 
+#![cfg_attr(all(test, feature = "unstable"), feature(test))]
 #![allow(clippy::redundant_field_names, clippy::identity_op)]
 
 extern crate bitcoin_hashes;
 extern crate byteorder;
 extern crate miniscript;
+#[cfg(all(test, feature = "unstable"))]
+extern crate test;
 
 #[macro_use]
 mod macros;
+#[macro_use]
+mod dsl;
 
+pub mod asm;
 pub mod bit_machine;
 pub mod bititer;
 pub mod cmr;
@@ -32,6 +38,8 @@ pub mod extension;
 #[cfg(feature = "bitcoin")]
 pub mod policy;
 pub mod program;
+#[cfg(test)]
+mod test_utils;
 
 use std::fmt;
 
@@ -39,6 +47,7 @@ pub use crate::bit_machine::exec;
 pub use crate::core::term::Term;
 pub use crate::core::term::UnTypedProg;
 pub use crate::core::term::Value;
+pub use crate::core::value::PackedValue;
 pub use crate::program::Program;
 
 use miniscript::{DummyKey, MiniscriptKey};
@@ -47,10 +56,18 @@ use miniscript::{DummyKey, MiniscriptKey};
 #[derive(Debug)]
 pub enum Error {
     /// Unable to unify types in a DAG
-    TypeCheck,
+    TypeCheck(core::types::TypeMismatch),
     /// A recursive type was inferred, violating the "occurs check" of the
     /// type inference engine
-    OccursCheck,
+    OccursCheck(core::types::OccursCheckFailure),
+    /// The type of a node did not match the constraint given for it
+    TypeConstraint(core::types::ConstraintViolation),
+    /// The children of a combinator had incompatible types when it was
+    /// built with a `TypedBuilder`
+    TypeBuild(core::types::BuildMismatch),
+    /// Witness nodes, at the given indices, whose types were not fully
+    /// determined by the program
+    DefaultedWitness(Vec<usize>),
     /// Node made a back-reference past the beginning of the program
     BadIndex,
     /// Number exceeded 32 bits
@@ -65,6 +82,14 @@ pub enum Error {
     TooManyNodes(usize),
     /// Unrecognized node
     ParseError(&'static str),
+    /// Malformed name of an extension node's source or target type
+    BadTypeName(String),
+    /// Malformed textual representation of a type or value
+    TextParse(String),
+    /// Value did not have the expected shape
+    ValueShape(&'static str),
+    /// Program could not be lifted to a policy
+    Unliftable(&'static str),
     /// Miniscript Error
     MiniscriptError(miniscript::Error),
 }
@@ -72,10 +97,19 @@ pub enum Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Error::TypeCheck => f.write_str("Unable to unify types in a DAG"),
-            Error::OccursCheck => f.write_str(
-                "A recursive type was inferred, violating the of the type inference engine",
+            Error::TypeCheck(ref e) => write!(f, "Unable to unify types in a DAG: {}", e),
+            Error::OccursCheck(ref e) => write!(
+                f,
+                "A recursive type was inferred, violating the occurs check of the type inference engine: {}",
+                e,
             ),
+            Error::TypeConstraint(ref e) => {
+                write!(f, "Type did not match the given constraint: {}", e)
+            }
+            Error::TypeBuild(ref e) => write!(f, "Unable to build typed expression: {}", e),
+            Error::DefaultedWitness(ref idxs) => {
+                write!(f, "Witness types were not fully determined: nodes {:?}", idxs)
+            }
             Error::BadIndex => {
                 f.write_str("Node made a back-reference past the beginning of the program")
             }
@@ -91,6 +125,10 @@ impl fmt::Display for Error {
                 write!(f, "Tried to allocate too many nodes in a program: {}", k)
             }
             Error::ParseError(s) => write!(f, "Unrecognized node {}", s),
+            Error::BadTypeName(ref s) => write!(f, "Malformed type name {}", s),
+            Error::TextParse(ref s) => write!(f, "Failed to parse text: {}", s),
+            Error::ValueShape(s) => write!(f, "Value did not have the expected shape: {}", s),
+            Error::Unliftable(s) => write!(f, "Program could not be lifted to a policy: {}", s),
             Error::MiniscriptError(ref e) => fmt::Display::fmt(e, f),
         }
     }
@@ -7,787 +7,7 @@ use bitcoin::hashes::hex::FromHex;
 use simplicity::Value;
 
 #[cfg(feature = "elements")]
-const SIGHASH_ALL: [u8; 12368] = [
-    0xec, 0x8f, 0x84, 0x90, 0x20, 0x40, 0x81, 0x02, 0x04, 0x17, 0x40, 0x81, 0x68, 0x0a, 0xea, 0x12,
-    0x8a, 0x85, 0xa8, 0x2d, 0x41, 0x6f, 0xd0, 0x26, 0x81, 0x67, 0xc0, 0x05, 0x02, 0xd7, 0xc0, 0x85,
-    0xb3, 0x50, 0xa0, 0x58, 0x05, 0xa3, 0x40, 0xb4, 0xee, 0x14, 0x0b, 0x81, 0x70, 0x21, 0x70, 0x20,
-    0xa8, 0x59, 0x85, 0xc0, 0x02, 0xe1, 0x21, 0x70, 0x6e, 0x02, 0x2e, 0x00, 0x17, 0x08, 0xe1, 0x02,
-    0x71, 0x08, 0xa8, 0x5c, 0x37, 0x83, 0x0b, 0x81, 0x05, 0xc2, 0x38, 0x98, 0x5a, 0x02, 0xa1, 0x64,
-    0x17, 0x15, 0x70, 0xc1, 0x31, 0x17, 0x03, 0xe0, 0xc2, 0x81, 0x6d, 0xda, 0x2e, 0x2f, 0xe2, 0x61,
-    0x38, 0x80, 0x54, 0x2c, 0x42, 0xd4, 0x17, 0x03, 0xe0, 0x02, 0xe3, 0x4e, 0x02, 0x28, 0x17, 0x08,
-    0xe2, 0x01, 0x70, 0x4e, 0x21, 0x14, 0x0b, 0x00, 0xb8, 0xb3, 0x86, 0x0b, 0x89, 0xb8, 0x8c, 0x50,
-    0x2e, 0x1f, 0xc5, 0x82, 0xe2, 0x8e, 0x37, 0x14, 0x0b, 0x00, 0xb4, 0x05, 0xc0, 0x02, 0xe2, 0x60,
-    0xb8, 0x9b, 0x8d, 0x45, 0xc4, 0xfc, 0x30, 0x50, 0x2e, 0x39, 0xe3, 0x61, 0x71, 0xd7, 0x0a, 0x14,
-    0x0b, 0x00, 0xb9, 0x17, 0xc8, 0x41, 0x72, 0x0f, 0x8b, 0x85, 0x02, 0xe3, 0x8e, 0x36, 0x17, 0x22,
-    0x39, 0x02, 0x28, 0x16, 0x01, 0x68, 0x0b, 0x8f, 0x78, 0x94, 0x5c, 0x82, 0xe4, 0x10, 0xa0, 0x5c,
-    0x96, 0xe3, 0xe1, 0x71, 0xbf, 0x19, 0x0a, 0x05, 0x80, 0x5c, 0x6b, 0xc7, 0x82, 0xe4, 0xa7, 0x21,
-    0x45, 0x02, 0xe4, 0x7f, 0x21, 0xc5, 0xc8, 0xfe, 0x35, 0x14, 0x0b, 0x00, 0xb4, 0x05, 0xc0, 0x02,
-    0xe3, 0xae, 0x3a, 0x17, 0x23, 0xf9, 0x0c, 0x28, 0x17, 0x25, 0xf9, 0x1c, 0x27, 0x01, 0x15, 0x0b,
-    0x94, 0x5c, 0x80, 0x17, 0x27, 0x39, 0x38, 0x28, 0x17, 0x28, 0xf9, 0x28, 0x2e, 0x52, 0x72, 0x28,
-    0x50, 0x2c, 0x02, 0xd0, 0x17, 0x2c, 0x79, 0x26, 0x2e, 0x4a, 0x72, 0xa4, 0x50, 0x2e, 0x4b, 0x72,
-    0x44, 0x5c, 0x92, 0xe5, 0xa8, 0xa0, 0x58, 0x05, 0xca, 0x2e, 0x55, 0x0b, 0x95, 0x5c, 0xa7, 0x14,
-    0x0b, 0x91, 0xbc, 0x96, 0x17, 0x2d, 0x79, 0x3c, 0x28, 0x16, 0x01, 0x68, 0x0b, 0x80, 0x05, 0xc3,
-    0xc8, 0xa4, 0x27, 0x30, 0xe9, 0x20, 0x40, 0x82, 0xe0, 0x26, 0x02, 0x05, 0x81, 0x02, 0x83, 0x85,
-    0x9e, 0x62, 0xcc, 0x2d, 0x41, 0x32, 0x38, 0x39, 0x0c, 0x49, 0x02, 0x04, 0x17, 0x01, 0x60, 0x16,
-    0x01, 0x60, 0x37, 0x02, 0x14, 0x07, 0x59, 0x06, 0x1a, 0x18, 0x61, 0x86, 0x1c, 0x2c, 0xc7, 0xd2,
-    0x61, 0xf4, 0x8a, 0x0c, 0x30, 0xc3, 0x85, 0xa4, 0x7d, 0x26, 0x1f, 0x80, 0x8a, 0x0f, 0xa8, 0x50,
-    0x61, 0xf3, 0x30, 0xfc, 0x1c, 0x50, 0x7e, 0x00, 0x28, 0x16, 0x63, 0x0f, 0xa8, 0xc3, 0xea, 0x30,
-    0xfc, 0x40, 0x28, 0x3f, 0x09, 0x14, 0x0b, 0x30, 0xb5, 0x0e, 0x17, 0x0b, 0x0b, 0x83, 0x70, 0x43,
-    0x85, 0xc0, 0x07, 0x1c, 0x2c, 0x80, 0xe3, 0x40, 0x39, 0x24, 0xa6, 0x27, 0x3b, 0x68, 0x31, 0x06,
-    0x18, 0x61, 0x87, 0x0b, 0x32, 0x48, 0x10, 0x20, 0xb8, 0x09, 0x80, 0x98, 0x0b, 0x00, 0x9b, 0xce,
-    0x16, 0x9c, 0x85, 0x90, 0x5a, 0x42, 0x70, 0x43, 0x83, 0x90, 0x1c, 0x04, 0x7e, 0x10, 0x61, 0xf8,
-    0x48, 0xa0, 0xc3, 0xe2, 0x61, 0xf8, 0x68, 0xa0, 0x58, 0x8c, 0x3e, 0x83, 0x0f, 0xa0, 0xc3, 0xf1,
-    0x20, 0xa0, 0x58, 0x85, 0xa0, 0x92, 0x89, 0x05, 0x02, 0x90, 0x50, 0x29, 0x05, 0x02, 0x90, 0x50,
-    0x2e, 0x10, 0x17, 0x0b, 0x1c, 0x2e, 0x0e, 0x49, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x81, 0x6a,
-    0x17, 0x50, 0xb5, 0x05, 0x35, 0x8a, 0x05, 0xac, 0x2c, 0x71, 0x16, 0x21, 0x37, 0x0b, 0x00, 0xb7,
-    0x05, 0xb8, 0x2d, 0xc1, 0x6e, 0x0b, 0x70, 0xa3, 0x85, 0xc5, 0x23, 0x85, 0xc7, 0xc3, 0x05, 0xc5,
-    0x5c, 0x56, 0x2e, 0x29, 0x0b, 0x8a, 0x02, 0x72, 0x60, 0x5c, 0x51, 0xc5, 0x02, 0x05, 0x96, 0x42,
-    0xe2, 0xd0, 0xb8, 0xcf, 0x8c, 0xc5, 0xc6, 0x41, 0x38, 0xc0, 0x58, 0x05, 0xa0, 0x2d, 0x01, 0x71,
-    0x78, 0x9c, 0x76, 0x28, 0x16, 0xe1, 0x81, 0xe0, 0x80, 0x72, 0xb8, 0x2e, 0x58, 0x93, 0x01, 0x02,
-    0x28, 0x06, 0x09, 0xe0, 0xc8, 0x83, 0x05, 0x42, 0xc8, 0x83, 0x85, 0x46, 0x18, 0x83, 0x0e, 0x30,
-    0xdb, 0x4e, 0x38, 0xc3, 0xf3, 0x7c, 0x71, 0xc6, 0x1b, 0x9c, 0x23, 0x0e, 0x16, 0x03, 0xf3, 0x1a,
-    0x61, 0xc2, 0xc0, 0x2d, 0xa1, 0x70, 0x21, 0xb8, 0x59, 0xc7, 0x0b, 0x00, 0xb8, 0x59, 0x07, 0x1c,
-    0x70, 0xb1, 0x03, 0x8c, 0x42, 0xe8, 0x72, 0xe8, 0x56, 0x17, 0x43, 0x27, 0x42, 0xd0, 0xa0, 0x5d,
-    0x0c, 0xfd, 0x0d, 0x42, 0xe8, 0x26, 0x0a, 0x85, 0xd0, 0xad, 0xd0, 0xe0, 0x2e, 0x86, 0xbe, 0x87,
-    0x71, 0x40, 0xba, 0x17, 0x7a, 0x17, 0x45, 0xd0, 0x10, 0x15, 0x0b, 0x30, 0xba, 0x1c, 0x7a, 0x1d,
-    0xc4, 0xe8, 0x2a, 0x17, 0x42, 0xff, 0x44, 0x40, 0xba, 0x22, 0x7a, 0x18, 0x05, 0x02, 0xc0, 0x2e,
-    0x87, 0xae, 0x87, 0x61, 0x74, 0x42, 0x74, 0x46, 0x8a, 0x05, 0xd0, 0xef, 0xd1, 0x10, 0x2e, 0x86,
-    0x7e, 0x88, 0x71, 0x40, 0xb0, 0x0b, 0x40, 0x5b, 0xc2, 0xe8, 0x46, 0xe8, 0x6a, 0x17, 0x43, 0xf7,
-    0x44, 0x20, 0xba, 0x1d, 0xfa, 0x20, 0x85, 0x02, 0xc0, 0x2e, 0x88, 0xce, 0x87, 0x51, 0x74, 0x3c,
-    0x74, 0x41, 0x8a, 0x05, 0xd1, 0x17, 0xd1, 0x10, 0x2e, 0x82, 0x30, 0xa8, 0x59, 0x85, 0xd0, 0xf9,
-    0xd1, 0x18, 0x27, 0x02, 0x17, 0x43, 0xf7, 0x44, 0x68, 0xba, 0x24, 0xfa, 0x23, 0x85, 0x02, 0xc0,
-    0x2e, 0x89, 0x1e, 0x88, 0x21, 0x3a, 0x18, 0x05, 0xd1, 0x35, 0xd1, 0x40, 0x26, 0xd1, 0x50, 0xb2,
-    0x0b, 0x68, 0x5c, 0x29, 0x6e, 0xe0, 0xa0, 0x58, 0x38, 0x10, 0x2e, 0x3d, 0x1f, 0x90, 0x66, 0x1f,
-    0xa0, 0xad, 0x06, 0x18, 0x2c, 0x00, 0x6a, 0x16, 0x63, 0xf4, 0x19, 0x1f, 0xa0, 0xcc, 0xda, 0x05,
-    0x00, 0x70, 0x00, 0xe2, 0x06, 0x60, 0x70, 0x10, 0xfa, 0x11, 0x42, 0xe8, 0x47, 0x01, 0xc1, 0x85,
-    0xd1, 0x77, 0xd1, 0x94, 0x27, 0x46, 0x50, 0x9d, 0x19, 0x42, 0x74, 0x65, 0x09, 0xd1, 0x94, 0x2e,
-    0x8c, 0xbe, 0x8c, 0xb1, 0x40, 0xba, 0x34, 0x7a, 0x2b, 0x04, 0xe8, 0xd1, 0x17, 0x46, 0x88, 0x5d,
-    0x1a, 0x21, 0x74, 0x68, 0x85, 0x98, 0x59, 0x85, 0xbc, 0x07, 0x13, 0x0b, 0xa2, 0x5c, 0xe2, 0x83,
-    0x74, 0x28, 0x24, 0xa1, 0x02, 0x04, 0x08, 0x10, 0x20, 0x41, 0x34, 0x8b, 0x48, 0x5a, 0x42, 0xd2,
-    0x16, 0x90, 0xb4, 0x85, 0x96, 0x42, 0xdc, 0x16, 0xe0, 0xae, 0x81, 0x38, 0x00, 0xa8, 0x5c, 0x00,
-    0x2d, 0x01, 0x70, 0x00, 0xb8, 0x00, 0x5c, 0x28, 0x6e, 0x69, 0x4c, 0x3c, 0x14, 0x1f, 0x9a, 0x93,
-    0x0f, 0x05, 0x06, 0xe6, 0xa0, 0xc3, 0xc1, 0x41, 0xf9, 0xac, 0x30, 0xf0, 0x50, 0x6e, 0x6e, 0x4c,
-    0x3c, 0x14, 0x1f, 0x9b, 0xd3, 0x0f, 0x05, 0x18, 0x82, 0x05, 0x98, 0xfc, 0xe2, 0x1c, 0x6e, 0x6f,
-    0x4d, 0xd0, 0x7a, 0x70, 0xab, 0x50, 0x40, 0xb3, 0x1f, 0x23, 0xea, 0x37, 0x39, 0xe2, 0x81, 0x60,
-    0x06, 0x40, 0x70, 0x00, 0x38, 0x60, 0x5c, 0x42, 0x3f, 0x39, 0xa7, 0x1b, 0x9d, 0x43, 0x74, 0x27,
-    0x9c, 0x2a, 0x37, 0x10, 0x1b, 0x86, 0x9f, 0xa1, 0x50, 0x50, 0x07, 0x06, 0x16, 0x20, 0x38, 0x00,
-    0x34, 0x05, 0xb4, 0x7d, 0x87, 0xe0, 0x06, 0xe7, 0x78, 0x50, 0x2c, 0x00, 0xc8, 0x0e, 0x0c, 0x07,
-    0x1e, 0x05, 0xc8, 0x11, 0xf9, 0xea, 0x38, 0xdd, 0x16, 0x46, 0xe8, 0x67, 0x38, 0x54, 0x6e, 0x40,
-    0x1b, 0x8f, 0x4f, 0xd0, 0xd8, 0x28, 0x03, 0x83, 0x0b, 0x10, 0x1c, 0x00, 0x1a, 0x02, 0xda, 0x3e,
-    0xc3, 0xf0, 0x03, 0x73, 0xf6, 0x28, 0x16, 0x00, 0x64, 0x07, 0x06, 0x01, 0xd0, 0xf2, 0x0e, 0x4e,
-    0x84, 0xe6, 0x70, 0xfc, 0xaf, 0x3f, 0x46, 0x50, 0xb9, 0x69, 0xca, 0xc1, 0x72, 0xd0, 0x2e, 0x59,
-    0x72, 0xe8, 0x5c, 0xb3, 0xe5, 0x98, 0xa0, 0x53, 0x96, 0x82, 0x81, 0x72, 0xe3, 0x98, 0x01, 0x73,
-    0x00, 0x13, 0x96, 0x62, 0xbc, 0xba, 0x17, 0x2e, 0x82, 0xa1, 0x66, 0x16, 0xbe, 0x61, 0x45, 0xa3,
-    0x97, 0x42, 0xe6, 0x03, 0x97, 0x62, 0x81, 0x60, 0x17, 0x2f, 0xb9, 0x7c, 0x2e, 0x02, 0x17, 0x30,
-    0x1c, 0xc6, 0x89, 0xc1, 0x05, 0x42, 0xc8, 0x2d, 0x81, 0x70, 0x8e, 0x64, 0x45, 0xc0, 0xf9, 0x83,
-    0x14, 0x0b, 0x83, 0x70, 0x61, 0x39, 0x8d, 0x16, 0xfe, 0x62, 0x45, 0x02, 0xc4, 0x2e, 0x15, 0xbc,
-    0x5b, 0xb8, 0x48, 0xa0, 0x5c, 0xc8, 0x70, 0xe1, 0x70, 0x70, 0x81, 0x60, 0x16, 0x80, 0xb8, 0x08,
-    0x5c, 0x54, 0x17, 0x16, 0x80, 0xe3, 0x23, 0x83, 0xc6, 0x20, 0x71, 0x99, 0x07, 0x1c, 0x60, 0xba,
-    0x2a, 0x42, 0xe8, 0xa9, 0x09, 0xd1, 0xc8, 0x60, 0x9d, 0x13, 0xc7, 0x1c, 0x72, 0x48, 0x10, 0x20,
-    0xdc, 0x00, 0xe3, 0x8c, 0x16, 0x41, 0x64, 0x3f, 0x04, 0x30, 0xda, 0x0c, 0x38, 0x58, 0x0d, 0xc2,
-    0xc4, 0xd8, 0x61, 0xc2, 0xc4, 0x2d, 0x83, 0x0f, 0xac, 0x5c, 0x42, 0x3e, 0x81, 0x41, 0xc6, 0x1f,
-    0x81, 0x98, 0x6e, 0x04, 0x70, 0xa8, 0xdc, 0x00, 0xe1, 0x50, 0xb4, 0x85, 0xc0, 0x07, 0x1c, 0x6e,
-    0x12, 0x2e, 0x8d, 0xb0, 0xba, 0x36, 0xc8, 0x38, 0xc3, 0x53, 0x85, 0x42, 0xcc, 0x71, 0xc6, 0x1f,
-    0x49, 0xc7, 0x18, 0x6d, 0x66, 0x18, 0x70, 0xb1, 0x1b, 0x71, 0x87, 0x0b, 0x00, 0xb7, 0x0e, 0x3f,
-    0x17, 0x18, 0x2e, 0x8d, 0x20, 0xba, 0x34, 0x47, 0xe3, 0x03, 0x04, 0xe2, 0xa1, 0x60, 0x17, 0x1d,
-    0xf1, 0xa9, 0x87, 0xe3, 0xf1, 0x39, 0x14, 0x60, 0x9c, 0x83, 0x16, 0x23, 0x85, 0xa4, 0x61, 0xc7,
-    0xe3, 0xb3, 0x8c, 0x17, 0x49, 0xa4, 0x2e, 0x93, 0x48, 0x4e, 0x91, 0x58, 0x9d, 0x22, 0xa3, 0x72,
-    0x68, 0x5d, 0x21, 0x10, 0xba, 0x42, 0x21, 0x39, 0x10, 0x60, 0xb9, 0x21, 0xc8, 0x91, 0x41, 0xc7,
-    0x1b, 0x84, 0x8b, 0x94, 0xe1, 0x72, 0x9c, 0x6e, 0x35, 0x3f, 0x19, 0x0a, 0x05, 0x80, 0xc3, 0xf1,
-    0xf9, 0x86, 0xe3, 0xb3, 0xf1, 0xc0, 0xa0, 0x58, 0x05, 0x98, 0xe1, 0x74, 0x8a, 0x79, 0x56, 0x2e,
-    0x91, 0x48, 0x4e, 0x2d, 0x38, 0xe3, 0x0f, 0xc6, 0xa6, 0x1b, 0x8a, 0x0d, 0xca, 0x23, 0x85, 0x42,
-    0xc4, 0x6e, 0x37, 0x38, 0x54, 0x2d, 0x63, 0xf3, 0x24, 0x60, 0xba, 0x42, 0xc1, 0x74, 0x85, 0x42,
-    0x74, 0x9b, 0x44, 0xe9, 0x17, 0x24, 0x81, 0x02, 0x04, 0x0a, 0xf3, 0x10, 0x27, 0x30, 0x82, 0xa3,
-    0x0c, 0x37, 0x2f, 0xc4, 0xe6, 0x18, 0x54, 0x60, 0xb7, 0x8c, 0x17, 0x07, 0x0b, 0x8a, 0x42, 0xe3,
-    0xa1, 0x82, 0xe4, 0x28, 0x5c, 0x8d, 0x0b, 0x94, 0x21, 0x72, 0xf8, 0x2e, 0x6a, 0x06, 0x09, 0xcf,
-    0x38, 0xb9, 0xb7, 0x24, 0x83, 0x90, 0x51, 0xc1, 0xc0, 0x0d, 0x03, 0x85, 0xd2, 0xb9, 0xe9, 0x5c,
-    0x88, 0x10, 0x20, 0x40, 0x81, 0x74, 0xaf, 0xfa, 0x58, 0xa2, 0xcc, 0x2c, 0xc2, 0xcc, 0x2e, 0x96,
-    0x1f, 0x4b, 0x30, 0x5a, 0xc2, 0xe9, 0x67, 0x74, 0xb2, 0xc4, 0xda, 0x2a, 0x16, 0xd0, 0xb3, 0x0b,
-    0x68, 0x5b, 0x42, 0xe9, 0x67, 0x80, 0xe8, 0x0a, 0x13, 0x88, 0x92, 0x41, 0xc4, 0xa2, 0x04, 0x08,
-    0x10, 0x5d, 0x02, 0xc8, 0x29, 0x05, 0x02, 0xcc, 0x2c, 0xc2, 0xcc, 0x2d, 0x1a, 0x04, 0x0a, 0x05,
-    0x42, 0xc0, 0x2c, 0x42, 0xc8, 0x2c, 0xc2, 0xd0, 0x16, 0xa2, 0x0d, 0xd2, 0x2a, 0x17, 0x48, 0xaf,
-    0xa4, 0x57, 0x90, 0x40, 0x30, 0x1f, 0xa5, 0x4a, 0x61, 0xba, 0x54, 0xe7, 0x0a, 0x8c, 0x3e, 0x46,
-    0x1b, 0x23, 0x85, 0x42, 0xc8, 0x61, 0xf4, 0x98, 0x7d, 0x26, 0x1b, 0x49, 0xc2, 0xa1, 0x64, 0x16,
-    0x91, 0x87, 0xd8, 0x61, 0xf6, 0x18, 0x7d, 0x86, 0x1b, 0x61, 0xc2, 0xa1, 0x64, 0x16, 0x90, 0xb6,
-    0x12, 0x40, 0xba, 0x59, 0x43, 0x8e, 0x17, 0x12, 0x0e, 0x17, 0x0f, 0x1c, 0x2e, 0x0e, 0x38, 0x5b,
-    0x06, 0x1b, 0xa4, 0xbe, 0x70, 0xa8, 0x0e, 0x36, 0x37, 0x49, 0x8c, 0xe3, 0x85, 0xa0, 0x07, 0x1d,
-    0x83, 0x47, 0x1d, 0x8a, 0x30, 0x07, 0x45, 0x58, 0x5d, 0x16, 0x0d, 0x01, 0xd1, 0x4a, 0x17, 0x45,
-    0x50, 0x0e, 0x89, 0xf0, 0x62, 0x03, 0xa2, 0x70, 0x1a, 0x86, 0xe9, 0x3a, 0x1c, 0x71, 0xc2, 0xe1,
-    0x40, 0x39, 0x1a, 0x6e, 0x1a, 0x70, 0x9c, 0x1c, 0x0e, 0x48, 0x8b, 0x10, 0x1b, 0x4d, 0xd2, 0x80,
-    0x38, 0xe3, 0x8e, 0x17, 0x13, 0x80, 0xe4, 0xd0, 0xb6, 0x70, 0x00, 0x39, 0x38, 0x0e, 0x24, 0xe4,
-    0xe0, 0xa0, 0x0e, 0x12, 0x2c, 0x80, 0x70, 0xb3, 0x74, 0xa3, 0x8e, 0x38, 0xe3, 0x8e, 0x17, 0x1b,
-    0x00, 0xe5, 0x48, 0xb8, 0x5f, 0x02, 0x03, 0x95, 0x66, 0xe3, 0xb3, 0x8e, 0x13, 0x89, 0x00, 0xe5,
-    0x80, 0xb2, 0x01, 0xc5, 0x82, 0xd4, 0x03, 0x8b, 0x8d, 0xd2, 0xa1, 0x38, 0xe3, 0x8e, 0x38, 0xe1,
-    0x72, 0x28, 0x07, 0x2f, 0x45, 0xc5, 0xfc, 0x1c, 0x0e, 0x5f, 0x8b, 0x81, 0x71, 0x40, 0x1c, 0xc0,
-    0x83, 0x8d, 0xf9, 0x81, 0x14, 0x01, 0xc7, 0xc2, 0xc8, 0x07, 0x20, 0x05, 0xa8, 0x07, 0x20, 0x8d,
-    0xd2, 0xb5, 0x38, 0xe3, 0x8e, 0x38, 0xe3, 0x85, 0xc9, 0xf0, 0x1c, 0xc9, 0x0b, 0x90, 0x9c, 0x20,
-    0x0e, 0x65, 0x05, 0xc4, 0xfc, 0x5c, 0x07, 0x32, 0xc6, 0xe5, 0x31, 0xc7, 0x1c, 0x27, 0x20, 0xc0,
-    0xe6, 0x70, 0x59, 0x80, 0xe4, 0xc0, 0xb5, 0x80, 0xe4, 0xd0, 0xb7, 0x80, 0xe4, 0xe1, 0xfa, 0x42,
-    0xc7, 0x1c, 0x71, 0xc7, 0x1c, 0x70, 0xb9, 0x72, 0x03, 0x9a, 0xc1, 0x72, 0x7b, 0x86, 0x81, 0xcd,
-    0x70, 0xb9, 0x03, 0xc6, 0x80, 0x73, 0x60, 0x2e, 0x0f, 0xc8, 0xa0, 0x39, 0xb2, 0x07, 0x26, 0xf9,
-    0xb2, 0x14, 0x01, 0xca, 0xf1, 0x64, 0x03, 0x96, 0x22, 0xd4, 0x03, 0x96, 0x62, 0xdc, 0x03, 0x96,
-    0xa2, 0xe1, 0x41, 0x71, 0xa8, 0x5c, 0x8a, 0x0b, 0x93, 0xc1, 0x72, 0xb4, 0x2e, 0x5d, 0x05, 0xcc,
-    0x60, 0x5c, 0xe0, 0x8d, 0xcc, 0x99, 0xc7, 0x1c, 0x70, 0xb9, 0x44, 0x03, 0x9c, 0xa1, 0x72, 0x43,
-    0x8c, 0x40, 0xe7, 0x30, 0x5c, 0x7b, 0xc9, 0xf0, 0x39, 0xce, 0x17, 0x12, 0xf3, 0x00, 0x07, 0x3a,
-    0x07, 0xe9, 0x20, 0x1c, 0x71, 0xc7, 0x1c, 0x71, 0xc2, 0x73, 0x4e, 0x07, 0x3b, 0x42, 0xd6, 0x03,
-    0x99, 0x61, 0x6f, 0x01, 0xcc, 0xc0, 0xb8, 0x18, 0x0e, 0x66, 0x85, 0xc1, 0xc0, 0x73, 0x38, 0x2e,
-    0x54, 0x70, 0xb0, 0x39, 0xe4, 0x17, 0x25, 0x79, 0x16, 0x07, 0x3c, 0xc2, 0xe3, 0xde, 0x5c, 0x81,
-    0xcf, 0x40, 0xb8, 0x0f, 0x33, 0x40, 0x73, 0xd4, 0x0e, 0x61, 0x39, 0xea, 0x14, 0x01, 0xcd, 0x38,
-    0xb2, 0x01, 0xcd, 0x48, 0xb5, 0x00, 0xe6, 0xac, 0x5b, 0x80, 0x73, 0x5a, 0x6e, 0x70, 0xce, 0x38,
-    0xe3, 0x8e, 0x17, 0x2f, 0xc0, 0x73, 0xf6, 0x2e, 0x56, 0x71, 0xa0, 0x1c, 0xfe, 0x8b, 0x92, 0x9c,
-    0xa4, 0x03, 0x9f, 0xf1, 0x71, 0x47, 0x32, 0x40, 0x74, 0x00, 0x8a, 0x80, 0xe6, 0xf4, 0x59, 0x80,
-    0xe6, 0xfc, 0x5a, 0xc0, 0x73, 0x82, 0x2e, 0x5c, 0x6f, 0x03, 0xa0, 0x24, 0x5c, 0xa0, 0xe4, 0x10,
-    0x1d, 0x01, 0x62, 0xe3, 0x4e, 0x59, 0x01, 0xd0, 0x1a, 0x0e, 0x64, 0xfa, 0x03, 0x45, 0x00, 0x73,
-    0x94, 0x2c, 0x80, 0x73, 0x98, 0x2d, 0x40, 0x39, 0xce, 0x37, 0x3c, 0x87, 0x1c, 0x71, 0xc7, 0x1c,
-    0x2e, 0x63, 0x00, 0x74, 0x0e, 0x8b, 0x96, 0x9c, 0x4a, 0x07, 0x40, 0xf8, 0xb9, 0x11, 0xc9, 0x80,
-    0x3a, 0x08, 0x45, 0x40, 0x73, 0xba, 0x2c, 0xc0, 0x73, 0xbe, 0x2e, 0x5e, 0x6b, 0x03, 0xa0, 0x9c,
-    0x5c, 0x90, 0xe2, 0xf0, 0x3a, 0x0a, 0x41, 0xcc, 0xd7, 0x41, 0x48, 0xa0, 0x0e, 0x79, 0x85, 0x90,
-    0x0e, 0x7a, 0x0d, 0xcf, 0xf1, 0xc7, 0x1c, 0x71, 0xc7, 0x1c, 0x2e, 0x63, 0x40, 0x74, 0x1c, 0x0b,
-    0x94, 0x3c, 0x38, 0x0e, 0x83, 0xa1, 0x50, 0x1c, 0xfb, 0x0b, 0x94, 0xb9, 0x81, 0xd0, 0x7c, 0x0e,
-    0x65, 0x7a, 0x0f, 0x85, 0x00, 0x73, 0xf6, 0x6e, 0x81, 0x13, 0x8e, 0x38, 0xe3, 0x8e, 0x38, 0xe1,
-    0x72, 0xcc, 0x07, 0x42, 0x60, 0x39, 0x6b, 0xd0, 0x98, 0x28, 0x16, 0xe0, 0xb8, 0x20, 0x5c, 0x46,
-    0x17, 0x16, 0x85, 0xc7, 0xe1, 0x72, 0x34, 0x2e, 0x51, 0x05, 0xcb, 0x40, 0xb9, 0x94, 0x20, 0xc3,
-    0xc3, 0x85, 0x42, 0xe8, 0xb0, 0xe8, 0xb0, 0x49, 0x02, 0x04, 0xe9, 0x29, 0x98, 0x60, 0x9d, 0x23,
-    0x91, 0x74, 0x5c, 0x05, 0xa4, 0x61, 0x86, 0x1b, 0xa2, 0xd8, 0x4e, 0x91, 0x60, 0xa8, 0x4e, 0x92,
-    0xb1, 0x87, 0x0b, 0x82, 0x10, 0x71, 0x87, 0x82, 0x83, 0x0d, 0xd3, 0x9a, 0x38, 0xc3, 0x74, 0xe7,
-    0x4e, 0x15, 0x01, 0xd0, 0x6e, 0x6c, 0xcc, 0x15, 0x1b, 0x82, 0x9b, 0xa4, 0xd6, 0x27, 0x4b, 0xe8,
-    0xfd, 0x3a, 0x74, 0x90, 0x20, 0x40, 0x81, 0x05, 0xc8, 0x40, 0xb2, 0x0b, 0x45, 0x15, 0x0a, 0x85,
-    0xaa, 0x89, 0xa8, 0x54, 0x2d, 0x41, 0x3a, 0x03, 0x00, 0xe8, 0x96, 0x17, 0x03, 0x01, 0xd0, 0xa4,
-    0x2e, 0x81, 0x00, 0xba, 0x4f, 0xbd, 0x27, 0xd1, 0x02, 0x04, 0xe9, 0xe4, 0x9b, 0xa4, 0xd6, 0x27,
-    0x49, 0xd8, 0xfd, 0x23, 0x91, 0x41, 0x87, 0xe9, 0x1d, 0x8b, 0xa4, 0x7e, 0x15, 0x0b, 0xa5, 0x31,
-    0xc8, 0x63, 0x8e, 0x17, 0x49, 0xf8, 0x27, 0x47, 0xe9, 0xc7, 0x0b, 0x60, 0xe1, 0x6a, 0x03, 0x81,
-    0x8c, 0x3f, 0x21, 0x85, 0x00, 0x74, 0x37, 0x1f, 0xa5, 0x3c, 0x28, 0x07, 0x0e, 0x03, 0x8d, 0x42,
-    0xe3, 0x80, 0x38, 0xe8, 0x7e, 0x9f, 0x39, 0xc2, 0xa1, 0x72, 0x20, 0x83, 0x8c, 0x3c, 0x14, 0x18,
-    0x6e, 0x9f, 0x41, 0xc6, 0x1b, 0xa7, 0xd6, 0x61, 0xc2, 0xc0, 0x07, 0x44, 0x31, 0xb4, 0x18, 0x7e,
-    0x4a, 0x98, 0x70, 0xb0, 0x01, 0xd1, 0x1a, 0x2d, 0x00, 0x38, 0x61, 0xf8, 0x59, 0x87, 0x1f, 0xa8,
-    0x05, 0x38, 0x54, 0x2c, 0xc2, 0xe1, 0x24, 0x1c, 0x61, 0xe0, 0xa0, 0xc3, 0x75, 0x00, 0xe7, 0x18,
-    0x6e, 0x1e, 0x70, 0xa8, 0x0e, 0x8a, 0x43, 0x66, 0x61, 0xb8, 0x71, 0xc2, 0xa0, 0x3a, 0x2a, 0x85,
-    0x98, 0x0e, 0x39, 0x37, 0x0d, 0x38, 0xfd, 0x36, 0x83, 0x85, 0x47, 0xe9, 0xb3, 0x9c, 0x2a, 0x16,
-    0x90, 0xb8, 0x49, 0x07, 0x18, 0x78, 0x28, 0x30, 0xdd, 0x41, 0x31, 0xc6, 0x1b, 0x8f, 0x8e, 0x15,
-    0x01, 0xd1, 0x86, 0x6c, 0xcc, 0x37, 0x1e, 0x9c, 0x2a, 0x03, 0xa3, 0x24, 0x59, 0x80, 0xe4, 0xc1,
-    0xb8, 0xf0, 0xe3, 0xf3, 0x34, 0x70, 0xa8, 0xfc, 0x9c, 0x38, 0x54, 0x2d, 0x21, 0x70, 0x92, 0x0e,
-    0x30, 0xf0, 0x50, 0x61, 0xba, 0x84, 0x53, 0x8c, 0x37, 0x27, 0x4e, 0x15, 0x01, 0xd1, 0xc4, 0x6c,
-    0xcc, 0x37, 0x27, 0x0e, 0x15, 0x01, 0xd1, 0xd0, 0x2c, 0xc0, 0x72, 0xdc, 0xdc, 0x9b, 0x39, 0x06,
-    0x1c, 0x70, 0xb1, 0x1f, 0xa8, 0x64, 0x38, 0xe1, 0x60, 0x16, 0xc0, 0xb8, 0x61, 0x07, 0x18, 0x78,
-    0x28, 0x30, 0xdd, 0x43, 0x39, 0xc6, 0x1b, 0x97, 0xe7, 0x0a, 0x80, 0xe9, 0x02, 0x1b, 0x33, 0x0d,
-    0xcb, 0xe3, 0x85, 0x40, 0x74, 0x82, 0x85, 0x98, 0x0e, 0x66, 0x4d, 0xcb, 0xd3, 0x90, 0x61, 0xc7,
-    0x0b, 0x11, 0xfa, 0x88, 0x63, 0x8e, 0x16, 0x01, 0x6c, 0x0b, 0x86, 0x0f, 0xd4, 0x44, 0x18, 0x78,
-    0x28, 0x30, 0xdd, 0x44, 0x41, 0xc6, 0x1b, 0x9a, 0x03, 0x85, 0x40, 0x74, 0x89, 0x4d, 0x99, 0x86,
-    0xe6, 0x7c, 0xe1, 0x50, 0x1d, 0x22, 0xb1, 0x66, 0x03, 0x9b, 0xa3, 0x73, 0x3c, 0x72, 0x0c, 0x38,
-    0xe1, 0x62, 0x3f, 0x51, 0x4e, 0x71, 0xc2, 0xc0, 0x2d, 0x81, 0x70, 0xc1, 0xba, 0x7e, 0xa6, 0xe9,
-    0xfd, 0x9b, 0x9b, 0xb3, 0x85, 0x40, 0x74, 0x90, 0x0d, 0xd4, 0x00, 0x9b, 0x9b, 0x93, 0x85, 0x40,
-    0x74, 0x91, 0x45, 0x90, 0x0e, 0x75, 0x0d, 0xcd, 0xc1, 0xc8, 0x30, 0xe3, 0x85, 0x88, 0xfd, 0x46,
-    0x09, 0xc7, 0x0b, 0x00, 0xb6, 0x05, 0xc2, 0x46, 0xea, 0x04, 0x8d, 0xce, 0xa1, 0xc2, 0xa0, 0x3a,
-    0x4b, 0x26, 0xea, 0x06, 0xcd, 0xce, 0x91, 0xc2, 0xa0, 0x3a, 0x4b, 0xc2, 0xc8, 0x07, 0x3d, 0xa6,
-    0xe7, 0x44, 0xe4, 0x18, 0x71, 0xc2, 0xc4, 0x7e, 0xa3, 0x68, 0xe3, 0x85, 0x80, 0x5b, 0x06, 0xe9,
-    0x9a, 0x9f, 0xa6, 0x02, 0x2e, 0x9b, 0xb8, 0x54, 0x2e, 0x85, 0x7e, 0x85, 0x71, 0x74, 0x09, 0x05,
-    0x20, 0xa0, 0xe3, 0x8e, 0x38, 0x5d, 0x34, 0x11, 0xc2, 0xd6, 0x30, 0xc4, 0x90, 0x20, 0x40, 0x81,
-    0x02, 0x0b, 0x98, 0x81, 0x66, 0x16, 0x9a, 0x2a, 0x15, 0x0b, 0x5d, 0x13, 0x58, 0xa8, 0x5a, 0xc2,
-    0xd6, 0x37, 0x40, 0x70, 0xa0, 0x5d, 0x42, 0x4f, 0x50, 0x8c, 0x2e, 0xa1, 0x2c, 0x2e, 0xa1, 0x33,
-    0xa8, 0x47, 0x03, 0xa5, 0xb6, 0x0e, 0xa1, 0x23, 0xa5, 0xb8, 0x28, 0x16, 0x21, 0x74, 0x95, 0xba,
-    0x47, 0x86, 0x1f, 0x33, 0x74, 0x2f, 0x0b, 0xa8, 0x51, 0xea, 0x15, 0x0e, 0x15, 0x6c, 0x10, 0x2c,
-    0xc2, 0xd2, 0x17, 0x50, 0xb7, 0xd4, 0x2e, 0x1c, 0x2e, 0x61, 0xc0, 0x66, 0x2e, 0xa1, 0x6f, 0xa8,
-    0x5c, 0x38, 0x54, 0x0d, 0x00, 0x70, 0x40, 0x1d, 0x43, 0x50, 0x38, 0x68, 0xdd, 0x04, 0xc7, 0xea,
-    0x48, 0x8e, 0x17, 0x17, 0x05, 0x80, 0x0e, 0x95, 0xa0, 0xb3, 0x01, 0xd2, 0xb6, 0x17, 0x53, 0x27,
-    0xd4, 0xc9, 0x88, 0x17, 0x22, 0x3a, 0x27, 0x8c, 0x30, 0xc1, 0x3a, 0x73, 0x82, 0xc8, 0x61, 0x82,
-    0x74, 0x31, 0x0b, 0xa6, 0xbb, 0xd3, 0xde, 0x13, 0xa7, 0x84, 0x71, 0x82, 0xe8, 0x72, 0x09, 0xd4,
-    0xca, 0x9c, 0x2d, 0x00, 0x70, 0x41, 0xba, 0x0e, 0xd0, 0x61, 0xc2, 0xc0, 0x2c, 0x82, 0xe4, 0xa0,
-    0xdd, 0x49, 0xd9, 0xba, 0x27, 0xce, 0x15, 0x01, 0xd2, 0xd6, 0x37, 0x43, 0x11, 0xc8, 0x38, 0xe1,
-    0x60, 0x16, 0x64, 0x90, 0x20, 0x40, 0x81, 0x02, 0x0f, 0xd2, 0x53, 0x14, 0x1b, 0xa9, 0x50, 0x2e,
-    0xa1, 0x02, 0xd4, 0x16, 0xa0, 0xb6, 0xe0, 0x2c, 0x02, 0xdd, 0x05, 0x02, 0xc4, 0x2d, 0xc1, 0x6e,
-    0x0b, 0x60, 0x0e, 0x43, 0x8b, 0x78, 0x0e, 0x97, 0xb2, 0x05, 0x06, 0xea, 0x0a, 0x0d, 0xd1, 0xa8,
-    0x7e, 0x94, 0xb8, 0xb8, 0x40, 0xfc, 0xc5, 0x9c, 0x70, 0xba, 0x81, 0x21, 0xc2, 0xe9, 0xca, 0x0e,
-    0x17, 0x46, 0xf8, 0xc1, 0x6a, 0x01, 0xd3, 0x07, 0x38, 0x5b, 0xc0, 0x74, 0xc2, 0x8f, 0xd3, 0x54,
-    0x38, 0x54, 0x6e, 0xa0, 0xf4, 0xdd, 0x1d, 0x20, 0x75, 0x18, 0xa7, 0xe9, 0x63, 0x8a, 0x01, 0xd3,
-    0x1b, 0xea, 0x30, 0x41, 0x02, 0xe9, 0x93, 0xf4, 0xd7, 0x45, 0xd3, 0x23, 0x0b, 0xa6, 0x24, 0x17,
-    0x4c, 0x18, 0x2e, 0x97, 0xc0, 0x5d, 0x2e, 0x51, 0xc2, 0xd0, 0x16, 0xf0, 0x38, 0x08, 0x5c, 0x4c,
-    0x07, 0x14, 0x01, 0xc8, 0x40, 0x39, 0x22, 0x07, 0x36, 0xe0, 0x73, 0xa0, 0x07, 0x3d, 0x60, 0x74,
-    0x06, 0x81, 0xd0, 0x60, 0x07, 0x42, 0x98, 0x1d, 0x0e, 0x60, 0x74, 0x49, 0x81, 0xd1, 0x66, 0x07,
-    0x48, 0x08, 0x0e, 0x90, 0xe0, 0x5d, 0x37, 0x71, 0xba, 0x6e, 0xe7, 0xe9, 0xbc, 0x0a, 0x00, 0xe9,
-    0xbc, 0x1f, 0xa6, 0x8a, 0x2c, 0x40, 0x74, 0xde, 0xcd, 0xd3, 0x48, 0x13, 0x30, 0x3a, 0x6f, 0xc2,
-    0xc0, 0x07, 0x4c, 0xe8, 0xfd, 0x34, 0x81, 0x6d, 0x01, 0xd3, 0x83, 0x16, 0x8d, 0x60, 0x74, 0xe1,
-    0x4d, 0xd3, 0x4d, 0x13, 0x80, 0x81, 0xd3, 0x88, 0x16, 0x00, 0x3a, 0x68, 0x82, 0xd0, 0x03, 0xa6,
-    0x8c, 0x7e, 0x9a, 0x10, 0xb8, 0x48, 0x0e, 0x9c, 0x78, 0xb8, 0x26, 0xf0, 0x3a, 0x72, 0x22, 0xd9,
-    0xc2, 0x40, 0xe9, 0xc9, 0x9b, 0xa6, 0x92, 0x27, 0x0f, 0x03, 0xa7, 0x2c, 0x2c, 0x00, 0x74, 0xd4,
-    0x85, 0xa0, 0x07, 0x4d, 0x50, 0x5b, 0x00, 0x74, 0xd5, 0x8f, 0xd3, 0x45, 0x17, 0x14, 0x80, 0xe9,
-    0xcf, 0x8b, 0x89, 0x38, 0x18, 0x1d, 0x3a, 0x11, 0x70, 0xce, 0x21, 0x03, 0xa7, 0x46, 0x2e, 0x01,
-    0xc5, 0x60, 0x74, 0xe9, 0x4d, 0xd3, 0x4e, 0x13, 0x8c, 0x40, 0xe9, 0xd4, 0x0b, 0x00, 0x1d, 0x36,
-    0x41, 0x68, 0x01, 0xd3, 0x66, 0x16, 0xc0, 0x1d, 0x36, 0x81, 0x70, 0x00, 0x1d, 0x36, 0xa3, 0xf4,
-    0xd2, 0x85, 0xc7, 0xa0, 0x3a, 0x76, 0x62, 0xe3, 0x8e, 0x0e, 0x07, 0x4e, 0xd4, 0x5c, 0x61, 0xc5,
-    0x20, 0x74, 0xed, 0xc5, 0xc4, 0x9c, 0x6e, 0x07, 0x4e, 0xe4, 0x5c, 0x13, 0x90, 0x20, 0x74, 0xee,
-    0xcd, 0xd3, 0x55, 0x13, 0x90, 0xe0, 0x74, 0xef, 0x85, 0x80, 0x0e, 0x9b, 0xd0, 0xb4, 0x00, 0xe9,
-    0xbe, 0x0b, 0x60, 0x0e, 0x9b, 0xf0, 0xb8, 0x00, 0x0e, 0x9c, 0x00, 0xb8, 0x20, 0x0e, 0x9c, 0x11,
-    0xfa, 0x69, 0xc2, 0xe4, 0xa8, 0x0e, 0x9e, 0x58, 0xb9, 0x21, 0xc2, 0xc0, 0xe9, 0xe6, 0x8b, 0x91,
-    0x1c, 0x62, 0x07, 0x4f, 0x3c, 0x5c, 0x79, 0xc8, 0x30, 0x3a, 0x7a, 0x22, 0xe2, 0xce, 0x48, 0x81,
-    0xd3, 0xd3, 0x17, 0x08, 0xe4, 0xd8, 0x1d, 0x3d, 0x53, 0x74, 0xd6, 0xc4, 0xe5, 0x08, 0x1d, 0x3d,
-    0x81, 0x60, 0x03, 0xa7, 0x28, 0x2d, 0x00, 0x3a, 0x72, 0xc2, 0xd8, 0x03, 0xa7, 0x30, 0x2e, 0x00,
-    0x03, 0xa7, 0x34, 0x2e, 0x08, 0x03, 0xa7, 0x38, 0x2e, 0x10, 0x03, 0xa7, 0x3c, 0x7e, 0x9a, 0xa8,
-    0xb9, 0x62, 0x03, 0xa7, 0xce, 0x2e, 0x56, 0x70, 0xf0, 0x3a, 0x7d, 0x22, 0xe5, 0x27, 0x1c, 0x81,
-    0xd3, 0xeb, 0x17, 0x26, 0x39, 0x1e, 0x07, 0x4f, 0xb4, 0x5c, 0x88, 0xe5, 0x08, 0x1d, 0x3e, 0xf1,
-    0x71, 0xa7, 0x2b, 0xc0, 0xe9, 0xf8, 0x8b, 0x86, 0x72, 0xe4, 0x0e, 0x9f, 0x99, 0xba, 0x6c, 0x82,
-    0x72, 0xfc, 0x0e, 0x9f, 0xb0, 0xb0, 0x01, 0xd3, 0xb2, 0x16, 0x80, 0x1d, 0x3b, 0x41, 0x6c, 0x01,
-    0xd3, 0xb6, 0x17, 0x00, 0x01, 0xd3, 0xb8, 0x17, 0x04, 0x01, 0xd3, 0xba, 0x17, 0x08, 0x01, 0xd3,
-    0xbc, 0x17, 0x0c, 0x01, 0xd3, 0xbe, 0x3f, 0x4d, 0x6c, 0x5c, 0xc8, 0x80, 0xea, 0x01, 0xc5, 0xcc,
-    0x67, 0x11, 0x81, 0xd4, 0x04, 0x8b, 0x98, 0x4e, 0x40, 0x81, 0xd4, 0x05, 0x8b, 0x97, 0x1c, 0x9b,
-    0x03, 0xa8, 0x0d, 0x17, 0x2a, 0x39, 0x62, 0x07, 0x50, 0x1e, 0x2e, 0x4a, 0x73, 0x06, 0x07, 0x50,
-    0x22, 0x2e, 0x3c, 0xe6, 0x44, 0x0e, 0xa0, 0x4c, 0x5c, 0x41, 0xcc, 0xd8, 0x1d, 0x40, 0xa9, 0xba,
-    0x6d, 0x82, 0x73, 0x42, 0x07, 0x50, 0x30, 0x2c, 0x00, 0x74, 0xf5, 0x05, 0xa0, 0x07, 0x4f, 0x58,
-    0x5b, 0x00, 0x74, 0xf6, 0x05, 0xc0, 0x00, 0x74, 0xf6, 0x85, 0xc1, 0x00, 0x74, 0xf7, 0x05, 0xc2,
-    0x00, 0x74, 0xf7, 0x85, 0xc3, 0x00, 0x74, 0xf8, 0x05, 0xc4, 0x00, 0x3a, 0x7c, 0x42, 0xe2, 0x50,
-    0xb9, 0x0c, 0x17, 0x28, 0xc2, 0xe5, 0xe0, 0x5c, 0xc8, 0x85, 0xcd, 0x10, 0x5c, 0xd7, 0x85, 0xcd,
-    0xc0, 0xfd, 0x35, 0x91, 0x73, 0x7a, 0x03, 0xa8, 0x33, 0x17, 0x37, 0x1c, 0x5e, 0x07, 0x50, 0x6a,
-    0x2e, 0x6c, 0x39, 0x22, 0x07, 0x50, 0x6e, 0x2e, 0x69, 0x39, 0x5e, 0x07, 0x50, 0x72, 0x2e, 0x65,
-    0x39, 0x89, 0x03, 0xa8, 0x3b, 0x17, 0x30, 0x1c, 0xcf, 0x81, 0xd4, 0x1e, 0x8b, 0x95, 0x1c, 0xd8,
-    0x81, 0xd4, 0x1f, 0x8b, 0x91, 0x9c, 0xdf, 0x81, 0xd4, 0x20, 0x8b, 0x8b, 0x39, 0xc9, 0x03, 0xa8,
-    0x43, 0x37, 0x4d, 0xb0, 0x4e, 0x73, 0xc0, 0xea, 0x11, 0x85, 0x80, 0x0e, 0xa0, 0x08, 0x5a, 0x00,
-    0x75, 0x00, 0x82, 0xd8, 0x03, 0xa8, 0x06, 0x17, 0x00, 0x01, 0xd4, 0x04, 0x0b, 0x82, 0x00, 0xea,
-    0x02, 0x85, 0xc2, 0x00, 0x75, 0x01, 0x82, 0xe1, 0x80, 0x3a, 0x80, 0xe1, 0x71, 0x00, 0x0e, 0xa0,
-    0x40, 0x5c, 0x48, 0x03, 0xa8, 0x12, 0x17, 0x3b, 0xbc, 0x50, 0x07, 0x50, 0xb4, 0x2e, 0x75, 0x79,
-    0x2c, 0x07, 0x50, 0xb8, 0x2e, 0x72, 0x79, 0x70, 0x07, 0x50, 0xbc, 0x2e, 0x6e, 0x79, 0x96, 0x03,
-    0xa8, 0x60, 0x17, 0x34, 0xbc, 0xd8, 0x01, 0xd4, 0x31, 0x0b, 0x98, 0xde, 0x71, 0x80, 0xea, 0x19,
-    0x05, 0xcb, 0x1e, 0x76, 0x00, 0xea, 0x19, 0x85, 0xc9, 0x1e, 0x79, 0x80, 0xea, 0x1a, 0x05, 0xc4,
-    0xbc, 0xf8, 0x01, 0xd4, 0x35, 0x0a, 0x80, 0xea, 0x09, 0x85, 0x98, 0x0e, 0xa0, 0xa0, 0x5a, 0xc0,
-    0x75, 0x05, 0x42, 0xde, 0x03, 0xa8, 0x2c, 0x17, 0x03, 0x01, 0xd4, 0x17, 0x0b, 0x83, 0x80, 0xea,
-    0x0c, 0x05, 0xc2, 0xc0, 0x75, 0x06, 0x42, 0xe1, 0xe0, 0x3a, 0x83, 0x41, 0x73, 0xeb, 0xc8, 0xa0,
-    0x3a, 0x87, 0xc1, 0x73, 0xd3, 0xcb, 0x80, 0x3a, 0x87, 0xe1, 0x73, 0xb3, 0xcc, 0xd0, 0x1d, 0x44,
-    0x00, 0xb9, 0xc5, 0xe6, 0xe0, 0x0e, 0xa2, 0x08, 0x5c, 0xd6, 0xf3, 0xa4, 0x07, 0x51, 0x08, 0x2e,
-    0x64, 0x79, 0xe8, 0x03, 0xa8, 0x86, 0x17, 0x2c, 0x79, 0xfa, 0x03, 0xa8, 0x88, 0x17, 0x21, 0x7a,
-    0x02, 0x00, 0xea, 0x22, 0x85, 0x40, 0x75, 0x08, 0xc2, 0xcc, 0x07, 0x50, 0x90, 0x2d, 0x60, 0x3a,
-    0x84, 0xa1, 0x6f, 0x01, 0xd4, 0x26, 0x0b, 0x81, 0x80, 0xea, 0x13, 0x85, 0xc1, 0xc0, 0x75, 0x0a,
-    0x02, 0xe1, 0x60, 0x3a, 0x85, 0x21, 0x74, 0x02, 0xf2, 0xa0, 0x0e, 0xa2, 0x68, 0x5c, 0xfa, 0xf3,
-    0x2c, 0x07, 0x51, 0x38, 0x2e, 0x78, 0x79, 0xb8, 0x03, 0xa8, 0x9e, 0x17, 0x39, 0x3c, 0xeb, 0x01,
-    0xd4, 0x50, 0x0b, 0x9a, 0xde, 0x7c, 0x00, 0xea, 0x28, 0x85, 0xcc, 0x6f, 0x40, 0x30, 0x1d, 0x45,
-    0x20, 0xb9, 0x43, 0xd0, 0x30, 0x07, 0x51, 0x4c, 0x2a, 0x03, 0xa8, 0x62, 0x16, 0x60, 0x3a, 0x86,
-    0x41, 0x6b, 0x01, 0xd4, 0x33, 0x0b, 0x78, 0x0e, 0xa1, 0xa0, 0x5c, 0x0c, 0x07, 0x50, 0xd4, 0x2e,
-    0x0e, 0x03, 0xa8, 0x6c, 0x17, 0x40, 0x6f, 0x30, 0x40, 0x75, 0x16, 0x82, 0xe7, 0xe7, 0x9b, 0x00,
-    0x3a, 0x8b, 0x61, 0x73, 0xc3, 0xce, 0x90, 0x1d, 0x45, 0xc0, 0xb9, 0xc5, 0xe7, 0xc0, 0x0e, 0xa2,
-    0xe8, 0x5c, 0xd2, 0xf4, 0x05, 0x01, 0xd4, 0x5e, 0x0b, 0x97, 0x3d, 0x04, 0x00, 0x75, 0x17, 0xc2,
-    0xa0, 0x3a, 0x87, 0xa1, 0x66, 0x03, 0xa8, 0x7c, 0x16, 0xb0, 0x1d, 0x43, 0xf0, 0xb7, 0x80, 0xea,
-    0x20, 0x05, 0xc0, 0xc0, 0x75, 0x10, 0x42, 0xe8, 0x0d, 0xe6, 0x60, 0x0e, 0xa3, 0x28, 0x5c, 0xfa,
-    0xf3, 0x8c, 0x07, 0x51, 0x98, 0x2e, 0x76, 0x79, 0xe8, 0x03, 0xa8, 0xce, 0x17, 0x37, 0x3d, 0x00,
-    0xc0, 0x75, 0x1a, 0x02, 0xe6, 0x37, 0xa0, 0x80, 0x0e, 0xa3, 0x48, 0x54, 0x07, 0x51, 0x1c, 0x2c,
-    0xc0, 0x75, 0x12, 0x02, 0xd6, 0x03, 0xa8, 0x92, 0x16, 0xf0, 0x1d, 0x44, 0xa0, 0xba, 0x01, 0x79,
-    0xaa, 0x03, 0xa8, 0xdc, 0x17, 0x3d, 0x3c, 0xec, 0x01, 0xd4, 0x6f, 0x0b, 0x9c, 0x9e, 0x7e, 0x80,
-    0xea, 0x38, 0x05, 0xcc, 0xef, 0x40, 0xc0, 0x1d, 0x47, 0x10, 0xa8, 0x0e, 0xa2, 0x78, 0x59, 0x80,
-    0xea, 0x28, 0x05, 0xac, 0x07, 0x51, 0x44, 0x2e, 0x7d, 0x79, 0xb8, 0x03, 0xa8, 0xea, 0x17, 0x3a,
-    0xbc, 0xf3, 0x01, 0xd4, 0x76, 0x0b, 0x9a, 0x9e, 0x80, 0x80, 0x3a, 0x8e, 0xe1, 0x50, 0x1d, 0x45,
-    0x50, 0xb3, 0x01, 0xd4, 0x56, 0x0b, 0x9d, 0xde, 0x70, 0x80, 0xea, 0x3d, 0x05, 0xcd, 0x8f, 0x3e,
-    0x00, 0x75, 0x1e, 0xc2, 0xa0, 0x3a, 0x8b, 0x21, 0x73, 0x6b, 0xce, 0x40, 0x1d, 0x47, 0xd0, 0xa8,
-    0x5a, 0x82, 0xe0, 0xa1, 0x71, 0x20, 0x5c, 0x6e, 0x17, 0x23, 0x82, 0xe5, 0x48, 0x5c, 0xc4, 0x05,
-    0xcd, 0x58, 0x5c, 0xea, 0x00, 0xe8, 0x93, 0x07, 0x44, 0x90, 0x5d, 0x48, 0x58, 0x1d, 0x12, 0x3d,
-    0x15, 0xe0, 0xe8, 0x85, 0xe8, 0xb0, 0x07, 0x43, 0x47, 0x45, 0x88, 0x3a, 0x0e, 0xfa, 0x2c, 0x81,
-    0xcf, 0xf7, 0x45, 0x98, 0x39, 0xac, 0xe8, 0xb4, 0x07, 0x2b, 0xfa, 0x2d, 0x41, 0xc8, 0x2e, 0x8b,
-    0x60, 0x71, 0x2f, 0x45, 0xb8, 0x38, 0x47, 0x45, 0xc0, 0xa0, 0x58, 0x05, 0x90, 0x5a, 0x02, 0xd4,
-    0x16, 0xc0, 0xb7, 0x05, 0xc0, 0x02, 0xe0, 0x41, 0x75, 0x23, 0xdc, 0x14, 0xfd, 0x48, 0xf9, 0xba,
-    0x92, 0x03, 0xf4, 0x55, 0x8a, 0x00, 0xe1, 0x62, 0xc4, 0x0e, 0x8a, 0xfe, 0x8a, 0xf0, 0x74, 0x58,
-    0x05, 0xd4, 0x93, 0x81, 0xc4, 0x10, 0x10, 0x2e, 0xa4, 0xa8, 0x07, 0x11, 0x9c, 0x06, 0x81, 0x6e,
-    0x03, 0xa2, 0xe7, 0x48, 0x3a, 0x2e, 0x80, 0xe8, 0xbb, 0x03, 0xa2, 0xf0, 0x0e, 0x8b, 0xd0, 0x3a,
-    0x2f, 0x80, 0xe8, 0xbf, 0x03, 0xa3, 0x00, 0x2e, 0xa4, 0xdc, 0x07, 0x18, 0x03, 0x8c, 0x40, 0xd8,
-    0x17, 0x0c, 0x03, 0xa3, 0x1b, 0x20, 0x74, 0x63, 0x81, 0xd1, 0x90, 0x07, 0x46, 0x48, 0x1d, 0x19,
-    0x40, 0x74, 0x65, 0x81, 0xd1, 0x98, 0x07, 0x46, 0x68, 0x1d, 0x19, 0xc0, 0x74, 0x67, 0x81, 0xd1,
-    0xa0, 0x17, 0x52, 0x8e, 0x07, 0x46, 0x94, 0x07, 0x46, 0x98, 0x1d, 0x1a, 0x80, 0x74, 0x6a, 0x81,
-    0xd1, 0xac, 0x07, 0x46, 0xb8, 0x1d, 0x1b, 0x00, 0x74, 0x6c, 0x81, 0xd1, 0xb4, 0x07, 0x46, 0xd8,
-    0x1d, 0x1b, 0x80, 0x74, 0x6e, 0x81, 0xd1, 0xbc, 0x07, 0x46, 0xf8, 0x1d, 0x1c, 0x00, 0x74, 0x70,
-    0x81, 0xd1, 0xc4, 0x07, 0x47, 0x18, 0x1d, 0x1c, 0x80, 0x74, 0x72, 0x81, 0xd1, 0xcc, 0x07, 0x47,
-    0x38, 0x5d, 0x4a, 0xf0, 0x1d, 0x1d, 0x30, 0x1d, 0x1d, 0x40, 0x74, 0x75, 0x81, 0xd1, 0xd8, 0x07,
-    0x47, 0x68, 0x1d, 0x1d, 0xc0, 0x74, 0x77, 0x81, 0xd1, 0xe0, 0x07, 0x47, 0x88, 0x1d, 0x1e, 0x40,
-    0x74, 0x79, 0x81, 0xd1, 0xe8, 0x07, 0x47, 0xa8, 0x1d, 0x1e, 0xc0, 0x74, 0x7b, 0x81, 0xd1, 0xf0,
-    0x07, 0x47, 0xc8, 0x1d, 0x1f, 0x40, 0x74, 0x7d, 0x81, 0xd1, 0xf8, 0x07, 0x47, 0xe8, 0x1d, 0x1f,
-    0xc0, 0x74, 0x7f, 0x81, 0xd2, 0x00, 0x03, 0xa4, 0x02, 0x07, 0x48, 0x08, 0x0e, 0x90, 0x18, 0x1d,
-    0x20, 0x40, 0x3a, 0x40, 0xa0, 0x74, 0x81, 0x80, 0xe9, 0x03, 0x81, 0xd2, 0x08, 0x03, 0xa4, 0x12,
-    0x07, 0x48, 0x28, 0x0e, 0x90, 0x58, 0x1d, 0x20, 0xc0, 0x3a, 0x41, 0xa0, 0x74, 0x83, 0x80, 0xe9,
-    0x07, 0x81, 0xd2, 0x10, 0x03, 0xa4, 0x22, 0x07, 0x48, 0x48, 0x0e, 0x90, 0x98, 0x1d, 0x21, 0x40,
-    0xba, 0x98, 0xb0, 0x39, 0x84, 0xe2, 0xf0, 0x40, 0x39, 0x86, 0x03, 0x8c, 0x42, 0xea, 0x64, 0x00,
-    0x73, 0x12, 0x07, 0x2b, 0x01, 0xcc, 0x58, 0x1c, 0x8d, 0x1c, 0x2e, 0x5b, 0x01, 0xcc, 0x7f, 0x23,
-    0xc1, 0x02, 0xe5, 0xd0, 0x1c, 0xc9, 0x72, 0xa8, 0x10, 0x0e, 0x50, 0x85, 0xcb, 0xd0, 0x39, 0x74,
-    0x07, 0x30, 0x21, 0x75, 0x34, 0x03, 0x75, 0x34, 0x20, 0xe5, 0xff, 0x2f, 0x4e, 0x15, 0x0b, 0xa9,
-    0xba, 0xea, 0x6d, 0x05, 0xd4, 0xdd, 0x05, 0xd4, 0xdb, 0x75, 0x36, 0xc2, 0x05, 0x42, 0xea, 0x6e,
-    0x7a, 0x9b, 0xf1, 0x60, 0x17, 0x53, 0x7b, 0xd4, 0xe1, 0x0b, 0xa9, 0xc2, 0x09, 0x98, 0xb0, 0x0b,
-    0x30, 0xb1, 0xea, 0x70, 0x04, 0xd6, 0x2d, 0x61, 0x6b, 0x0b, 0x6e, 0xd1, 0x6d, 0x0b, 0x81, 0x70,
-    0x01, 0x75, 0x39, 0x61, 0x30, 0x16, 0x01, 0x62, 0x16, 0x41, 0x66, 0x16, 0x80, 0xb4, 0x85, 0xa8,
-    0x2d, 0xa1, 0x70, 0x21, 0x7a, 0xaa, 0x61, 0x75, 0x54, 0xc1, 0x3a, 0xaa, 0x61, 0x75, 0x54, 0xc1,
-    0x75, 0x05, 0x41, 0x75, 0x05, 0x41, 0x75, 0x7e, 0x40, 0x75, 0x38, 0xfd, 0x59, 0xb0, 0x20, 0xc3,
-    0x43, 0x53, 0x60, 0x6c, 0x4d, 0x91, 0xb3, 0x36, 0x83, 0x69, 0x3e, 0xa1, 0x41, 0xc2, 0xc4, 0x70,
-    0xb4, 0x0e, 0x16, 0xb1, 0xc2, 0xdc, 0x38, 0x5c, 0x04, 0x70, 0xb8, 0x20, 0xe1, 0x70, 0x71, 0xc2,
-    0xe1, 0x40, 0x71, 0x28, 0x4e, 0xa7, 0xa0, 0x1d, 0x5a, 0x8f, 0x56, 0x9c, 0x0e, 0x93, 0x80, 0x1d,
-    0x26, 0x48, 0x0e, 0x92, 0x3d, 0x07, 0x48, 0x6b, 0x00, 0x74, 0x6e, 0xe2, 0x0e, 0x88, 0xbc, 0x81,
-    0xd0, 0x9d, 0x98, 0x3a, 0x08, 0x74, 0x03, 0xa0, 0x13, 0x48, 0x39, 0xf7, 0xd4, 0x28, 0x16, 0x01,
-    0x64, 0x16, 0x80, 0xb5, 0x05, 0xb0, 0x2d, 0xc1, 0x70, 0x00, 0xb8, 0x10, 0x1c, 0x14, 0x0e, 0x94,
-    0x40, 0xe1, 0x73, 0xc0, 0x3f, 0x4b, 0xe4, 0xe3, 0x8e, 0x38, 0xe3, 0x8e, 0x38, 0x4e, 0xa8, 0x28,
-    0xfd, 0x50, 0x51, 0xba, 0xc1, 0xaa, 0x48, 0x10, 0x20, 0x40, 0x82, 0xe4, 0x20, 0x59, 0x05, 0x90,
-    0x5a, 0x70, 0x16, 0x01, 0x6a, 0x82, 0x81, 0x62, 0x16, 0xa0, 0xb8, 0x08, 0x0e, 0x4a, 0x0b, 0x82,
-    0x00, 0xe9, 0x62, 0x1f, 0xa9, 0xea, 0x37, 0x4b, 0x5c, 0x50, 0x07, 0x4b, 0x20, 0xfd, 0x31, 0x03,
-    0x8e, 0x16, 0x01, 0x69, 0x1b, 0xab, 0x11, 0x37, 0x51, 0x80, 0x2e, 0xa7, 0xd4, 0x07, 0x4b, 0x44,
-    0x4d, 0x06, 0xea, 0xc5, 0x8d, 0xd4, 0x62, 0x8b, 0xa9, 0xf6, 0x01, 0xd2, 0xd6, 0x3f, 0x4e, 0x1c,
-    0xe1, 0x51, 0xba, 0xb1, 0xd3, 0x75, 0x19, 0x82, 0xea, 0x7b, 0x00, 0x74, 0xb7, 0x4f, 0xd3, 0xa7,
-    0x38, 0x54, 0x6e, 0xac, 0x90, 0xdd, 0x46, 0x98, 0xba, 0x9e, 0x70, 0x1d, 0x2e, 0x43, 0xf4, 0xf2,
-    0x0e, 0x15, 0x1b, 0xab, 0x2b, 0x37, 0x51, 0xb4, 0x2e, 0xa7, 0x80, 0x07, 0x4b, 0xac, 0xfd, 0x3f,
-    0x03, 0x85, 0x46, 0xea, 0xcc, 0x8d, 0xd4, 0x70, 0x8b, 0xa9, 0xd4, 0x01, 0xd2, 0xf2, 0x3f, 0x50,
-    0x32, 0x70, 0xa8, 0xdd, 0x59, 0xc9, 0xba, 0x8e, 0x81, 0x75, 0x38, 0xe0, 0x3a, 0x5f, 0x27, 0xea,
-    0x33, 0xc5, 0x06, 0xea, 0xcf, 0xcd, 0xd4, 0x77, 0x0b, 0xa9, 0xb4, 0x01, 0xd2, 0xff, 0x13, 0xa9,
-    0x88, 0x37, 0x56, 0x88, 0x2e, 0x9a, 0x6f, 0x51, 0xe4, 0x07, 0x4c, 0x0c, 0x54, 0x0c, 0x42, 0xd4,
-    0x06, 0xc0, 0xb8, 0x10, 0x1c, 0x10, 0x2e, 0x16, 0x07, 0x0d, 0x0b, 0x89, 0x00, 0xe2, 0x60, 0xb8,
-    0xb4, 0x0e, 0x2f, 0x0b, 0x8d, 0x80, 0xe3, 0x80, 0xb8, 0xfc, 0x0e, 0x40, 0x85, 0xc8, 0x60, 0x39,
-    0x10, 0x07, 0x2a, 0x02, 0xea, 0x9f, 0x7a, 0xa7, 0x51, 0x3a, 0xa7, 0x41, 0x75, 0x91, 0x0e, 0xb1,
-    0xf0, 0x2e, 0xb2, 0x93, 0xd6, 0x50, 0x45, 0xd5, 0x90, 0x75, 0x65, 0x42, 0xea, 0xd3, 0xfa, 0xb5,
-    0x61, 0x75, 0x4d, 0x7d, 0x53, 0x81, 0xfa, 0xc8, 0xca, 0x0c, 0x15, 0x24, 0xa2, 0x40, 0xc0, 0x31,
-    0x06, 0xe0, 0x0c, 0xc1, 0xa0, 0x6d, 0x0b, 0xaa, 0x80, 0xea, 0x9e, 0xc5, 0x07, 0xea, 0x49, 0xcf,
-    0x88, 0x60, 0x18, 0x01, 0xc1, 0x42, 0xea, 0xd8, 0x7a, 0xb7, 0xb1, 0x42, 0x48, 0x38, 0xf9, 0x86,
-    0x01, 0x90, 0x1c, 0x3c, 0x2e, 0xad, 0x33, 0xab, 0x44, 0x14, 0x1f, 0xaa, 0xec, 0x3e, 0x21, 0x80,
-    0x60, 0x07, 0x13, 0x85, 0xd6, 0x5a, 0xba, 0xcb, 0x10, 0xa0, 0xfd, 0x64, 0xd4, 0xf8, 0x86, 0x01,
-    0x80, 0x1c, 0x5e, 0x17, 0x59, 0x29, 0xeb, 0x24, 0xa2, 0x83, 0xf5, 0x71, 0x27, 0xc4, 0x30, 0x0c,
-    0x00, 0xe9, 0x98, 0x8f, 0xd3, 0x8a, 0x30, 0xf0, 0x50, 0x2c, 0x49, 0x20, 0xe3, 0x75, 0x4f, 0xc7,
-    0xea, 0x9f, 0xc5, 0x00, 0x6d, 0x37, 0x32, 0x67, 0xe6, 0x50, 0x50, 0x2c, 0x09, 0x20, 0xe3, 0x75,
-    0x50, 0xa7, 0xea, 0xa1, 0x85, 0x00, 0x70, 0x83, 0x73, 0x20, 0x7e, 0x64, 0x45, 0x02, 0xc0, 0x92,
-    0x0e, 0x37, 0x54, 0xfe, 0x7e, 0xaa, 0x00, 0x50, 0x07, 0x11, 0x9b, 0x99, 0x43, 0xf3, 0x2a, 0x28,
-    0x16, 0x04, 0x90, 0x71, 0xba, 0xa7, 0xe3, 0xf5, 0x4f, 0xe2, 0x80, 0x38, 0xb8, 0xdc, 0xcc, 0x1f,
-    0x99, 0x91, 0x40, 0xb0, 0x24, 0x83, 0x8d, 0xd5, 0x3d, 0x9f, 0xaa, 0x7c, 0x14, 0x01, 0xc7, 0x26,
-    0xe6, 0x70, 0xfc, 0xce, 0x8a, 0x05, 0x81, 0x24, 0x1c, 0x6e, 0xa9, 0xcc, 0xfd, 0x53, 0xa0, 0xa0,
-    0x0e, 0x42, 0x1b, 0x9a, 0x03, 0xf3, 0x42, 0x28, 0x16, 0x04, 0x90, 0x71, 0xba, 0xa6, 0xa3, 0xf5,
-    0x4d, 0x62, 0x80, 0x39, 0x1e, 0x6e, 0xa5, 0xcc, 0xfd, 0x4b, 0xa0, 0xa0, 0x58, 0x0f, 0xd6, 0x58,
-    0x41, 0xd6, 0x1b, 0x39, 0x2a, 0x27, 0x58, 0x69, 0x3f, 0x59, 0x71, 0x3f, 0x26, 0x03, 0x00, 0xc0,
-    0x70, 0x74, 0x01, 0xa8, 0x70, 0x78, 0x20, 0x1c, 0x20, 0x70, 0x78, 0x88, 0x0e, 0x26, 0x1c, 0x1e,
-    0x30, 0x03, 0x8d, 0x07, 0x07, 0x8f, 0x80, 0xe4, 0x10, 0xe0, 0xf2, 0x30, 0x0e, 0x48, 0x0e, 0x0f,
-    0x26, 0x80, 0xe4, 0xf0, 0xe0, 0xf2, 0xa0, 0x0e, 0x56, 0x01, 0xcd, 0x48, 0x4e, 0xac, 0x78, 0x5d,
-    0x0b, 0xbd, 0x58, 0xf8, 0x18, 0x1c, 0x7e, 0xac, 0xcc, 0x30, 0x0c, 0x80, 0xe6, 0xcc, 0x0e, 0x90,
-    0x48, 0x1d, 0x04, 0xe1, 0x3a, 0x41, 0x24, 0xea, 0xd0, 0x8e, 0x2f, 0x56, 0x56, 0x70, 0x68, 0x19,
-    0x01, 0xd1, 0x16, 0x07, 0x44, 0x80, 0x1d, 0x21, 0x40, 0x3a, 0x45, 0x01, 0x3a, 0xb3, 0x34, 0x90,
-    0x2e, 0x90, 0xbf, 0x48, 0x59, 0x24, 0x08, 0x10, 0x20, 0x41, 0x72, 0x16, 0x41, 0x64, 0x16, 0x41,
-    0x64, 0x16, 0x41, 0x67, 0x98, 0x81, 0x40, 0xa8, 0x58, 0x05, 0x88, 0x59, 0x05, 0x98, 0x5a, 0x02,
-    0xd4, 0x17, 0x07, 0x14, 0x1e, 0x14, 0x07, 0x0d, 0x1c, 0x1e, 0xad, 0xa4, 0x0e, 0xad, 0xb4, 0x61,
-    0x89, 0x28, 0x40, 0x81, 0x02, 0x04, 0x08, 0x26, 0x81, 0x68, 0x0b, 0x40, 0x5a, 0x02, 0xd0, 0x16,
-    0x80, 0xb4, 0x05, 0xa0, 0x2c, 0xf7, 0x0b, 0x46, 0x81, 0x40, 0xa6, 0x91, 0x40, 0xb6, 0xf0, 0x41,
-    0x70, 0x40, 0xb7, 0xef, 0x17, 0x06, 0x0a, 0x85, 0x8f, 0x01, 0x17, 0x01, 0x0a, 0x85, 0xa4, 0x2d,
-    0xbc, 0x2c, 0x5a, 0xb4, 0x0b, 0x83, 0xe9, 0x14, 0x0b, 0x00, 0xb6, 0x6c, 0x17, 0x03, 0x0b, 0x77,
-    0x11, 0x89, 0xc1, 0x85, 0x42, 0xc8, 0x2d, 0x81, 0x70, 0xae, 0x27, 0x17, 0x04, 0xe2, 0x21, 0x70,
-    0x60, 0xa8, 0x5c, 0x27, 0x84, 0x89, 0xc4, 0xa2, 0xe0, 0x1c, 0x20, 0x50, 0x2c, 0x42, 0xe1, 0xbc,
-    0x00, 0x5b, 0xf8, 0x50, 0xa0, 0x5c, 0x3f, 0x88, 0x45, 0xc2, 0x02, 0x05, 0x80, 0x5a, 0x02, 0xe0,
-    0x41, 0x71, 0x68, 0xfd, 0x64, 0xb8, 0xdd, 0x64, 0xbc, 0x50, 0x6e, 0xb1, 0x6e, 0x2e, 0x21, 0xe1,
-    0xc2, 0xe1, 0xfc, 0x3c, 0x50, 0x29, 0x05, 0x02, 0x90, 0x50, 0x29, 0x05, 0x02, 0xe2, 0x7e, 0x18,
-    0x26, 0x91, 0x68, 0x0b, 0x30, 0xb2, 0x0b, 0x20, 0xb8, 0x00, 0x5c, 0x08, 0x2e, 0x2f, 0xe3, 0x31,
-    0x71, 0x9f, 0x1e, 0x8a, 0x05, 0xc8, 0xce, 0x40, 0x09, 0xc8, 0x51, 0x71, 0x4c, 0x14, 0x0b, 0x10,
-    0xb8, 0xd7, 0x11, 0x72, 0x40, 0x2e, 0x4c, 0xf2, 0x30, 0x4e, 0x06, 0x2a, 0x16, 0x41, 0x71, 0xaf,
-    0x20, 0x05, 0xc8, 0xce, 0x4f, 0x8b, 0x90, 0x61, 0x50, 0xb7, 0xf2, 0x28, 0x4e, 0x42, 0x0a, 0x85,
-    0xc9, 0xde, 0x44, 0x0b, 0x92, 0xfc, 0x8e, 0x14, 0x0b, 0x92, 0x1c, 0x87, 0x17, 0x2a, 0x38, 0x30,
-    0xa0, 0x58, 0x05, 0xa0, 0x2e, 0x00, 0x17, 0x24, 0x38, 0xe8, 0x5c, 0x92, 0xe5, 0x08, 0xa0, 0x5c,
-    0x3b, 0x95, 0x22, 0xe4, 0xd7, 0x26, 0x85, 0x02, 0xc0, 0x2e, 0x4e, 0xf1, 0x10, 0xb8, 0x98, 0x2e,
-    0x57, 0xf2, 0x64, 0x5c, 0x97, 0xe4, 0x20, 0xa0, 0x58, 0x05, 0x98, 0x5c, 0x9e, 0xe4, 0xc8, 0xb8,
-    0xab, 0x93, 0x82, 0x81, 0x71, 0x67, 0x25, 0x84, 0xe4, 0x08, 0xa8, 0x5c, 0xb4, 0xe4, 0xf8, 0xb9,
-    0x35, 0xc6, 0x02, 0x81, 0x72, 0x67, 0x95, 0x02, 0xe2, 0x10, 0xa8, 0x59, 0x85, 0xb8, 0x2e, 0x1a,
-    0x37, 0x59, 0xa2, 0x14, 0x01, 0xd6, 0x6a, 0x8f, 0xd6, 0x69, 0x45, 0x00, 0x75, 0x9a, 0xd0, 0x72,
-    0x08, 0x2e, 0xb0, 0x2d, 0xd6, 0x04, 0x05, 0xd6, 0x05, 0x82, 0xeb, 0x5a, 0x7d, 0x6b, 0x64, 0x5d,
-    0x6b, 0x6b, 0xac, 0x0b, 0x0a, 0x05, 0xd6, 0xb8, 0x7a, 0xd6, 0xf0, 0xba, 0xd6, 0xc0, 0x4e, 0xb0,
-    0x2c, 0x2b, 0xd6, 0xb8, 0x05, 0xd6, 0x07, 0x02, 0xa1, 0x66, 0x17, 0x5a, 0xe3, 0xeb, 0x04, 0xa2,
-    0xd1, 0xd6, 0xba, 0x05, 0xd6, 0xb9, 0xba, 0xd7, 0x48, 0xa0, 0x58, 0x05, 0xd6, 0xba, 0x3a, 0xd7,
-    0x58, 0xba, 0xd7, 0x6f, 0x5a, 0xe4, 0x13, 0xad, 0x72, 0x0a, 0x85, 0x88, 0x5a, 0xc2, 0xeb, 0x5d,
-    0x1d, 0x60, 0xc0, 0x5c, 0x0b, 0xac, 0x12, 0x8a, 0x05, 0xc1, 0x78, 0x28, 0x9d, 0x60, 0xb8, 0x5b,
-    0xba, 0xc1, 0x58, 0xa0, 0x58, 0x85, 0xc2, 0x7a, 0xd7, 0xc8, 0xb7, 0x70, 0x81, 0x40, 0xba, 0xd7,
-    0xef, 0x0d, 0x17, 0x06, 0x08, 0x16, 0x01, 0x68, 0x0b, 0x80, 0x85, 0xc4, 0xe1, 0x75, 0xaf, 0xe0,
-    0x1d, 0x64, 0xe8, 0x4e, 0xb6, 0x00, 0x6e, 0xb5, 0x68, 0x7e, 0xb6, 0x04, 0x61, 0xfa, 0xd8, 0x20,
-    0xa0, 0x58, 0x8f, 0xd6, 0x6d, 0xcf, 0xd6, 0x4f, 0x83, 0x00, 0xc0, 0x0d, 0x60, 0x71, 0xc0, 0x5c,
-    0xc8, 0x01, 0xd6, 0x52, 0x76, 0x02, 0x05, 0xce, 0x80, 0x5d, 0x61, 0x58, 0x92, 0x10, 0x61, 0xc7,
-    0xeb, 0x62, 0xe7, 0x1b, 0xac, 0x31, 0x8a, 0x05, 0x89, 0x24, 0x1b, 0xac, 0x19, 0x9c, 0x72, 0x0c,
-    0x37, 0x5b, 0x23, 0x38, 0x54, 0x7e, 0xb6, 0x4c, 0x71, 0xc2, 0xc0, 0x92, 0x04, 0x08, 0x10, 0x20,
-    0x40, 0x81, 0x02, 0x0f, 0xd6, 0xcd, 0x0c, 0x15, 0x03, 0xac, 0xe0, 0xf4, 0x24, 0x09, 0xd6, 0x6f,
-    0x80, 0xe8, 0x48, 0x07, 0x59, 0xcc, 0x09, 0xd6, 0x72, 0x4f, 0xd6, 0xd0, 0xcf, 0x80, 0x60, 0x18,
-    0x01, 0xa8, 0x81, 0x41, 0x78, 0x48, 0xb8, 0x48, 0x5c, 0x24, 0x2e, 0x12, 0x17, 0x09, 0x0b, 0x84,
-    0x85, 0xc2, 0x78, 0x40, 0xb8, 0x50, 0x5c, 0x2c, 0x2e, 0x18, 0x17, 0x0d, 0x0b, 0x87, 0x05, 0xc3,
-    0xc2, 0xd0, 0x10, 0x2e, 0x22, 0xa2, 0x83, 0x71, 0x99, 0x87, 0xe8, 0x46, 0x13, 0x8d, 0x8d, 0xd4,
-    0x7b, 0x8b, 0xa5, 0x57, 0xd2, 0xab, 0x17, 0x59, 0x50, 0x0b, 0xac, 0xa8, 0x05, 0xd5, 0x6a, 0x05,
-    0xd5, 0x6a, 0x05, 0xd6, 0xa7, 0x00, 0x74, 0xaa, 0x8c, 0x34, 0x35, 0x36, 0x06, 0xc4, 0xd9, 0x1b,
-    0x33, 0x68, 0x36, 0x93, 0xea, 0x14, 0x1c, 0x2c, 0x47, 0x0b, 0x40, 0xe1, 0x6b, 0x1c, 0x2d, 0xc3,
-    0x85, 0xc0, 0x47, 0x0b, 0x82, 0x0e, 0x17, 0x07, 0x1c, 0x2e, 0x14, 0x07, 0x11, 0x85, 0xd2, 0xce,
-    0xeb, 0x2e, 0x62, 0xeb, 0x2e, 0x61, 0x75, 0x97, 0x30, 0xba, 0xaf, 0x70, 0xba, 0xaf, 0x70, 0xba,
-    0xd5, 0xf8, 0x0e, 0x96, 0x69, 0x86, 0x86, 0xa6, 0xc0, 0xd8, 0x9b, 0x23, 0x66, 0x6d, 0x06, 0xd2,
-    0x7d, 0x42, 0x83, 0x85, 0x88, 0xe1, 0x68, 0x1c, 0x2d, 0x63, 0x85, 0xb8, 0x70, 0xb8, 0x08, 0xe1,
-    0x70, 0x41, 0xc2, 0xe0, 0xe3, 0x85, 0xc2, 0x80, 0xe9, 0xc9, 0x05, 0xc4, 0x81, 0x74, 0xe5, 0x46,
-    0x1f, 0x91, 0x47, 0xe9, 0xcc, 0x89, 0xd3, 0x18, 0x03, 0xa7, 0x64, 0x2c, 0x02, 0xe9, 0xce, 0x69,
-    0x38, 0x54, 0x7e, 0x96, 0xc8, 0xb8, 0xbf, 0x8b, 0xc5, 0xd6, 0x68, 0x42, 0xea, 0xc9, 0x42, 0xea,
-    0xc9, 0x42, 0xeb, 0x5d, 0xa0, 0x3a, 0x5f, 0x66, 0x1a, 0x1a, 0x9b, 0x03, 0x62, 0x6c, 0x8d, 0x99,
-    0xb4, 0x1b, 0x49, 0xf5, 0x0a, 0x0e, 0x16, 0x23, 0x85, 0xa0, 0x70, 0xb5, 0x8e, 0x16, 0xe1, 0xc2,
-    0xe0, 0x23, 0x85, 0xc1, 0x07, 0x0b, 0x83, 0x8e, 0x17, 0x0a, 0x03, 0xa6, 0x3e, 0x13, 0xac, 0x71,
-    0x01, 0xd2, 0xe7, 0x07, 0x12, 0x85, 0xd3, 0xc5, 0x01, 0xd3, 0xc9, 0x30, 0x5d, 0x32, 0x4e, 0x4b,
-    0x8b, 0xac, 0xe4, 0x85, 0xd5, 0xa6, 0x85, 0xd5, 0xa6, 0x85, 0xd6, 0xc5, 0x40, 0x74, 0xc8, 0xcc,
-    0x34, 0x35, 0x36, 0x06, 0xc4, 0xd9, 0x1b, 0x33, 0x68, 0x36, 0x93, 0xea, 0x14, 0x1c, 0x2c, 0x47,
-    0x0b, 0x40, 0xe1, 0x6b, 0x1c, 0x2d, 0xc3, 0x85, 0xc0, 0x47, 0x0b, 0x82, 0x0e, 0x17, 0x07, 0x1c,
-    0x2e, 0x14, 0x07, 0x4d, 0x1c, 0x27, 0x59, 0x0a, 0x03, 0xa6, 0x1e, 0x71, 0xc7, 0xe9, 0xf7, 0x1b,
-    0xa6, 0x24, 0x7d, 0x02, 0x80, 0x3a, 0x7e, 0xa6, 0x0b, 0x20, 0x1d, 0x40, 0x68, 0xb4, 0x80, 0xe9,
-    0xfd, 0x0b, 0x8c, 0x40, 0xe4, 0xd8, 0x1c, 0xa4, 0x1b, 0xa7, 0xf8, 0x7e, 0xb2, 0x38, 0x28, 0x07,
-    0x50, 0x2d, 0xca, 0xc0, 0x40, 0xb2, 0x1c, 0x92, 0x04, 0x08, 0x10, 0x20, 0xb9, 0x0b, 0x20, 0xb2,
-    0x0b, 0x20, 0xb2, 0x0b, 0x20, 0xb3, 0xcc, 0x40, 0xa0, 0x54, 0x2c, 0x02, 0xc4, 0x2c, 0x82, 0xcc,
-    0x2d, 0x01, 0x6a, 0x08, 0x16, 0xba, 0x28, 0x38, 0x3c, 0x2c, 0x0e, 0x74, 0x42, 0xe8, 0x08, 0x1f,
-    0x88, 0xd0, 0x61, 0x82, 0x60, 0x0e, 0x28, 0xea, 0x73, 0x85, 0xc5, 0x21, 0x75, 0x04, 0xfd, 0x41,
-    0xf0, 0x20, 0x59, 0x8d, 0xd4, 0x14, 0x89, 0xc7, 0x80, 0x75, 0x08, 0x66, 0xea, 0x0b, 0x4f, 0xd4,
-    0x17, 0x0a, 0x00, 0xea, 0x11, 0xc5, 0x88, 0x1a, 0x40, 0xde, 0x37, 0x1b, 0x8a, 0x0f, 0xc1, 0x05,
-    0x02, 0xe1, 0x03, 0x75, 0x95, 0x83, 0x73, 0xdc, 0x81, 0x41, 0xba, 0x5d, 0x47, 0xe9, 0x75, 0x8a,
-    0x0c, 0x17, 0x41, 0x50, 0xc3, 0xf1, 0x11, 0xfa, 0x9e, 0xd3, 0x05, 0x42, 0xc4, 0x7e, 0x4c, 0x18,
-    0x6e, 0xa1, 0x24, 0x4e, 0x47, 0x01, 0xd4, 0x31, 0x9c, 0x2c, 0x40, 0x75, 0x0a, 0x27, 0xea, 0x13,
-    0xcc, 0x15, 0x1f, 0xa6, 0xea, 0x71, 0x86, 0xe4, 0xc9, 0x80, 0xe4, 0xd7, 0x50, 0xde, 0x70, 0xa8,
-    0x0e, 0xa1, 0xc8, 0x59, 0x80, 0xea, 0x17, 0xcf, 0xd4, 0x2f, 0x1b, 0xa1, 0x20, 0x50, 0x60, 0xb1,
-    0x0b, 0x80, 0x8d, 0xd2, 0x5e, 0x31, 0x02, 0x83, 0x75, 0x0c, 0xe7, 0xe5, 0x61, 0xc7, 0x0b, 0x00,
-    0x1d, 0x44, 0x20, 0xb2, 0x1b, 0xa0, 0x84, 0xdd, 0x43, 0x80, 0xa0, 0xdd, 0x66, 0x44, 0xe3, 0x75,
-    0x99, 0x33, 0x8e, 0x16, 0x01, 0x66, 0x16, 0xb1, 0xb9, 0x32, 0x6e, 0xa1, 0xe8, 0xfc, 0x92, 0x14,
-    0x01, 0xd4, 0x4a, 0x9b, 0xa8, 0x96, 0x38, 0x54, 0x7e, 0x50, 0x9c, 0x2a, 0x16, 0xb1, 0xf9, 0xf3,
-    0x17, 0x51, 0x0c, 0x17, 0x40, 0xb7, 0x5a, 0x88, 0x17, 0x5a, 0x88, 0x0b, 0xac, 0x06, 0x05, 0xd6,
-    0x03, 0x02, 0xeb, 0x7a, 0x80, 0x3a, 0x7c, 0x46, 0x1a, 0x1a, 0x9b, 0x03, 0x62, 0x6c, 0x8d, 0x99,
-    0xb4, 0x1b, 0x49, 0xf5, 0x0a, 0x0e, 0x16, 0x23, 0x85, 0xa0, 0x70, 0xb5, 0x8e, 0x16, 0xe1, 0xc2,
-    0xe0, 0x23, 0x85, 0xc1, 0x07, 0x0b, 0x83, 0x8e, 0x17, 0x0a, 0x03, 0xa8, 0x06, 0x09, 0xd6, 0x72,
-    0x40, 0xe9, 0xe7, 0x03, 0xa8, 0xae, 0x03, 0x89, 0x86, 0x1f, 0xa1, 0x84, 0x50, 0x07, 0x51, 0x66,
-    0x27, 0x30, 0xa2, 0xe6, 0x27, 0x98, 0x50, 0x75, 0x19, 0x7d, 0x3d, 0x60, 0x40, 0xb9, 0xc7, 0x0b,
-    0x11, 0xc2, 0xe3, 0xf0, 0xba, 0x1a, 0xfa, 0xd5, 0xd0, 0xba, 0xd5, 0xd0, 0x5d, 0x60, 0xf8, 0x2e,
-    0xb0, 0x7c, 0x17, 0x5c, 0x06, 0x01, 0xd4, 0x0a, 0x18, 0x68, 0x6a, 0x6c, 0x0d, 0x89, 0xb2, 0x36,
-    0x66, 0xd0, 0x6d, 0x27, 0xd4, 0x28, 0x38, 0x58, 0x8e, 0x16, 0x81, 0xc2, 0xd6, 0x38, 0x5b, 0x87,
-    0x0b, 0x80, 0x8e, 0x17, 0x04, 0x1c, 0x2e, 0x0e, 0x38, 0x5c, 0x28, 0x0e, 0xa0, 0xe0, 0x27, 0x59,
-    0xfb, 0x03, 0xa8, 0x00, 0x30, 0xc1, 0x73, 0x66, 0x03, 0xa8, 0xe3, 0x17, 0x3e, 0x60, 0x3a, 0x8f,
-    0xa1, 0x73, 0x6c, 0x03, 0xa8, 0xe7, 0x17, 0x37, 0x83, 0x8d, 0xd4, 0x74, 0x0a, 0x05, 0xca, 0xf0,
-    0x38, 0xc8, 0x0e, 0x53, 0x81, 0xcb, 0x41, 0xc6, 0xe9, 0x74, 0xa0, 0xe1, 0x52, 0x48, 0x10, 0x20,
-    0x40, 0x81, 0x02, 0x05, 0x02, 0xa1, 0x60, 0x16, 0x21, 0x64, 0x16, 0x61, 0x68, 0x0b, 0x48, 0xbc,
-    0x00, 0x5c, 0x00, 0x2e, 0x00, 0x17, 0x00, 0x0b, 0x80, 0x05, 0xc0, 0x02, 0x68, 0x10, 0x26, 0x91,
-    0x6a, 0x1c, 0x6e, 0x87, 0xb3, 0xf3, 0xe2, 0x28, 0x38, 0x38, 0x81, 0xc4, 0x23, 0x8e, 0x0f, 0x14,
-    0x01, 0xcd, 0x80, 0x1c, 0xda, 0x81, 0xce, 0x90, 0xe4, 0x90, 0x20, 0x40, 0x81, 0x02, 0x04, 0x0a,
-    0x05, 0x42, 0xc0, 0x2c, 0x42, 0xc8, 0x2c, 0xc2, 0xd0, 0x16, 0x90, 0xba, 0x02, 0xba, 0x5f, 0xc7,
-    0x0a, 0x8e, 0x0f, 0x05, 0x03, 0x9f, 0x40, 0x9c, 0xe9, 0x09, 0xcf, 0x79, 0xfa, 0x43, 0x86, 0x18,
-    0x7e, 0x81, 0x51, 0x40, 0x1a, 0x10, 0x60, 0xa8, 0x4c, 0x8d, 0xa8, 0xc3, 0xf4, 0x0f, 0x0a, 0x00,
-    0xe0, 0x28, 0x30, 0x54, 0x26, 0x46, 0xe0, 0x66, 0x1f, 0xa0, 0x9c, 0x50, 0x07, 0x0a, 0x41, 0x82,
-    0xa1, 0x32, 0x37, 0x0c, 0x30, 0xfd, 0x06, 0x02, 0x80, 0x38, 0x8d, 0x06, 0x0a, 0x84, 0xc8, 0xdc,
-    0x4a, 0x61, 0xfa, 0x0e, 0x45, 0x00, 0x71, 0x62, 0x0c, 0x15, 0x09, 0x91, 0xb8, 0xb8, 0xc3, 0xf4,
-    0x21, 0x0a, 0x00, 0xe3, 0x54, 0x18, 0x2a, 0x06, 0x90, 0x38, 0x18, 0x1c, 0x34, 0x0e, 0x27, 0x03,
-    0x8c, 0x40, 0xe3, 0xc1, 0xc2, 0xe8, 0x37, 0x1b, 0xad, 0x48, 0x1b, 0xa0, 0x24, 0xe1, 0x50, 0x1d,
-    0x4c, 0x61, 0xba, 0x00, 0x8f, 0xd0, 0xaa, 0x60, 0xa8, 0xfc, 0x10, 0x50, 0x7e, 0x85, 0xb3, 0x0d,
-    0xd4, 0x7e, 0x1b, 0xa0, 0x2c, 0xe1, 0x50, 0x1d, 0x4c, 0xc9, 0xb6, 0x1c, 0x26, 0x60, 0x74, 0x2a,
-    0x0b, 0x11, 0xf8, 0xac, 0xe1, 0x50, 0xb7, 0x0d, 0xd0, 0xd6, 0x6e, 0x02, 0x6e, 0xa4, 0x30, 0xdd,
-    0x03, 0x67, 0x0a, 0x80, 0xea, 0x6a, 0x4d, 0xc3, 0x0e, 0x13, 0x30, 0x3a, 0x19, 0x05, 0x88, 0xfc,
-    0x84, 0x38, 0x54, 0x2d, 0xc3, 0x74, 0x3d, 0x9b, 0x88, 0x4d, 0xd4, 0x8e, 0x1b, 0xa0, 0xac, 0xe1,
-    0x50, 0x1d, 0x4d, 0xc9, 0xb8, 0xb0, 0xe1, 0x33, 0x03, 0xa1, 0xd0, 0x58, 0x8f, 0xc9, 0xd3, 0x85,
-    0x42, 0xdc, 0x37, 0x44, 0x59, 0xb8, 0xc4, 0xdd, 0x49, 0x61, 0xba, 0x0e, 0xce, 0x15, 0x01, 0xd4,
-    0xe4, 0x9b, 0x8f, 0x0e, 0x13, 0x30, 0x3a, 0x21, 0x05, 0x88, 0xfc, 0xb6, 0x38, 0x54, 0x2d, 0xc3,
-    0x74, 0x4d, 0x9b, 0x90, 0x26, 0xea, 0x4f, 0x0d, 0xd0, 0x96, 0x70, 0xa8, 0x0e, 0xa7, 0x64, 0xdc,
-    0x8c, 0x38, 0x4c, 0xc0, 0xe8, 0x94, 0x16, 0x23, 0xf3, 0x1e, 0x70, 0xa8, 0x5b, 0x86, 0xe8, 0xab,
-    0x37, 0x24, 0x4d, 0xd4, 0xa6, 0x1b, 0xa1, 0x6c, 0xe1, 0x50, 0x1d, 0x4f, 0x49, 0xb9, 0x38, 0x70,
-    0x99, 0x81, 0xd1, 0x48, 0x2c, 0x47, 0xe9, 0x60, 0x9c, 0x2a, 0x16, 0xe1, 0xba, 0x2e, 0xcd, 0xca,
-    0x13, 0xf4, 0x5e, 0x1b, 0xa9, 0x5d, 0x14, 0x01, 0xd1, 0x5e, 0x2c, 0x47, 0x0b, 0x40, 0x1a, 0x87,
-    0x0b, 0x85, 0x81, 0xc3, 0x47, 0x0b, 0x8b, 0x80, 0xe3, 0x01, 0xc2, 0xe4, 0x08, 0x1c, 0x83, 0x1c,
-    0x2e, 0x4a, 0x01, 0xc9, 0x61, 0xc2, 0xe5, 0x38, 0x1c, 0xa9, 0x03, 0x97, 0x43, 0x74, 0x6a, 0x8b,
-    0xa1, 0xd4, 0x71, 0xc7, 0xe8, 0x80, 0x37, 0x46, 0xd0, 0xa0, 0x58, 0x0e, 0x38, 0xe3, 0x8d, 0xd1,
-    0xc2, 0x71, 0xba, 0x38, 0xc5, 0x07, 0x1c, 0x6e, 0x8e, 0x73, 0x8d, 0xd1, 0xd2, 0x28, 0x16, 0x21,
-    0x6c, 0x03, 0x84, 0x8e, 0x17, 0x33, 0x60, 0x73, 0x42, 0x07, 0x46, 0xd0, 0x1d, 0x1e, 0x80, 0x74,
-    0xb2, 0x46, 0xeb, 0x62, 0x26, 0x18, 0x60, 0x9d, 0x78, 0x8c, 0x5d, 0x78, 0xaf, 0xaf, 0x12, 0x24,
-    0xa1, 0x3a, 0xee, 0xd0, 0xba, 0x8a, 0xce, 0xa2, 0xb0, 0x40, 0x9d, 0x6f, 0xd8, 0xc3, 0x04, 0xeb,
-    0x7e, 0x86, 0x0b, 0xad, 0xdd, 0x75, 0xbb, 0x71, 0x41, 0x87, 0xeb, 0x7d, 0x62, 0xeb, 0x76, 0xe3,
-    0xe2, 0x28, 0x16, 0x23, 0x0d, 0xa4, 0xe1, 0x75, 0xbf, 0x01, 0xf2, 0x14, 0x0b, 0x21, 0x87, 0xeb,
-    0x7f, 0xe6, 0x1b, 0xad, 0xfe, 0x9c, 0x2a, 0x37, 0x5b, 0xfc, 0x38, 0x54, 0x6d, 0xe7, 0x0a, 0x8d,
-    0xc1, 0x4e, 0x15, 0x1f, 0x80, 0x8a, 0x05, 0xc0, 0x42, 0xeb, 0x67, 0x7d, 0x6c, 0xec, 0x5d, 0x6c,
-    0xf3, 0xaf, 0x02, 0x8a, 0x0c, 0x30, 0xc1, 0x3a, 0xda, 0xd1, 0x82, 0xeb, 0x6a, 0x7d, 0x6d, 0x40,
-    0x50, 0x61, 0xfa, 0xda, 0xf0, 0xba, 0xda, 0x58, 0xf8, 0x8a, 0x05, 0x88, 0xc3, 0xf5, 0xb5, 0x73,
-    0x0f, 0xd6, 0xd5, 0xcc, 0x3f, 0x21, 0x85, 0x02, 0xc4, 0x6e, 0xb6, 0xd6, 0x70, 0xa8, 0xdc, 0x0c,
-    0xe1, 0x51, 0xf8, 0x00, 0xa0, 0x5c, 0x00, 0x61, 0xf8, 0x09, 0x87, 0xe0, 0x26, 0x1f, 0x92, 0xc2,
-    0x81, 0x62, 0x37, 0x01, 0x38, 0x54, 0x6e, 0x02, 0x70, 0xa8, 0xdc, 0x42, 0x70, 0xa8, 0xfc, 0x0c,
-    0x50, 0x2e, 0x06, 0x30, 0xfc, 0x10, 0xc3, 0xf0, 0x43, 0x0f, 0xca, 0x91, 0x40, 0xb1, 0x1b, 0x82,
-    0x1c, 0x2a, 0x37, 0x04, 0x38, 0x54, 0x6e, 0x08, 0x70, 0xa8, 0xdc, 0x4e, 0x70, 0xa8, 0xfc, 0x18,
-    0x50, 0x2e, 0x0c, 0x17, 0x23, 0x46, 0xeb, 0x49, 0x62, 0x75, 0xc7, 0xc3, 0x04, 0xeb, 0x86, 0x86,
-    0x1f, 0xae, 0x1b, 0x0b, 0xac, 0x80, 0x05, 0x46, 0x1b, 0xae, 0x3e, 0x1c, 0x2e, 0xb8, 0x3a, 0x37,
-    0x5c, 0x08, 0x38, 0x54, 0x7e, 0xb2, 0x16, 0x28, 0x3e, 0xb1, 0x40, 0xb5, 0x8c, 0x37, 0x5c, 0x29,
-    0x38, 0x5d, 0x71, 0x48, 0x6d, 0xe7, 0x0a, 0x8d, 0xbc, 0xe1, 0x51, 0xba, 0xc8, 0xd1, 0xc2, 0xa3,
-    0x70, 0xb3, 0x85, 0x47, 0xe0, 0x42, 0x81, 0x70, 0x21, 0xb9, 0x66, 0x60, 0x9d, 0x6f, 0x44, 0xc1,
-    0x75, 0xbc, 0xde, 0xb7, 0x94, 0x28, 0x30, 0xfd, 0x6f, 0x54, 0x5d, 0x6f, 0x20, 0x7c, 0x45, 0x02,
-    0xc4, 0x61, 0xba, 0xde, 0xd9, 0xc2, 0xeb, 0x78, 0x63, 0x6c, 0x38, 0x54, 0x7d, 0x22, 0x81, 0x69,
-    0x18, 0x6d, 0x47, 0x0b, 0x96, 0xe3, 0x6a, 0x38, 0x54, 0x6e, 0x06, 0x70, 0xa8, 0xfb, 0x05, 0x02,
-    0xd8, 0x30, 0xdb, 0x4e, 0x17, 0x2c, 0xc6, 0xda, 0x70, 0xa8, 0xdb, 0x4e, 0x15, 0x1b, 0x84, 0x9c,
-    0x2a, 0x3e, 0xf1, 0x40, 0xb7, 0x8c, 0x37, 0x00, 0x38, 0x5c, 0xaf, 0x1b, 0x80, 0x1c, 0x2a, 0x37,
-    0x00, 0x38, 0x54, 0x6e, 0x00, 0x70, 0xa8, 0xdc, 0x3c, 0xe1, 0x51, 0xf8, 0x10, 0xa0, 0x5c, 0x08,
-    0x2e, 0x43, 0x85, 0xca, 0xf0, 0xba, 0xf8, 0x60, 0xe1, 0x75, 0xec, 0x12, 0x0e, 0x17, 0x5e, 0xc5,
-    0xeb, 0x7f, 0xa7, 0xeb, 0xf3, 0x47, 0xeb, 0xf4, 0x41, 0x80, 0x60, 0x07, 0x5c, 0x23, 0x03, 0xae,
-    0x13, 0x41, 0x40, 0x3a, 0xe8, 0x68, 0x1d, 0x74, 0x76, 0x0a, 0x01, 0xd7, 0x39, 0x40, 0xeb, 0x9d,
-    0x10, 0x50, 0x0e, 0xbe, 0x10, 0x07, 0x5f, 0x02, 0x82, 0x80, 0x75, 0xed, 0x30, 0x3a, 0xf5, 0xec,
-    0x14, 0x03, 0xae, 0xd5, 0x01, 0xd7, 0x69, 0xe0, 0xa0, 0x1d, 0x76, 0xd4, 0x0e, 0xbb, 0x65, 0x05,
-    0x00, 0xeb, 0xb8, 0x00, 0x75, 0xdb, 0xd8, 0x28, 0x30, 0x1d, 0x77, 0x7e, 0x03, 0xae, 0xb3, 0x51,
-    0x40, 0x3a, 0x86, 0x40, 0x3a, 0x86, 0x28, 0x28, 0x38, 0x5a, 0x07, 0x07, 0x8a, 0x00, 0xe2, 0xb0,
-    0xba, 0x04, 0xc8, 0x30, 0xdd, 0x77, 0x38, 0xdd, 0x77, 0x38, 0xdd, 0x77, 0x38, 0x50, 0x7e, 0xbb,
-    0xa2, 0x7e, 0xbb, 0xa2, 0x28, 0x16, 0x03, 0x85, 0xa4, 0x70, 0xb6, 0x0d, 0xb8, 0xdd, 0x77, 0x4c,
-    0xdd, 0x77, 0x4c, 0xdd, 0x77, 0x4c, 0x50, 0x7e, 0xbb, 0xac, 0x7e, 0xbb, 0xac, 0x28, 0x16, 0x03,
-    0x85, 0xa4, 0x70, 0xb6, 0x0d, 0xc2, 0xcd, 0xd7, 0x76, 0x0d, 0xd7, 0x76, 0x0d, 0xd7, 0x76, 0x05,
-    0x07, 0xeb, 0xbb, 0x67, 0xeb, 0xbb, 0x62, 0x81, 0x60, 0x38, 0x5a, 0x47, 0x0b, 0x60, 0xdc, 0x50,
-    0x6e, 0xbd, 0xfc, 0x6e, 0xbd, 0xfc, 0x27, 0x5e, 0xfa, 0x3f, 0x5f, 0x00, 0x13, 0xaf, 0x7a, 0x8a,
-    0x8e, 0x16, 0x63, 0x85, 0xa8, 0x6e, 0x33, 0x37, 0x5f, 0x51, 0x37, 0x5f, 0x51, 0x13, 0xaf, 0xa4,
-    0x9f, 0xaf, 0xa9, 0x89, 0xd7, 0xd1, 0x45, 0x47, 0x0b, 0x31, 0xc2, 0xd4, 0x37, 0x1f, 0x1b, 0xae,
-    0xa2, 0x9b, 0xae, 0xa2, 0x89, 0xd7, 0x42, 0x4f, 0xd7, 0x51, 0xc4, 0xeb, 0xa7, 0xe2, 0xa3, 0x85,
-    0x98, 0xe1, 0x6a, 0x1b, 0x91, 0x26, 0xeb, 0xb1, 0x02, 0xeb, 0xaa, 0xbd, 0x76, 0x1c, 0x5d, 0x74,
-    0xc3, 0xae, 0xba, 0x8a, 0x0e, 0x16, 0x23, 0x85, 0xa0, 0x6e, 0x49, 0x1b, 0xae, 0x4e, 0x9b, 0xae,
-    0x4e, 0x89, 0xd7, 0x26, 0x0f, 0xd7, 0x27, 0xc4, 0xeb, 0x92, 0x42, 0xa3, 0x85, 0x98, 0xe1, 0x6a,
-    0x20, 0xc3, 0x0f, 0xd7, 0xd3, 0xcc, 0x41, 0xc2, 0xa1, 0x64, 0x37, 0x5c, 0xae, 0x37, 0x5c, 0xb1,
-    0x37, 0x5f, 0x57, 0x38, 0x54, 0x2c, 0x45, 0xeb, 0x92, 0x62, 0x75, 0xf5, 0xc3, 0x89, 0xd0, 0xb4,
-    0x27, 0x5f, 0x5f, 0x38, 0x38, 0x0e, 0x2f, 0x5c, 0x9b, 0x17, 0x5c, 0x9b, 0x10, 0x27, 0x5f, 0x66,
-    0x38, 0x58, 0xf5, 0xc9, 0xf2, 0x04, 0xeb, 0xed, 0x47, 0x07, 0x11, 0xc1, 0xd8, 0x07, 0x05, 0x24,
-    0x81, 0x51, 0xba, 0xfb, 0x99, 0xc2, 0xe1, 0xc0, 0x3a, 0xa5, 0x23, 0xf5, 0xd8, 0xf3, 0x85, 0xc4,
-    0x41, 0x60, 0x49, 0x42, 0x08, 0x3f, 0x5c, 0xd2, 0x14, 0x17, 0x50, 0xa0, 0xe2, 0xe9, 0x13, 0x81,
-    0x13, 0x58, 0xa0, 0xe0, 0xe4, 0x07, 0x00, 0x1c, 0x1e, 0x14, 0x07, 0x1c, 0x81, 0xc8, 0x31, 0xba,
-    0xe7, 0x41, 0xba, 0xe7, 0x40, 0xba, 0xe6, 0xd8, 0xfd, 0x73, 0xa8, 0x5d, 0x73, 0x94, 0x2a, 0x38,
-    0x59, 0x8e, 0x16, 0xa2, 0x0e, 0x30, 0xdc, 0xd6, 0x98, 0x7e, 0x46, 0x8a, 0x00, 0xe0, 0x62, 0xc8,
-    0x7c, 0x8d, 0xa8, 0xfa, 0x85, 0x02, 0xc0, 0x0c, 0x80, 0xe0, 0x00, 0x72, 0xa4, 0x6e, 0xbb, 0xd4,
-    0x2e, 0xbb, 0xc5, 0xd7, 0x64, 0x05, 0xd7, 0x76, 0x7a, 0xef, 0x50, 0xa0, 0xe1, 0x62, 0x38, 0x5a,
-    0x08, 0x38, 0xc3, 0x73, 0x8c, 0x62, 0x0e, 0x15, 0x01, 0xc0, 0x85, 0x98, 0xf9, 0x9b, 0x59, 0xf5,
-    0x8a, 0x05, 0x80, 0x19, 0x01, 0xc0, 0x40, 0xe6, 0x30, 0x6e, 0xbb, 0xb2, 0x6e, 0xbb, 0xb2, 0x2e,
-    0xbb, 0x9e, 0x3f, 0x5d, 0xdb, 0x17, 0x5d, 0xa0, 0x0a, 0x8e, 0x16, 0x63, 0x85, 0xa8, 0x83, 0x8c,
-    0x37, 0x3c, 0x46, 0x20, 0xe1, 0x50, 0x1c, 0x10, 0x59, 0x8f, 0x99, 0xb5, 0x9f, 0x58, 0xa0, 0x58,
-    0x01, 0x90, 0x1c, 0x04, 0x0e, 0x6c, 0xc6, 0xec, 0x01, 0x53, 0x76, 0x00, 0x68, 0xbb, 0x00, 0x54,
-    0x7e, 0xc0, 0x0f, 0x17, 0x60, 0x03, 0x85, 0x47, 0x0b, 0x31, 0xc2, 0xd4, 0x41, 0xc6, 0x1b, 0x9f,
-    0xe3, 0x10, 0x70, 0xa8, 0x0e, 0x08, 0x2c, 0xc7, 0xcc, 0xda, 0xcf, 0xac, 0x50, 0x2c, 0x00, 0xc8,
-    0x0e, 0x02, 0x07, 0x3b, 0x43, 0x75, 0xff, 0x93, 0x75, 0xfe, 0x11, 0x75, 0xff, 0x91, 0xfa, 0xff,
-    0x18, 0xba, 0xfe, 0xf8, 0x54, 0x70, 0xb3, 0x1c, 0x2d, 0x44, 0x1c, 0x61, 0xba, 0x06, 0x8c, 0x41,
-    0xc2, 0xa0, 0x38, 0x20, 0xb3, 0x1f, 0x33, 0x6b, 0x3e, 0xb1, 0x40, 0xb0, 0x03, 0x20, 0x38, 0x08,
-    0x1d, 0x00, 0x23, 0x75, 0xee, 0x93, 0x75, 0xee, 0x93, 0x75, 0xee, 0x91, 0x41, 0xfa, 0xf7, 0x61,
-    0xfa, 0xf7, 0x60, 0xa0, 0x58, 0x0e, 0x16, 0x91, 0xc2, 0xd8, 0x41, 0xc6, 0x1b, 0xa0, 0xe0, 0xc4,
-    0x1c, 0x2a, 0x03, 0x83, 0x0b, 0x31, 0xf3, 0x36, 0xb3, 0xeb, 0x14, 0x0b, 0x00, 0x32, 0x03, 0x80,
-    0x81, 0xd0, 0x58, 0x37, 0x5f, 0x0f, 0x37, 0x5f, 0x0f, 0x37, 0x5f, 0x0f, 0x14, 0x1f, 0xaf, 0x89,
-    0x1f, 0xaf, 0x89, 0x0a, 0x05, 0x80, 0xe1, 0x69, 0x1c, 0x2d, 0x84, 0x1c, 0x61, 0xba, 0x15, 0x8c,
-    0x41, 0xc2, 0xa0, 0x38, 0x30, 0xb3, 0x1f, 0x33, 0x6b, 0x3e, 0xb1, 0x40, 0xb0, 0x03, 0x20, 0x38,
-    0x08, 0x1d, 0x0a, 0xe3, 0x75, 0xf3, 0x53, 0x75, 0xf3, 0x53, 0x75, 0xf3, 0x51, 0x41, 0xfa, 0xf9,
-    0xc1, 0xfa, 0xf9, 0xc0, 0xa0, 0x58, 0x0e, 0x16, 0x91, 0xc2, 0xd8, 0x41, 0xc6, 0x1b, 0xa1, 0xd0,
-    0xc4, 0x1c, 0x2a, 0x03, 0x83, 0x0b, 0x31, 0xf3, 0x36, 0xb3, 0xeb, 0x14, 0x0b, 0x00, 0x32, 0x03,
-    0x80, 0x90, 0x70, 0x28, 0x1d, 0x10, 0xc0, 0x74, 0xa7, 0x47, 0x0b, 0xa9, 0xc6, 0xec, 0x0c, 0x30,
-    0x3a, 0x9b, 0x61, 0x76, 0x06, 0x48, 0x0c, 0x8e, 0x0e, 0x40, 0x74, 0xa9, 0x80, 0xea, 0x65, 0x47,
-    0xec, 0x0c, 0xb2, 0xf4, 0xa9, 0xc4, 0xec, 0x08, 0x01, 0x74, 0x9f, 0xba, 0x4f, 0xc2, 0x05, 0x3a,
-    0x4f, 0xc2, 0x83, 0x0c, 0x30, 0xc3, 0x04, 0xeb, 0xb8, 0x86, 0x1f, 0xae, 0xe9, 0x98, 0x7e, 0xbb,
-    0xa6, 0x60, 0x9d, 0x27, 0x61, 0x60, 0x3f, 0x5d, 0xcc, 0x14, 0x0b, 0x48, 0xc3, 0xea, 0x30, 0xfa,
-    0x8c, 0x13, 0xa4, 0xde, 0x2c, 0x06, 0xeb, 0xba, 0xc7, 0x0a, 0x8f, 0xb0, 0x50, 0x2d, 0x83, 0x0f,
-    0xb4, 0xc3, 0xed, 0x30, 0x4e, 0x93, 0x40, 0xb0, 0x1b, 0x69, 0xc2, 0xa3, 0x70, 0x93, 0x85, 0x47,
-    0xde, 0x28, 0x16, 0xf0, 0xba, 0xda, 0xc7, 0x5b, 0x12, 0x30, 0xc3, 0x0c, 0x17, 0x5e, 0xae, 0xeb,
-    0x8e, 0xe2, 0x83, 0x0d, 0xd7, 0x1e, 0x44, 0xeb, 0xd6, 0xa7, 0xc4, 0x50, 0x2c, 0x46, 0x1f, 0xae,
-    0x3f, 0x98, 0x27, 0x5e, 0x83, 0x36, 0xc3, 0x85, 0x47, 0xd2, 0x28, 0x16, 0x91, 0x87, 0xd4, 0x61,
-    0xfa, 0xf4, 0x99, 0x82, 0x74, 0x99, 0xc5, 0x80, 0xdb, 0x4e, 0x15, 0x1b, 0x83, 0x1c, 0x2a, 0x3e,
-    0xf1, 0x40, 0xb7, 0x85, 0xc5, 0x01, 0x74, 0xbf, 0xba, 0x5f, 0xc2, 0xe9, 0x7f, 0xf4, 0x98, 0x85,
-    0x06, 0x18, 0x60, 0xba, 0x4b, 0x3d, 0x26, 0x13, 0x0d, 0xd2, 0x62, 0x13, 0xa4, 0xba, 0x2a, 0x16,
-    0x43, 0x0f, 0x99, 0x87, 0xe9, 0x34, 0x98, 0x27, 0x49, 0x6c, 0x58, 0x0f, 0xa4, 0x50, 0x2d, 0x23,
-    0x0f, 0xa8, 0xc3, 0xea, 0x30, 0x4e, 0x92, 0xc0, 0xb0, 0x1b, 0x81, 0x9c, 0x2a, 0x3e, 0xc1, 0x40,
-    0xb6, 0x0c, 0x3e, 0xd3, 0x0f, 0xb4, 0xc1, 0x3a, 0x4a, 0xa2, 0xc0, 0x6d, 0xa7, 0x0a, 0x8d, 0xc2,
-    0x4e, 0x15, 0x1f, 0x78, 0xa0, 0x5b, 0xc2, 0xe3, 0x3e, 0x99, 0x81, 0x86, 0x18, 0x2e, 0xbe, 0x2f,
-    0xd7, 0xba, 0x45, 0x06, 0x1e, 0x8b, 0xaf, 0x72, 0x85, 0x46, 0x1f, 0xaf, 0x76, 0x98, 0x27, 0x5f,
-    0x21, 0x36, 0xa3, 0x85, 0x47, 0xd2, 0x28, 0x16, 0x91, 0x87, 0xd4, 0x60, 0x9d, 0x32, 0xe3, 0x6a,
-    0x38, 0x54, 0x6e, 0x06, 0x70, 0xa8, 0xfb, 0x05, 0x02, 0xd8, 0x17, 0x11, 0x05, 0xc9, 0x60, 0xba,
-    0xf7, 0x17, 0x4c, 0x9c, 0xc3, 0x05, 0xd3, 0x15, 0xe9, 0x92, 0x0a, 0x0c, 0x37, 0x4c, 0x98, 0x4e,
-    0x98, 0xd1, 0xf1, 0x14, 0x0b, 0x11, 0x87, 0xe9, 0x96, 0x18, 0x27, 0x4c, 0x40, 0xdb, 0x0e, 0x15,
-    0x1f, 0x48, 0xa0, 0x5a, 0x46, 0x1f, 0x51, 0x82, 0x74, 0xc1, 0x8d, 0xa8, 0xe1, 0x51, 0xb8, 0x19,
-    0xc2, 0xa3, 0xec, 0x14, 0x0b, 0x60, 0xdd, 0x75, 0x30, 0xc1, 0x3a, 0xf7, 0xf9, 0x87, 0xeb, 0xe0,
-    0x22, 0xeb, 0xee, 0xe1, 0x51, 0x87, 0xeb, 0xf0, 0x86, 0x09, 0xd3, 0x01, 0x3f, 0x5d, 0x52, 0x14,
-    0x1f, 0x40, 0xa0, 0x5a, 0x06, 0x1f, 0x49, 0x82, 0x74, 0xbe, 0xcd, 0xd7, 0x56, 0xce, 0x15, 0x1b,
-    0x81, 0x1c, 0x2a, 0x3e, 0xc1, 0x40, 0xb6, 0x05, 0xc3, 0x82, 0xeb, 0xe4, 0x5d, 0x2f, 0x43, 0x0c,
-    0x17, 0x4b, 0x7f, 0xa5, 0xd4, 0x28, 0x30, 0xdd, 0x2e, 0xc1, 0x3a, 0x5c, 0x87, 0xc4, 0x50, 0x2c,
-    0x46, 0x1f, 0xa5, 0xe4, 0x60, 0x9d, 0x2d, 0xe3, 0x6c, 0x38, 0x54, 0x7d, 0x22, 0x81, 0x69, 0x18,
-    0x7d, 0x46, 0x09, 0xd2, 0xd8, 0x36, 0xa3, 0x85, 0x46, 0xe0, 0x67, 0x0a, 0x8f, 0xb0, 0x50, 0x2d,
-    0x81, 0x75, 0xf4, 0x4e, 0xc1, 0xca, 0x30, 0xc1, 0x3b, 0x07, 0x04, 0xc1, 0x3b, 0x06, 0xe0, 0xc1,
-    0x76, 0x0d, 0x9f, 0x60, 0xd8, 0x0a, 0x0c, 0x3f, 0x60, 0xde, 0x0b, 0xb0, 0x6b, 0x47, 0xc4, 0x50,
-    0x2c, 0x42, 0xe0, 0x01, 0x71, 0x98, 0x5c, 0xb4, 0x1b, 0xa0, 0x84, 0xc3, 0x0c, 0x13, 0xaf, 0x77,
-    0x98, 0x2e, 0xbd, 0xcb, 0xd0, 0x3c, 0x28, 0x30, 0xfd, 0x7b, 0xcc, 0x5d, 0x03, 0xc3, 0xe2, 0x28,
-    0x16, 0x23, 0x0d, 0xd7, 0xbe, 0x4e, 0x17, 0x40, 0xd8, 0xdb, 0x0e, 0x15, 0x1f, 0x48, 0xa0, 0x5a,
-    0x46, 0x1b, 0x51, 0xc2, 0xe8, 0x18, 0x1b, 0x51, 0xc2, 0xa3, 0x70, 0x33, 0x85, 0x47, 0xd8, 0x28,
-    0x16, 0xc1, 0xba, 0xe1, 0x28, 0x9d, 0x76, 0x20, 0xc3, 0xf5, 0xd8, 0xa1, 0x75, 0xd8, 0x80, 0xa8,
-    0xc3, 0xf5, 0xd8, 0xd1, 0x75, 0xf8, 0xf1, 0xf1, 0x14, 0x0b, 0x11, 0x86, 0xeb, 0xf2, 0xc7, 0x0b,
-    0xa0, 0x28, 0x6e, 0xbb, 0x2c, 0x70, 0xa8, 0xdb, 0xce, 0x15, 0x1f, 0x60, 0xa0, 0x5b, 0x02, 0xe1,
-    0x43, 0x74, 0x04, 0x18, 0x27, 0x40, 0x29, 0x82, 0xe8, 0x04, 0xe8, 0x00, 0x14, 0x18, 0x7e, 0x80,
-    0x91, 0x74, 0x00, 0x0f, 0x88, 0xa0, 0x58, 0x8c, 0x37, 0x40, 0x79, 0xc2, 0xe7, 0xf4, 0x6d, 0x87,
-    0x0a, 0x8f, 0xa4, 0x50, 0x2d, 0x23, 0x0d, 0xa8, 0xe1, 0x73, 0xf4, 0x36, 0xa3, 0x85, 0x46, 0xe0,
-    0x67, 0x0a, 0x8f, 0xb0, 0x50, 0x2d, 0x81, 0x3a, 0x80, 0x41, 0x71, 0x28, 0x5d, 0x3b, 0xae, 0x9c,
-    0x90, 0xba, 0x69, 0xbd, 0x84, 0xac, 0x28, 0x16, 0x01, 0x72, 0xa4, 0x70, 0xbb, 0x08, 0x38, 0x83,
-    0x90, 0x62, 0x0c, 0x03, 0xa6, 0x5c, 0x7a, 0x07, 0x4c, 0xc0, 0x54, 0x0c, 0xc7, 0xd2, 0x05, 0x15,
-    0x1c, 0x1d, 0xc0, 0x70, 0x10, 0xba, 0x28, 0x08, 0x30, 0xdd, 0x81, 0xfc, 0x7e, 0xc0, 0xff, 0x14,
-    0x18, 0x78, 0x28, 0x30, 0xf0, 0x50, 0x70, 0xb5, 0x8d, 0xb4, 0xdd, 0x82, 0x04, 0x7e, 0xc1, 0x03,
-    0x14, 0x18, 0x78, 0x28, 0x30, 0xf0, 0x50, 0x70, 0xb5, 0x8d, 0xc2, 0x4d, 0xd8, 0x20, 0xc7, 0xec,
-    0x10, 0x71, 0x41, 0x87, 0x82, 0x83, 0x0f, 0x05, 0x07, 0x0b, 0x58, 0xdc, 0x4a, 0x6e, 0xb2, 0x08,
-    0x7e, 0xb2, 0x0a, 0x28, 0x30, 0xf0, 0x50, 0x61, 0xe0, 0xa0, 0xe1, 0x6b, 0x1b, 0x8c, 0x4d, 0xd6,
-    0x45, 0xcf, 0xd6, 0x46, 0x05, 0x06, 0x1e, 0x0a, 0x0c, 0x3c, 0x14, 0x1c, 0x2d, 0x63, 0x71, 0xe9,
-    0xba, 0xc9, 0x51, 0xfa, 0xc9, 0x58, 0xa0, 0xc3, 0xc1, 0x41, 0x87, 0x82, 0x83, 0x85, 0xac, 0x6e,
-    0x44, 0x9b, 0xac, 0x9f, 0x9f, 0xac, 0xa0, 0x0a, 0x0c, 0x3c, 0x14, 0x18, 0x78, 0x28, 0x38, 0x5a,
-    0xc6, 0xe4, 0xa9, 0xba, 0xca, 0x91, 0xfa, 0xca, 0x98, 0xa0, 0xc3, 0xc1, 0x41, 0x87, 0x82, 0x83,
-    0x85, 0xac, 0x83, 0x0c, 0x3f, 0x61, 0x43, 0x18, 0x7e, 0xc2, 0x86, 0x14, 0x0b, 0x12, 0x48, 0x10,
-    0x20, 0xdd, 0x81, 0x4e, 0x28, 0x03, 0xac, 0xae, 0x9b, 0xb0, 0xa4, 0x8e, 0x16, 0x21, 0x60, 0x37,
-    0x60, 0x57, 0x24, 0x81, 0x02, 0x05, 0x88, 0xb8, 0x8a, 0x0d, 0xd6, 0x61, 0xcd, 0xd6, 0x5c, 0x8f,
-    0xd8, 0x54, 0x02, 0x83, 0x75, 0x98, 0xa3, 0x75, 0x97, 0xd3, 0xf6, 0x15, 0x20, 0xa0, 0x5d, 0x85,
-    0x4d, 0xd8, 0x54, 0xc7, 0x0b, 0xaf, 0xef, 0xf4, 0xf7, 0xce, 0x0d, 0x18, 0x7e, 0xc2, 0xac, 0x14,
-    0x1b, 0xb0, 0x01, 0xcd, 0xd8, 0x01, 0x42, 0x60, 0x06, 0x62, 0xc0, 0x7e, 0xc0, 0x0c, 0x2f, 0x5e,
-    0xb4, 0x38, 0x34, 0x7e, 0xc0, 0x12, 0x14, 0x03, 0x30, 0x36, 0x81, 0xc1, 0x82, 0xe1, 0x41, 0x76,
-    0x07, 0xef, 0x60, 0x0a, 0x81, 0x80, 0xbb, 0x00, 0x50, 0x7e, 0xc0, 0x19, 0x37, 0x61, 0x60, 0x8b,
-    0x58, 0x54, 0x0c, 0x40, 0xd2, 0x07, 0x12, 0x85, 0xc5, 0x23, 0xf6, 0x08, 0xc1, 0xc6, 0xec, 0x0e,
-    0x23, 0xea, 0x14, 0x01, 0xa0, 0x58, 0x8f, 0xb8, 0xfa, 0x0d, 0xd8, 0x24, 0x22, 0x81, 0x60, 0x06,
-    0x40, 0x6e, 0x01, 0xd3, 0x5f, 0x07, 0x1d, 0x08, 0x13, 0x8d, 0x4e, 0x2f, 0x21, 0x04, 0xe3, 0x83,
-    0x83, 0x80, 0x1c, 0x8a, 0x13, 0x92, 0xa2, 0x83, 0xf6, 0x01, 0x10, 0xb9, 0x22, 0x03, 0xac, 0xe0,
-    0x20, 0x50, 0x92, 0x84, 0x12, 0x8a, 0x85, 0x44, 0x1f, 0xb0, 0x0b, 0x05, 0x02, 0xec, 0x04, 0x9e,
-    0x3c, 0x38, 0x5d, 0x82, 0x64, 0x38, 0x5d, 0x82, 0x12, 0x38, 0x5d, 0x86, 0x56, 0x38, 0xa1, 0x6a,
-    0x1c, 0x5e, 0x08, 0x27, 0x2a, 0x89, 0xc1, 0x85, 0x07, 0x07, 0x20, 0x38, 0x58, 0xe0, 0xf1, 0x10,
-    0x1c, 0xba, 0x03, 0x98, 0x31, 0xba, 0xcf, 0x71, 0xfa, 0xcf, 0x78, 0xa0, 0xc3, 0xc1, 0x41, 0x87,
-    0x82, 0x83, 0x85, 0xac, 0x83, 0x8c, 0x37, 0x3e, 0x46, 0x1f, 0xb0, 0xc5, 0x05, 0x00, 0x70, 0x41,
-    0x64, 0x3e, 0x46, 0xd4, 0x7d, 0x42, 0x81, 0x60, 0x06, 0x40, 0x70, 0x00, 0x39, 0xab, 0x1b, 0xad,
-    0x05, 0x9f, 0xad, 0x06, 0x0a, 0x0c, 0x3c, 0x14, 0x18, 0x78, 0x28, 0x38, 0x5a, 0xc8, 0x38, 0xc3,
-    0x74, 0x07, 0x18, 0x7e, 0xc2, 0xbc, 0x14, 0x01, 0xc1, 0x05, 0x90, 0xf9, 0x1b, 0x51, 0xf5, 0x0a,
-    0x05, 0x80, 0x19, 0x01, 0xc0, 0x00, 0xe7, 0x4c, 0x6e, 0xb4, 0x50, 0x7e, 0xb4, 0x52, 0x28, 0x30,
-    0xf0, 0x50, 0x61, 0xe0, 0xa0, 0xe1, 0x6b, 0x20, 0xe3, 0x0d, 0xd0, 0x54, 0x61, 0xfb, 0x0b, 0x3c,
-    0x50, 0x07, 0x04, 0x16, 0x43, 0xe4, 0x6d, 0x47, 0xd4, 0x28, 0x16, 0x00, 0x64, 0x07, 0x00, 0x03,
-    0x9f, 0xb1, 0xba, 0xd2, 0x29, 0xfa, 0xd2, 0x30, 0xa0, 0xc3, 0xc1, 0x41, 0x87, 0x82, 0x83, 0x85,
-    0xac, 0x83, 0x8c, 0x37, 0x42, 0x31, 0x87, 0xec, 0x2f, 0x01, 0x40, 0x1c, 0x10, 0x59, 0x0f, 0x91,
-    0xb5, 0x1f, 0x50, 0xa0, 0x58, 0x01, 0x90, 0x1c, 0x00, 0x0e, 0x82, 0x31, 0xba, 0xd3, 0x11, 0xfa,
-    0xd3, 0x18, 0xa0, 0xc3, 0xc1, 0x41, 0x87, 0x82, 0x83, 0x85, 0xac, 0x83, 0x8c, 0x37, 0x43, 0x11,
-    0x87, 0xec, 0x3d, 0x21, 0x40, 0x1c, 0x10, 0x59, 0x0f, 0x91, 0xb5, 0x1f, 0x50, 0xa0, 0x58, 0x01,
-    0x90, 0x1c, 0x00, 0x0e, 0x84, 0xb1, 0xbb, 0x09, 0x80, 0xfd, 0x84, 0xc2, 0x28, 0x30, 0xf0, 0x50,
-    0x61, 0xe0, 0xa0, 0xe1, 0x6b, 0x20, 0xe3, 0x0d, 0xd0, 0xfc, 0x61, 0xfb, 0x0b, 0x08, 0x50, 0x07,
-    0x04, 0x16, 0x43, 0xe4, 0x6d, 0x47, 0xd4, 0x28, 0x16, 0x00, 0x64, 0x07, 0x00, 0x03, 0xa1, 0xcc,
-    0x6e, 0xc2, 0x84, 0x3f, 0x61, 0x42, 0x8a, 0x0c, 0x3c, 0x14, 0x18, 0x78, 0x28, 0x38, 0x5a, 0xc8,
-    0x38, 0xc3, 0x74, 0x4d, 0x18, 0x7e, 0x8a, 0xa1, 0x40, 0x1c, 0x10, 0x59, 0x0f, 0x91, 0xb5, 0x1f,
-    0x50, 0xa0, 0x58, 0x01, 0x90, 0x1c, 0x00, 0x0e, 0x89, 0xb1, 0xbb, 0x0a, 0xa0, 0xfd, 0x85, 0x52,
-    0x28, 0x30, 0xf0, 0x50, 0x61, 0xe0, 0xa0, 0xe1, 0x6b, 0x20, 0xe3, 0x0d, 0xd1, 0x6c, 0x61, 0xfa,
-    0x32, 0x05, 0x00, 0x70, 0x41, 0x64, 0x3e, 0x46, 0xd4, 0x7d, 0x42, 0x81, 0x60, 0x06, 0x40, 0x70,
-    0x02, 0x0e, 0x05, 0x03, 0xa3, 0x1c, 0x0e, 0xa8, 0x44, 0x70, 0x3a, 0x6e, 0x54, 0xe0, 0xd0, 0x3a,
-    0xa1, 0x70, 0x3a, 0x6e, 0x01, 0x74, 0xdc, 0x82, 0xea, 0x87, 0xc2, 0xeb, 0x1d, 0x63, 0x76, 0x0a,
-    0xa1, 0x87, 0x1b, 0xac, 0x90, 0xa0, 0xc3, 0xf4, 0xe6, 0x4c, 0x38, 0xfc, 0x4e, 0x71, 0xc2, 0xc0,
-    0x2d, 0x03, 0x85, 0xb0, 0x6e, 0xb2, 0x5c, 0x6d, 0xa7, 0xe9, 0xe1, 0x98, 0x71, 0xf9, 0x1e, 0x71,
-    0xc2, 0xc0, 0x2d, 0x03, 0x85, 0xac, 0x6e, 0xb2, 0x74, 0x6e, 0x12, 0x7e, 0x9f, 0x69, 0x87, 0x1f,
-    0x96, 0xe7, 0x1c, 0x2c, 0x02, 0xd0, 0x38, 0x5a, 0xc6, 0xeb, 0x28, 0xc6, 0xe2, 0x53, 0xf5, 0x02,
-    0xe6, 0x1c, 0x7e, 0x67, 0xce, 0x38, 0x58, 0x05, 0xa0, 0x70, 0xb5, 0x8d, 0xd6, 0x54, 0x8d, 0xc6,
-    0x27, 0xea, 0x0f, 0xcc, 0x38, 0xfc, 0xe3, 0x9c, 0x70, 0xb0, 0x0b, 0x40, 0xe1, 0x6b, 0x1b, 0xac,
-    0xaf, 0x1b, 0x8f, 0x4f, 0xd4, 0x33, 0x98, 0x71, 0xf9, 0xef, 0x38, 0xe1, 0x60, 0x16, 0x81, 0xc2,
-    0xd6, 0x37, 0x59, 0x6a, 0x37, 0x22, 0x4f, 0xd4, 0x47, 0x98, 0x71, 0xfa, 0x05, 0xce, 0x38, 0x58,
-    0x05, 0xa0, 0x70, 0xb5, 0x8d, 0xd6, 0x5d, 0x8d, 0xc9, 0x53, 0xf5, 0x16, 0x86, 0x1c, 0x7e, 0x83,
-    0xf3, 0x8e, 0x16, 0x01, 0x68, 0x1c, 0x2d, 0x63, 0xf4, 0x23, 0x1c, 0x71, 0xba, 0x8c, 0x13, 0x8e,
-    0x37, 0x58, 0x81, 0x41, 0xc6, 0x1c, 0x2c, 0x42, 0xcc, 0x6e, 0xb3, 0x1e, 0x71, 0xe1, 0xbb, 0x09,
-    0xfc, 0xfa, 0x8e, 0x15, 0x1b, 0xb0, 0x6f, 0x8d, 0xd8, 0x38, 0xc6, 0xec, 0x2b, 0xc3, 0xf0, 0x01,
-    0x40, 0xb0, 0x0b, 0x21, 0xbb, 0x07, 0x34, 0xe3, 0x76, 0x0e, 0x69, 0xc6, 0xec, 0x47, 0x63, 0xf0,
-    0x93, 0x85, 0x48, 0x30, 0xe3, 0xd3, 0x83, 0x40, 0xcc, 0x2d, 0x84, 0x18, 0x71, 0xe9, 0xc1, 0xa0,
-    0x66, 0x17, 0x06, 0x1f, 0xaa, 0xd6, 0x3f, 0x58, 0x4b, 0x0c, 0x03, 0x01, 0xc1, 0xe0, 0x3c, 0x0c,
-    0x1c, 0x1c, 0x2e, 0x18, 0x0e, 0xcd, 0xc0, 0x81, 0x71, 0x08, 0x1d, 0x6e, 0x13, 0xad, 0xfc, 0x9c,
-    0x2e, 0xb9, 0xb0, 0x38, 0xe3, 0x53, 0x83, 0x40, 0xd2, 0x38, 0x3c, 0x04, 0x0e, 0x2f, 0x03, 0x8d,
-    0xc2, 0xe3, 0xc0, 0x3a, 0xbb, 0x5e, 0xae, 0xc8, 0x10, 0x1e, 0x40, 0x01, 0xc8, 0x31, 0xb9, 0xb4,
-    0x38, 0x54, 0x2e, 0x48, 0x8d, 0xd6, 0x75, 0x8e, 0x3c, 0x17, 0x60, 0xf6, 0x76, 0x25, 0x60, 0xbb,
-    0x12, 0x97, 0xb1, 0x2b, 0x4e, 0x17, 0x5f, 0x11, 0xec, 0x4a, 0xf0, 0x75, 0xb8, 0xce, 0x90, 0x40,
-    0xba, 0xdc, 0xd8, 0x0e, 0x8f, 0x00, 0x75, 0xb8, 0x80, 0xba, 0xdc, 0xf8, 0x0e, 0x8e, 0x30, 0x75,
-    0xb8, 0x20, 0xba, 0xdd, 0x18, 0xfd, 0x4a, 0x19, 0xbb, 0x13, 0xc8, 0xfd, 0x4a, 0x48, 0xa0, 0x0e,
-    0x8e, 0x91, 0x62, 0x3f, 0x52, 0x9a, 0x7e, 0xa5, 0x28, 0xdd, 0x85, 0xb4, 0x28, 0x16, 0x00, 0x64,
-    0x06, 0xd0, 0x3a, 0xdc, 0x50, 0x5d, 0x6e, 0xcc, 0x7e, 0xc5, 0x00, 0x38, 0xdd, 0x89, 0x92, 0x6e,
-    0xc4, 0xcc, 0x38, 0x54, 0x6e, 0xb7, 0x72, 0x6e, 0xb7, 0x34, 0x7e, 0xc4, 0xd1, 0x14, 0x01, 0xd1,
-    0xfc, 0x2c, 0x40, 0x70, 0x00, 0x68, 0x0b, 0x68, 0xfb, 0x0f, 0xc0, 0x0d, 0xd8, 0xa2, 0x22, 0x81,
-    0x60, 0x06, 0x40, 0x70, 0x61, 0xfb, 0x13, 0x74, 0x10, 0x0e, 0x30, 0x0b, 0xb1, 0x35, 0x47, 0x07,
-    0x8d, 0x00, 0xe3, 0x71, 0x7a, 0x3f, 0xc4, 0xe9, 0x2a, 0x8b, 0xab, 0x07, 0xea, 0xb6, 0xc5, 0xd5,
-    0x4b, 0xf5, 0x4c, 0xc2, 0x81, 0x76, 0x27, 0x48, 0xe1, 0x74, 0x96, 0xc7, 0xea, 0x38, 0xcd, 0xd5,
-    0x0d, 0x1f, 0xaa, 0x15, 0x14, 0x1c, 0x1c, 0x40, 0xcc, 0x2d, 0xc0, 0x75, 0x1f, 0x9d, 0x47, 0x78,
-    0x20, 0x1c, 0x89, 0x0b, 0xb1, 0x3b, 0xbb, 0x13, 0xac, 0x5d, 0x89, 0xdc, 0x17, 0x62, 0x77, 0x76,
-    0x27, 0x70, 0x81, 0x3b, 0x13, 0xb8, 0x58, 0x05, 0xd8, 0x9e, 0x9d, 0x89, 0xd8, 0x2e, 0xc4, 0xed,
-    0xc4, 0x50, 0x2e, 0xc4, 0xee, 0xec, 0x4f, 0x31, 0x40, 0xb2, 0x0b, 0xb1, 0x3d, 0xfb, 0x13, 0xdc,
-    0x4e, 0xc4, 0xfa, 0x17, 0x5f, 0x68, 0x0b, 0xb1, 0x3d, 0x7b, 0x13, 0xd4, 0x5d, 0x89, 0xed, 0xd8,
-    0x9f, 0x62, 0x81, 0x70, 0x0e, 0xc5, 0x00, 0x13, 0x01, 0x50, 0xb3, 0x0b, 0x82, 0x76, 0x27, 0xe0,
-    0xbb, 0x14, 0x04, 0x2e, 0x0e, 0x17, 0x62, 0x7f, 0x6a, 0x17, 0x62, 0x7f, 0xf0, 0x71, 0x40, 0xb0,
-    0x0b, 0xb1, 0x42, 0xf8, 0x58, 0xbb, 0x14, 0x2c, 0x2d, 0xfb, 0x45, 0x02, 0xec, 0x50, 0xce, 0x1e,
-    0x27, 0x0b, 0x16, 0x5d, 0x8a, 0x1a, 0x28, 0x16, 0x21, 0x68, 0xe2, 0x51, 0x76, 0x28, 0x66, 0x42,
-    0xe2, 0x5e, 0x0c, 0x28, 0x16, 0x01, 0x70, 0xfe, 0x25, 0x16, 0xe0, 0xb8, 0x5f, 0x16, 0x8b, 0x86,
-    0x71, 0x40, 0xa0, 0x58, 0x05, 0xd8, 0xa3, 0xdc, 0x42, 0x2e, 0x31, 0x0b, 0x8c, 0xfb, 0x14, 0x60,
-    0x5c, 0x4b, 0xc4, 0x42, 0x81, 0x60, 0x17, 0x1a, 0xf6, 0x29, 0x10, 0x81, 0x71, 0xc0, 0x5c, 0x27,
-    0x8d, 0x04, 0xe2, 0xa1, 0x50, 0xb8, 0xdb, 0x21, 0x71, 0xe8, 0x5c, 0x79, 0xd8, 0xa4, 0x82, 0xe2,
-    0xae, 0x2e, 0x14, 0x0b, 0x00, 0xb8, 0xf7, 0x60, 0xba, 0xfc, 0xf0, 0x5c, 0x6b, 0xc8, 0x21, 0x76,
-    0x29, 0x5f, 0x62, 0x95, 0x8a, 0x05, 0x80, 0x59, 0x85, 0xb8, 0x2e, 0x0a, 0x17, 0x0c, 0x0b, 0x88,
-    0xc2, 0xe2, 0x90, 0xb8, 0xcc, 0x2e, 0x3b, 0x0b, 0x90, 0xc1, 0x72, 0x33, 0xb1, 0x4f, 0xc5, 0xc6,
-    0xa1, 0x76, 0x29, 0xd7, 0x62, 0xa1, 0x0b, 0x8f, 0xc2, 0xe3, 0x5e, 0xc5, 0x40, 0x14, 0x0b, 0x10,
-    0xbb, 0x15, 0x1b, 0x92, 0x62, 0xe4, 0xbe, 0x42, 0x81, 0x72, 0x73, 0x8f, 0xc5, 0xc9, 0xae, 0x26,
-    0x14, 0x0b, 0x00, 0xb6, 0x72, 0x68, 0x5a, 0x02, 0xe4, 0x86, 0xe1, 0x70, 0xd0, 0xa8, 0x5c, 0xa5,
-    0xe5, 0x28, 0xb6, 0x85, 0xd8, 0xa9, 0x9c, 0x9b, 0x17, 0x28, 0xb9, 0x32, 0x28, 0x16, 0x01, 0x72,
-    0xa3, 0x92, 0xa2, 0xeb, 0xf8, 0xc1, 0x72, 0x33, 0x84, 0x8b, 0x94, 0xc1, 0x50, 0xbb, 0x15, 0x6b,
-    0x86, 0x0b, 0x92, 0x61, 0x70, 0xee, 0x59, 0x0b, 0x96, 0x7c, 0xa7, 0x14, 0x0b, 0x00, 0xb8, 0x8b,
-    0x96, 0x62, 0xe5, 0xb0, 0x5c, 0x97, 0xe5, 0xa8, 0xb9, 0x4f, 0xd8, 0xac, 0xc2, 0x81, 0x60, 0x17,
-    0x2d, 0xb9, 0x6c, 0x2e, 0x4f, 0x85, 0xcb, 0x7e, 0x57, 0x0b, 0x94, 0xc1, 0x50, 0xb9, 0x75, 0xcb,
-    0xa1, 0x73, 0x02, 0x17, 0x30, 0x7c, 0xb3, 0x17, 0x2e, 0x39, 0x4e, 0x28, 0x16, 0x01, 0x73, 0x03,
-    0xcc, 0x38, 0xba, 0xff, 0x10, 0x5d, 0x8a, 0xed, 0xc9, 0x21, 0x72, 0xa7, 0x97, 0x42, 0x81, 0x60,
-    0x16, 0x61, 0x6e, 0x0b, 0x82, 0x05, 0xc2, 0xc2, 0xe2, 0x20, 0xb8, 0xa0, 0x2e, 0x2f, 0x0b, 0x8d,
-    0x42, 0xe3, 0xd0, 0xb9, 0x0a, 0x17, 0x5f, 0xf1, 0xe3, 0xe1, 0x75, 0xff, 0x10, 0xba, 0xff, 0x97,
-    0x5f, 0xf2, 0x10, 0x28, 0x15, 0x0b, 0x00, 0xb1, 0x0b, 0x20, 0xb3, 0x0b, 0x40, 0x5a, 0x82, 0xda,
-    0x03, 0xab, 0xcc, 0x17, 0x34, 0xc3, 0xf4, 0x56, 0x1b, 0xa9, 0x9a, 0x49, 0x02, 0x04, 0x08, 0x10,
-    0x5c, 0x85, 0x90, 0x59, 0x05, 0x90, 0x59, 0x05, 0x90, 0x59, 0xe6, 0x20, 0x50, 0x2a, 0x16, 0x01,
-    0x62, 0x16, 0x41, 0x66, 0x16, 0x80, 0xb5, 0x04, 0x0b, 0x5d, 0x14, 0x1f, 0xa1, 0x6c, 0x50, 0x2e,
-    0x18, 0x37, 0x53, 0xa6, 0x61, 0xba, 0x25, 0x90, 0x70, 0xa8, 0xdd, 0x4f, 0xa9, 0x86, 0xe8, 0x90,
-    0x3e, 0x42, 0x83, 0x75, 0x42, 0xc6, 0x1b, 0xa2, 0x24, 0xfa, 0xc5, 0x06, 0xea, 0x8d, 0x0c, 0x37,
-    0x44, 0x11, 0xf8, 0x00, 0xa0, 0xdd, 0x67, 0xd8, 0xe3, 0xc1, 0x3a, 0x0d, 0x00, 0xe8, 0x33, 0x0e,
-    0x00, 0x66, 0x3f, 0x43, 0x88, 0xa0, 0x5b, 0x40, 0x62, 0x0e, 0x02, 0x3f, 0x43, 0xb0, 0xa0, 0x5c,
-    0x1c, 0x06, 0x20, 0xe1, 0x63, 0xf4, 0x3d, 0x8a, 0x05, 0xc4, 0x20, 0x31, 0x07, 0x12, 0x8f, 0xd1,
-    0x00, 0x28, 0x17, 0x16, 0x00, 0xc4, 0x1c, 0x60, 0x17, 0x23, 0x82, 0xeb, 0x37, 0x9d, 0x67, 0x1c,
-    0x0e, 0xbc, 0x90, 0x2e, 0xb1, 0x76, 0x3f, 0x5e, 0x32, 0x17, 0x5d, 0xb2, 0xeb, 0x53, 0x60, 0x75,
-    0xe2, 0xc1, 0x50, 0x3a, 0xf2, 0x77, 0x5e, 0x31, 0x04, 0x0b, 0xae, 0xdb, 0x80, 0xeb, 0xca, 0x20,
-    0xeb, 0xb7, 0x41, 0x75, 0xfe, 0x5e, 0xc3, 0x5b, 0x04, 0x03, 0xad, 0x20, 0x85, 0xd6, 0x87, 0x47,
-    0xea, 0xae, 0xcc, 0x38, 0xe3, 0x8e, 0x38, 0xe3, 0x8e, 0x38, 0x4e, 0xc3, 0x6a, 0x17, 0x61, 0xbd,
-    0xf6, 0x1c, 0x00, 0x9d, 0x87, 0x00, 0x2e, 0xc3, 0x80, 0x0b, 0xb0, 0xe0, 0x02, 0xec, 0x37, 0xee,
-    0xc3, 0x7f, 0x17, 0x61, 0xc2, 0xf6, 0x1c, 0x00, 0xa0, 0x5d, 0x77, 0x30, 0x2e, 0xc3, 0x85, 0x09,
-    0xd8, 0x6e, 0x60, 0xec, 0x01, 0x9e, 0xc2, 0x0a, 0x04, 0x0b, 0xb0, 0xd0, 0x00, 0x76, 0x00, 0xe0,
-    0xb5, 0x80, 0xec, 0x01, 0xe1, 0x75, 0xdc, 0x50, 0x3b, 0x13, 0x93, 0xb1, 0x37, 0x81, 0x02, 0x76,
-    0x1b, 0xf1, 0xfb, 0x0e, 0x55, 0x24, 0x08, 0x10, 0x5c, 0x04, 0x0b, 0x00, 0xb0, 0x0a, 0xd1, 0x02,
-    0xa1, 0x48, 0x28, 0x13, 0xb0, 0xd4, 0xc0, 0xeb, 0x9c, 0x82, 0x76, 0x03, 0xd1, 0xf7, 0xa4, 0x81,
-    0x02, 0x0b, 0x80, 0x81, 0x60, 0x16, 0x01, 0x5a, 0x20, 0x54, 0x29, 0x05, 0x02, 0x76, 0x1a, 0x98,
-    0x1d, 0x73, 0xd4, 0x4e, 0xc0, 0xf4, 0x3e, 0xf4, 0x90, 0x20, 0x41, 0x70, 0x10, 0x2c, 0x02, 0xc0,
-    0x2b, 0x44, 0x0a, 0x85, 0x20, 0xa0, 0x4e, 0xc3, 0x58, 0x03, 0xae, 0x83, 0x09, 0xd8, 0x23, 0xc7,
-    0xde, 0x92, 0x04, 0x08, 0x2e, 0x02, 0x05, 0x80, 0x58, 0x05, 0x68, 0x81, 0x50, 0xa4, 0x14, 0x09,
-    0xd8, 0x6b, 0x60, 0x75, 0xd1, 0x71, 0x3b, 0x05, 0x24, 0xfb, 0xd2, 0x40, 0x81, 0x05, 0xc0, 0x40,
-    0xb0, 0x0b, 0x00, 0xad, 0x10, 0x2a, 0x14, 0x82, 0x81, 0x3b, 0x0d, 0x64, 0x0e, 0xba, 0x50, 0x27,
-    0x60, 0xbd, 0x9f, 0x78, 0xbb, 0x09, 0x2b, 0xb0, 0x91, 0x45, 0xd8, 0x48, 0xfd, 0x84, 0x8e, 0x20,
-    0x54, 0x29, 0x05, 0x02, 0x76, 0x1a, 0x78, 0x1d, 0x74, 0xc8, 0x4e, 0xc1, 0xa7, 0x3f, 0x61, 0x2a,
-    0x8b, 0xb1, 0x93, 0x7b, 0x18, 0x74, 0x5d, 0x8c, 0x98, 0x17, 0x63, 0x0f, 0xf6, 0x30, 0xf8, 0x81,
-    0x50, 0xa4, 0x14, 0x09, 0xd8, 0xaa, 0xc0, 0x75, 0xd3, 0xd1, 0x3b, 0x18, 0x20, 0x5a, 0xb4, 0x0b,
-    0xb1, 0x95, 0x02, 0x66, 0x27, 0x62, 0xac, 0x01, 0xd7, 0x50, 0xce, 0x0f, 0x5c, 0xee, 0x03, 0x40,
-    0xe3, 0x83, 0xc1, 0x40, 0xe0, 0xe3, 0x8e, 0x0f, 0x11, 0x81, 0xc4, 0xa3, 0x8e, 0x0f, 0x1c, 0x01,
-    0xc7, 0x43, 0x8e, 0x0f, 0x23, 0x40, 0xe4, 0x78, 0xe3, 0x83, 0xca, 0x20, 0x39, 0x48, 0x38, 0xe0,
-    0xf2, 0xdc, 0x0e, 0x5c, 0x8e, 0x38, 0xe0, 0xf3, 0x1a, 0x07, 0x31, 0xe3, 0x82, 0xeb, 0x9d, 0xa0,
-    0xe6, 0x98, 0x27, 0x62, 0x08, 0x9f, 0xb1, 0x04, 0x85, 0xd8, 0x84, 0xfc, 0xd1, 0x8b, 0xb1, 0x09,
-    0xc2, 0xec, 0x42, 0x70, 0x9d, 0x88, 0x2c, 0x2e, 0xc5, 0x87, 0xec, 0x10, 0x63, 0x85, 0xd8, 0x71,
-    0xa3, 0x85, 0xd8, 0x74, 0x63, 0x85, 0xd8, 0x77, 0x03, 0x85, 0xd8, 0x79, 0x03, 0x85, 0xd8, 0x7a,
-    0xc3, 0x85, 0xd8, 0x7d, 0x23, 0x85, 0xd8, 0x7d, 0xe3, 0x85, 0xd8, 0x84, 0xc0, 0x75, 0xdb, 0x70,
-    0x38, 0x18, 0xe0, 0xf0, 0x90, 0x38, 0x58, 0x1d, 0x76, 0xec, 0x0e, 0xbe, 0x4c, 0x07, 0x5a, 0xe5,
-    0x0b, 0xad, 0x91, 0x75, 0xae, 0x30, 0x3a, 0xf9, 0x48, 0xba, 0xd7, 0x30, 0x0c, 0x45, 0x88, 0x1c,
-    0xeb, 0x81, 0xcf, 0x28, 0x1c, 0xf4, 0x81, 0xd0, 0x72, 0x38, 0x5d, 0x42, 0x38, 0xfd, 0x6b, 0xec,
-    0x5d, 0x7c, 0x73, 0xad, 0x65, 0x1c, 0x1a, 0x06, 0x20, 0x74, 0x7d, 0x0c, 0x3f, 0x62, 0x21, 0x1c,
-    0x2a, 0x17, 0x48, 0xdc, 0x6e, 0xb7, 0xb6, 0x81, 0x41, 0xba, 0xd7, 0x48, 0xba, 0xd8, 0x5f, 0x5a,
-    0xf7, 0x07, 0x5f, 0x43, 0xec, 0x0d, 0x40, 0x40, 0xba, 0xd7, 0xc0, 0x58, 0x8d, 0xd6, 0xba, 0x01,
-    0xd6, 0x9f, 0xba, 0xfa, 0x41, 0xc2, 0xa0, 0x3a, 0xf9, 0xb1, 0xba, 0xd7, 0x28, 0xa0, 0xdd, 0x6b,
-    0x8c, 0x5d, 0x6d, 0x27, 0xad, 0x84, 0x01, 0xd7, 0xd4, 0x0e, 0x16, 0x00, 0x3a, 0xf9, 0xf1, 0xba,
-    0xd6, 0xe8, 0xa0, 0x5a, 0x42, 0xeb, 0x4b, 0x1d, 0x6a, 0xf0, 0xdd, 0x88, 0xc0, 0x7e, 0xb5, 0x16,
-    0x28, 0x3f, 0x5a, 0x5e, 0x13, 0x00, 0x50, 0x33, 0x03, 0xad, 0x67, 0x8e, 0x3f, 0x5a, 0x3f, 0x3f,
-    0x5a, 0x3c, 0x0c, 0x03, 0xad, 0x2d, 0x8e, 0x38, 0x39, 0x81, 0xd6, 0xba, 0x80, 0xe0, 0x60, 0x71,
-    0x20, 0xe1, 0x75, 0xc3, 0xee, 0xb6, 0x22, 0x6e, 0xb6, 0x18, 0x27, 0x1a, 0x01, 0xd7, 0xdb, 0xcd,
-    0xd6, 0xd7, 0x4d, 0xd6, 0xd7, 0x44, 0xe3, 0x80, 0x3a, 0xfb, 0x98, 0xb0, 0x01, 0xd7, 0xdd, 0x45,
-    0x98, 0x1a, 0xc2, 0x75, 0xb4, 0x93, 0x8e, 0x0f, 0x00, 0x03, 0x8f, 0x00, 0xe4, 0x00, 0x3d, 0x27,
-    0x90, 0x3a, 0x4f, 0x80, 0x74, 0xb4, 0x46, 0xe8, 0x5b, 0x38, 0xdd, 0x40, 0xc9, 0xc7, 0x0b, 0x00,
-    0xba, 0x5c, 0x43, 0x85, 0xa4, 0x06, 0xb0, 0x74, 0xba, 0x06, 0xe8, 0x6b, 0x38, 0xdd, 0x40, 0x41,
-    0xc7, 0x0b, 0x00, 0xba, 0x5f, 0x23, 0x85, 0xa4, 0x06, 0xb0, 0x74, 0xbf, 0xc6, 0xe8, 0x7b, 0x38,
-    0xdd, 0x3f, 0x73, 0x8e, 0x16, 0x01, 0x74, 0xc4, 0x07, 0x0b, 0x48, 0x0d, 0x60, 0xe9, 0x8b, 0x0d,
-    0xd1, 0x18, 0x71, 0xba, 0x7c, 0xc7, 0x1c, 0x2c, 0x02, 0xe9, 0x93, 0x8e, 0x16, 0x90, 0x1a, 0xc1,
-    0xd3, 0x2d, 0x1b, 0xab, 0x4e, 0x30, 0xe3, 0x74, 0xf5, 0x8e, 0x38, 0x58, 0x05, 0xd3, 0x3f, 0x1c,
-    0x2d, 0x40, 0x36, 0x03, 0xa6, 0x8a, 0x37, 0x56, 0x78, 0x61, 0xc6, 0xe9, 0xe3, 0x1c, 0x70, 0xb0,
-    0x0b, 0xa6, 0xae, 0x38, 0x5a, 0x80, 0x6c, 0x07, 0x4d, 0x74, 0x6e, 0xac, 0xa8, 0xc3, 0x8d, 0xd3,
-    0xb6, 0x38, 0xe1, 0x60, 0x17, 0x4d, 0xbc, 0x70, 0xb5, 0x00, 0xd8, 0x0e, 0x9b, 0xa8, 0xdd, 0x58,
-    0xc1, 0x87, 0x1b, 0xa7, 0x4c, 0x71, 0xc2, 0xc0, 0x2e, 0x9c, 0x38, 0xe1, 0x6a, 0x01, 0xb0, 0x5d,
-    0x38, 0xe0, 0x3a, 0x72, 0x43, 0x85, 0xd8, 0xfa, 0xfd, 0x8f, 0xae, 0x20, 0x5d, 0x8f, 0xb3, 0xd7,
-    0x37, 0x45, 0x42, 0xa1, 0x61, 0x80, 0x81, 0x40, 0xa8, 0x58, 0x05, 0x88, 0x59, 0x05, 0x98, 0x5a,
-    0x02, 0xd4, 0x10, 0x2d, 0x74, 0x50, 0x2e, 0x9d, 0x50, 0xdc, 0x38, 0xdd, 0x39, 0xe3, 0xf5, 0xcc,
-    0x61, 0x41, 0xb8, 0xb8, 0xdd, 0x39, 0x63, 0xf5, 0xcc, 0xa1, 0x41, 0xb8, 0xf8, 0xdd, 0x38, 0xe3,
-    0xf5, 0xcc, 0xe1, 0x41, 0xb9, 0x1c, 0x6e, 0x9c, 0x31, 0xfa, 0xe6, 0x90, 0xa0, 0xdc, 0x9e, 0x37,
-    0x4d, 0xf8, 0xfd, 0x73, 0x58, 0x50, 0x6e, 0x56, 0x9b, 0xa6, 0xec, 0x7e, 0xb9, 0xb4, 0x28, 0x37,
-    0x2f, 0x0d, 0xd3, 0x6e, 0x3f, 0x5c, 0xde, 0x14, 0x1b, 0x98, 0xb3, 0x74, 0xd9, 0x8f, 0xd7, 0x38,
-    0x85, 0x07, 0xeb, 0x7b, 0xa7, 0xeb, 0x7b, 0xc6, 0xe7, 0x71, 0x24, 0x08, 0x10, 0x20, 0x41, 0x72,
-    0x16, 0x41, 0x64, 0x16, 0x41, 0x64, 0x16, 0x41, 0x67, 0x98, 0x81, 0x40, 0xa8, 0x58, 0x05, 0x88,
-    0x59, 0x05, 0x98, 0x5a, 0x02, 0xd4, 0x17, 0x07, 0x0b, 0x84, 0x80, 0xe6, 0xc4, 0x3c, 0x30, 0x0e,
-    0x1c, 0x17, 0x11, 0x00, 0xa0, 0xe2, 0x50, 0xb8, 0xa4, 0x05, 0x07, 0x16, 0x05, 0xc6, 0x00, 0x28,
-    0x38, 0xcc, 0x2e, 0x37, 0x01, 0x41, 0xc7, 0x41, 0x71, 0xf0, 0x0a, 0x0e, 0x40, 0x85, 0xc8, 0x50,
-    0x14, 0x1c, 0x88, 0x0b, 0x91, 0x80, 0x28, 0x39, 0x1e, 0x17, 0x24, 0xc0, 0x50, 0x72, 0x58, 0x2e,
-    0x4d, 0x8e, 0x38, 0x3c, 0xad, 0x01, 0xd2, 0x03, 0x07, 0x5c, 0xfe, 0x03, 0xae, 0x8c, 0x05, 0xd7,
-    0x48, 0x86, 0xeb, 0x7f, 0x87, 0x1f, 0xad, 0xfb, 0x89, 0xd6, 0xf2, 0x00, 0xeb, 0x71, 0x22, 0xc0,
-    0x07, 0x5e, 0x38, 0x13, 0xad, 0xa8, 0x1f, 0xad, 0xa6, 0x8b, 0xae, 0x7b, 0x75, 0xc3, 0xf0, 0x3a,
-    0xc9, 0xc8, 0x9d, 0x74, 0xb4, 0xfd, 0x71, 0x04, 0x5d, 0x78, 0xd7, 0xb1, 0x5f, 0x92, 0x50, 0x7a,
-    0xf7, 0x40, 0x18, 0x01, 0xd8, 0x0c, 0x40, 0x75, 0xc4, 0xe1, 0xc1, 0xd2, 0x06, 0xb1, 0xc7, 0x07,
-    0x80, 0x81, 0xc0, 0xc0, 0xe1, 0x80, 0xf5, 0xd4, 0x20, 0x3a, 0xea, 0x28, 0x3d, 0x75, 0x38, 0x0e,
-    0xba, 0xa2, 0x13, 0xaf, 0x11, 0x31, 0xbb, 0x2e, 0x9c, 0xf3, 0xc2, 0xcf, 0xcd, 0x6e, 0x05, 0xc6,
-    0x95, 0x15, 0x61, 0x08, 0x15, 0xfb, 0xc5, 0x10, 0x42, 0x0c, 0xb9, 0xdf, 0xfd, 0xf9, 0x39, 0xa8,
-    0xc7, 0x03, 0x37, 0x9b, 0x1c, 0x00, 0x5d, 0x7b, 0xf0, 0x14, 0x0e, 0xc7, 0x18, 0x7a, 0x40, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07, 0x6f, 0x19, 0xca, 0xc7, 0xf1,
-    0x34, 0x1d, 0xb2, 0x82, 0x9e, 0xb5, 0x45, 0x15, 0xa1, 0xb2, 0xda, 0xcf, 0x2b, 0xf3, 0x8c, 0x65,
-    0x46, 0xb1, 0xb0, 0x86, 0x5c, 0x6d, 0xdf, 0x73, 0x9c, 0x69, 0xea, 0xc0, 0xe0, 0xab, 0xa0, 0x96,
-    0x63, 0x70, 0xc0, 0x8c, 0xf0, 0x5e, 0x35, 0x60, 0xfc, 0xc8, 0x8a, 0x43, 0x1a, 0x08, 0xef, 0x80,
-];
-
-#[cfg(feature = "elements")]
-const SIGHASH_ALL_CMR: [u8; 32] = [
-    0xe6, 0xce, 0x80, 0x7b, 0x06, 0x79, 0x4d, 0x83, 0x77, 0x2d, 0x75, 0x68, 0xdc, 0x1d, 0xbc, 0xb5,
-    0x6e, 0x07, 0x03, 0x70, 0x21, 0x39, 0x39, 0x6a, 0x14, 0xa1, 0x60, 0xb2, 0x78, 0x5b, 0x10, 0x8d,
-];
+include!("test_programs/sighash_all.rs");
 
 // Some Fibonnacci program that uses Disconnect
 const FIB_DISCONNECT: [u8; 1105] = [
@@ -2725,7 +1945,7 @@ fn main() {
         use std::fs;
 
         let bytes =
-            fs::read(&format!("fuzz/simplicityC_test_inputs/test_{}", i)).expect("opening file");
+            fs::read(format!("fuzz/simplicityC_test_inputs/test_{}", i)).expect("opening file");
         let mut bits: simplicity::bititer::BitIter<_> = bytes.into_iter().into();
         match simplicity::program::Program::<simplicity::extension::dummy::DummyNode>::decode(
             &mut bits,
@@ -2775,16 +1995,13 @@ fn main() {
     println!("frame count: {}", exec_node.frame_count_bound);
 
     let mut mac = simplicity::exec::BitMachine::for_program(&program);
-    mac.input(&Value::prod(
-        Value::u2(3),
-        Value::prod(Value::u16(1), Value::u16(0)),
-    ));
-    println!(
-        " input: {}",
-        Value::prod(Value::prod(Value::u2(3), Value::u16(1)), Value::u16(0))
-    );
+    let input = Value::prod(Value::u2(3), Value::prod(Value::u16(1), Value::u16(0)));
+    let input = simplicity::PackedValue::from_value(&input, &exec_node.source_ty)
+        .expect("input has the program's source type");
+    mac.input(&input);
+    println!(" input: {}", input.pretty());
     let output = mac.exec(&program, &simplicity::extension::dummy::TxEnv);
-    println!(" output: {}", output);
+    println!(" output: {}", output.pretty());
     println!();
     println!();
     println!();
@@ -31,6 +31,7 @@ use miniscript::MiniscriptKey;
 
 use crate::extension::bitcoin::BtcNode;
 
+use crate::core::builder::DagBuilder;
 use crate::core::term::UnTypedProg;
 use crate::Error;
 use crate::PubkeyKey32;
@@ -65,8 +66,8 @@ pub enum Policy<Pk: MiniscriptKey> {
 impl<Pk: MiniscriptKey + PubkeyKey32> Policy<Pk> {
     /// Compile a policy into a simplicity frgament
     pub fn compile(&self) -> Result<UnTypedProg<(), BtcNode>, Error> {
-        let dag = compiler::compile(&self)?;
-        Ok(dag.into_untyped_prog())
+        let dag = compiler::compile(&mut DagBuilder::new(), self)?;
+        Ok(dag.to_untyped_prog())
     }
 }
 
@@ -361,14 +362,31 @@ mod tests {
 
         let prog =
             Program::from_untyped_nodes(prog, &mut BitIter::from(witness.into_iter())).unwrap();
-        // prog.graph_print();
+        // prog.write_graph(&mut std::io::stdout(), &Default::default()).unwrap();
 
         let txenv = TxEnv::default();
 
         let mut mac = exec::BitMachine::for_program(&prog);
         let output = mac.exec(&prog, &txenv);
 
-        assert!(output == Value::Unit);
+        assert_eq!(output.to_value(), Value::Unit);
+    }
+
+    #[test]
+    fn cmr_without_witnesses() {
+        for pol in &["pk()", "and(pk(),after(10))", "or(pk(),older(3))"] {
+            let pol = Policy::<DummyKey>::from_str(pol).unwrap();
+            let dag = compiler::compile(&mut DagBuilder::new(), &pol).unwrap();
+            let untyped = pol.compile().unwrap();
+            assert_eq!(untyped.cmrs().last(), Some(&dag.cmr()));
+
+            let mut witness = vec![0x00];
+            witness.extend(vec![0x34; 128]);
+            let prog =
+                Program::from_untyped_nodes(untyped, &mut BitIter::from(witness.into_iter()))
+                    .unwrap();
+            assert_eq!(prog.root_node().cmr, dag.cmr());
+        }
     }
 
     #[test]
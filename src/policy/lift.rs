@@ -16,11 +16,10 @@
 //! Currently the policy compilation is one to one mapping
 //! between policy fragment and a simplicity program.
 
-use crate::core::bitvec_to_bytevec;
 use crate::core::term::DagTerm;
 use crate::extension::bitcoin::BtcNode;
 use crate::PubkeyKey32;
-use crate::Value;
+use crate::{Error, Value};
 use bitcoin_hashes::{sha256, Hash};
 use miniscript::policy::Liftable;
 use miniscript::policy::Semantic;
@@ -28,22 +27,69 @@ use miniscript::{DummyKey, MiniscriptKey};
 
 use crate::extension::jets::JetsNode::{EqV256, LessThanV32, SchnorrAssert, Sha256};
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 /// Functional opposite of scribe. Read the scribed value
 /// by interpretting that as constant function and return
 /// a value corresponding to it.
-pub fn read_scribed_value<Witness, Ext>(dag: Rc<DagTerm<Witness, Ext>>) -> Value {
-    match dag.as_ref() {
-        DagTerm::Unit => Value::Unit,
-        DagTerm::InjL(l) => Value::sum_l(read_scribed_value(Rc::clone(l))),
-        DagTerm::InjR(r) => Value::sum_r(read_scribed_value(Rc::clone(r))),
-        DagTerm::Pair(l, r) => Value::prod(
-            read_scribed_value(Rc::clone(l)),
-            read_scribed_value(Rc::clone(r)),
-        ),
-        // Fixme: Change to errors
-        _ => unreachable!(),
+/// Returns `None` if the program is not a scribe.
+pub fn read_scribed_value<Witness, Ext>(dag: Arc<DagTerm<Witness, Ext>>) -> Option<Value> {
+    dag.post_order_fold(|node, children: Vec<Option<Value>>| {
+        let mut children = children.into_iter();
+        let mut child = || children.next().unwrap();
+        match *node {
+            DagTerm::Unit => Some(Value::Unit),
+            DagTerm::InjL(..) => Some(Value::sum_l(child()?)),
+            DagTerm::InjR(..) => Some(Value::sum_r(child()?)),
+            DagTerm::Pair(..) => Some(Value::prod(child()?, child()?)),
+            _ => None,
+        }
+    })
+}
+
+/// Lift a simplicity program into a semantic policy, returning an error
+/// if it is not of a shape produced by the policy compiler, or if it
+/// scribes a value of the wrong shape
+pub fn try_lift<Witness>(dag: &DagTerm<Witness, BtcNode>) -> Result<Semantic<DummyKey>, Error> {
+    let scribed = |dag: &Arc<DagTerm<Witness, BtcNode>>| {
+        read_scribed_value(Arc::clone(dag)).ok_or(Error::Unliftable("expected a scribe"))
+    };
+
+    match dag {
+        DagTerm::Unit => Ok(Semantic::Trivial),
+        DagTerm::Comp(l, r) => {
+            // check for Key
+            match (&**l, &**r) {
+                (DagTerm::Pair(key, w), DagTerm::Jet(SchnorrAssert)) => {
+                    let key_bytes = scribed(key)?.to_u256()?;
+                    let k = DummyKey::from_32_byte_pubkey(&key_bytes);
+                    match &**w {
+                        DagTerm::Witness(..) => Ok(Semantic::KeyHash(k.to_pubkeyhash())),
+                        _ => Err(Error::Unliftable("expected a signature witness")),
+                    }
+                }
+                (DagTerm::Pair(scribed_hash, computed_hash), DagTerm::Jet(EqV256)) => {
+                    let h = sha256::Hash::from_inner(scribed(scribed_hash)?.to_u256()?);
+                    match &**computed_hash {
+                        DagTerm::Comp(w, sha_jet) => match (&**w, &**sha_jet) {
+                            (DagTerm::Witness(..), DagTerm::Jet(Sha256)) => Ok(Semantic::Sha256(h)),
+                            _ => Err(Error::Unliftable("expected the hash of a witness")),
+                        },
+                        _ => Err(Error::Unliftable("expected the hash of a witness")),
+                    }
+                }
+                (DagTerm::Pair(scibe_t, computed_t), DagTerm::Jet(LessThanV32)) => {
+                    let t = scribed(scibe_t)?.to_u32()?;
+                    match &**computed_t {
+                        DagTerm::Ext(BtcNode::LockTime) => Ok(Semantic::After(t)),
+                        DagTerm::Ext(BtcNode::CurrentSequence) => Ok(Semantic::After(t)),
+                        _ => Err(Error::Unliftable("expected a timelock")),
+                    }
+                }
+                _ => Err(Error::Unliftable("unknown fragment")),
+            }
+        }
+        _ => Err(Error::Unliftable("unknown fragment")),
     }
 }
 
@@ -53,59 +99,80 @@ impl<Witness> Liftable<DummyKey> for DagTerm<Witness, BtcNode>
 where
     Witness: Eq,
 {
-    // Lift a simplicity program into a semantic policy
+    // Lift a simplicity program into a semantic policy, panicking if it
+    // cannot be lifted. Use `try_lift` to handle such programs.
     fn lift(&self) -> Semantic<DummyKey> {
-        match self {
-            DagTerm::Unit => Semantic::Trivial,
-            DagTerm::Comp(l, r) => {
-                // check for Key
-                match (&**l, &**r) {
-                    (DagTerm::Pair(key, w), DagTerm::Jet(SchnorrAssert)) => {
-                        let key_value = read_scribed_value(Rc::clone(&Rc::clone(&key)));
-                        let key_bytes = bitvec_to_bytevec(key_value.into_bits());
-                        let k = DummyKey::from_32_byte_pubkey(&key_bytes);
-                        match &**w {
-                            DagTerm::Witness(..) => Semantic::KeyHash(k.to_pubkeyhash()),
-                            _ => unimplemented!(),
-                        }
-                    }
-                    (DagTerm::Pair(scribed_hash, computed_hash), DagTerm::Jet(EqV256)) => {
-                        let hash_value = read_scribed_value(Rc::clone(&Rc::clone(&scribed_hash)));
-                        let hash_bytes = bitvec_to_bytevec(hash_value.into_bits());
-                        let h = sha256::Hash::from_slice(&hash_bytes).unwrap();
-                        match &**computed_hash {
-                            DagTerm::Pair(w, sha_jet) => match (&**w, &**sha_jet) {
-                                (DagTerm::Witness(..), DagTerm::Jet(Sha256)) => Semantic::Sha256(h),
-                                _ => unimplemented!(),
-                            },
-                            _ => unimplemented!(),
-                        }
-                    }
-                    (DagTerm::Pair(scibe_t, computed_t), DagTerm::Jet(LessThanV32)) => {
-                        let timelock_value = read_scribed_value(Rc::clone(&Rc::clone(&scibe_t)));
-                        let timelock_bytes = bitvec_to_bytevec(timelock_value.into_bits());
-                        let t = u32_from_be_bytes(&timelock_bytes);
-                        match &**computed_t {
-                            DagTerm::Ext(BtcNode::LockTime) => Semantic::After(t),
-                            DagTerm::Ext(BtcNode::CurrentSequence) => Semantic::After(t),
-                            _ => unimplemented!(),
-                        }
-                    }
-                    _ => unimplemented!(),
-                }
-            }
-            _ => unimplemented!(),
+        match try_lift(self) {
+            Ok(policy) => policy,
+            Err(e) => unimplemented!("{}", e),
         }
     }
 }
 
-// utility function to convert 4 bytes [u8;4] to u32
-// panics if bytes.len() !=4
-fn u32_from_be_bytes(bytes: &[u8]) -> u32 {
-    assert!(bytes.len() == 4);
-    let mut ret: u32 = 0;
-    for (i, byte) in bytes.iter().enumerate() {
-        ret += (*byte as u32) * (1 << (24 - 8 * i));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bititer::BitIter;
+    use crate::policy::ast::Policy;
+    use crate::Program;
+    use std::str::FromStr;
+
+    fn lift_decoded(pol: &str, witness: Vec<u8>) -> Semantic<DummyKey> {
+        let pol = Policy::<DummyKey>::from_str(pol).unwrap();
+        let prog = Program::<BtcNode>::from_untyped_nodes(
+            pol.compile().unwrap(),
+            &mut BitIter::from(witness.into_iter()),
+        )
+        .unwrap();
+
+        let dag = prog.to_dag();
+        assert_eq!(Program::from_typed_dag(&dag).unwrap().nodes, prog.nodes);
+        dag.root().lift()
+    }
+
+    #[test]
+    fn lift_decoded_programs() {
+        assert_eq!(
+            lift_decoded("pk()", vec![0x00; 65]),
+            Semantic::KeyHash(DummyKey.to_pubkeyhash())
+        );
+        let h = sha256::Hash::hash(&[]);
+        assert_eq!(
+            lift_decoded(&format!("sha256({})", h), vec![0x00; 65]),
+            Semantic::Sha256(h)
+        );
+        assert_eq!(lift_decoded("after(100)", vec![0x00]), Semantic::After(100));
+    }
+
+    #[test]
+    fn lift_malformed_programs() {
+        // A 32-bit word is scribed where a public key is expected
+        let key = crate::core::combinators::scribe::<(), BtcNode>(&Value::u32(1));
+        let dag = DagTerm::Comp(
+            Arc::new(DagTerm::Pair(key, Arc::new(DagTerm::Witness(())))),
+            Arc::new(DagTerm::Jet(SchnorrAssert)),
+        );
+        assert!(try_lift(&dag).is_err());
+
+        // A witness is used where a timelock is expected
+        let dag = DagTerm::Comp(
+            Arc::new(DagTerm::Pair(
+                Arc::new(DagTerm::Witness(())),
+                Arc::new(DagTerm::Ext(BtcNode::LockTime)),
+            )),
+            Arc::new(DagTerm::Jet(LessThanV32)),
+        );
+        assert!(try_lift(&dag).is_err());
+        assert!(try_lift(&DagTerm::<(), BtcNode>::Iden).is_err());
+    }
+
+    #[test]
+    fn read_deep_scribe() {
+        let mut dag = Arc::new(DagTerm::<(), BtcNode>::Unit);
+        for _ in 0..100_000 {
+            dag = Arc::new(DagTerm::InjL(dag));
+        }
+        let value = read_scribed_value(dag).unwrap();
+        assert_eq!(value.len(), 100_000);
     }
-    ret
 }
@@ -19,22 +19,21 @@
 //! data.
 //!
 
-use std::{cmp, fmt, sync::Arc};
+use std::{cmp, collections::HashMap, fmt, io, sync::Arc};
 
 use crate::bititer::BitIter;
-use crate::cmr::{self, Cmr};
+use crate::cmr::Cmr;
 use crate::core::types;
-use crate::extension::Jet as ExtNode;
 use crate::{encode, extension};
-use crate::{Error, Term, Value};
+use crate::{Error, PackedValue, Term};
 
-use crate::core::term::UnTypedProg;
+use crate::core::term::{DagTerm, UnTypedProg};
 
 /// A node in a complete program, with associated metadata
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct ProgramNode<Ext> {
     /// The underlying node
-    pub node: Term<Value, Ext>,
+    pub node: Term<PackedValue, Ext>,
     /// Its index within the total program
     pub index: usize,
     /// Its Commitment Merkle Root
@@ -51,6 +50,13 @@ pub struct ProgramNode<Ext> {
     pub frame_count_bound: usize,
 }
 
+impl<Ext> ProgramNode<Ext> {
+    /// The absolute indices of the children of this node, left first
+    pub fn children(&self) -> impl Iterator<Item = usize> + '_ {
+        self.node.children().map(move |i| self.index - i)
+    }
+}
+
 impl<Ext: fmt::Display> fmt::Display for ProgramNode<Ext> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "[{}] ", self.index)?;
@@ -65,7 +71,7 @@ impl<Ext: fmt::Display> fmt::Display for ProgramNode<Ext> {
             Term::Case(i, j) => write!(f, "case({}, {})", i, j)?,
             Term::Pair(i, j) => write!(f, "pair({}, {})", i, j)?,
             Term::Disconnect(i, j) => write!(f, "disconnect({}, {})", i, j)?,
-            Term::Witness(..) => f.write_str("witness")?,
+            Term::Witness(ref w) => write!(f, "witness({})", w.pretty())?,
             Term::Hidden(..) => f.write_str("hidden")?,
             Term::Fail(..) => f.write_str("fail")?,
             Term::Ext(ref b) => write!(f, "[ext]{}", b)?,
@@ -82,6 +88,32 @@ pub struct Program<Ext> {
     pub nodes: Vec<ProgramNode<Ext>>,
 }
 
+/// A program converted into a DAG by `Program::to_dag`, which remembers
+/// the source and target type of every node
+#[derive(Clone, Debug)]
+pub struct TypedDag<Ext> {
+    /// Every node of the program, the root last. Holding the nodes keeps
+    /// the addresses in `types` valid.
+    nodes: Vec<Arc<DagTerm<PackedValue, Ext>>>,
+    /// Source and target types of each node, keyed by address
+    types: HashMap<usize, (Arc<types::FinalType>, Arc<types::FinalType>)>,
+}
+
+impl<Ext> TypedDag<Ext> {
+    /// The root of the DAG
+    pub fn root(&self) -> &Arc<DagTerm<PackedValue, Ext>> {
+        self.nodes.last().expect("programs are nonempty")
+    }
+
+    /// The source and target types of a node of the DAG, if it is one
+    pub fn types(
+        &self,
+        node: &DagTerm<PackedValue, Ext>,
+    ) -> Option<&(Arc<types::FinalType>, Arc<types::FinalType>)> {
+        self.types.get(&(node as *const DagTerm<_, _> as usize))
+    }
+}
+
 impl<Ext: extension::Jet> Program<Ext> {
     /// Obtain the node representing the root of the program DAG
     pub fn root_node(&self) -> &ProgramNode<Ext> {
@@ -96,13 +128,67 @@ impl<Ext: extension::Jet> Program<Ext> {
         Program::<Ext>::from_untyped_nodes(nodes, iter)
     }
 
+    /// Decode a program from a stream of bits, requiring that the given
+    /// nodes have the given types
+    pub fn decode_with_constraints<I: Iterator<Item = u8>>(
+        iter: &mut BitIter<I>,
+        constraints: &[types::TypeConstraint],
+    ) -> Result<Program<Ext>, Error> {
+        let nodes = encode::decode_program_no_witness(&mut *iter)?;
+
+        Program::<Ext>::from_untyped_nodes_with_constraints(nodes, iter, constraints)
+    }
+
+    /// Decode a program from a stream of bits, as with
+    /// `decode_with_constraints`, but reject the program if the type of
+    /// any witness node was not fully determined by the program and the
+    /// constraints. (Since witness types usually depend on the program's
+    /// input type, this should generally be used with a root constraint.)
+    pub fn decode_strict<I: Iterator<Item = u8>>(
+        iter: &mut BitIter<I>,
+        constraints: &[types::TypeConstraint],
+    ) -> Result<Program<Ext>, Error> {
+        let nodes = encode::decode_program_no_witness(&mut *iter)?;
+
+        Program::<Ext>::from_untyped_nodes_inner(nodes, iter, constraints, true)
+    }
+
     /// Decode a program from a stream of bits
     pub fn from_untyped_nodes<I: Iterator<Item = u8>>(
         nodes: UnTypedProg<(), Ext>,
         iter: &mut BitIter<I>,
+    ) -> Result<Program<Ext>, Error> {
+        Program::<Ext>::from_untyped_nodes_with_constraints(nodes, iter, &[])
+    }
+
+    /// Decode a program from a stream of bits, requiring that the given
+    /// nodes have the given types
+    pub fn from_untyped_nodes_with_constraints<I: Iterator<Item = u8>>(
+        nodes: UnTypedProg<(), Ext>,
+        iter: &mut BitIter<I>,
+        constraints: &[types::TypeConstraint],
+    ) -> Result<Program<Ext>, Error> {
+        Program::<Ext>::from_untyped_nodes_inner(nodes, iter, constraints, false)
+    }
+
+    fn from_untyped_nodes_inner<I: Iterator<Item = u8>>(
+        nodes: UnTypedProg<(), Ext>,
+        iter: &mut BitIter<I>,
+        constraints: &[types::TypeConstraint],
+        strict: bool,
     ) -> Result<Program<Ext>, Error> {
         // Do type-checking
-        let typed_nodes = types::type_check(nodes)?;
+        let typed_nodes = types::type_check_with_constraints(
+            nodes,
+            constraints,
+            &mut types::TypeInterner::new(),
+        )?;
+        if strict {
+            let defaulted = types::defaulted_witnesses(&typed_nodes);
+            if !defaulted.is_empty() {
+                return Err(Error::DefaultedWitness(defaulted));
+            }
+        }
 
         // Parse witnesses, if available
         // FIXME actually only read as much as wit_len
@@ -128,7 +214,7 @@ impl<Ext: extension::Jet> Program<Ext> {
                         Term::Case(i, j) => Term::Case(i, j),
                         Term::Pair(i, j) => Term::Pair(i, j),
                         Term::Disconnect(i, j) => Term::Disconnect(i, j),
-                        Term::Witness(()) => Term::Witness(Value::from_bits_and_type(
+                        Term::Witness(()) => Term::Witness(PackedValue::from_bits_and_type(
                             &mut iter.by_ref(),
                             &node.target_ty,
                         )?),
@@ -139,11 +225,16 @@ impl<Ext: extension::Jet> Program<Ext> {
                     },
                     source_ty: node.source_ty,
                     target_ty: node.target_ty,
+                    target_defaulted: node.target_defaulted,
                 })
             })
             .collect::<Result<Vec<_>, _>>()?;
 
-        // Compute cached data and return
+        Ok(Program::from_typed_nodes(typed_nodes))
+    }
+
+    /// Compute cached data for type-checked nodes with populated witnesses
+    fn from_typed_nodes(typed_nodes: Vec<types::TypedNode<PackedValue, Ext>>) -> Program<Ext> {
         let mut ret = Vec::<ProgramNode<Ext>>::with_capacity(typed_nodes.len());
         for (index, node) in typed_nodes.into_iter().enumerate() {
             let final_node = ProgramNode {
@@ -163,83 +254,259 @@ impl<Ext: extension::Jet> Program<Ext> {
             ret.push(final_node);
         }
 
-        Ok(Program { nodes: ret })
+        Program { nodes: ret }
+    }
+
+    /// Type-check a DAG with populated witnesses. The target type of each
+    /// witness node is constrained to be the type of its value; the types
+    /// of other nodes are inferred, so they may differ from those of a
+    /// program which was decoded with type constraints. Use
+    /// `from_typed_dag` to convert a program to a DAG and back unchanged.
+    pub fn from_dag(dag: &DagTerm<PackedValue, Ext>) -> Result<Program<Ext>, Error>
+    where
+        Ext: Clone,
+    {
+        Program::from_dag_with_types(dag, |_| None)
+    }
+
+    /// Type-check a DAG obtained from `to_dag`, with each node constrained
+    /// to have the type it had in the original program
+    pub fn from_typed_dag(dag: &TypedDag<Ext>) -> Result<Program<Ext>, Error>
+    where
+        Ext: Clone,
+    {
+        Program::from_dag_with_types(dag.root(), |node| dag.types(node))
+    }
+
+    fn from_dag_with_types<'a, F>(
+        dag: &DagTerm<PackedValue, Ext>,
+        node_types: F,
+    ) -> Result<Program<Ext>, Error>
+    where
+        Ext: Clone + 'a,
+        F: Fn(
+            &DagTerm<PackedValue, Ext>,
+        ) -> Option<&'a (Arc<types::FinalType>, Arc<types::FinalType>)>,
+    {
+        let final_pattern = |ty: &Arc<types::FinalType>| types::TypePattern::Final(Arc::clone(ty));
+        // `to_untyped_prog` numbers the nodes in the order of `post_order_iter`
+        let mut constraints = vec![];
+        for (index, node) in dag.post_order_iter().enumerate() {
+            if let Some((source, target)) = node_types(node) {
+                constraints.push(types::TypeConstraint::node(
+                    index,
+                    final_pattern(source),
+                    final_pattern(target),
+                ));
+            } else if let DagTerm::Witness(ref value) = *node {
+                constraints.push(types::TypeConstraint::node(
+                    index,
+                    types::TypePattern::Var(0),
+                    final_pattern(value.ty()),
+                ));
+            }
+        }
+        let typed_nodes = types::type_check_with_constraints(
+            dag.to_untyped_prog(),
+            &constraints,
+            &mut types::TypeInterner::new(),
+        )?;
+        Ok(Program::from_typed_nodes(typed_nodes))
     }
 
-    /// Print out the program in a graphviz-parseable format
-    pub fn graph_print(&self) {
+    /// Convert the program into a DAG, so that it can be analysed and
+    /// rewritten with the same tools as programs built from `DagTerm`s.
+    /// Witness nodes keep their values, and the types of all nodes are
+    /// kept alongside the DAG.
+    pub fn to_dag(&self) -> TypedDag<Ext>
+    where
+        Ext: Clone,
+    {
+        let mut nodes = Vec::<Arc<DagTerm<_, _>>>::with_capacity(self.nodes.len());
+        let mut types = HashMap::with_capacity(self.nodes.len());
         for node in &self.nodes {
-            println!(
-                "{} [label=\"{}\\n{}\\n{} → {}\"];",
-                node.index,
-                match node.node {
-                    Term::Iden => "iden",
-                    Term::Unit => "unit",
-                    Term::InjL(..) => "injl",
-                    Term::InjR(..) => "injr",
-                    Term::Take(..) => "take",
-                    Term::Drop(..) => "drop",
-                    Term::Comp(..) => "comp",
-                    Term::Case(..) => "case",
-                    Term::Pair(..) => "pair",
-                    Term::Disconnect(..) => "disconnect",
-                    Term::Witness(..) => "witness",
-                    Term::Hidden(..) => "hidden",
-                    Term::Fail(..) => "fail",
-                    Term::Ext(..) => "[ext]", // FIXME `ext` and `jet` should passthrough
-                    Term::Jet(..) => "[jet]",
-                },
-                node.index,
-                node.source_ty,
-                node.target_ty,
+            let child = |i: usize| Arc::clone(&nodes[node.index - i]);
+            let dag = Arc::new(match node.node {
+                Term::Iden => DagTerm::Iden,
+                Term::Unit => DagTerm::Unit,
+                Term::InjL(i) => DagTerm::InjL(child(i)),
+                Term::InjR(i) => DagTerm::InjR(child(i)),
+                Term::Take(i) => DagTerm::Take(child(i)),
+                Term::Drop(i) => DagTerm::Drop(child(i)),
+                Term::Comp(i, j) => DagTerm::Comp(child(i), child(j)),
+                Term::Case(i, j) => DagTerm::Case(child(i), child(j)),
+                Term::Pair(i, j) => DagTerm::Pair(child(i), child(j)),
+                Term::Disconnect(i, j) => DagTerm::Disconnect(child(i), child(j)),
+                Term::Witness(ref w) => DagTerm::Witness(w.clone()),
+                Term::Fail(a, b) => DagTerm::Fail(a, b),
+                Term::Hidden(cmr) => DagTerm::Hidden(cmr),
+                Term::Ext(ref e) => DagTerm::Ext(e.clone()),
+                Term::Jet(j) => DagTerm::Jet(j),
+            });
+            types.insert(
+                &*dag as *const DagTerm<_, _> as usize,
+                (Arc::clone(&node.source_ty), Arc::clone(&node.target_ty)),
             );
-            match node.node {
-                Term::Iden
-                | Term::Unit
-                | Term::Witness(..)
-                | Term::Hidden(..)
-                | Term::Fail(..)
-                | Term::Ext(..)
-                | Term::Jet(..) => {}
-                Term::InjL(i) | Term::InjR(i) | Term::Take(i) | Term::Drop(i) => {
-                    println!("  {} -> {};", node.index, node.index - i);
+            nodes.push(dag);
+        }
+        TypedDag {
+            nodes: nodes,
+            types: types,
+        }
+    }
+
+    /// Write the program as a graph, in the format and with the labels
+    /// given by `options`. Each node appears once, however many parents
+    /// it has; left children are joined by red edges and right children
+    /// by blue ones.
+    pub fn write_graph<W: io::Write>(&self, w: &mut W, options: &GraphOptions) -> io::Result<()> {
+        let mut parents = vec![0usize; self.nodes.len()];
+        for node in &self.nodes {
+            for child in node.children() {
+                parents[child] += 1;
+            }
+        }
+        let shared = |node: &ProgramNode<Ext>| options.highlight_shared && parents[node.index] > 1;
+
+        let newline = match options.format {
+            GraphFormat::Dot => {
+                writeln!(w, "digraph {{")?;
+                "\\n"
+            }
+            GraphFormat::Mermaid => {
+                writeln!(w, "graph TD")?;
+                if options.highlight_shared {
+                    writeln!(w, "  classDef shared fill:lightgrey")?;
                 }
-                Term::Comp(i, j) | Term::Case(i, j) | Term::Pair(i, j) | Term::Disconnect(i, j) => {
-                    println!("  {} -> {} [color=red];", node.index, node.index - i);
-                    println!("  {} -> {} [color=blue];", node.index, node.index - j);
+                "<br/>"
+            }
+        };
+        let mut edges = 0;
+        for node in &self.nodes {
+            let mut label = node.node.label();
+            if let Term::Witness(ref value) = node.node {
+                if options.witness_values {
+                    label = format!("{}{}{}", label, newline, value.pretty());
                 }
             }
+            label = format!("{}{}{}", label, newline, node.index);
+            if options.cmr_digits > 0 {
+                let cmr = node.cmr.to_string();
+                label = format!(
+                    "{}{}{}",
+                    label,
+                    newline,
+                    &cmr[..cmp::min(options.cmr_digits, cmr.len())],
+                );
+            }
+            label = if options.type_widths {
+                format!(
+                    "{}{}{} → {} bits",
+                    label,
+                    newline,
+                    node.source_ty.bit_width(),
+                    node.target_ty.bit_width(),
+                )
+            } else {
+                format!(
+                    "{}{}{} → {}",
+                    label, newline, node.source_ty, node.target_ty
+                )
+            };
+
+            let children = node.children().collect::<Vec<_>>();
+            match options.format {
+                GraphFormat::Dot => {
+                    let style = if shared(node) {
+                        ", style=filled, fillcolor=lightgrey"
+                    } else {
+                        ""
+                    };
+                    writeln!(w, "  {} [label=\"{}\"{}];", node.index, label, style)?;
+                    match children[..] {
+                        [i] => writeln!(w, "  {} -> {};", node.index, i)?,
+                        [i, j] => {
+                            writeln!(w, "  {} -> {} [color=red];", node.index, i)?;
+                            writeln!(w, "  {} -> {} [color=blue];", node.index, j)?;
+                        }
+                        _ => {}
+                    }
+                }
+                GraphFormat::Mermaid => {
+                    writeln!(w, "  n{}[\"{}\"]", node.index, label)?;
+                    if shared(node) {
+                        writeln!(w, "  class n{} shared", node.index)?;
+                    }
+                    match children[..] {
+                        [i] => writeln!(w, "  n{} --> n{}", node.index, i)?,
+                        [i, j] => {
+                            writeln!(w, "  n{} --> n{}", node.index, i)?;
+                            writeln!(w, "  linkStyle {} stroke:red", edges)?;
+                            writeln!(w, "  n{} --> n{}", node.index, j)?;
+                            writeln!(w, "  linkStyle {} stroke:blue", edges + 1)?;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            edges += children.len();
+        }
+        match options.format {
+            GraphFormat::Dot => writeln!(w, "}}"),
+            GraphFormat::Mermaid => Ok(()),
         }
     }
 }
 
+/// Output format of `Program::write_graph`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
+    /// Mermaid flowchart
+    Mermaid,
+}
+
+impl Default for GraphFormat {
+    fn default() -> Self {
+        GraphFormat::Dot
+    }
+}
+
+/// Options for `Program::write_graph`. By default, nodes are labelled with
+/// their combinator, index and type, in DOT format.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct GraphOptions {
+    /// Output format
+    pub format: GraphFormat,
+    /// Number of leading hex digits of each node's CMR to show, if any
+    pub cmr_digits: usize,
+    /// Whether to show the values of witness nodes
+    pub witness_values: bool,
+    /// Whether to show the bit widths of each node's source and target
+    /// types instead of the types themselves
+    pub type_widths: bool,
+    /// Whether to highlight nodes with more than one parent
+    pub highlight_shared: bool,
+}
+
 fn compute_cmr<Ext: extension::Jet>(
     program: &[ProgramNode<Ext>],
-    node: &Term<Value, Ext>,
+    node: &Term<PackedValue, Ext>,
     idx: usize,
 ) -> Cmr {
-    match *node {
-        Term::Iden => cmr::tag::iden(),
-        Term::Unit => cmr::tag::unit(),
-        Term::InjL(i) => cmr::tag::injl().update_1(program[idx - i].cmr),
-        Term::InjR(i) => cmr::tag::injr().update_1(program[idx - i].cmr),
-        Term::Take(i) => cmr::tag::take().update_1(program[idx - i].cmr),
-        Term::Drop(i) => cmr::tag::drop().update_1(program[idx - i].cmr),
-        Term::Comp(i, j) => cmr::tag::comp().update(program[idx - i].cmr, program[idx - j].cmr),
-        Term::Case(i, j) => cmr::tag::case().update(program[idx - i].cmr, program[idx - j].cmr),
-        Term::Pair(i, j) => cmr::tag::pair().update(program[idx - i].cmr, program[idx - j].cmr),
-        Term::Disconnect(i, _) => cmr::tag::disconnect().update_1(program[idx - i].cmr),
-        Term::Witness(..) => cmr::tag::witness(),
-        Term::Fail(..) => unimplemented!(),
-        Term::Hidden(cmr) => cmr,
-        Term::Ext(ref b) => b.cmr(),
-        Term::Jet(ref j) => j.cmr(),
+    let mut children = [Cmr::from([0; 32]); 2];
+    let mut n_children = 0;
+    for (n, i) in node.children().enumerate() {
+        children[n] = program[idx - i].cmr;
+        n_children = n + 1;
     }
+    node.cmr(&children[..n_children])
 }
 
 fn compute_extra_cells_bound<Ext: extension::Jet>(
     program: &[ProgramNode<Ext>],
-    node: &Term<Value, Ext>,
+    node: &Term<PackedValue, Ext>,
     idx: usize,
     witness_target_width: usize,
 ) -> usize {
@@ -283,7 +550,7 @@ fn compute_extra_cells_bound<Ext: extension::Jet>(
 
 fn compute_frame_count_bound<Ext: extension::Jet>(
     program: &[ProgramNode<Ext>],
-    node: &Term<Value, Ext>,
+    node: &Term<PackedValue, Ext>,
     idx: usize,
 ) -> usize {
     match *node {
@@ -327,6 +594,7 @@ mod tests {
     use crate::exec;
 
     use crate::bititer::BitIter;
+    use crate::encode::BitWrite;
     use crate::extension::{
         dummy::{DummyNode, TxEnv},
         jets::JetsNode,
@@ -362,7 +630,14 @@ mod tests {
         let prog = Program::<DummyNode>::decode(&mut BitIter::from(prog.into_iter()))
             .expect("decoding program");
 
-        prog.graph_print();
+        let mut dot = vec![];
+        prog.write_graph(&mut dot, &GraphOptions::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(dot).unwrap(),
+            "digraph {\n  0 [label=\"unit\\n0\\n1 → 1\"];\n  \
+             1 [label=\"injl\\n1\\n1 → 2\"];\n  1 -> 0;\n}\n",
+        );
         assert_eq!(prog.nodes.len(), 2);
         assert_eq!(prog.nodes[0].node, Term::Unit);
         assert_eq!(prog.nodes[1].node, Term::InjL(1));
@@ -380,6 +655,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::vec_init_then_push)] // one node per line
     fn encode_prog() {
         let mut prog: Vec<Term<(), DummyNode>> = vec![];
 
@@ -391,10 +667,14 @@ mod tests {
             &mut BitIter::from(vec![0x00].into_iter()),
         )
         .unwrap();
-        prog.graph_print();
+        let mut dot = vec![];
+        prog.write_graph(&mut dot, &GraphOptions::default())
+            .unwrap();
+        assert!(String::from_utf8(dot).unwrap().contains("jet adder32"));
     }
 
     #[test]
+    #[allow(clippy::vec_init_then_push)] // one node per line
     fn witness_and() {
         let mut prog: Vec<Term<(), DummyNode>> = vec![];
 
@@ -410,12 +690,110 @@ mod tests {
             &mut BitIter::from(vec![0x80].into_iter()),
         )
         .unwrap();
-        prog.graph_print();
+        let options = GraphOptions {
+            format: GraphFormat::Mermaid,
+            cmr_digits: 8,
+            witness_values: true,
+            type_widths: true,
+            highlight_shared: true,
+        };
+        let mut mermaid = vec![];
+        prog.write_graph(&mut mermaid, &options).unwrap();
+        let mermaid = String::from_utf8(mermaid).unwrap();
+        assert!(mermaid.starts_with("graph TD\n"));
+        assert!(
+            mermaid.contains("  n4[\"witness<br/>(0, ())<br/>4<br/>1404aab1<br/>0 → 1 bits\"]\n")
+        );
+        assert!(mermaid.contains("  n5 --> n3\n  linkStyle 4 stroke:blue\n"));
 
         let mut mac = exec::BitMachine::for_program(&prog);
         // mac.input(&Value::prod(Value::u1(0), Value::Unit));
         let output = mac.exec(&prog, &TxEnv);
 
-        println!("{}", output);
+        println!("{}", output.pretty());
+    }
+
+    #[test]
+    fn decode_strict_defaulted_witness() {
+        // The `witness_and` program: the type of the first witness is fixed
+        // by a root constraint, but nothing determines the second one
+        let nodes: Vec<Term<(), DummyNode>> = vec![
+            Term::Unit,
+            Term::InjR(1),
+            Term::Witness(()),
+            Term::Case(2, 1),
+            Term::Witness(()),
+            Term::Comp(1, 2),
+        ];
+        let mut w = encode::BitWriter::new(vec![]);
+        encode::encode_natural(nodes.len(), &mut w).unwrap();
+        for node in &nodes {
+            encode::encode_node_no_witness(node, &mut w).unwrap();
+        }
+        w.write_bit(false).unwrap();
+        w.flush_all().unwrap();
+        let bytes = w.into_inner();
+
+        let bit = types::TypePattern::Sum(
+            Box::new(types::TypePattern::Unit),
+            Box::new(types::TypePattern::Unit),
+        );
+        let root = types::TypeConstraint::root(types::TypePattern::Unit, bit);
+        let decode = |constraints: &[types::TypeConstraint]| {
+            let mut iter = BitIter::from(bytes.clone().into_iter());
+            Program::<DummyNode>::decode_strict(&mut iter, constraints)
+        };
+        match decode(&[]) {
+            Err(Error::DefaultedWitness(idxs)) => assert_eq!(idxs, vec![2, 4]),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("decoded a program with defaulted witness types"),
+        }
+        match decode(std::slice::from_ref(&root)) {
+            Err(Error::DefaultedWitness(idxs)) => assert_eq!(idxs, vec![4]),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("decoded a program with defaulted witness types"),
+        }
+
+        // The same program decodes in the non-strict mode
+        let mut iter = BitIter::from(bytes.into_iter());
+        assert!(Program::<DummyNode>::decode_with_constraints(&mut iter, &[root]).is_ok());
+    }
+
+    #[test]
+    fn typed_dag_round_trip() {
+        // The `witness_and` program in post order, with a root constraint
+        // which widens the source type of the program and of its first node
+        let nodes: Vec<Term<(), DummyNode>> = vec![
+            Term::Witness(()),
+            Term::Unit,
+            Term::InjR(1),
+            Term::Witness(()),
+            Term::Case(2, 1),
+            Term::Comp(5, 1),
+        ];
+        let bit = types::TypePattern::Sum(
+            Box::new(types::TypePattern::Unit),
+            Box::new(types::TypePattern::Unit),
+        );
+        let root = types::TypeConstraint::root(bit.clone(), bit);
+        let prog = Program::<DummyNode>::from_untyped_nodes_with_constraints(
+            UnTypedProg(nodes),
+            &mut BitIter::from(vec![0x80].into_iter()),
+            &[root],
+        )
+        .unwrap();
+        assert_eq!(prog.root_node().source_ty.to_string(), "2");
+        assert_eq!(prog.nodes[0].source_ty.to_string(), "2");
+
+        let dag = prog.to_dag();
+        let root_types = dag.types(dag.root()).unwrap();
+        assert_eq!(root_types.0, prog.root_node().source_ty);
+        assert_eq!(Program::from_typed_dag(&dag).unwrap().nodes, prog.nodes);
+
+        // Without the types, the source type of the program is inferred
+        // to be the unit type
+        let inferred = Program::from_dag(dag.root()).unwrap();
+        assert_eq!(inferred.root_node().source_ty.to_string(), "1");
+        assert_eq!(inferred.root_node().cmr, prog.root_node().cmr);
     }
 }
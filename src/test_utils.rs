@@ -0,0 +1,62 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Test Support
+//!
+//! Helpers shared by the unit tests of several modules
+//!
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+use crate::bititer::BitIter;
+use crate::core::types::{FinalType, TypeConstraint};
+use crate::exec::BitMachine;
+use crate::extension::dummy::{DummyNode, TxEnv};
+use crate::{PackedValue, Program, UnTypedProg, Value};
+
+/// Type-check a program with the given source and target types, failing
+/// if it does not have them, and run it in the Bit Machine on each of the
+/// inputs, giving `None` wherever execution fails
+pub fn run(
+    program: UnTypedProg<(), DummyNode>,
+    source: &Arc<FinalType>,
+    target: &Arc<FinalType>,
+    inputs: &[Value],
+) -> Vec<Option<Value>> {
+    let prog = Program::from_untyped_nodes_with_constraints(
+        program,
+        &mut BitIter::from(vec![0x00].into_iter()),
+        &[TypeConstraint::root(
+            Arc::clone(source).into(),
+            Arc::clone(target).into(),
+        )],
+    )
+    .unwrap();
+    assert_eq!(prog.root_node().source_ty, *source);
+    assert_eq!(prog.root_node().target_ty, *target);
+
+    inputs
+        .iter()
+        .map(|input| {
+            let input = PackedValue::from_value(input, source).unwrap();
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut mac = BitMachine::for_program(&prog);
+                mac.input(&input);
+                mac.exec(&prog, &TxEnv).to_value()
+            }))
+            .ok()
+        })
+        .collect()
+}